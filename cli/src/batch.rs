@@ -0,0 +1,229 @@
+//! Batch mode, for scripts: runs SQL given on the command line, in files or
+//! on stdin, then exits.
+//!
+//! Usage: cli [database] [-c sql]... [-f path]... [--format table|csv|tsv|json]
+//!
+//! `-f -` reads stdin. The sources run in the order they're given, and stop
+//! at the first error. If a database is given, it's opened if it exists, and
+//! saved once every statement has succeeded, if any of them changed it.
+
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::path::Path;
+
+use llamadb::sqlsyntax::lexer::{Lexer, Token};
+use llamadb::sqlsyntax::parser;
+use llamadb::tempdb::{ExecuteStatementResponse, TempDb};
+
+use output::{OutputFormat, write_select};
+
+/// Exit codes
+pub const EXIT_ERROR: i32 = 1;
+pub const EXIT_USAGE: i32 = 2;
+
+pub struct Options {
+    pub database: Option<String>,
+    pub sources: Vec<Source>,
+    pub format: OutputFormat
+}
+
+pub enum Source {
+    Sql(String),
+    /// A file, or stdin if the path is `-`
+    File(String)
+}
+
+impl Options {
+    pub fn parse(args: &[String]) -> Result<Options, String> {
+        let mut options = Options {
+            database: None,
+            sources: Vec::new(),
+            format: OutputFormat::Table
+        };
+
+        let mut args = args.iter();
+
+        while let Some(arg) = args.next() {
+            let mut value = |flag: &str| args.next().map(|v| v.clone()).ok_or(format!("{} needs a value", flag));
+
+            match arg as &str {
+                "-c" => options.sources.push(Source::Sql(try!(value("-c")))),
+                "-f" => options.sources.push(Source::File(try!(value("-f")))),
+                "--format" => {
+                    let name = try!(value("--format"));
+                    options.format = try!(OutputFormat::from_name(&name).ok_or(format!(
+                        "unknown format {}; expected table, csv, tsv or json", name)));
+                },
+                arg if arg.starts_with("-") => return Err(format!("unknown option {}", arg)),
+                arg if options.database.is_none() => options.database = Some(arg.to_string()),
+                arg => return Err(format!("unexpected argument {}", arg))
+            }
+        }
+
+        Ok(options)
+    }
+
+    /// Batch mode runs if there's SQL to run. Otherwise, the prompt starts
+    /// with the database opened.
+    pub fn is_batch(&self) -> bool {
+        !self.sources.is_empty()
+    }
+}
+
+pub fn usage() -> &'static str {
+    "usage: cli [database] [-c sql]... [-f path]... [--format table|csv|tsv|json]"
+}
+
+/// Opens the database if it exists, or starts an empty one.
+pub fn open_database(options: &Options) -> Result<TempDb, String> {
+    match options.database {
+        Some(ref path) if Path::new(path).exists() => TempDb::open(path),
+        _ => Ok(TempDb::new())
+    }
+}
+
+/// Runs every source, and returns the exit code.
+pub fn run(options: &Options) -> i32 {
+    let mut stderr = io::stderr();
+
+    match run_sources(options) {
+        Ok(()) => 0,
+        Err(message) => {
+            let _ = writeln!(stderr, "{}", message);
+            EXIT_ERROR
+        }
+    }
+}
+
+fn run_sources(options: &Options) -> Result<(), String> {
+    let mut db = try!(open_database(options));
+    let mut out = io::stdout();
+    let mut changed = false;
+
+    for source in options.sources.iter() {
+        let (name, sql) = match source {
+            &Source::Sql(ref sql) => ("-c".to_string(), sql.clone()),
+            &Source::File(ref path) => (path.clone(), try!(read_source(path)))
+        };
+
+        if try!(run_sql(&mut out, &mut db, options.format, &sql).map_err(|e| format!("{}:{}", name, e))) {
+            changed = true;
+        }
+    }
+
+    match options.database {
+        Some(ref path) if changed => db.save_to(path),
+        _ => Ok(())
+    }
+}
+
+fn read_source(path: &str) -> Result<String, String> {
+    let mut sql = String::new();
+
+    let result = if path == "-" {
+        io::stdin().read_to_string(&mut sql)
+    } else {
+        File::open(path).and_then(|mut file| file.read_to_string(&mut sql))
+    };
+
+    try!(result.map_err(|e| format!("cannot read {}: {}", path, e)));
+    Ok(sql)
+}
+
+/// Runs the statements in `sql`, a line at a time so that `PRAGMA sql_mode`
+/// applies to the lines after it. Errors start with the line they're on.
+/// Returns whether any statement could have changed what's saved.
+fn run_sql(out: &mut Write, db: &mut TempDb, format: OutputFormat, sql: &str) -> Result<bool, String> {
+    let mut lexer = Lexer::new();
+    let mut changed = false;
+
+    for (i, line) in sql.lines().enumerate() {
+        lexer.set_mode(db.sql_mode());
+
+        try!(lexer.feed_characters(line.chars()).and_then(|()| lexer.feed_character(Some('\n'))).map_err(|e| {
+            format!("{}: syntax error: {}", i + 1, e)
+        }));
+
+        while let Some(i) = lexer.tokens.iter().position(|token| token == &Token::Semicolon) {
+            if try!(run_tokens(out, db, format, &lexer, i + 1)) {
+                changed = true;
+            }
+
+            let right = lexer.tokens.split_off(i+1);
+            lexer.tokens = right;
+            let right = lexer.spans.split_off(i+1);
+            lexer.spans = right;
+        }
+    }
+
+    try!(lexer.feed_character(None).map_err(|e| format!("{}: syntax error: {}", sql.lines().count(), e)));
+
+    // The last statement doesn't need a semicolon
+    if !lexer.tokens.is_empty() {
+        let count = lexer.tokens.len();
+
+        if try!(run_tokens(out, db, format, &lexer, count)) {
+            changed = true;
+        }
+    }
+
+    Ok(changed)
+}
+
+/// Runs the statement made of the first `count` tokens.
+fn run_tokens(out: &mut Write, db: &mut TempDb, format: OutputFormat, lexer: &Lexer, count: usize)
+-> Result<bool, String>
+{
+    let line = lexer.spans[0].line;
+
+    let statement = try!(parser::parse_statement(&lexer.tokens[0..count], &lexer.spans[0..count]).map_err(|e| {
+        format!("{}: syntax error: {}", line, e)
+    }));
+
+    let user_version = db.user_version();
+    let sequence_positions = db.sequence_positions();
+
+    let (write_result, changed) = {
+        let response = try!(db.execute_statement(statement).map_err(|e| format!("{}: execution error: {}", line, e)));
+
+        match response {
+            ExecuteStatementResponse::Select { column_names, rows, .. } => {
+                (write_select(out, format, &column_names, rows).map(|_| ()), false)
+            },
+            ExecuteStatementResponse::Explain(plan) => (writeln!(out, "{}", plan), false),
+            // Settings and session variables aren't saved
+            ExecuteStatementResponse::Set => (Ok(()), false),
+            _ => (Ok(()), true)
+        }
+    };
+
+    try!(write_result.map_err(|e| format!("{}: cannot write output: {}", line, e)));
+
+    // ...but the user version and the sequences' positions are, and even a
+    // query can advance a sequence
+    Ok(changed || db.user_version() != user_version || db.sequence_positions() != sequence_positions)
+}
+
+#[cfg(test)]
+mod test {
+    use llamadb::tempdb::TempDb;
+
+    use output::OutputFormat;
+    use super::run_sql;
+
+    #[test]
+    fn test_run_sql_changes() {
+        let mut db = TempDb::new();
+        let mut run = |sql: &str| run_sql(&mut Vec::new(), &mut db, OutputFormat::Table, sql);
+
+        assert_eq!(run("PRAGMA synchronous = off; SET x = 1; PRAGMA sql_mode; SELECT 1; EXPLAIN SELECT 1;"), Ok(false));
+        assert_eq!(run("PRAGMA user_version = 0;"), Ok(false));
+        assert_eq!(run("PRAGMA user_version = 2;"), Ok(true));
+        assert_eq!(run("CREATE TABLE t (a U32);"), Ok(true));
+        assert_eq!(run("INSERT INTO t VALUES (1);"), Ok(true));
+        assert_eq!(run("CREATE SEQUENCE s;"), Ok(true));
+        assert_eq!(run("SELECT nextval('s');"), Ok(true));
+        assert_eq!(run("SELECT currval('s'); SELECT nextval('s') FROM t WHERE a = 2;"), Ok(false));
+        assert!(run("INSERT INTO u VALUES (1);").is_err());
+    }
+}
@@ -11,15 +11,39 @@ extern crate llamadb;
 use std::io::Write;
 use std::time::duration::Duration;
 
+mod batch;
+
+mod output;
+
 mod prettyselect;
 use prettyselect::pretty_select;
 
 fn main() {
     env_logger::init().unwrap();
 
+    let args: Vec<String> = std::env::args().skip(1).collect();
+
+    let options = match batch::Options::parse(&args) {
+        Ok(options) => options,
+        Err(message) => {
+            let _ = writeln!(std::io::stderr(), "{}\n{}", message, batch::usage());
+            std::process::exit(batch::EXIT_USAGE);
+        }
+    };
+
+    if options.is_batch() {
+        std::process::exit(batch::run(&options));
+    }
+
     let mut lexer = llamadb::sqlsyntax::lexer::Lexer::new();
 
-    let mut db = llamadb::tempdb::TempDb::new();
+    let mut db = match batch::open_database(&options) {
+        Ok(db) => db,
+        Err(message) => {
+            let _ = writeln!(std::io::stderr(), "{}", message);
+            std::process::exit(batch::EXIT_ERROR);
+        }
+    };
 
     let mut out = std::io::stdout();
 
@@ -45,31 +69,92 @@ fn main() {
                     continue;
                 }
 
-                lexer.feed_characters(input.chars());
-                lexer.feed_character(Some('\n'));
+                if input.starts_with("importjson ") {
+                    let args: Vec<&str> = input.split(' ').filter(|s| !s.is_empty()).collect();
+
+                    match import_json_lines(&mut db, &args[1..]) {
+                        Ok(rows) => println!("{} rows imported.", rows),
+                        Err(message) => println!("{}", message)
+                    };
+                    continue;
+                }
+
+                if input.starts_with("save ") || input.starts_with("open ") {
+                    let args: Vec<&str> = input.split(' ').filter(|s| !s.is_empty()).collect();
+
+                    match save_or_open(&mut db, &args) {
+                        Ok(()) => println!("Done."),
+                        Err(message) => println!("{}", message)
+                    };
+                    continue;
+                }
+
+                if input.starts_with("fmt ") {
+                    let args: Vec<&str> = input.split(' ').filter(|s| !s.is_empty()).collect();
+
+                    match format_file(&db, &args[1..]) {
+                        Ok(sql) => print!("{}", sql),
+                        Err(message) => println!("{}", message)
+                    };
+                    continue;
+                }
+
+                if input.starts_with("diff ") {
+                    let args: Vec<&str> = input.split(' ').filter(|s| !s.is_empty()).collect();
+
+                    match diff(&db, &args[1..]) {
+                        Ok(diff) => {
+                            if diff.is_empty() {
+                                println!("No differences.");
+                            } else {
+                                print!("{}", diff);
+                            }
+                        },
+                        Err(message) => println!("{}", message)
+                    };
+                    continue;
+                }
+
+                // PRAGMA sql_mode changes what double quotes mean
+                lexer.set_mode(db.sql_mode());
+
+                let lexed = lexer.feed_characters(input.chars()).and_then(|()| {
+                    lexer.feed_character(Some('\n'))
+                });
+
+                if let Err(e) = lexed {
+                    // Discard the statement that contained the error
+                    println!("syntax error: {}", e);
+                    lexer.tokens.clear();
+                    lexer.spans.clear();
+                    continue;
+                }
 
                 if !input.is_empty() && !lexer.tokens.is_empty() {
                     linenoise::history_add(&input);
                 }
 
                 while let Some(i) = lexer.tokens.iter().position(|token| token == &llamadb::sqlsyntax::lexer::Token::Semicolon) {
-                    match execute(&mut out, &mut db, &lexer.tokens[0..i+1]) {
+                    match execute(&mut out, &mut db, &lexer.tokens[0..i+1], &lexer.spans[0..i+1]) {
                         Ok(()) => (),
                         Err(message) => println!("{}", message)
                     };
 
                     let right = lexer.tokens.split_off(i+1);
                     lexer.tokens = right;
+                    let right = lexer.spans.split_off(i+1);
+                    lexer.spans = right;
                 }
             }
         }
     }
 }
 
-fn execute(out: &mut Write, db: &mut llamadb::tempdb::TempDb, tokens: &[llamadb::sqlsyntax::lexer::Token])
+fn execute(out: &mut Write, db: &mut llamadb::tempdb::TempDb, tokens: &[llamadb::sqlsyntax::lexer::Token],
+    spans: &[llamadb::sqlsyntax::lexer::Span])
 -> Result<(), String>
 {
-    let statement = match llamadb::sqlsyntax::parser::parse_statement(tokens) {
+    let statement = match llamadb::sqlsyntax::parser::parse_statement(tokens, spans) {
         Ok(stmt) => stmt,
         Err(e) => return Err(format!("syntax error: {}", e))
     };
@@ -102,7 +187,25 @@ fn execute_statement(out: &mut Write, db: &mut llamadb::tempdb::TempDb, statemen
         ExecuteStatementResponse::Inserted(rows) => {
             writeln!(out, "{} rows inserted ({}).", rows, duration_string)
         },
-        ExecuteStatementResponse::Select { column_names, rows } => {
+        ExecuteStatementResponse::Updated(rows) => {
+            writeln!(out, "{} rows updated ({}).", rows, duration_string)
+        },
+        ExecuteStatementResponse::Deleted(rows) => {
+            writeln!(out, "{} rows deleted ({}).", rows, duration_string)
+        },
+        ExecuteStatementResponse::Altered => {
+            writeln!(out, "Altered ({}).", duration_string)
+        },
+        ExecuteStatementResponse::Commented => {
+            writeln!(out, "Commented.")
+        },
+        ExecuteStatementResponse::Refreshed(rows) => {
+            writeln!(out, "{} rows refreshed ({}).", rows, duration_string)
+        },
+        ExecuteStatementResponse::Set => {
+            writeln!(out, "Set.")
+        },
+        ExecuteStatementResponse::Select { column_names, rows, .. } => {
             pretty_select(out, &column_names, rows, 32).and_then(|row_count| {
                 writeln!(out, "{} rows selected ({}).", row_count, duration_string)
             })
@@ -117,10 +220,83 @@ fn execute_statement(out: &mut Write, db: &mut llamadb::tempdb::TempDb, statemen
     Ok(())
 }
 
+/// Usage: importjson <table> <path>
+fn import_json_lines(db: &mut llamadb::tempdb::TempDb, args: &[&str]) -> Result<u64, String> {
+    use std::fs::File;
+    use std::io::BufReader;
+
+    if args.len() != 2 {
+        return Err(format!("usage: importjson <table> <path>"));
+    }
+
+    let (table_name, path) = (args[0], args[1]);
+
+    let file = try!(File::open(path).map_err(|e| format!("cannot open {}: {}", path, e)));
+
+    db.import_json_lines(table_name, BufReader::new(file)).map_err(|e| {
+        format!("import error: {}", e)
+    })
+}
+
+/// Usage: save <path>, or open <path>
+///
+/// Opening a database replaces the current one.
+fn save_or_open(db: &mut llamadb::tempdb::TempDb, args: &[&str]) -> Result<(), String> {
+    if args.len() != 2 {
+        return Err(format!("usage: {} <path>", args[0]));
+    }
+
+    let path = args[1];
+
+    if args[0] == "save" {
+        db.save_to(path)
+    } else {
+        *db = try!(llamadb::tempdb::TempDb::open(path));
+        Ok(())
+    }
+}
+
+/// Usage: diff <path>
+///
+/// Prints the statements that would turn the current database into the one
+/// saved at the path.
+fn diff(db: &llamadb::tempdb::TempDb, args: &[&str]) -> Result<llamadb::tempdb::Diff, String> {
+    if args.len() != 1 {
+        return Err(format!("usage: diff <path>"));
+    }
+
+    let other = try!(llamadb::tempdb::TempDb::open(args[0]));
+    Ok(db.diff(&other))
+}
+
+/// Usage: fmt <path>
+///
+/// Prints the SQL in the file, formatted. The database's SQL mode decides
+/// what double quotes mean.
+fn format_file(db: &llamadb::tempdb::TempDb, args: &[&str]) -> Result<String, String> {
+    use std::fs::File;
+    use std::io::Read;
+
+    if args.len() != 1 {
+        return Err(format!("usage: fmt <path>"));
+    }
+
+    let path = args[0];
+    let mut sql = String::new();
+
+    try!(File::open(path).and_then(|mut file| file.read_to_string(&mut sql)).map_err(|e| {
+        format!("cannot read {}: {}", path, e)
+    }));
+
+    llamadb::sqlsyntax::format_sql_with_mode(&sql, db.sql_mode()).map_err(|e| format!("syntax error: {}", e))
+}
+
 fn load_testdata(out: &mut Write, db: &mut llamadb::tempdb::TempDb) -> Result<(), String> {
     let test_data = include_str!("testdata.sql");
 
-    let statements = llamadb::sqlsyntax::parse_statements(test_data);
+    let statements = try!(llamadb::sqlsyntax::parse_statements(test_data).map_err(|e| {
+        format!("syntax error: {}", e)
+    }));
 
     for statement in statements {
         try!(execute_statement(out, db, statement));
@@ -0,0 +1,199 @@
+use std::io::{self, Write};
+use std::usize;
+
+use llamadb::Variant;
+
+use prettyselect::pretty_select;
+
+/// How batch mode prints the rows of a query
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum OutputFormat {
+    /// The same tables as the interactive prompt, without paging
+    Table,
+    /// RFC 4180, with a header. NULL is an empty field, and an empty string
+    /// is `""`, as the `csv` virtual table reads them.
+    Csv,
+    /// Tab-separated, with a header. NULL is `\N`, and tabs, newlines and
+    /// backslashes are escaped with a backslash.
+    Tsv,
+    /// One JSON object per row, as `importjson` reads them
+    Json
+}
+
+impl OutputFormat {
+    pub fn from_name(name: &str) -> Option<OutputFormat> {
+        match name {
+            "table" => Some(OutputFormat::Table),
+            "csv" => Some(OutputFormat::Csv),
+            "tsv" => Some(OutputFormat::Tsv),
+            "json" => Some(OutputFormat::Json),
+            _ => None
+        }
+    }
+}
+
+/// Prints the rows of a query, and returns how many there were.
+pub fn write_select<I>(out: &mut Write, format: OutputFormat, column_names: &[String], rows: I)
+-> Result<u64, io::Error>
+where I: Iterator<Item=Box<[Variant]>>
+{
+    let separator = match format {
+        OutputFormat::Table => return pretty_select(out, column_names, rows, usize::MAX),
+        OutputFormat::Json => return write_json(out, column_names, rows),
+        OutputFormat::Csv => ",",
+        OutputFormat::Tsv => "\t"
+    };
+
+    let quote = |s: &str| match format {
+        OutputFormat::Csv => csv_field(s),
+        _ => tsv_field(s)
+    };
+
+    let header: Vec<String> = column_names.iter().map(|name| quote(name)).collect();
+    try!(writeln!(out, "{}", header.connect(separator)));
+
+    let mut row_count = 0;
+
+    for row in rows {
+        let fields: Vec<String> = row.iter().map(|value| match value {
+            &Variant::Null if format == OutputFormat::Csv => String::new(),
+            &Variant::Null => "\\N".to_string(),
+            value => quote(&text(value))
+        }).collect();
+
+        try!(writeln!(out, "{}", fields.connect(separator)));
+        row_count += 1;
+    }
+
+    Ok(row_count)
+}
+
+fn write_json<I>(out: &mut Write, column_names: &[String], rows: I) -> Result<u64, io::Error>
+where I: Iterator<Item=Box<[Variant]>>
+{
+    let mut row_count = 0;
+
+    for row in rows {
+        let mut line = String::new();
+        line.push('{');
+
+        for (i, (name, value)) in column_names.iter().zip(row.iter()).enumerate() {
+            if i > 0 {
+                line.push_str(", ");
+            }
+
+            line.push_str(&json_string(name));
+            line.push_str(": ");
+
+            match value {
+                &Variant::Null => line.push_str("null"),
+                &Variant::SignedInteger(n) => line.push_str(&n.to_string()),
+                &Variant::UnsignedInteger(n) => line.push_str(&n.to_string()),
+                // JSON has no infinities
+                &Variant::Float(n) if n.is_finite() => line.push_str(&n.to_string()),
+                &Variant::Float(_) => line.push_str("null"),
+                value => line.push_str(&json_string(&text(value)))
+            }
+        }
+
+        line.push('}');
+        try!(writeln!(out, "{}", line));
+        row_count += 1;
+    }
+
+    Ok(row_count)
+}
+
+/// A value as text. Byte arrays are written in hexadecimal, as `hex` returns
+/// them.
+fn text(value: &Variant) -> String {
+    match value {
+        &Variant::Bytes(ref bytes) => {
+            let mut s = String::new();
+            for byte in bytes.iter() {
+                s.push_str(&format!("{:02X}", byte));
+            }
+            s
+        },
+        value => value.to_string()
+    }
+}
+
+fn csv_field(s: &str) -> String {
+    if s.is_empty() || s.contains(|c| c == ',' || c == '"' || c == '\n' || c == '\r') {
+        format!("\"{}\"", s.replace("\"", "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+fn tsv_field(s: &str) -> String {
+    let mut field = String::new();
+
+    for c in s.chars() {
+        match c {
+            '\\' => field.push_str("\\\\"),
+            '\t' => field.push_str("\\t"),
+            '\n' => field.push_str("\\n"),
+            '\r' => field.push_str("\\r"),
+            c => field.push(c)
+        }
+    }
+
+    field
+}
+
+fn json_string(s: &str) -> String {
+    let mut json = String::new();
+    json.push('"');
+
+    for c in s.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            c if (c as u32) < 0x20 => json.push_str(&format!("\\u{:04x}", c as u32)),
+            c => json.push(c)
+        }
+    }
+
+    json.push('"');
+    json
+}
+
+#[cfg(test)]
+mod test {
+    use super::{csv_field, json_string, tsv_field};
+
+    #[test]
+    fn test_csv_field() {
+        assert_eq!(csv_field("plain text"), "plain text");
+        assert_eq!(csv_field(""), "\"\"");
+        assert_eq!(csv_field("a,b"), "\"a,b\"");
+        assert_eq!(csv_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+        assert_eq!(csv_field("two\nlines"), "\"two\nlines\"");
+        assert_eq!(csv_field("cr\r"), "\"cr\r\"");
+        assert_eq!(csv_field("tab\there"), "tab\there");
+    }
+
+    #[test]
+    fn test_tsv_field() {
+        assert_eq!(tsv_field("plain text"), "plain text");
+        assert_eq!(tsv_field("a\tb"), "a\\tb");
+        assert_eq!(tsv_field("crlf\r\n"), "crlf\\r\\n");
+        assert_eq!(tsv_field("back\\slash"), "back\\\\slash");
+        assert_eq!(tsv_field("\\N"), "\\\\N");
+        assert_eq!(tsv_field("say \"hi\""), "say \"hi\"");
+    }
+
+    #[test]
+    fn test_json_string() {
+        assert_eq!(json_string("plain"), "\"plain\"");
+        assert_eq!(json_string("say \"hi\""), "\"say \\\"hi\\\"\"");
+        assert_eq!(json_string("back\\slash"), "\"back\\\\slash\"");
+        assert_eq!(json_string("crlf\r\n\t"), "\"crlf\\r\\n\\t\"");
+        assert_eq!(json_string("\u{0}\u{1f}\u{7f}é"), "\"\\u0000\\u001f\u{7f}é\"");
+    }
+}
@@ -141,13 +141,109 @@ pub fn write_dbfloat(value: f64, buf: &mut [u8]) {
         mem::transmute(value)
     };
 
-    if value < 0.0 {
+    // Test the sign bit rather than the value, so that -0.0 is negative
+    if raw & (1 << 63) != 0 {
         write_udbinteger(raw ^ (!0), buf)
     } else {
         write_udbinteger(raw ^ (1 << 63), buf)
     }
 }
 
+// Key encodings
+//
+// A key is a concatenation of encoded values that compares with memcmp in
+// the same order as the values themselves. Fixed-length integers and floats
+// already compare correctly with `write_udbinteger`, `write_sdbinteger` and
+// `write_dbfloat`. Variable-length data and NULLs need the encodings below.
+
+/// Marks a NULL in a key. NULLs sort before every other value.
+pub const KEY_NULL: u8 = 0x00;
+
+/// Marks a value that isn't NULL in a key. The value's encoding follows.
+pub const KEY_NOT_NULL: u8 = 0x01;
+
+// A value whose type isn't known ahead of time, such as a GROUP BY value,
+// has one of these after `KEY_NOT_NULL`, so that numbers sort before strings
+// and strings before byte arrays.
+pub const KEY_NUMBER: u8 = 0x00;
+pub const KEY_STRING: u8 = 0x01;
+pub const KEY_BYTES: u8 = 0x02;
+
+/// Like `write_dbfloat`, but for keys: -0.0 and 0.0 encode the same,
+/// and every NaN encodes the same, sorting after positive infinity.
+pub fn write_dbfloat_key(value: f64, buf: &mut [u8]) {
+    use std::mem;
+
+    let value = if value.is_nan() {
+        // The sign and payload of a NaN are arbitrary
+        unsafe { mem::transmute(0x7FF8_0000_0000_0000u64) }
+    } else if value == 0.0 {
+        0.0
+    } else {
+        value
+    };
+
+    write_dbfloat(value, buf)
+}
+
+/// Appends variable-length bytes to a key.
+///
+/// 0x00 is escaped as 0x00 0xFF, and the bytes are terminated by 0x00 0x01.
+/// A prefix therefore sorts before the longer bytes, and any values after
+/// the bytes in the key don't affect their order.
+///
+/// UTF-8 text compares by code point when encoded this way.
+pub fn write_dbbytes_key(bytes: &[u8], key: &mut Vec<u8>) {
+    for &b in bytes {
+        if b == 0x00 {
+            key.push_all(&[0x00, 0xFF]);
+        } else {
+            key.push(b);
+        }
+    }
+
+    key.push_all(&[0x00, 0x01]);
+}
+
+/// Reads bytes written by `write_dbbytes_key`.
+/// Returns None if the bytes are malformed or aren't terminated.
+#[must_use = "must use returned length"]
+pub fn read_dbbytes_key(buf: &[u8]) -> Option<(usize, Vec<u8>)> {
+    let mut bytes = Vec::new();
+    let mut i = 0;
+
+    while i < buf.len() {
+        if buf[i] == 0x00 {
+            match buf.get(i + 1) {
+                Some(&0xFF) => bytes.push(0x00),
+                Some(&0x01) => return Some((i + 2, bytes)),
+                _ => return None
+            }
+            i += 2;
+        } else {
+            bytes.push(buf[i]);
+            i += 1;
+        }
+    }
+
+    None
+}
+
+/// The CRC-32 checksum of some bytes, as used by zlib and PNG
+pub fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+
+    for &b in bytes {
+        crc ^= b as u32;
+
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB88320 } else { crc >> 1 };
+        }
+    }
+
+    !crc
+}
+
 /// Maximum buffer size needed for 64-bit number: 10 bytes
 #[must_use = "must use returned length"]
 pub fn write_uvar(value: u64, buf: &mut [u8]) -> Option<usize> {
@@ -181,6 +277,9 @@ mod test {
     use super::{read_udbinteger, read_sdbinteger};
     use super::{write_u16_le, write_u32_le, write_u64_le, write_uvar};
     use super::{write_udbinteger, write_sdbinteger};
+    use super::{read_dbfloat, write_dbfloat, write_dbfloat_key};
+    use super::{read_dbbytes_key, write_dbbytes_key};
+    use super::crc32;
     use std;
 
     static TEST_U16: [(u16, &'static [u8]); 3] = [
@@ -329,4 +428,74 @@ mod test {
             assert_eq!(buf, b);
         }
     }
+
+    #[test]
+    fn test_dbfloat() {
+        let mut buf = [0; 8];
+
+        for &v in [-1.5, -0.0, 0.0, 2.25, std::f64::INFINITY, std::f64::NEG_INFINITY].iter() {
+            write_dbfloat(v, &mut buf);
+            let r = read_dbfloat(&buf);
+            assert_eq!(v, r);
+            assert_eq!(v.is_sign_negative(), r.is_sign_negative());
+        }
+    }
+
+    #[test]
+    fn test_dbfloat_key_order() {
+        use std::f64::{INFINITY, NEG_INFINITY, NAN};
+
+        let key = |v: f64| {
+            let mut buf = [0; 8];
+            write_dbfloat_key(v, &mut buf);
+            buf
+        };
+
+        let ordered = [NEG_INFINITY, -1e300, -1.0, -1e-300, 0.0, 1e-300, 1.0, 1e300, INFINITY, NAN];
+        for pair in ordered.windows(2) {
+            assert!(key(pair[0]) < key(pair[1]), "{} < {}", pair[0], pair[1]);
+        }
+
+        assert_eq!(key(-0.0), key(0.0));
+        assert_eq!(key(NAN), key(-NAN));
+    }
+
+    #[test]
+    fn test_dbbytes_key() {
+        let key = |bytes: &[u8]| {
+            let mut key = Vec::new();
+            write_dbbytes_key(bytes, &mut key);
+            key
+        };
+
+        assert_eq!(key(b""), vec![0x00, 0x01]);
+        assert_eq!(key(b"a\0b"), vec![0x61, 0x00, 0xFF, 0x62, 0x00, 0x01]);
+
+        let ordered: [&[u8]; 6] = [b"", b"\0", b"\0\0", b"a", b"a\0", b"ab"];
+        for pair in ordered.windows(2) {
+            // The trailing byte stands in for a following column
+            let mut a = key(pair[0]);
+            a.push(0xFF);
+            let mut b = key(pair[1]);
+            b.push(0x00);
+            assert!(a < b, "{:?} < {:?}", pair[0], pair[1]);
+        }
+
+        for &bytes in ordered.iter() {
+            let mut k = key(bytes);
+            let len = k.len();
+            k.push(0x42);
+            assert_eq!(read_dbbytes_key(&k), Some((len, bytes.to_vec())));
+        }
+
+        assert_eq!(read_dbbytes_key(&[0x61, 0x00]), None);
+        assert_eq!(read_dbbytes_key(&[0x00, 0x02]), None);
+    }
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xCBF43926);
+        assert_eq!(crc32(b"The quick brown fox jumps over the lazy dog"), 0x414FA339);
+    }
 }
@@ -1,13 +1,15 @@
 use types::DbType;
 use std::borrow::Cow;
+use std::cmp::Ordering;
 
 pub trait ColumnValueOps: Sized {
     fn from_string_literal(s: Cow<str>) -> Result<Self, Cow<str>>;
-    fn from_number_literal(s: Cow<str>) -> Result<Self, Cow<str>>;
 
     fn from_f64(value: f64) -> Self;
     fn to_f64(self) -> Result<f64, ()>;
 
+    fn from_i64(value: i64) -> Self;
+
     fn from_u64(value: u64) -> Self;
     fn to_u64(self) -> Result<u64, ()>;
 
@@ -15,6 +17,10 @@ pub trait ColumnValueOps: Sized {
     fn to_bytes(self, dbtype: DbType) -> Result<Box<[u8]>, ()>;
     fn get_dbtype(&self) -> DbType;
 
+    /// Roughly how many bytes the value takes up in memory, including any
+    /// data it owns.
+    fn memory_size(&self) -> usize;
+
     /// Must return one of the following:
     ///
     /// * -1 for false
@@ -23,18 +29,53 @@ pub trait ColumnValueOps: Sized {
     fn to_3vl(&self) -> i8;
     fn from_3vl(value: i8) -> Self;
 
-    /// * None: self or rhs is NULL, or comparison is otherwise invalid
+    /// The comparison used by operators such as `=` and `<`.
+    ///
+    /// Numbers are compared by value regardless of their types. Strings are
+    /// compared with strings, and byte arrays with byte arrays, byte by byte.
+    ///
+    /// * None: self or rhs is NULL, or the values aren't comparable
     /// * -1: self < rhs
     /// * 0: self == rhs
     /// * 1: self > rhs
     fn compare(&self, rhs: &Self) -> Option<i8>;
+
+    /// The ordering used to sort values, such as for ORDER BY and index
+    /// keys. Unlike `compare`, every pair of values is ordered: NULL comes
+    /// first, then numbers, strings and byte arrays. NULLs are equal.
+    fn total_compare(&self, rhs: &Self) -> Ordering;
+
+    /// Appends the value to a key, such as a group's key. Keys compare
+    /// byte by byte in the same order as `total_compare`, and values that
+    /// `total_compare` finds equal have the same key: NULLs are grouped
+    /// together, and so are equal numbers of different types, such as 1 and
+    /// 1.0.
+    fn write_key(&self, key: &mut Vec<u8>);
+
     fn cast(self, dbtype: DbType) -> Option<Self>;
-    fn concat(&self, rhs: &Self) -> Self;
-    fn add(&self, rhs: &Self) -> Self;
-    fn sub(&self, rhs: &Self) -> Self;
-    fn mul(&self, rhs: &Self) -> Self;
-    fn div(&self, rhs: &Self) -> Self;
-    fn negate(&self) -> Self;
+    /// Concatenation is NULL if either operand is NULL. Numbers are
+    /// converted to text. Byte arrays can only be concatenated with byte
+    /// arrays.
+    fn concat(&self, rhs: &Self) -> Result<Self, String>;
+
+    /// Arithmetic is NULL if either operand is NULL. Fails if an integer
+    /// overflows, or if an operand isn't a number.
+    fn add(&self, rhs: &Self) -> Result<Self, String>;
+    fn sub(&self, rhs: &Self) -> Result<Self, String>;
+    fn mul(&self, rhs: &Self) -> Result<Self, String>;
+    /// Division by zero is NULL.
+    fn div(&self, rhs: &Self) -> Result<Self, String>;
+    fn negate(&self) -> Result<Self, String>;
+
+    /// The number of characters in a string, or of bytes in a byte array.
+    /// Numbers are converted to text. NULL gives NULL.
+    fn length(&self) -> Result<Self, String>;
+    /// The bytes of a byte array or string, as uppercase hexadecimal text.
+    /// Numbers are converted to text first. NULL gives NULL.
+    fn hex(&self) -> Result<Self, String>;
+    /// The byte array written as hexadecimal text, the inverse of `hex`.
+    /// Text that isn't an even number of hexadecimal digits gives NULL.
+    fn unhex(&self) -> Result<Self, String>;
 }
 
 pub trait ColumnValueOpsExt: ColumnValueOps {
@@ -0,0 +1,4 @@
+//! APIs shaped like those of other databases' Rust crates, so that code
+//! written for them can try llamadb with few changes.
+
+pub mod rusqlite;
@@ -0,0 +1,540 @@
+//! A subset of rusqlite's API over a `TempDb`: `Connection`, `Statement`,
+//! `Row`, the `ToSql` and `FromSql` traits, and the `params!` macro.
+//!
+//! Parameters are bound as literals, and byte arrays as calls to `unhex`.
+//! Statements run on the whole database at once, so there are no
+//! transactions.
+//!
+//! ```
+//! #[macro_use] extern crate llamadb;
+//! use llamadb::compat::rusqlite::Connection;
+//!
+//! # fn main() {
+//! let conn = Connection::open_in_memory().unwrap();
+//! conn.execute("CREATE TABLE person (name STRING, age U8)", params![]).unwrap();
+//! conn.execute("INSERT INTO person VALUES (?, ?)", params!["Alice", 30]).unwrap();
+//!
+//! let age: u8 = conn.query_row("SELECT age FROM person WHERE name = ?", params!["Alice"], |row| row.get(0)).unwrap();
+//! assert_eq!(age, 30);
+//! # }
+//! ```
+
+use std::cell::RefCell;
+use std::fmt;
+use std::i64;
+use std::rc::Rc;
+use std::vec;
+
+use sqlsyntax::{self, ast, placeholders};
+use tempdb::{ExecuteStatementResponse, TempDb};
+use types::Variant;
+
+pub type Result<T> = ::std::result::Result<T, Error>;
+
+#[derive(Debug, PartialEq)]
+pub enum Error {
+    /// The SQL couldn't be parsed
+    Syntax(String),
+    /// The statement failed, or its parameters couldn't be bound
+    Database(String),
+    /// `query_row` found no rows
+    QueryReturnedNoRows,
+    InvalidColumnIndex(usize),
+    InvalidColumnName(String),
+    /// The column's value can't be converted to the requested type
+    InvalidColumnType(usize)
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> ::std::result::Result<(), fmt::Error> {
+        match self {
+            &Error::Syntax(ref message) => write!(f, "syntax error: {}", message),
+            &Error::Database(ref message) => write!(f, "{}", message),
+            &Error::QueryReturnedNoRows => write!(f, "query returned no rows"),
+            &Error::InvalidColumnIndex(i) => write!(f, "invalid column index: {}", i),
+            &Error::InvalidColumnName(ref name) => write!(f, "invalid column name: {}", name),
+            &Error::InvalidColumnType(i) => write!(f, "column {} has an incompatible type", i)
+        }
+    }
+}
+
+/// A value, as stored or bound
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Null,
+    Integer(i64),
+    /// Also unsigned integers too large for an i64
+    Real(f64),
+    Text(String),
+    Blob(Vec<u8>)
+}
+
+impl Value {
+    fn from_variant(value: Variant) -> Value {
+        match value {
+            Variant::Null => Value::Null,
+            Variant::SignedInteger(n) => Value::Integer(n),
+            Variant::UnsignedInteger(n) if n <= i64::MAX as u64 => Value::Integer(n as i64),
+            Variant::UnsignedInteger(n) => Value::Real(n as f64),
+            Variant::Float(n) => Value::Real(*n),
+            Variant::StringLiteral(s) => Value::Text(s),
+            Variant::Bytes(bytes) => Value::Blob(bytes)
+        }
+    }
+
+    /// Byte arrays have no literal, so they're written as hexadecimal text
+    /// and converted back by `unhex`
+    fn to_expression(&self) -> ast::Expression {
+        let number = |n| ast::Expression::Number(n);
+        let negate = |expr| ast::Expression::UnaryOp {
+            expr: Box::new(expr),
+            op: ast::UnaryOp::Negate
+        };
+
+        match self {
+            &Value::Null => ast::Expression::Null,
+            &Value::Integer(n) if n >= 0 => number(ast::NumberLiteral::Integer(n as u64)),
+            &Value::Integer(n) => negate(number(ast::NumberLiteral::Integer((n as u64).wrapping_neg()))),
+            &Value::Real(n) if n < 0.0 => negate(number(ast::NumberLiteral::Float(-n))),
+            &Value::Real(n) => number(ast::NumberLiteral::Float(n)),
+            &Value::Text(ref s) => ast::Expression::StringLiteral(s.clone()),
+            &Value::Blob(ref bytes) => {
+                let mut hex = String::with_capacity(bytes.len() * 2);
+                for byte in bytes {
+                    hex.push_str(&format!("{:02X}", byte));
+                }
+
+                ast::Expression::FunctionCall {
+                    name: "unhex".to_string(),
+                    arguments: vec![ast::Expression::StringLiteral(hex)],
+                    distinct: false
+                }
+            }
+        }
+    }
+}
+
+/// Values that can be bound to parameters
+pub trait ToSql {
+    fn to_sql(&self) -> Value;
+}
+
+/// Values that can be read from columns. Fails if the value has another type.
+pub trait FromSql: Sized {
+    fn column_result(value: &Value) -> ::std::result::Result<Self, ()>;
+}
+
+/// Builds a parameter list for `execute`, `query_map` and `query_row`
+#[macro_export]
+macro_rules! params {
+    () => {
+        &[] as &[&$crate::compat::rusqlite::ToSql]
+    };
+    ($($param:expr),+) => {
+        &[$(&$param as &$crate::compat::rusqlite::ToSql),+] as &[&$crate::compat::rusqlite::ToSql]
+    };
+}
+
+pub struct Connection {
+    db: RefCell<TempDb>
+}
+
+impl Connection {
+    pub fn open_in_memory() -> Result<Connection> {
+        Ok(Connection::from_tempdb(TempDb::new()))
+    }
+
+    /// Wraps an existing database, such as one opened with `TempDb::open`
+    pub fn from_tempdb(db: TempDb) -> Connection {
+        Connection {
+            db: RefCell::new(db)
+        }
+    }
+
+    pub fn into_tempdb(self) -> TempDb {
+        self.db.into_inner()
+    }
+
+    /// Executes a statement, and returns the amount of rows it inserted or
+    /// refreshed
+    pub fn execute(&self, sql: &str, params: &[&ToSql]) -> Result<usize> {
+        let stmt = try!(self.parse(sql));
+        self.execute_parsed(stmt, params)
+    }
+
+    /// Executes statements separated by semicolons, without parameters. Each
+    /// statement is parsed after the ones before it have run, so `PRAGMA
+    /// sql_mode` applies to the statements after it.
+    pub fn execute_batch(&self, sql: &str) -> Result<()> {
+        let mut sql = sql;
+
+        while let Some((mut stmt, tail)) = try!(self.prepare_with_tail(sql)) {
+            try!(stmt.execute(&[]));
+            sql = tail;
+        }
+
+        Ok(())
+    }
+
+    pub fn prepare(&self, sql: &str) -> Result<Statement> {
+        let stmt = try!(self.parse(sql));
+        self.prepare_parsed(stmt)
+    }
+
+    /// Prepares the first statement of `sql`, and returns it along with the
+    /// SQL after it, like the tail of `sqlite3_prepare`. Returns None if
+    /// there's no statement left.
+    pub fn prepare_with_tail<'s>(&self, sql: &'s str) -> Result<Option<(Statement, &'s str)>> {
+        let mode = self.db.borrow().sql_mode();
+
+        match try!(sqlsyntax::parse_first_statement_with_mode(sql, mode).map_err(|e| Error::Syntax(e.to_string()))) {
+            Some((stmt, tail)) => Ok(Some((try!(self.prepare_parsed(stmt)), tail))),
+            None => Ok(None)
+        }
+    }
+
+    /// Runs a query, and converts its first row with `f`
+    pub fn query_row<T, F>(&self, sql: &str, params: &[&ToSql], f: F) -> Result<T>
+    where F: FnOnce(&Row) -> Result<T>
+    {
+        let mut stmt = try!(self.prepare(sql));
+        stmt.query_row(params, f)
+    }
+
+    /// Parses with the database's SQL mode
+    fn parse(&self, sql: &str) -> Result<ast::Statement> {
+        let mode = self.db.borrow().sql_mode();
+        sqlsyntax::parse_statement_with_mode(sql, mode).map_err(|e| Error::Syntax(e.to_string()))
+    }
+
+    fn prepare_parsed(&self, stmt: ast::Statement) -> Result<Statement> {
+        // Checks the parameters up front, like SQLite
+        try!(self.db.borrow().prepare(stmt.clone()).map_err(Error::Database));
+
+        Ok(Statement {
+            conn: self,
+            stmt: stmt
+        })
+    }
+
+    fn execute_parsed(&self, stmt: ast::Statement, params: &[&ToSql]) -> Result<usize> {
+        match try!(self.run(stmt, params)) {
+            ExecuteStatementResponse::Inserted(n) | ExecuteStatementResponse::Updated(n) |
+            ExecuteStatementResponse::Deleted(n) | ExecuteStatementResponse::Refreshed(n) => Ok(n as usize),
+            _ => Ok(0)
+        }
+    }
+
+    fn query(&self, stmt: ast::Statement, params: &[&ToSql]) -> Result<Vec<Row>> {
+        match try!(self.run(stmt, params)) {
+            ExecuteStatementResponse::Select { column_names, rows, .. } => {
+                let column_names: Rc<Vec<String>> = Rc::new(column_names.into_vec());
+
+                Ok(rows.map(|row| Row {
+                    column_names: column_names.clone(),
+                    values: row.into_vec().into_iter().map(Value::from_variant).collect()
+                }).collect())
+            },
+            _ => Ok(Vec::new())
+        }
+    }
+
+    fn run(&self, mut stmt: ast::Statement, params: &[&ToSql]) -> Result<ExecuteStatementResponse<'static>> {
+        let values: Vec<_> = params.iter().map(|param| param.to_sql().to_expression()).collect();
+
+        try!(placeholders::bind_parameters(&mut stmt, &values).map_err(|e| Error::Database(e.to_string())));
+
+        self.db.borrow_mut().execute_statement(stmt).map_err(Error::Database)
+    }
+}
+
+
+/// A parsed statement that can be executed many times
+pub struct Statement<'conn> {
+    conn: &'conn Connection,
+    stmt: ast::Statement
+}
+
+impl<'conn> Statement<'conn> {
+    pub fn execute(&mut self, params: &[&ToSql]) -> Result<usize> {
+        self.conn.execute_parsed(self.stmt.clone(), params)
+    }
+
+    /// Runs the query, and returns an iterator that converts each row with
+    /// `f`. The rows are computed before the iterator is returned.
+    pub fn query_map<T, F>(&mut self, params: &[&ToSql], f: F) -> Result<MappedRows<F>>
+    where F: FnMut(&Row) -> Result<T>
+    {
+        let rows = try!(self.conn.query(self.stmt.clone(), params));
+
+        Ok(MappedRows {
+            rows: rows.into_iter(),
+            f: f
+        })
+    }
+
+    pub fn query_row<T, F>(&mut self, params: &[&ToSql], f: F) -> Result<T>
+    where F: FnOnce(&Row) -> Result<T>
+    {
+        let rows = try!(self.conn.query(self.stmt.clone(), params));
+
+        match rows.first() {
+            Some(row) => f(row),
+            None => Err(Error::QueryReturnedNoRows)
+        }
+    }
+
+    /// Whether the query returns at least one row
+    pub fn exists(&mut self, params: &[&ToSql]) -> Result<bool> {
+        let rows = try!(self.conn.query(self.stmt.clone(), params));
+        Ok(!rows.is_empty())
+    }
+}
+
+pub struct MappedRows<F> {
+    rows: vec::IntoIter<Row>,
+    f: F
+}
+
+impl<T, F> Iterator for MappedRows<F> where F: FnMut(&Row) -> Result<T> {
+    type Item = Result<T>;
+
+    fn next(&mut self) -> Option<Result<T>> {
+        match self.rows.next() {
+            Some(row) => Some((self.f)(&row)),
+            None => None
+        }
+    }
+}
+
+pub struct Row {
+    column_names: Rc<Vec<String>>,
+    values: Vec<Value>
+}
+
+impl Row {
+    /// Reads a column by index, from 0, or by name
+    pub fn get<I: RowIndex, T: FromSql>(&self, index: I) -> Result<T> {
+        let index = try!(index.index(&self.column_names));
+
+        match self.values.get(index) {
+            Some(value) => T::column_result(value).map_err(|()| Error::InvalidColumnType(index)),
+            None => Err(Error::InvalidColumnIndex(index))
+        }
+    }
+}
+
+/// A column index, or a column name
+pub trait RowIndex {
+    fn index(&self, column_names: &[String]) -> Result<usize>;
+}
+
+impl RowIndex for usize {
+    fn index(&self, column_names: &[String]) -> Result<usize> {
+        if *self < column_names.len() {
+            Ok(*self)
+        } else {
+            Err(Error::InvalidColumnIndex(*self))
+        }
+    }
+}
+
+impl<'a> RowIndex for &'a str {
+    fn index(&self, column_names: &[String]) -> Result<usize> {
+        let name = self.to_lowercase();
+
+        match column_names.iter().position(|c| c.to_lowercase() == name) {
+            Some(i) => Ok(i),
+            None => Err(Error::InvalidColumnName(self.to_string()))
+        }
+    }
+}
+
+impl ToSql for Value {
+    fn to_sql(&self) -> Value { self.clone() }
+}
+
+impl<'a, T: ToSql + ?Sized> ToSql for &'a T {
+    fn to_sql(&self) -> Value { (**self).to_sql() }
+}
+
+impl<T: ToSql> ToSql for Option<T> {
+    fn to_sql(&self) -> Value {
+        match self {
+            &Some(ref value) => value.to_sql(),
+            &None => Value::Null
+        }
+    }
+}
+
+impl ToSql for str {
+    fn to_sql(&self) -> Value { Value::Text(self.to_string()) }
+}
+
+impl ToSql for String {
+    fn to_sql(&self) -> Value { Value::Text(self.clone()) }
+}
+
+impl ToSql for Vec<u8> {
+    fn to_sql(&self) -> Value { Value::Blob(self.clone()) }
+}
+
+impl ToSql for bool {
+    fn to_sql(&self) -> Value { Value::Integer(*self as i64) }
+}
+
+impl ToSql for f64 {
+    fn to_sql(&self) -> Value { Value::Real(*self) }
+}
+
+impl ToSql for u64 {
+    fn to_sql(&self) -> Value {
+        if *self <= i64::MAX as u64 { Value::Integer(*self as i64) } else { Value::Real(*self as f64) }
+    }
+}
+
+macro_rules! integer_to_sql {
+    ($($t:ty),*) => {
+        $(
+            impl ToSql for $t {
+                fn to_sql(&self) -> Value { Value::Integer(*self as i64) }
+            }
+        )*
+    }
+}
+
+integer_to_sql!(i8, i16, i32, i64, u8, u16, u32);
+
+impl FromSql for Value {
+    fn column_result(value: &Value) -> ::std::result::Result<Value, ()> { Ok(value.clone()) }
+}
+
+impl<T: FromSql> FromSql for Option<T> {
+    fn column_result(value: &Value) -> ::std::result::Result<Option<T>, ()> {
+        match value {
+            &Value::Null => Ok(None),
+            value => T::column_result(value).map(Some)
+        }
+    }
+}
+
+impl FromSql for String {
+    fn column_result(value: &Value) -> ::std::result::Result<String, ()> {
+        match value {
+            &Value::Text(ref s) => Ok(s.clone()),
+            _ => Err(())
+        }
+    }
+}
+
+impl FromSql for Vec<u8> {
+    fn column_result(value: &Value) -> ::std::result::Result<Vec<u8>, ()> {
+        match value {
+            &Value::Blob(ref bytes) => Ok(bytes.clone()),
+            _ => Err(())
+        }
+    }
+}
+
+impl FromSql for f64 {
+    fn column_result(value: &Value) -> ::std::result::Result<f64, ()> {
+        match value {
+            &Value::Integer(n) => Ok(n as f64),
+            &Value::Real(n) => Ok(n),
+            _ => Err(())
+        }
+    }
+}
+
+impl FromSql for bool {
+    fn column_result(value: &Value) -> ::std::result::Result<bool, ()> {
+        i64::column_result(value).map(|n| n != 0)
+    }
+}
+
+impl FromSql for u64 {
+    fn column_result(value: &Value) -> ::std::result::Result<u64, ()> {
+        match value {
+            &Value::Integer(n) if n >= 0 => Ok(n as u64),
+            // Unsigned integers too large for an i64
+            &Value::Real(n) if n >= 9223372036854775808.0 && n < 18446744073709551616.0 && n.fract() == 0.0 => {
+                Ok(n as u64)
+            },
+            _ => Err(())
+        }
+    }
+}
+
+macro_rules! integer_from_sql {
+    ($($t:ident),*) => {
+        $(
+            impl FromSql for $t {
+                fn column_result(value: &Value) -> ::std::result::Result<$t, ()> {
+                    match value {
+                        &Value::Integer(n) if n >= ::std::$t::MIN as i64 && n <= ::std::$t::MAX as i64 => Ok(n as $t),
+                        _ => Err(())
+                    }
+                }
+            }
+        )*
+    }
+}
+
+integer_from_sql!(i8, i16, i32, u8, u16, u32);
+
+impl FromSql for i64 {
+    fn column_result(value: &Value) -> ::std::result::Result<i64, ()> {
+        match value {
+            &Value::Integer(n) => Ok(n),
+            _ => Err(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Connection, Error, Value};
+
+    #[test]
+    fn test_rusqlite_compat() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch("CREATE TABLE t (a I32, b STRING NULL); CREATE TABLE u (c byte[]);").unwrap();
+
+        assert_eq!(conn.execute("INSERT INTO t VALUES (?, ?)", params![-1, "x"]), Ok(1));
+        assert_eq!(conn.execute("INSERT INTO t VALUES ($1, $2)", params![2, None::<String>]), Ok(1));
+
+        let mut stmt = conn.prepare("SELECT a, b FROM t WHERE a > ?").unwrap();
+        let rows: Vec<(i64, Option<String>)> = stmt.query_map(params![-5], |row| {
+            Ok((try!(row.get(0)), try!(row.get("B"))))
+        }).unwrap().map(|row| row.unwrap()).collect();
+        assert_eq!(rows, vec![(-1, Some("x".to_string())), (2, None)]);
+
+        assert_eq!(conn.query_row("SELECT a FROM t WHERE a > 5", params![], |row| row.get::<_, i64>(0)),
+            Err(Error::QueryReturnedNoRows));
+        assert_eq!(conn.query_row("SELECT b FROM t WHERE a = 2", params![], |row| row.get::<_, String>(0)),
+            Err(Error::InvalidColumnType(0)));
+        assert_eq!(conn.query_row("SELECT a FROM t WHERE a = 2", params![], |row| row.get::<_, Value>(0)),
+            Ok(Value::Integer(2)));
+
+        assert_eq!(conn.execute("INSERT INTO u VALUES (?)", params![vec![0u8, 171, 255]]), Ok(1));
+        assert_eq!(conn.query_row("SELECT c FROM u WHERE c = ?", params![vec![0u8, 171, 255]], |row| row.get::<_, Vec<u8>>(0)),
+            Ok(vec![0, 171, 255]));
+        assert!(conn.execute("INSERT INTO u VALUES (?)", params![1, 2]).is_err());
+        assert!(conn.prepare("SELECT ?, $1").is_err());
+    }
+
+    #[test]
+    fn test_rusqlite_compat_tail() {
+        let conn = Connection::open_in_memory().unwrap();
+
+        let (mut stmt, tail) = conn.prepare_with_tail("CREATE TABLE t (a STRING); INSERT INTO t VALUES (\"x\");").unwrap().unwrap();
+        assert_eq!(tail, " INSERT INTO t VALUES (\"x\");");
+        assert_eq!(stmt.execute(params![]), Ok(0));
+        assert!(conn.prepare_with_tail(" ;; ").unwrap().is_none());
+        assert!(conn.prepare("SELECT 1; SELECT 2").is_err());
+
+        // The statements after a PRAGMA are parsed in its SQL mode
+        assert!(conn.execute_batch("PRAGMA sql_mode = strict; INSERT INTO t VALUES (\"x\");").is_err());
+        assert_eq!(conn.query_row("SELECT count(*) FROM t", params![], |row| row.get::<_, i64>(0)), Ok(0));
+    }
+}
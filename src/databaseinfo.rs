@@ -1,5 +1,5 @@
 use columnvalueops::ColumnValueOps;
-use identifier::Identifier;
+use identifier::{CaseFolding, Identifier};
 use types::DbType;
 use std::fmt;
 use std::cmp::Eq;
@@ -11,6 +11,14 @@ pub trait DatabaseInfo {
     type ColumnValue: ColumnValueOps + fmt::Display + Clone + Eq + Hash + 'static;
 
     fn find_table_by_name(&self, name: &Identifier) -> Option<&Self::Table>;
+
+    fn has_sequence(&self, _name: &Identifier) -> bool { false }
+
+    /// The columns of a table function, or None if there's no such function.
+    fn get_table_function_columns(&self, _name: &Identifier) -> Option<Vec<Identifier>> { None }
+
+    /// How new identifiers, such as column aliases, are displayed.
+    fn get_identifier_case_folding(&self) -> CaseFolding { CaseFolding::Lower }
 }
 
 pub trait TableInfo {
@@ -21,6 +29,9 @@ pub trait TableInfo {
     fn find_column_by_offset(&self, offset: u32) -> Option<&Self::Column>;
     fn find_column_by_name(&self, name: &Identifier) -> Option<&Self::Column>;
 
+    /// The text set by `COMMENT ON TABLE`, if any.
+    fn get_comment(&self) -> Option<&str> { None }
+
     fn get_column_names(&self) -> Vec<Identifier> {
         (0..self.get_column_count()).map(|i| {
             let column = self.find_column_by_offset(i).unwrap();
@@ -33,4 +44,7 @@ pub trait ColumnInfo {
     fn get_offset(&self) -> u32;
     fn get_name(&self) -> &Identifier;
     fn get_dbtype(&self) -> &DbType;
+
+    /// The text set by `COMMENT ON COLUMN`, if any.
+    fn get_comment(&self) -> Option<&str> { None }
 }
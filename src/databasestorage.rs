@@ -1,4 +1,5 @@
 use databaseinfo::DatabaseInfo;
+use identifier::Identifier;
 use std::borrow::Cow;
 use std::cmp::Eq;
 use std::hash::Hash;
@@ -6,8 +7,29 @@ use std::hash::Hash;
 pub trait DatabaseStorage {
     type Info: DatabaseInfo;
 
+    /// Iterates over the rows of a table. Rows that can't be read, such as
+    /// those of a virtual table, yield an error and end the scan.
     fn scan_table<'a>(&'a self, table: &'a <Self::Info as DatabaseInfo>::Table)
-    -> Box<Group<ColumnValue=<Self::Info as DatabaseInfo>::ColumnValue> + 'a>;
+    -> Result<Box<Iterator<Item=Result<Cow<'a, [<Self::Info as DatabaseInfo>::ColumnValue]>, String>> + 'a>, String>;
+
+    /// Returns the amount of rows in a table.
+    fn count_rows(&self, table: &<Self::Info as DatabaseInfo>::Table) -> Result<u64, String>;
+
+    /// Advances the sequence, and returns its new value.
+    fn next_sequence_value(&self, name: &Identifier)
+    -> Result<<Self::Info as DatabaseInfo>::ColumnValue, String>;
+
+    /// Returns the value most recently returned by `next_sequence_value`.
+    fn current_sequence_value(&self, name: &Identifier)
+    -> Result<<Self::Info as DatabaseInfo>::ColumnValue, String>;
+
+    /// Returns the value of a session variable, or an error if it isn't set.
+    fn session_variable(&self, name: &Identifier)
+    -> Result<<Self::Info as DatabaseInfo>::ColumnValue, String>;
+
+    /// Computes the rows of a table function from its arguments.
+    fn call_table_function<'a>(&'a self, name: &Identifier, arguments: Vec<<Self::Info as DatabaseInfo>::ColumnValue>)
+    -> Result<Box<Iterator<Item=Vec<<Self::Info as DatabaseInfo>::ColumnValue>> + 'a>, String>;
 }
 
 pub trait Group {
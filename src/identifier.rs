@@ -1,46 +1,98 @@
 use std::fmt;
+use std::hash::{Hash, Hasher};
 use std::ops::Deref;
 
+/// Determines how an identifier is stored and displayed.
+///
+/// Identifiers are compared case-insensitively regardless of the folding.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum CaseFolding {
+    /// Fold to lower case, like PostgreSQL. This is the default.
+    Lower,
+    /// Fold to upper case, as the SQL standard specifies.
+    Upper,
+    /// Keep the case as written, like SQLite.
+    Preserve
+}
+
 /// An identifier is the name for a database object.
 /// Table names, column names, constraint names are identifiers.
 ///
 /// Allowed characters:
 ///
-/// * `a-z`
-/// * `A-Z`
-/// * `0-9`
+/// * Letters, including non-ASCII letters such as `é` or `ж`
+/// * Digits
 /// * `_`
 /// * Space (allowed in SQL with quoted identifiers)
 ///
 /// Other rules:
 ///
 /// * Identifiers must have a minimum length of 1.
-/// * Identifiers cannot start with a number (0-9) or space.
+/// * Identifiers cannot start with a digit or space.
 /// * Identifiers are case insensitive.
 ///
-/// When stored and compared, identifiers must be folded into a canonical,
-/// lower-case representation. This process is known as normalization.
-#[derive(PartialEq, Eq, Clone)]
+/// When compared, identifiers must be folded into a canonical, lower-case
+/// representation. This process is known as normalization.
+/// Dereferencing an identifier yields the normalized representation.
+///
+/// When displayed, identifiers are folded according to a `CaseFolding`.
+#[derive(Clone)]
 pub struct Identifier {
-    value: String
+    value: String,
+    normalized: String
 }
 
 impl Identifier {
-    pub fn new(value: &str) -> Option<Identifier>
-    {
+    pub fn new(value: &str) -> Option<Identifier> {
+        Identifier::new_with_case_folding(value, CaseFolding::Lower)
+    }
+
+    pub fn new_with_case_folding(value: &str, case_folding: CaseFolding) -> Option<Identifier> {
         match normalize(value) {
             Some(s) => Some(Identifier {
-                value: s
+                value: match case_folding {
+                    CaseFolding::Lower => s.clone(),
+                    CaseFolding::Upper => value.to_uppercase(),
+                    CaseFolding::Preserve => value.to_string()
+                },
+                normalized: s
             }),
             None => None
         }
     }
+
+    /// An identifier from its displayed and normalized representations, as
+    /// they were saved. Folding can change an identifier, as `ß` becomes
+    /// `SS` in upper case, so the normalized one isn't derived again.
+    pub fn from_parts(value: &str, normalized: &str) -> Option<Identifier> {
+        match (normalize(value), normalize(normalized)) {
+            (Some(_), Some(ref s)) if s == normalized => Some(Identifier {
+                value: value.to_string(),
+                normalized: normalized.to_string()
+            }),
+            _ => None
+        }
+    }
+}
+
+impl PartialEq for Identifier {
+    fn eq(&self, other: &Identifier) -> bool {
+        self.normalized == other.normalized
+    }
+}
+
+impl Eq for Identifier { }
+
+impl Hash for Identifier {
+    fn hash<H>(&self, state: &mut H) where H: Hasher {
+        self.normalized.hash(state)
+    }
 }
 
 impl Deref for Identifier {
     type Target = str;
 
-    fn deref(&self) -> &str { &self.value }
+    fn deref(&self) -> &str { &self.normalized }
 }
 
 impl fmt::Display for Identifier {
@@ -56,19 +108,14 @@ impl fmt::Debug for Identifier {
 }
 
 fn normalize(value: &str) -> Option<String> {
-    use std::ascii::AsciiExt;
-
     fn is_valid(value: &str) -> bool {
         if let Some(c) = value.chars().nth(0) {
             // Test if the first character is not a digit or space
             match c {
-                '0'...'9' | ' ' => false,
+                c if c.is_numeric() || c == ' ' => false,
                 _ => {
                     value.chars().all(|c| {
-                        match c {
-                            'a'...'z' | 'A'...'Z' | '0'...'9' | '_' | ' ' => true,
-                            _ => false
-                        }
+                        c.is_alphanumeric() || c == '_' || c == ' '
                     })
                 }
             }
@@ -78,9 +125,7 @@ fn normalize(value: &str) -> Option<String> {
     }
 
     if is_valid(value) {
-        Some(value.chars().map(|c| {
-            c.to_ascii_lowercase()
-        }).collect())
+        Some(value.to_lowercase())
     } else {
         None
     }
@@ -107,5 +152,45 @@ mod test {
         assert!(cmp_none("1a"));
         assert!(cmp_none(" abc "));
         assert!(cmp("_1a", "_1a"));
+        assert!(cmp("Größe", "größe"));
+        assert!(cmp("ИМЯ", "имя"));
+        assert!(cmp_none("١a"));
+        assert!(cmp_none("a-b"));
+    }
+
+    #[test]
+    fn test_identifier_case_folding() {
+        use super::CaseFolding;
+
+        fn display(a: &'static str, case_folding: CaseFolding) -> String {
+            Identifier::new_with_case_folding(a, case_folding).unwrap().to_string()
+        }
+
+        assert_eq!(display("AbC dE", CaseFolding::Lower), "abc de");
+        assert_eq!(display("AbC dE", CaseFolding::Upper), "ABC DE");
+        assert_eq!(display("AbC dE", CaseFolding::Preserve), "AbC dE");
+        assert_eq!(display("Straße", CaseFolding::Upper), "STRASSE");
+
+        // Comparisons are case-insensitive for every folding
+        let upper = Identifier::new_with_case_folding("Name", CaseFolding::Upper).unwrap();
+        let preserved = Identifier::new_with_case_folding("Name", CaseFolding::Preserve).unwrap();
+        assert!(upper == preserved);
+        assert!(upper == Identifier::new("NAME").unwrap());
+        assert_eq!(&upper as &str, "name");
+    }
+
+    #[test]
+    fn test_identifier_from_parts() {
+        use super::CaseFolding;
+
+        let upper = Identifier::new_with_case_folding("Straße", CaseFolding::Upper).unwrap();
+        let restored = Identifier::from_parts(&upper.to_string(), &upper).unwrap();
+        assert_eq!(restored.to_string(), "STRASSE");
+        assert_eq!(&restored as &str, "straße");
+        assert!(restored == Identifier::new("Straße").unwrap());
+
+        assert!(Identifier::from_parts("A", "A").is_none());
+        assert!(Identifier::from_parts("1a", "a").is_none());
+        assert!(Identifier::from_parts("a", "1a").is_none());
     }
 }
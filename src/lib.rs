@@ -1,8 +1,9 @@
-#![feature(collections, into_cow)]
+#![feature(collections, into_cow, std_misc)]
 
 #[macro_use]
 extern crate log;
 
+pub mod compat;
 pub mod sqlsyntax;
 pub mod tempdb;
 
@@ -13,3 +14,6 @@ mod databasestorage;
 mod identifier;
 mod queryplan;
 mod types;
+
+pub use identifier::CaseFolding;
+pub use types::Variant;
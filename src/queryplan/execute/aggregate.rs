@@ -1,9 +1,20 @@
+use std::cmp::Ordering;
+
 use columnvalueops::{ColumnValueOps, ColumnValueOpsExt};
 use super::super::sexpression::AggregateOp;
 
+/// An aggregate function is fed values one at a time. Window frames that
+/// slide along a partition also take values back out with `remove`, so that
+/// each row doesn't have to feed its whole frame again.
 pub trait AggregateFunction<ColumnValue> {
-    fn feed(&mut self, value: ColumnValue);
-    fn finish(self: Box<Self>) -> ColumnValue;
+    fn feed(&mut self, value: ColumnValue) -> Result<(), String>;
+
+    /// Takes back a value that was fed. Returns false if the function can't,
+    /// in which case it's left unchanged and has to be fed its values again.
+    fn remove(&mut self, value: ColumnValue) -> Result<bool, String>;
+
+    /// The result of the values fed so far
+    fn result(&self) -> ColumnValue;
 }
 
 struct Count {
@@ -12,13 +23,23 @@ struct Count {
 
 impl<ColumnValue: ColumnValueOps> AggregateFunction<ColumnValue> for Count
 {
-    fn feed(&mut self, value: ColumnValue) {
+    fn feed(&mut self, value: ColumnValue) -> Result<(), String> {
         if !value.is_null() {
             self.count += 1;
         }
+
+        Ok(())
+    }
+
+    fn remove(&mut self, value: ColumnValue) -> Result<bool, String> {
+        if !value.is_null() {
+            self.count -= 1;
+        }
+
+        Ok(true)
     }
 
-    fn finish(self: Box<Self>) -> ColumnValue {
+    fn result(&self) -> ColumnValue {
         ColumnValueOps::from_u64(self.count)
     }
 }
@@ -29,14 +50,30 @@ struct Avg {
 }
 
 impl<ColumnValue: ColumnValueOps> AggregateFunction<ColumnValue> for Avg {
-    fn feed(&mut self, value: ColumnValue) {
+    fn feed(&mut self, value: ColumnValue) -> Result<(), String> {
         if !value.is_null() {
-            self.sum += value.to_f64().unwrap();
+            self.sum += try!(value.to_f64().map_err(|()| format!("avg requires numbers")));
             self.count += 1;
         }
+
+        Ok(())
     }
 
-    fn finish(self: Box<Self>) -> ColumnValue {
+    fn remove(&mut self, value: ColumnValue) -> Result<bool, String> {
+        // Subtracting can't take an infinity back out of the sum
+        if !self.sum.is_finite() {
+            return Ok(false);
+        }
+
+        if !value.is_null() {
+            self.sum -= try!(value.to_f64().map_err(|()| format!("avg requires numbers")));
+            self.count -= 1;
+        }
+
+        Ok(true)
+    }
+
+    fn result(&self) -> ColumnValue {
         if self.count == 0 {
             ColumnValueOpsExt::null()
         } else {
@@ -45,48 +82,76 @@ impl<ColumnValue: ColumnValueOps> AggregateFunction<ColumnValue> for Avg {
     }
 }
 
-struct Sum {
-    sum: f64,
+/// The sum of integers is an integer, and the sum is checked for overflow.
+struct Sum<ColumnValue> {
+    sum: ColumnValue,
     count: u64
 }
 
-impl<ColumnValue: ColumnValueOps> AggregateFunction<ColumnValue> for Sum {
-    fn feed(&mut self, value: ColumnValue) {
+impl<ColumnValue: ColumnValueOps + Clone> AggregateFunction<ColumnValue> for Sum<ColumnValue> {
+    fn feed(&mut self, value: ColumnValue) -> Result<(), String> {
         if !value.is_null() {
-            self.sum += value.to_f64().unwrap();
+            self.sum = try!(self.sum.add(&value));
             self.count += 1;
         }
+
+        Ok(())
+    }
+
+    fn remove(&mut self, value: ColumnValue) -> Result<bool, String> {
+        // Subtracting can't take an infinity back out of the sum
+        if self.sum.clone().to_f64().map(|sum| sum.is_infinite()).unwrap_or(false) {
+            return Ok(false);
+        }
+
+        if !value.is_null() {
+            self.sum = try!(self.sum.sub(&value));
+            self.count -= 1;
+
+            // Start over as an integer sum, even if floats were removed
+            if self.count == 0 {
+                self.sum = ColumnValueOps::from_u64(0);
+            }
+        }
+
+        Ok(true)
     }
 
-    fn finish(self: Box<Self>) -> ColumnValue {
+    fn result(&self) -> ColumnValue {
         if self.count == 0 {
             ColumnValueOpsExt::null()
         } else {
-            ColumnValueOps::from_f64(self.sum)
+            self.sum.clone()
         }
     }
 }
 
+/// `min` and `max` skip NULLs, and order the other values as `total_compare`
+/// does, so that the result doesn't depend on the order of values that
+/// `compare` can't order, such as numbers and strings.
 struct Min<ColumnValue> {
     value: Option<ColumnValue>
 }
 
-impl<ColumnValue: ColumnValueOps> AggregateFunction<ColumnValue> for Min<ColumnValue> {
-    fn feed(&mut self, value: ColumnValue) {
-        let set = !value.is_null() && if let Some(r) = self.value.as_ref() {
-            match value.compare(r) {
-                Some(-1) => true,
-                _ => false
-            }
-        } else {
-            true
+impl<ColumnValue: ColumnValueOps + Clone> AggregateFunction<ColumnValue> for Min<ColumnValue> {
+    fn feed(&mut self, value: ColumnValue) -> Result<(), String> {
+        let set = !value.is_null() && match self.value {
+            Some(ref r) => value.total_compare(r) == Ordering::Less,
+            None => true
         };
 
         if set { self.value = Some(value); }
+
+        Ok(())
     }
 
-    fn finish(self: Box<Self>) -> ColumnValue {
-        self.value.unwrap_or_else(|| ColumnValueOpsExt::null())
+    fn remove(&mut self, _value: ColumnValue) -> Result<bool, String> {
+        // The next smallest value isn't known
+        Ok(false)
+    }
+
+    fn result(&self) -> ColumnValue {
+        self.value.clone().unwrap_or_else(|| ColumnValueOpsExt::null())
     }
 }
 
@@ -94,32 +159,35 @@ struct Max<ColumnValue> {
     value: Option<ColumnValue>
 }
 
-impl<ColumnValue: ColumnValueOps> AggregateFunction<ColumnValue> for Max<ColumnValue> {
-    fn feed(&mut self, value: ColumnValue) {
-        let set = !value.is_null() && if let Some(r) = self.value.as_ref() {
-            match value.compare(r) {
-                Some(1) => true,
-                _ => false
-            }
-        } else {
-            true
+impl<ColumnValue: ColumnValueOps + Clone> AggregateFunction<ColumnValue> for Max<ColumnValue> {
+    fn feed(&mut self, value: ColumnValue) -> Result<(), String> {
+        let set = !value.is_null() && match self.value {
+            Some(ref r) => value.total_compare(r) == Ordering::Greater,
+            None => true
         };
 
         if set { self.value = Some(value); }
+
+        Ok(())
+    }
+
+    fn remove(&mut self, _value: ColumnValue) -> Result<bool, String> {
+        // The next largest value isn't known
+        Ok(false)
     }
 
-    fn finish(self: Box<Self>) -> ColumnValue {
-        self.value.unwrap_or_else(|| ColumnValueOpsExt::null())
+    fn result(&self) -> ColumnValue {
+        self.value.clone().unwrap_or_else(|| ColumnValueOpsExt::null())
     }
 }
 
 pub fn get_aggregate_function<ColumnValue>(op: AggregateOp) -> Box<AggregateFunction<ColumnValue> + 'static>
-where ColumnValue: Sized + ColumnValueOps + 'static
+where ColumnValue: Sized + ColumnValueOps + Clone + 'static
 {
     match op {
         AggregateOp::Count => Box::new(Count { count: 0 }),
         AggregateOp::Avg => Box::new(Avg { sum: 0.0, count: 0 }),
-        AggregateOp::Sum => Box::new(Sum { sum: 0.0, count: 0 }),
+        AggregateOp::Sum => Box::new(Sum { sum: ColumnValueOps::from_u64(0), count: 0 }),
         AggregateOp::Min => Box::new(Min { value: None }),
         AggregateOp::Max => Box::new(Max { value: None })
     }
@@ -4,20 +4,22 @@ use std::borrow::Cow;
 use std::cmp::Eq;
 use std::hash::Hash;
 use std::iter::IntoIterator;
-use std::collections::HashMap;
+use std::collections::BTreeMap;
 
+/// Groups rows by their encoded GROUP BY values (see
+/// `ColumnValueOps::write_key`). The groups are iterated in key order.
 pub struct GroupBuckets<ColumnValue: Clone + Eq + Hash + 'static> {
-    buckets: HashMap<Box<[ColumnValue]>, GroupBucket<ColumnValue>>
+    buckets: BTreeMap<Box<[u8]>, GroupBucket<ColumnValue>>
 }
 
 impl<ColumnValue: Clone + Eq + Hash + 'static> GroupBuckets<ColumnValue> {
     pub fn new() -> GroupBuckets<ColumnValue> {
         GroupBuckets {
-            buckets: HashMap::new()
+            buckets: BTreeMap::new()
         }
     }
 
-    pub fn insert(&mut self, key: Box<[ColumnValue]>, row: Box<[ColumnValue]>) {
+    pub fn insert(&mut self, key: Box<[u8]>, row: Box<[ColumnValue]>) {
         if let Some(bucket) = self.buckets.get_mut(&key) {
             bucket.rows.push(row);
             return;
@@ -29,6 +31,14 @@ impl<ColumnValue: Clone + Eq + Hash + 'static> GroupBuckets<ColumnValue> {
 
         self.buckets.insert(key, bucket);
     }
+
+    /// Adds an empty group if there are none. Without GROUP BY, an aggregate
+    /// query has one group even if it has no rows.
+    pub fn ensure_one_group(&mut self) {
+        if self.buckets.is_empty() {
+            self.buckets.insert(Vec::new().into_boxed_slice(), GroupBucket { rows: Vec::new() });
+        }
+    }
 }
 
 impl<ColumnValue: Clone + Eq + Hash + 'static> IntoIterator for GroupBuckets<ColumnValue> {
@@ -43,7 +53,7 @@ impl<ColumnValue: Clone + Eq + Hash + 'static> IntoIterator for GroupBuckets<Col
 }
 
 pub struct IntoIter<ColumnValue: Clone + Eq + Hash + 'static> {
-    i: std::collections::hash_map::IntoIter<Box<[ColumnValue]>, GroupBucket<ColumnValue>>
+    i: std::collections::btree_map::IntoIter<Box<[u8]>, GroupBucket<ColumnValue>>
 }
 
 impl<ColumnValue: Clone + Eq + Hash + 'static> Iterator for IntoIter<ColumnValue> {
@@ -80,3 +90,41 @@ impl<ColumnValue: Clone + Eq + Hash + 'static> Group for GroupBucket<ColumnValue
         }))
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::GroupBuckets;
+    use databasestorage::Group;
+
+    #[test]
+    fn test_ensure_one_group() {
+        let mut buckets: GroupBuckets<u32> = GroupBuckets::new();
+        buckets.ensure_one_group();
+        let groups: Vec<_> = buckets.into_iter().collect();
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].count(), 0);
+        assert!(groups[0].get_any_row().is_none());
+
+        let mut buckets = GroupBuckets::new();
+        buckets.insert(vec![].into_boxed_slice(), vec![1u32].into_boxed_slice());
+        buckets.ensure_one_group();
+        let groups: Vec<_> = buckets.into_iter().collect();
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].count(), 1);
+    }
+
+    #[test]
+    fn test_key_order() {
+        let mut buckets = GroupBuckets::new();
+        buckets.insert(vec![1, 2].into_boxed_slice(), vec![3u32].into_boxed_slice());
+        buckets.insert(vec![0].into_boxed_slice(), vec![1u32].into_boxed_slice());
+        buckets.insert(vec![1].into_boxed_slice(), vec![2u32].into_boxed_slice());
+        buckets.insert(vec![0].into_boxed_slice(), vec![1u32].into_boxed_slice());
+
+        let groups: Vec<_> = buckets.into_iter().map(|group| {
+            let first = group.get_any_row().unwrap()[0];
+            (first, group.count())
+        }).collect();
+        assert_eq!(groups, vec![(1, 2), (2, 1), (3, 1)]);
+    }
+}
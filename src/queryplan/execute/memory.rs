@@ -0,0 +1,58 @@
+use std::cell::Cell;
+
+/// Counts the memory a query holds on to, such as the rows buffered by
+/// GROUP BY, and fails once it's more than the limit.
+///
+/// None of the operators can spill to disk yet, so going over the limit is
+/// always an error.
+pub struct MemoryBudget {
+    used: Cell<u64>,
+    limit: Option<u64>
+}
+
+impl MemoryBudget {
+    /// No limit if `limit` is None
+    pub fn new(limit: Option<u64>) -> MemoryBudget {
+        MemoryBudget {
+            used: Cell::new(0),
+            limit: limit
+        }
+    }
+
+    pub fn reserve(&self, bytes: u64) -> Result<(), String> {
+        let used = self.used.get().saturating_add(bytes);
+
+        match self.limit {
+            Some(limit) if used > limit => {
+                Err(format!("out of memory budget: the query needs more than {} bytes", limit))
+            },
+            _ => {
+                self.used.set(used);
+                Ok(())
+            }
+        }
+    }
+
+    /// Releases memory that was reserved, once it's been freed
+    pub fn release(&self, bytes: u64) {
+        self.used.set(self.used.get() - bytes);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::MemoryBudget;
+
+    #[test]
+    fn test_memory_budget() {
+        let budget = MemoryBudget::new(Some(100));
+        assert!(budget.reserve(60).is_ok());
+        assert!(budget.reserve(60).is_err());
+        budget.release(60);
+        assert!(budget.reserve(100).is_ok());
+
+        let unlimited = MemoryBudget::new(None);
+        assert!(unlimited.reserve(!0).is_ok());
+        assert!(unlimited.reserve(!0).is_ok());
+    }
+}
@@ -1,7 +1,11 @@
+use std::cell::Cell;
+use std::cmp::{self, Ordering};
+use std::collections::{HashMap, HashSet};
+
 use columnvalueops::{ColumnValueOps, ColumnValueOpsExt};
 use databaseinfo::DatabaseInfo;
 use databasestorage::{DatabaseStorage, Group};
-use super::sexpression::{BinaryOp, UnaryOp, SExpression};
+use super::sexpression::{BinaryOp, UnaryOp, SequenceOp, FunctionOp, SExpression, WindowFunction};
 
 mod aggregate;
 use self::aggregate::*;
@@ -9,9 +13,46 @@ use self::aggregate::*;
 mod groupbuckets;
 use self::groupbuckets::GroupBuckets;
 
+mod memory;
+use self::memory::MemoryBudget;
+
+mod window;
+use self::window::WindowRow;
+
+/// Why a plan stopped yielding rows before it ran out of them
+enum ExecuteError {
+    /// Returned by the row callback of a semi-join at the first match, or of
+    /// a limit once it has yielded enough rows. It never escapes the
+    /// semi-join or limit that returned it.
+    Stop,
+    Error(String)
+}
+
+impl From<String> for ExecuteError {
+    fn from(e: String) -> ExecuteError {
+        ExecuteError::Error(e)
+    }
+}
+
+impl From<ExecuteError> for String {
+    fn from(e: ExecuteError) -> String {
+        match e {
+            ExecuteError::Stop => format!("query plan stopped outside of a limit or semi-join"),
+            ExecuteError::Error(e) => e
+        }
+    }
+}
+
+/// What a row callback returns, and what executing a plan returns
+type ExecuteResult = Result<(), ExecuteError>;
+
+/// The rows of a hash join's table, by their encoded keys
+type HashTable<ColumnValue> = HashMap<Box<[u8]>, Vec<Box<[ColumnValue]>>>;
+
 enum SourceType<'a, ColumnValue: Sized + 'static> {
     Row(&'a [ColumnValue]),
-    Group(&'a Group<ColumnValue=ColumnValue>)
+    Group(&'a Group<ColumnValue=ColumnValue>),
+    HashTable(&'a HashTable<ColumnValue>)
 }
 
 struct Source<'a, ColumnValue: Sized + 'static> {
@@ -34,6 +75,19 @@ impl<'a, ColumnValue: Sized> Source<'a, ColumnValue> {
         }
     }
 
+    fn find_hash_table_from_source_id(&self, source_id: u32) -> Option<&HashTable<ColumnValue>> {
+        if self.source_id == source_id {
+            match &self.source_type {
+                &SourceType::HashTable(table) => Some(table),
+                _ => None
+            }
+        } else if let Some(parent) = self.parent {
+            parent.find_hash_table_from_source_id(source_id)
+        } else {
+            None
+        }
+    }
+
     fn find_group_from_source_id(&self, source_id: u32) -> Option<&Group<ColumnValue=ColumnValue>> {
         if self.source_id == source_id {
             match &self.source_type {
@@ -48,6 +102,76 @@ impl<'a, ColumnValue: Sized> Source<'a, ColumnValue> {
     }
 }
 
+fn row_memory_size<ColumnValue: ColumnValueOps>(row: &[ColumnValue]) -> u64 {
+    row.iter().fold(0, |size, v| size + v.memory_size() as u64)
+}
+
+// Plans can be decoded from bytes that the compiler didn't produce, so the
+// offsets they use in a row are checked rather than trusted.
+
+fn column_value<ColumnValue: Clone>(row: &[ColumnValue], column_offset: u32) -> Result<ColumnValue, String> {
+    row.get(column_offset as usize).cloned().ok_or_else(|| {
+        format!("ColumnField: column offset {} is past the end of a row of {}", column_offset, row.len())
+    })
+}
+
+fn check_row_width<ColumnValue>(row: &[ColumnValue], width: usize) -> Result<(), String> {
+    if row.len() < width {
+        Err(format!("expected a row of at least {} values, got {}", width, row.len()))
+    } else {
+        Ok(())
+    }
+}
+
+/// Encodes the values of a hash join's key, or of a row or value that
+/// DISTINCT has seen. Values that are equal have the same key, even if their
+/// types differ.
+fn hash_key<ColumnValue: ColumnValueOps>(values: &[ColumnValue]) -> Box<[u8]> {
+    let mut key = Vec::new();
+
+    for value in values {
+        value.write_key(&mut key);
+    }
+
+    key.into_boxed_slice()
+}
+
+/// Compares two rows of the same size with a comparison operator. Rows are
+/// equal if each pair of values is, and are otherwise ordered by their first
+/// pair of values that aren't equal.
+fn compare_rows<ColumnValue: ColumnValueOps>(op: BinaryOp, lhs: &[ColumnValue], rhs: &[ColumnValue]) -> ColumnValue {
+    let pairs = lhs.iter().zip(rhs.iter());
+
+    match op {
+        BinaryOp::Equal => pairs.fold(ColumnValueOps::from_3vl(1), |result: ColumnValue, (l, r)| {
+            result.and(&l.equals(r))
+        }),
+        BinaryOp::NotEqual => pairs.fold(ColumnValueOps::from_3vl(-1), |result: ColumnValue, (l, r)| {
+            result.or(&l.not_equals(r))
+        }),
+        _ => {
+            for (l, r) in pairs {
+                match l.compare(r) {
+                    Some(0) => (),
+                    Some(_) => {
+                        return match op {
+                            BinaryOp::LessThan | BinaryOp::LessThanOrEqual => l.less_than(r),
+                            _ => l.greater_than(r)
+                        };
+                    },
+                    None => return ColumnValueOpsExt::null()
+                }
+            }
+
+            // The rows are equal
+            ColumnValueOps::from_3vl(match op {
+                BinaryOp::LessThanOrEqual | BinaryOp::GreaterThanOrEqual => 1,
+                _ => -1
+            })
+        }
+    }
+}
+
 /// The query plan is currently defined as a recursive language.
 /// Because of this, it would take some work (and foresight) to make query plan
 /// execution co-operate with the concept of iterators.
@@ -56,24 +180,40 @@ impl<'a, ColumnValue: Sized> Source<'a, ColumnValue> {
 /// TODO: translate query plan into procedural language
 /// (such as VM instructions, like those found in SQLite's VBDE).
 pub struct ExecuteQueryPlan<'s, Storage: DatabaseStorage + 's> {
-    storage: &'s Storage
+    storage: &'s Storage,
+    /// How many plan expressions are being executed
+    depth: Cell<u32>,
+    max_depth: u32,
+    memory: MemoryBudget
 }
 
 impl<'a, 's, Storage: DatabaseStorage> ExecuteQueryPlan<'s, Storage>
 where <Storage::Info as DatabaseInfo>::Table: 'a
 {
-    pub fn new(storage: &'s Storage) -> ExecuteQueryPlan<'s, Storage> {
+    /// `max_depth` limits how deeply the plan's expressions can be nested,
+    /// and `memory_limit` limits how many bytes the query can hold on to.
+    pub fn new(storage: &'s Storage, max_depth: u32, memory_limit: Option<u64>) -> ExecuteQueryPlan<'s, Storage> {
         ExecuteQueryPlan {
-            storage: storage
+            storage: storage,
+            depth: Cell::new(0),
+            max_depth: max_depth,
+            memory: MemoryBudget::new(memory_limit)
         }
     }
 
+    /// Counts a row that the caller keeps, such as a row of the results,
+    /// against the query's memory limit.
+    pub fn reserve_row(&self, row: &[<Storage::Info as DatabaseInfo>::ColumnValue]) -> Result<(), String> {
+        self.memory.reserve(row_memory_size(row))
+    }
+
     // TODO: result_cb should yield a boxed array instead of a reference
     pub fn execute_query_plan<'b, 'c>(&self, expr: &SExpression<'a, Storage::Info>,
     result_cb: &'c mut FnMut(&[<Storage::Info as DatabaseInfo>::ColumnValue]) -> Result<(), String>)
     -> Result<(), String>
     {
-        self.execute(expr, result_cb, None)
+        let result = self.execute(expr, &mut |row| result_cb(row).map_err(ExecuteError::Error), None);
+        result.map_err(String::from)
     }
 
     pub fn execute_expression(&self, expr: &SExpression<'a, Storage::Info>)
@@ -82,15 +222,72 @@ where <Storage::Info as DatabaseInfo>::Table: 'a
         self.resolve_value(expr, None)
     }
 
+    /// Evaluates an expression compiled by `compile_row_expression` for one
+    /// row of its table.
+    pub fn execute_expression_with_row(&self, expr: &SExpression<'a, Storage::Info>,
+        row: &[<Storage::Info as DatabaseInfo>::ColumnValue])
+    -> Result<<Storage::Info as DatabaseInfo>::ColumnValue, String>
+    {
+        let source = Source {
+            parent: None,
+            source_id: 0,
+            source_type: SourceType::Row(row)
+        };
+
+        self.resolve_value(expr, Some(&source))
+    }
+
+    /// Runs `f` one level deeper into the plan. Fails if that's more than
+    /// `max_depth` levels, rather than risking a stack overflow.
+    fn nested<T, E, F>(&self, f: F) -> Result<T, E>
+    where E: From<String>, F: FnOnce() -> Result<T, E>
+    {
+        let depth = self.depth.get();
+
+        if depth >= self.max_depth {
+            return Err(E::from(format!("query plan can't be nested more than {} deep", self.max_depth)));
+        }
+
+        self.depth.set(depth + 1);
+        let result = f();
+        self.depth.set(depth);
+        result
+    }
+
     fn execute<'b, 'c>(&self, expr: &SExpression<'a, Storage::Info>,
-        result_cb: &'c mut FnMut(&[<Storage::Info as DatabaseInfo>::ColumnValue]) -> Result<(), String>,
+        result_cb: &'c mut FnMut(&[<Storage::Info as DatabaseInfo>::ColumnValue]) -> ExecuteResult,
         source: Option<&Source<'b, <Storage::Info as DatabaseInfo>::ColumnValue>>)
-    -> Result<(), String>
+    -> ExecuteResult
+    {
+        self.nested(|| self.execute_nested(expr, result_cb, source))
+    }
+
+    fn execute_nested<'b, 'c>(&self, expr: &SExpression<'a, Storage::Info>,
+        result_cb: &'c mut FnMut(&[<Storage::Info as DatabaseInfo>::ColumnValue]) -> ExecuteResult,
+        source: Option<&Source<'b, <Storage::Info as DatabaseInfo>::ColumnValue>>)
+    -> ExecuteResult
     {
         match expr {
             &SExpression::Scan { table, source_id, ref yield_fn } => {
-                let group = self.storage.scan_table(table);
-                for row in group.iter() {
+                for row in try!(self.storage.scan_table(table)) {
+                    let row = try!(row);
+                    let new_source = Source {
+                        parent: source,
+                        source_id: source_id,
+                        source_type: SourceType::Row(&row)
+                    };
+
+                    try!(self.execute(yield_fn, result_cb, Some(&new_source)));
+                }
+
+                Ok(())
+            },
+            &SExpression::TableFunction { ref function, source_id, ref arguments, ref yield_fn } => {
+                let arguments: Result<Vec<_>, _> = arguments.iter().map(|argument| {
+                    self.resolve_value(argument, source)
+                }).collect();
+
+                for row in try!(self.storage.call_table_function(function, try!(arguments))) {
                     let new_source = Source {
                         parent: source,
                         source_id: source_id,
@@ -113,10 +310,108 @@ where <Storage::Info as DatabaseInfo>::Table: 'a
                     self.execute(yield_out_fn, result_cb, Some(&new_source))
                 }, source)
             },
+            &SExpression::LeftJoin { source_id, column_count, ref yield_in_fn, ref yield_out_fn } => {
+                let mut matched = false;
+
+                try!(self.execute(yield_in_fn, &mut |row| {
+                    matched = true;
+
+                    let new_source = Source {
+                        parent: source,
+                        source_id: source_id,
+                        source_type: SourceType::Row(row)
+                    };
+
+                    self.execute(yield_out_fn, result_cb, Some(&new_source))
+                }, source));
+
+                if matched {
+                    return Ok(());
+                }
+
+                let row: Vec<<Storage::Info as DatabaseInfo>::ColumnValue> = (0..column_count).map(|_| {
+                    ColumnValueOpsExt::null()
+                }).collect();
+
+                let new_source = Source {
+                    parent: source,
+                    source_id: source_id,
+                    source_type: SourceType::Row(&row)
+                };
+
+                self.execute(yield_out_fn, result_cb, Some(&new_source))
+            },
+            &SExpression::HashJoin { source_id, ref keys, ref yield_in_fn, ref yield_out_fn } => {
+                let mut table = HashMap::new();
+                let mut reserved = 0;
+
+                let result = self.execute(yield_in_fn, &mut |row| {
+                    let new_source = Source {
+                        parent: source,
+                        source_id: source_id,
+                        source_type: SourceType::Row(row)
+                    };
+
+                    let key: Vec<_> = try!(keys.iter().map(|key| self.resolve_value(key, Some(&new_source))).collect());
+
+                    if key.iter().any(|value| value.is_null()) {
+                        return Ok(());
+                    }
+
+                    let key = hash_key(&key);
+                    let size = key.len() as u64 + row_memory_size(row);
+                    try!(self.memory.reserve(size));
+                    reserved += size;
+
+                    table.entry(key).or_insert_with(Vec::new).push(row.to_vec().into_boxed_slice());
+                    Ok(())
+                }, source);
+
+                let result = result.and_then(|()| {
+                    let new_source = Source {
+                        parent: source,
+                        source_id: source_id,
+                        source_type: SourceType::HashTable(&table)
+                    };
+
+                    self.execute(yield_out_fn, result_cb, Some(&new_source))
+                });
+
+                self.memory.release(reserved);
+                result
+            },
+            &SExpression::HashProbe { source_id, ref values, ref yield_fn } => {
+                let table = match source.and_then(|s| s.find_hash_table_from_source_id(source_id)) {
+                    Some(table) => table,
+                    None => return Err(ExecuteError::from(format!("HashProbe: source id is not a hash table: {}", source_id)))
+                };
+
+                let values: Vec<_> = try!(values.iter().map(|value| self.resolve_value(value, source)).collect());
+
+                // NULL is never equal to a key
+                if values.iter().any(|value| value.is_null()) {
+                    return Ok(());
+                }
+
+                if let Some(rows) = table.get(&hash_key(&values)) {
+                    for row in rows {
+                        let new_source = Source {
+                            parent: source,
+                            source_id: source_id,
+                            source_type: SourceType::Row(row)
+                        };
+
+                        try!(self.execute(yield_fn, result_cb, Some(&new_source)));
+                    }
+                }
+
+                Ok(())
+            },
             &SExpression::TempGroupBy { source_id, ref yield_in_fn, ref group_by_values, ref yield_out_fn } => {
                 let mut group_buckets = GroupBuckets::new();
+                let mut reserved = 0;
 
-                try!(self.execute(yield_in_fn, &mut |row| {
+                let result = self.execute(yield_in_fn, &mut |row| {
                     let new_source = Source {
                         parent: source,
                         source_id: source_id,
@@ -129,35 +424,199 @@ where <Storage::Info as DatabaseInfo>::Table: 'a
 
                     let key = try!(result);
 
-                    // TODO: don't box up row
-                    let row_boxed = row.to_vec().into_boxed_slice();
+                    // NULLs are grouped together, and so are equal numbers
+                    // of different types
+                    let mut encoded = Vec::new();
+                    for value in key.iter() {
+                        value.write_key(&mut encoded);
+                    }
+
+                    // Keys of existing groups are counted too, so that the
+                    // budget doesn't depend on how the rows are grouped
+                    let size = encoded.len() as u64 + row_memory_size(&key) + row_memory_size(row);
+                    try!(self.memory.reserve(size));
+                    reserved += size;
 
-                    group_buckets.insert(key.into_boxed_slice(), row_boxed);
+                    // The result columns and HAVING read the GROUP BY values
+                    // from after the row's columns
+                    let mut group_row = Vec::with_capacity(row.len() + key.len());
+                    group_row.push_all(row);
+                    group_row.push_all(&key);
+
+                    group_buckets.insert(encoded.into_boxed_slice(), group_row.into_boxed_slice());
 
                     Ok(())
-                }, source));
+                }, source);
 
                 // the group buckets have been filled.
                 // now to yield for each group...
 
-                for group in group_buckets {
-                    let new_source = Source {
-                        parent: source,
-                        source_id: source_id,
-                        source_type: SourceType::Group(&group)
-                    };
+                if group_by_values.is_empty() {
+                    group_buckets.ensure_one_group();
+                }
+
+                let result = result.and_then(|()| {
+                    for group in group_buckets {
+                        let new_source = Source {
+                            parent: source,
+                            source_id: source_id,
+                            source_type: SourceType::Group(&group)
+                        };
+
+                        try!(self.execute(yield_out_fn, result_cb, Some(&new_source)));
+                    }
+
+                    Ok(())
+                });
+
+                self.memory.release(reserved);
+                result
+            },
+            &SExpression::Window { source_id, ref yield_in_fn, ref functions, ref yield_out_fn } => {
+                let mut rows = Vec::new();
+                let mut reserved = 0;
+
+                let result = self.execute(yield_in_fn, &mut |row| {
+                    let size = row_memory_size(row);
+                    try!(self.memory.reserve(size));
+                    reserved += size;
+
+                    rows.push(row.to_vec());
+                    Ok(())
+                }, source);
 
-                    try!(self.execute(yield_out_fn, result_cb, Some(&new_source)));
+                let result = result.and_then(|()| {
+                    let mut results = Vec::with_capacity(functions.len());
+
+                    for function in functions {
+                        let values = try!(self.window_function(function, source_id, &rows, source));
+
+                        let size = row_memory_size(&values);
+                        try!(self.memory.reserve(size));
+                        reserved += size;
+
+                        results.push(values);
+                    }
+
+                    // Each row is followed by its results
+                    for (i, row) in rows.iter_mut().enumerate() {
+                        row.extend(results.iter().map(|values| values[i].clone()));
+
+                        let new_source = Source {
+                            parent: source,
+                            source_id: source_id,
+                            source_type: SourceType::Row(row)
+                        };
+
+                        try!(self.execute(yield_out_fn, result_cb, Some(&new_source)));
+                    }
+
+                    Ok(())
+                });
+
+                self.memory.release(reserved);
+                result
+            },
+            &SExpression::Distinct { ref yield_in_fn, column_count } => {
+                let mut seen = HashSet::new();
+                let mut reserved = 0;
+
+                let result = self.execute(yield_in_fn, &mut |row| {
+                    try!(check_row_width(row, column_count as usize));
+                    let key = hash_key(&row[..column_count as usize]);
+
+                    if seen.contains(&key) {
+                        return Ok(());
+                    }
+
+                    let size = key.len() as u64;
+                    try!(self.memory.reserve(size));
+                    reserved += size;
+
+                    seen.insert(key);
+                    result_cb(row)
+                }, source);
+
+                self.memory.release(reserved);
+                result
+            },
+            &SExpression::Sort { ref yield_in_fn, column_count, ref order_by } => {
+                let width = order_by.iter().map(|&(column_offset, _)| column_offset as usize + 1)
+                    .fold(column_count as usize, cmp::max);
+                let mut rows = Vec::new();
+                let mut reserved = 0;
+
+                let result = self.execute(yield_in_fn, &mut |row| {
+                    try!(check_row_width(row, width));
+                    let size = row_memory_size(row);
+                    try!(self.memory.reserve(size));
+                    reserved += size;
+
+                    rows.push(row.to_vec());
+                    Ok(())
+                }, source);
+
+                let result = result.and_then(|()| {
+                    // The sort is stable, so rows with equal keys stay in the
+                    // order they were yielded
+                    rows.sort_by(|a, b| {
+                        for &(column_offset, descending) in order_by {
+                            let i = column_offset as usize;
+                            let ordering = a[i].total_compare(&b[i]);
+
+                            if ordering != Ordering::Equal {
+                                return if descending { ordering.reverse() } else { ordering };
+                            }
+                        }
+
+                        Ordering::Equal
+                    });
+
+                    for row in &rows {
+                        try!(result_cb(&row[..column_count as usize]));
+                    }
+
+                    Ok(())
+                });
+
+                self.memory.release(reserved);
+                result
+            },
+            &SExpression::Limit { ref yield_in_fn, count, offset } => {
+                if count == 0 {
+                    return Ok(());
                 }
 
-                Ok(())
+                let mut skipped = 0;
+                let mut yielded = 0;
+
+                let result = self.execute(yield_in_fn, &mut |row| {
+                    if skipped < offset {
+                        skipped += 1;
+                        return Ok(());
+                    }
+
+                    try!(result_cb(row));
+                    yielded += 1;
+
+                    if yielded == count {
+                        Err(ExecuteError::Stop)
+                    } else {
+                        Ok(())
+                    }
+                }, source);
+
+                match result {
+                    Err(ExecuteError::Stop) if yielded == count => Ok(()),
+                    result => result
+                }
             },
             &SExpression::Yield { ref fields } => {
                 let columns: Result<Vec<_>, _>;
                 columns = fields.iter().map(|e| self.resolve_value(e, source)).collect();
                 match columns {
                     Ok(columns) => result_cb(&columns),
-                    Err(e) => Err(e)
+                    Err(e) => Err(ExecuteError::Error(e))
                 }
             },
             &SExpression::If { ref predicate, ref yield_fn } => {
@@ -174,31 +633,81 @@ where <Storage::Info as DatabaseInfo>::Table: 'a
             &SExpression::UnaryOp { .. } |
             &SExpression::AggregateOp { .. } |
             &SExpression::CountAll { .. } |
+            &SExpression::RowCount { .. } |
+            &SExpression::SequenceOp { .. } |
+            &SExpression::SessionVariable { .. } |
+            &SExpression::FunctionOp { .. } |
+            &SExpression::RowCompare { .. } |
+            &SExpression::SemiJoin { .. } |
             &SExpression::Value(..) => {
-                Err(format!("encountered expression that cannot yield rows"))
+                Err(ExecuteError::from(format!("encountered expression that cannot yield rows")))
             }
         }
     }
 
+    /// Computes a window function for each of the window's rows
+    fn window_function<'b>(&self, function: &WindowFunction<'a, Storage::Info>, source_id: u32,
+        rows: &[Vec<<Storage::Info as DatabaseInfo>::ColumnValue>],
+        source: Option<&Source<'b, <Storage::Info as DatabaseInfo>::ColumnValue>>)
+    -> Result<Vec<<Storage::Info as DatabaseInfo>::ColumnValue>, String>
+    {
+        let mut keys = Vec::with_capacity(rows.len());
+
+        for row in rows {
+            let row_source = Source {
+                parent: source,
+                source_id: source_id,
+                source_type: SourceType::Row(row)
+            };
+
+            let arguments: Result<Vec<_>, _> = function.arguments.iter().map(|value| {
+                self.resolve_value(value, Some(&row_source))
+            }).collect();
+
+            let partition: Result<Vec<_>, _> = function.partition_by.iter().map(|value| {
+                self.resolve_value(value, Some(&row_source))
+            }).collect();
+
+            let order: Result<Vec<_>, _> = function.order_by.iter().map(|&(ref value, _)| {
+                self.resolve_value(value, Some(&row_source))
+            }).collect();
+
+            keys.push(WindowRow {
+                arguments: try!(arguments),
+                partition: try!(partition),
+                order: try!(order)
+            });
+        }
+
+        let descending: Vec<bool> = function.order_by.iter().map(|&(_, descending)| descending).collect();
+
+        window::compute(function.op, function.frame, &keys, &descending)
+    }
+
     fn resolve_value<'b>(&self, expr: &SExpression<'a, Storage::Info>,
         source: Option<&Source<'b, <Storage::Info as DatabaseInfo>::ColumnValue>>)
     -> Result<<Storage::Info as DatabaseInfo>::ColumnValue, String>
+    {
+        self.nested(|| self.resolve_value_nested(expr, source))
+    }
+
+    fn resolve_value_nested<'b>(&self, expr: &SExpression<'a, Storage::Info>,
+        source: Option<&Source<'b, <Storage::Info as DatabaseInfo>::ColumnValue>>)
+    -> Result<<Storage::Info as DatabaseInfo>::ColumnValue, String>
     {
         match expr {
             &SExpression::Value(ref v) => Ok(v.clone()),
             &SExpression::ColumnField { source_id, column_offset } => {
                 let row = source.and_then(|s| s.find_row_from_source_id(source_id));
                 match row {
-                    Some(row) => Ok(row[column_offset as usize].clone()),
+                    Some(row) => column_value(row, column_offset),
                     None => {
                         // the source might actually be a group.
                         // in this case, any arbitrary row from the group is valid to yield.
                         match source.and_then(|s| s.find_group_from_source_id(source_id)) {
                             Some(group) => {
                                 match group.get_any_row() {
-                                    Some(row) => {
-                                        Ok(row[column_offset as usize].clone())
-                                    },
+                                    Some(row) => column_value(&row, column_offset),
                                     None => Ok(ColumnValueOpsExt::null())
                                 }
                             },
@@ -220,11 +729,11 @@ where <Storage::Info as DatabaseInfo>::Table: 'a
                     BinaryOp::GreaterThanOrEqual => l.greater_than_or_equal(&r),
                     BinaryOp::And => l.and(&r),
                     BinaryOp::Or => l.or(&r),
-                    BinaryOp::Concatenate => l.concat(&r),
-                    BinaryOp::Add => l.add(&r),
-                    BinaryOp::Subtract => l.sub(&r),
-                    BinaryOp::Multiply => l.mul(&r),
-                    BinaryOp::Divide => l.div(&r),
+                    BinaryOp::Concatenate => try!(l.concat(&r)),
+                    BinaryOp::Add => try!(l.add(&r)),
+                    BinaryOp::Subtract => try!(l.sub(&r)),
+                    BinaryOp::Multiply => try!(l.mul(&r)),
+                    BinaryOp::Divide => try!(l.div(&r)),
                     _ => unimplemented!()
                 })
             },
@@ -232,16 +741,18 @@ where <Storage::Info as DatabaseInfo>::Table: 'a
                 let e = try!(self.resolve_value(expr, source));
 
                 Ok(match op {
-                    UnaryOp::Negate => e.negate()
+                    UnaryOp::Negate => try!(e.negate())
                 })
             },
-            &SExpression::AggregateOp { op, source_id, ref value } => {
+            &SExpression::AggregateOp { op, distinct, source_id, ref value } => {
                 let group = source.and_then(|s| s.find_group_from_source_id(source_id));
                 match group {
                     Some(group) => {
                         let mut op_functor = get_aggregate_function(op);
+                        let mut seen = HashSet::new();
+                        let mut reserved = 0;
 
-                        for row in group.iter() {
+                        let result = group.iter().map(|row| {
                             let new_source = Source {
                                 parent: source,
                                 source_id: source_id,
@@ -249,10 +760,27 @@ where <Storage::Info as DatabaseInfo>::Table: 'a
                             };
 
                             let v = try!(self.resolve_value(value, Some(&new_source)));
-                            op_functor.feed(v);
-                        }
 
-                        Ok(op_functor.finish())
+                            if distinct {
+                                let key = hash_key(&[v.clone()]);
+
+                                if seen.contains(&key) {
+                                    return Ok(());
+                                }
+
+                                let size = key.len() as u64;
+                                try!(self.memory.reserve(size));
+                                reserved += size;
+                                seen.insert(key);
+                            }
+
+                            op_functor.feed(v)
+                        }).collect::<Result<(), String>>();
+
+                        self.memory.release(reserved);
+                        try!(result);
+
+                        Ok(op_functor.result())
                     },
                     None => Err(format!("AggregateOp: source id is not a valid group: {}", source_id))
                 }
@@ -266,6 +794,101 @@ where <Storage::Info as DatabaseInfo>::Table: 'a
                     None => Err(format!("CountAll: source id is not a valid group: {}", source_id))
                 }
             },
+            &SExpression::RowCount { table } => {
+                let count = try!(self.storage.count_rows(table));
+                Ok(ColumnValueOps::from_u64(count))
+            },
+            &SExpression::SequenceOp { op, ref sequence } => {
+                match op {
+                    SequenceOp::NextVal => self.storage.next_sequence_value(sequence),
+                    SequenceOp::CurrVal => self.storage.current_sequence_value(sequence)
+                }
+            },
+            &SExpression::SessionVariable { ref name } => self.storage.session_variable(name),
+            &SExpression::FunctionOp { op: FunctionOp::Coalesce, ref arguments }
+            | &SExpression::FunctionOp { op: FunctionOp::IfNull, ref arguments } => {
+                // Arguments after the first non-NULL one aren't evaluated,
+                // so they can't fail or advance a sequence
+                for argument in arguments {
+                    let value = try!(self.resolve_value(argument, source));
+
+                    if !value.is_null() {
+                        return Ok(value);
+                    }
+                }
+
+                Ok(ColumnValueOpsExt::null())
+            },
+            &SExpression::FunctionOp { op: FunctionOp::NullIf, ref arguments } => {
+                let value = try!(self.resolve_value(&arguments[0], source));
+
+                if value.is_null() {
+                    return Ok(value);
+                }
+
+                let other = try!(self.resolve_value(&arguments[1], source));
+
+                if value.equals(&other).tests_true() {
+                    Ok(ColumnValueOpsExt::null())
+                } else {
+                    Ok(value)
+                }
+            },
+            &SExpression::FunctionOp { op, ref arguments } => {
+                let arguments: Result<Vec<_>, _> = arguments.iter().map(|argument| {
+                    self.resolve_value(argument, source)
+                }).collect();
+                let arguments = try!(arguments);
+
+                match op {
+                    FunctionOp::Length => arguments[0].length(),
+                    FunctionOp::Hex => arguments[0].hex(),
+                    FunctionOp::Unhex => arguments[0].unhex(),
+                    FunctionOp::Coalesce | FunctionOp::NullIf | FunctionOp::IfNull => unreachable!()
+                }
+            },
+            &SExpression::RowCompare { op, ref lhs, ref rhs } => {
+                let l: Vec<_> = try!(lhs.iter().map(|value| self.resolve_value(value, source)).collect());
+                let r: Vec<_> = try!(rhs.iter().map(|value| self.resolve_value(value, source)).collect());
+
+                Ok(compare_rows(op, &l, &r))
+            },
+            &SExpression::SemiJoin { anti, ref values, ref query } => {
+                let values: Vec<_> = try!(values.iter().map(|value| self.resolve_value(value, source)).collect());
+
+                // 1 if a row matched, 0 if a row might have matched (NULL),
+                // and -1 if none did
+                let mut matched = -1;
+
+                let result = self.execute(query, &mut |row| {
+                    try!(check_row_width(row, values.len()));
+
+                    let row_matched = if values.is_empty() {
+                        1
+                    } else {
+                        compare_rows(BinaryOp::Equal, &values, &row[..values.len()]).to_3vl()
+                    };
+
+                    if row_matched == 1 {
+                        matched = 1;
+                        Err(ExecuteError::Stop)
+                    } else {
+                        if row_matched == 0 {
+                            matched = 0;
+                        }
+                        Ok(())
+                    }
+                }, source);
+
+                match result {
+                    Err(ExecuteError::Stop) if matched == 1 => (),
+                    result => try!(result)
+                }
+
+                let matched: <Storage::Info as DatabaseInfo>::ColumnValue = ColumnValueOps::from_3vl(matched);
+
+                Ok(if anti { matched.not() } else { matched })
+            },
             &SExpression::Map { source_id, ref yield_in_fn, ref yield_out_fn } => {
                 trace!("resolve_value; map {}", source_id);
 
@@ -297,7 +920,15 @@ where <Storage::Info as DatabaseInfo>::Table: 'a
                 }
             },
             &SExpression::Scan { .. } |
+            &SExpression::TableFunction { .. } |
+            &SExpression::LeftJoin { .. } |
+            &SExpression::HashJoin { .. } |
+            &SExpression::HashProbe { .. } |
             &SExpression::TempGroupBy { .. } |
+            &SExpression::Window { .. } |
+            &SExpression::Distinct { .. } |
+            &SExpression::Sort { .. } |
+            &SExpression::Limit { .. } |
             &SExpression::Yield { .. } |
             &SExpression::If { .. } => {
                 Err(format!("encounted expression that cannot resolve to a single value"))
@@ -0,0 +1,335 @@
+//! Computes window functions over the rows of a window, once each row's
+//! arguments and values for PARTITION BY and ORDER BY have been evaluated.
+
+use std::cmp::{self, Ordering};
+
+use columnvalueops::{ColumnValueOps, ColumnValueOpsExt};
+use super::aggregate::get_aggregate_function;
+use super::super::sexpression::{AggregateOp, FrameBound, WindowFrame, WindowOp};
+
+/// A row's values for a window function's arguments, PARTITION BY and
+/// ORDER BY
+pub struct WindowRow<ColumnValue> {
+    pub arguments: Vec<ColumnValue>,
+    pub partition: Vec<ColumnValue>,
+    pub order: Vec<ColumnValue>
+}
+
+/// Returns the window function's result for each row.
+///
+/// `descending` says which of the ORDER BY values are in descending order.
+/// Rows that are equal in every ORDER BY value are peers, and have the same
+/// rank.
+pub fn compute<ColumnValue>(op: WindowOp, frame: Option<WindowFrame>, keys: &[WindowRow<ColumnValue>],
+    descending: &[bool])
+-> Result<Vec<ColumnValue>, String>
+where ColumnValue: ColumnValueOps + Clone + 'static
+{
+    // The rows in order, partition by partition. The sort is stable, so
+    // rows without an order stay in the order they were yielded in.
+    let mut rows: Vec<usize> = (0..keys.len()).collect();
+
+    rows.sort_by(|&a, &b| {
+        match compare(&keys[a].partition, &keys[b].partition, &[]) {
+            Ordering::Equal => compare(&keys[a].order, &keys[b].order, descending),
+            ordering => ordering
+        }
+    });
+
+    let mut results: Vec<ColumnValue> = keys.iter().map(|_| ColumnValueOpsExt::null()).collect();
+    let mut start = 0;
+
+    while start < rows.len() {
+        let partition = &keys[rows[start]].partition;
+        let len = rows[start..].iter().take_while(|&&row| {
+            compare(&keys[row].partition, partition, &[]) == Ordering::Equal
+        }).count();
+
+        let partition = &rows[start..start + len];
+
+        match op {
+            WindowOp::RowNumber | WindowOp::Rank | WindowOp::DenseRank => {
+                rank(op, partition, keys, descending, &mut results);
+            },
+            WindowOp::Lag | WindowOp::Lead => {
+                try!(offset(op, partition, keys, &mut results));
+            },
+            WindowOp::Aggregate(op) => {
+                try!(aggregate(op, frame, partition, keys, descending, &mut results));
+            }
+        }
+
+        start += len;
+    }
+
+    Ok(results)
+}
+
+fn rank<ColumnValue>(op: WindowOp, partition: &[usize], keys: &[WindowRow<ColumnValue>], descending: &[bool],
+    results: &mut [ColumnValue])
+where ColumnValue: ColumnValueOps
+{
+    let mut rank = 0;
+    let mut dense_rank = 0;
+
+    for (position, &row) in partition.iter().enumerate() {
+        let is_peer = position > 0 && {
+            let previous = partition[position - 1];
+            compare(&keys[previous].order, &keys[row].order, descending) == Ordering::Equal
+        };
+
+        if !is_peer {
+            rank = position + 1;
+            dense_rank += 1;
+        }
+
+        let value = match op {
+            WindowOp::RowNumber => position + 1,
+            WindowOp::Rank => rank,
+            WindowOp::DenseRank => dense_rank,
+            _ => unreachable!()
+        };
+
+        results[row] = ColumnValueOps::from_u64(value as u64);
+    }
+}
+
+/// LAG and LEAD: the value of the row that's `offset` rows before or after
+/// the current row in its partition, or the default value if there's no such
+/// row. The offset and default value are evaluated for the current row.
+fn offset<ColumnValue>(op: WindowOp, partition: &[usize], keys: &[WindowRow<ColumnValue>],
+    results: &mut [ColumnValue])
+-> Result<(), String>
+where ColumnValue: ColumnValueOps + Clone
+{
+    for (position, &row) in partition.iter().enumerate() {
+        let arguments = &keys[row].arguments;
+
+        let offset = match arguments.get(1) {
+            Some(offset) if offset.is_null() => {
+                results[row] = ColumnValueOpsExt::null();
+                continue;
+            },
+            Some(offset) => {
+                // Casting to an unsigned integer wraps negative integers around
+                let negative = offset.compare(&ColumnValueOps::from_u64(0)) == Some(-1);
+
+                match offset.clone().to_u64() {
+                    Ok(offset) if !negative => offset,
+                    _ => return Err("the offset of LAG or LEAD must be a non-negative integer".to_string())
+                }
+            },
+            None => 1
+        };
+
+        let other = match op {
+            WindowOp::Lag => (position as u64).checked_sub(offset),
+            WindowOp::Lead => (position as u64).checked_add(offset),
+            _ => unreachable!()
+        };
+
+        results[row] = match other {
+            Some(other) if other < partition.len() as u64 => keys[partition[other as usize]].arguments[0].clone(),
+            _ => arguments.get(2).cloned().unwrap_or_else(ColumnValueOpsExt::null)
+        };
+    }
+
+    Ok(())
+}
+
+/// An aggregate function over each row's frame. The start and end of the
+/// frame only move forward from row to row, so the function is fed the rows
+/// entering the frame and has the rows leaving it removed.
+fn aggregate<ColumnValue>(op: AggregateOp, frame: Option<WindowFrame>, partition: &[usize],
+    keys: &[WindowRow<ColumnValue>], descending: &[bool], results: &mut [ColumnValue])
+-> Result<(), String>
+where ColumnValue: ColumnValueOps + Clone + 'static
+{
+    let value = |position: usize| keys[partition[position]].arguments[0].clone();
+
+    let mut function = get_aggregate_function(op);
+    // The function has been fed the rows from fed_start up to fed_end
+    let mut fed_start = 0;
+    let mut fed_end = 0;
+    // Without a frame, the end of the current row's peers
+    let mut peers_end = 0;
+
+    for (position, &row) in partition.iter().enumerate() {
+        let (start, end) = match frame {
+            Some(frame) => frame_rows(frame, position, partition.len()),
+            None if keys[row].order.is_empty() => (0, partition.len()),
+            None => {
+                while peers_end < partition.len() &&
+                    compare(&keys[partition[peers_end]].order, &keys[row].order, descending) == Ordering::Equal
+                {
+                    peers_end += 1;
+                }
+
+                (0, peers_end)
+            }
+        };
+
+        while fed_start < start && fed_start < fed_end {
+            if try!(function.remove(value(fed_start))) {
+                fed_start += 1;
+            } else {
+                break;
+            }
+        }
+
+        if fed_start < start {
+            // The function couldn't remove a row, or the frame skipped ahead
+            function = get_aggregate_function(op);
+            fed_start = start;
+            fed_end = start;
+        }
+
+        while fed_end < end {
+            try!(function.feed(value(fed_end)));
+            fed_end += 1;
+        }
+
+        results[row] = function.result();
+    }
+
+    Ok(())
+}
+
+/// The start and end of the frame's rows in a partition, with the end
+/// exclusive. A frame that ends before it starts is empty.
+fn frame_rows(frame: WindowFrame, position: usize, len: usize) -> (usize, usize) {
+    let position = position as u64;
+    let len = len as u64;
+
+    let start = match frame.start {
+        FrameBound::UnboundedPreceding => 0,
+        FrameBound::Preceding(n) => position.saturating_sub(n),
+        FrameBound::CurrentRow => position,
+        FrameBound::Following(n) => position.saturating_add(n),
+        FrameBound::UnboundedFollowing => len
+    };
+
+    let end = match frame.end {
+        FrameBound::UnboundedPreceding => 0,
+        FrameBound::Preceding(n) => (position + 1).saturating_sub(n),
+        FrameBound::CurrentRow => position + 1,
+        FrameBound::Following(n) => position.saturating_add(n).saturating_add(1),
+        FrameBound::UnboundedFollowing => len
+    };
+
+    let start = cmp::min(start, len);
+    let end = cmp::max(start, cmp::min(end, len));
+
+    (start as usize, end as usize)
+}
+
+/// Compares values in the order used for sorting. NULLs are equal to each
+/// other, so they're in the same partition.
+fn compare<ColumnValue: ColumnValueOps>(a: &[ColumnValue], b: &[ColumnValue], descending: &[bool]) -> Ordering {
+    for (i, (a, b)) in a.iter().zip(b.iter()).enumerate() {
+        let ordering = a.total_compare(b);
+
+        let ordering = if descending.get(i) == Some(&true) {
+            ordering.reverse()
+        } else {
+            ordering
+        };
+
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
+
+    Ordering::Equal
+}
+
+#[cfg(test)]
+mod test {
+    use super::{compute, WindowRow};
+    use queryplan::{AggregateOp, FrameBound, WindowFrame, WindowOp};
+    use types::Variant;
+
+    /// A row whose argument is its ORDER BY value
+    fn key(partition: u64, order: Option<u64>) -> WindowRow<Variant> {
+        let order = order.map_or(Variant::Null, Variant::UnsignedInteger);
+
+        WindowRow {
+            arguments: vec![order.clone()],
+            partition: vec![Variant::UnsignedInteger(partition)],
+            order: vec![order]
+        }
+    }
+
+    fn ranks(op: WindowOp, keys: &[WindowRow<Variant>], descending: bool) -> Vec<u64> {
+        compute(op, None, keys, &[descending]).unwrap().into_iter().map(|v| match v {
+            Variant::UnsignedInteger(n) => n,
+            _ => panic!()
+        }).collect()
+    }
+
+    fn offsets(op: WindowOp, keys: &[WindowRow<Variant>]) -> Vec<Option<u64>> {
+        compute(op, None, keys, &[false]).unwrap().into_iter().map(|v| match v {
+            Variant::UnsignedInteger(n) => Some(n),
+            Variant::Null => None,
+            _ => panic!()
+        }).collect()
+    }
+
+    #[test]
+    fn test_window_ranks() {
+        let keys = [key(1, Some(20)), key(1, Some(10)), key(2, Some(5)), key(1, Some(20)), key(1, None), key(1, Some(30))];
+
+        assert_eq!(ranks(WindowOp::RowNumber, &keys, false), vec![3, 2, 1, 4, 1, 5]);
+        assert_eq!(ranks(WindowOp::Rank, &keys, false), vec![3, 2, 1, 3, 1, 5]);
+        assert_eq!(ranks(WindowOp::DenseRank, &keys, false), vec![3, 2, 1, 3, 1, 4]);
+        assert_eq!(ranks(WindowOp::Rank, &keys, true), vec![2, 4, 1, 2, 5, 1]);
+    }
+
+    #[test]
+    fn test_window_offsets() {
+        let mut keys = vec![key(1, Some(20)), key(1, Some(10)), key(2, Some(5)), key(1, Some(30))];
+
+        assert_eq!(offsets(WindowOp::Lag, &keys), vec![Some(10), None, None, Some(20)]);
+        assert_eq!(offsets(WindowOp::Lead, &keys), vec![Some(30), Some(20), None, None]);
+
+        // An offset of 2, with a default value of 0
+        for key in keys.iter_mut() {
+            key.arguments.push(Variant::UnsignedInteger(2));
+            key.arguments.push(Variant::UnsignedInteger(0));
+        }
+
+        assert_eq!(offsets(WindowOp::Lag, &keys), vec![Some(0), Some(0), Some(0), Some(10)]);
+        assert_eq!(offsets(WindowOp::Lead, &keys), vec![Some(0), Some(30), Some(0), Some(0)]);
+
+        keys[0].arguments[1] = Variant::SignedInteger(-1);
+        assert!(compute(WindowOp::Lag, None, &keys, &[false]).is_err());
+    }
+
+    fn aggregates(op: AggregateOp, frame: Option<(FrameBound, FrameBound)>, keys: &[WindowRow<Variant>])
+    -> Vec<Option<u64>>
+    {
+        let frame = frame.map(|(start, end)| WindowFrame { start: start, end: end });
+
+        compute(WindowOp::Aggregate(op), frame, keys, &[false]).unwrap().into_iter().map(|v| match v {
+            Variant::UnsignedInteger(n) => Some(n),
+            Variant::Null => None,
+            _ => panic!()
+        }).collect()
+    }
+
+    #[test]
+    fn test_window_frames() {
+        let keys = [key(1, Some(1)), key(1, Some(4)), key(1, Some(2)), key(1, Some(2)), key(1, Some(8)), key(2, Some(3))];
+        let moving = Some((FrameBound::Preceding(1), FrameBound::CurrentRow));
+
+        // Without a frame, up to the current row and its peers
+        assert_eq!(aggregates(AggregateOp::Sum, None, &keys), vec![Some(1), Some(9), Some(5), Some(5), Some(17), Some(3)]);
+        assert_eq!(aggregates(AggregateOp::Sum, moving, &keys), vec![Some(1), Some(6), Some(3), Some(4), Some(12), Some(3)]);
+        // max can't remove values, so it's computed again
+        assert_eq!(aggregates(AggregateOp::Max, moving, &keys), vec![Some(1), Some(4), Some(2), Some(2), Some(8), Some(3)]);
+        assert_eq!(aggregates(AggregateOp::Count, Some((FrameBound::Following(1), FrameBound::UnboundedFollowing)), &keys),
+            vec![Some(4), Some(1), Some(3), Some(2), Some(0), Some(0)]);
+        assert_eq!(aggregates(AggregateOp::Sum, Some((FrameBound::Following(1), FrameBound::Preceding(1))), &keys),
+            vec![None, None, None, None, None, None]);
+    }
+}
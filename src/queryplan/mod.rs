@@ -1,10 +1,12 @@
 use columnvalueops::{ColumnValueOps, ColumnValueOpsExt};
 use databaseinfo::{DatabaseInfo, TableInfo};
-use identifier::Identifier;
+use identifier::{CaseFolding, Identifier};
 use sqlsyntax::ast;
 
 use std::fmt;
+use std::mem;
 use std::collections::HashMap;
+use std::i64;
 
 mod execute;
 mod sexpression;
@@ -15,15 +17,60 @@ use self::source::*;
 
 pub enum QueryPlanCompileError {
     TableDoesNotExist(Identifier),
+    TableFunctionDoesNotExist(Identifier),
     /// ambiguous column name; two or more tables have a column of the same name
     AmbiguousColumnName(Identifier),
     BadIdentifier(String),
     BadStringLiteral(String),
-    BadNumberLiteral(String),
+    /// placeholders can't be executed without a value bound to them
+    UnboundPlaceholder,
     UnknownFunctionName(Identifier),
     AggregateFunctionRequiresOneArgument,
     AggregateFunctionHasNoQueryToAggregate,
-    AggregateAllMustBeCount(Identifier)
+    AggregateAllMustBeCount(Identifier),
+    /// Only aggregate functions can be DISTINCT
+    DistinctNotAggregate(Identifier),
+    SequenceDoesNotExist(Identifier),
+    /// nextval and currval take a sequence name as their only argument
+    SequenceFunctionRequiresName(Identifier),
+    FunctionArgumentCount(Identifier),
+    /// current_setting takes a variable name as its only argument
+    CurrentSettingRequiresName,
+    /// `SELECT *` has no columns to select without a FROM clause
+    AllColumnsWithoutFrom,
+    /// The subquery of `IN` must select a column for each value of the row
+    /// it's compared with: (values, columns)
+    InSubqueryColumnCount(usize, usize),
+    /// Row values can only be compared with rows, or used with `IN`
+    RowValueNotAllowed,
+    /// Compared rows must have the same number of values
+    RowValueSize(usize, usize),
+    /// Expressions and subqueries are nested more deeply than the limit
+    NestedTooDeeply(u32),
+    /// Window functions can only be used in a query's result columns and ORDER BY
+    WindowFunctionNotAllowed(Identifier),
+    /// Window functions can't be used along with grouping yet
+    WindowFunctionWithGroupBy,
+    WindowFunctionArgumentCount(Identifier),
+    /// The number of rows before or after the current row isn't an integer
+    WindowFrameOffset(String),
+    /// A frame can't start at UNBOUNDED FOLLOWING or end at UNBOUNDED PRECEDING
+    WindowFrameBounds,
+    /// `GROUP BY n` must refer to one of the result columns, and not to `*`
+    GroupByPosition(u64),
+    /// `ORDER BY n` must refer to one of the result columns
+    OrderByPosition(u64),
+    /// With `SELECT DISTINCT`, ORDER BY can only sort by result columns,
+    /// since equal rows may have different values for anything else
+    DistinctOrderBy,
+    /// A query can't be grouped by its own aggregate functions
+    AggregateInGroupBy,
+    /// Aggregate functions need a query to aggregate, which a DELETE doesn't
+    /// have
+    AggregateOutsideQuery,
+    /// The values of an UPDATE ... FROM are computed for each joined row, so
+    /// they can't aggregate the rows or use window functions
+    AggregateInUpdate
 }
 
 impl fmt::Display for QueryPlanCompileError {
@@ -34,6 +81,9 @@ impl fmt::Display for QueryPlanCompileError {
             &TableDoesNotExist(ref name) => {
                 write!(f, "table does not exist: {}", name)
             },
+            &TableFunctionDoesNotExist(ref name) => {
+                write!(f, "table function does not exist: {}", name)
+            },
             &AmbiguousColumnName(ref name) => {
                 write!(f, "ambiguous column name: {}", name)
             },
@@ -43,8 +93,8 @@ impl fmt::Display for QueryPlanCompileError {
             &BadStringLiteral(ref s) => {
                 write!(f, "bad string literal: {}", s)
             },
-            &BadNumberLiteral(ref s) => {
-                write!(f, "bad number literal: {}", s)
+            &UnboundPlaceholder => {
+                write!(f, "placeholder has no value bound to it")
             },
             &UnknownFunctionName(ref s) => {
                 write!(f, "unknown function name: {}", s)
@@ -58,6 +108,72 @@ impl fmt::Display for QueryPlanCompileError {
             &AggregateAllMustBeCount(ref name) => {
                 write!(f, "aggregate (*) function must be `count` (found {})", name)
             },
+            &DistinctNotAggregate(ref name) => {
+                write!(f, "DISTINCT is only allowed in aggregate functions (found {})", name)
+            },
+            &SequenceDoesNotExist(ref name) => {
+                write!(f, "sequence does not exist: {}", name)
+            },
+            &SequenceFunctionRequiresName(ref name) => {
+                write!(f, "{} requires a sequence name as its only argument", name)
+            },
+            &FunctionArgumentCount(ref name) => {
+                write!(f, "wrong number of arguments to function {}", name)
+            },
+            &CurrentSettingRequiresName => {
+                write!(f, "current_setting requires a variable name as its only argument")
+            },
+            &AllColumnsWithoutFrom => {
+                write!(f, "SELECT * requires a FROM clause")
+            },
+            &InSubqueryColumnCount(1, count) => {
+                write!(f, "subquery of IN must select exactly one column (found {})", count)
+            },
+            &InSubqueryColumnCount(values, count) => {
+                write!(f, "subquery of IN must select a column for each of the row's {} values (found {})", values, count)
+            },
+            &RowValueNotAllowed => {
+                write!(f, "row values can only be compared with rows of the same size, or used with IN")
+            },
+            &RowValueSize(lhs, rhs) => {
+                write!(f, "cannot compare a row of {} values with a row of {} values", lhs, rhs)
+            },
+            &NestedTooDeeply(max_depth) => {
+                write!(f, "expressions and subqueries can't be nested more than {} deep", max_depth)
+            },
+            &WindowFunctionNotAllowed(ref name) => {
+                write!(f, "window function {} is only allowed in the result columns and ORDER BY", name)
+            },
+            &WindowFunctionWithGroupBy => {
+                write!(f, "window functions can't be used with GROUP BY or aggregate functions")
+            },
+            &WindowFunctionArgumentCount(ref name) => {
+                write!(f, "wrong number of arguments to window function {}", name)
+            },
+            &WindowFrameOffset(ref rows) => {
+                write!(f, "window frame offset must be a non-negative integer: {}", rows)
+            },
+            &WindowFrameBounds => {
+                write!(f, "a window frame can't start at UNBOUNDED FOLLOWING or end at UNBOUNDED PRECEDING")
+            },
+            &GroupByPosition(n) => {
+                write!(f, "GROUP BY position {} is not an expression in the result columns", n)
+            },
+            &OrderByPosition(n) => {
+                write!(f, "ORDER BY position {} is not one of the result columns", n)
+            },
+            &DistinctOrderBy => {
+                write!(f, "for SELECT DISTINCT, ORDER BY expressions must be result columns")
+            },
+            &AggregateInGroupBy => {
+                write!(f, "aggregate functions are not allowed in GROUP BY")
+            },
+            &AggregateOutsideQuery => {
+                write!(f, "aggregate functions are only allowed in queries")
+            },
+            &AggregateInUpdate => {
+                write!(f, "aggregate and window functions are not allowed in UPDATE")
+            },
         }
     }
 }
@@ -66,17 +182,46 @@ pub struct QueryPlan<'a, DB: DatabaseInfo>
 where <DB as DatabaseInfo>::Table: 'a
 {
     pub expr: SExpression<'a, DB>,
-    pub out_column_names: Vec<Identifier>
+    pub out_column_names: Vec<Identifier>,
+    /// The table column each result column selects, if it selects one as is
+    pub out_column_origins: Vec<Option<ColumnOrigin<'a, <DB as DatabaseInfo>::Table>>>
+}
+
+/// A column of a table, which a result column selects directly or through
+/// subqueries in FROM
+pub struct ColumnOrigin<'a, Table: 'a> {
+    pub table: &'a Table,
+    pub column_offset: u32,
+    /// Whether a LEFT JOIN can make the column NULL, even if the table's
+    /// column can't be
+    pub outer_joined: bool
+}
+
+impl<'a, Table: 'a> Clone for ColumnOrigin<'a, Table> {
+    fn clone(&self) -> ColumnOrigin<'a, Table> {
+        ColumnOrigin {
+            table: self.table,
+            column_offset: self.column_offset,
+            outer_joined: self.outer_joined
+        }
+    }
 }
 
 fn new_identifier(value: &str) -> Result<Identifier, QueryPlanCompileError> {
     Identifier::new(value).ok_or(QueryPlanCompileError::BadIdentifier(value.to_string()))
 }
 
+fn new_identifier_with_case_folding(value: &str, case_folding: CaseFolding)
+-> Result<Identifier, QueryPlanCompileError>
+{
+    Identifier::new_with_case_folding(value, case_folding).ok_or(QueryPlanCompileError::BadIdentifier(value.to_string()))
+}
+
 impl<'a, DB: DatabaseInfo> QueryPlan<'a, DB>
 where <DB as DatabaseInfo>::Table: 'a
 {
-    pub fn compile_select(db: &'a DB, stmt: ast::SelectStatement)
+    /// `max_depth` limits how deeply expressions and subqueries can be nested.
+    pub fn compile_select(db: &'a DB, stmt: ast::SelectStatement, max_depth: u32)
     -> Result<QueryPlan<'a, DB>, QueryPlanCompileError>
     {
         let scope = SourceScope::new(None, Vec::new(), Vec::new());
@@ -91,6 +236,11 @@ where <DB as DatabaseInfo>::Table: 'a
         let plan = {
             let compiler = QueryCompiler {
                 query_id: 0,
+                depth: 0,
+                max_depth: max_depth,
+                windows: None,
+                group_by: None,
+                origins: HashMap::new(),
                 db: db,
                 source_id_to_query_id: &mut source_id_to_query_id,
                 query_to_aggregated_source_id: &mut query_to_aggregated_source_id,
@@ -106,9 +256,28 @@ where <DB as DatabaseInfo>::Table: 'a
 
         plan
     }
+
+    /// Compiles the join of an UPDATE ... FROM: a query whose result columns
+    /// are computed for each joined row.
+    pub fn compile_update_join(db: &'a DB, stmt: ast::SelectStatement, max_depth: u32)
+    -> Result<QueryPlan<'a, DB>, QueryPlanCompileError>
+    {
+        let plan = try!(QueryPlan::compile_select(db, stmt, max_depth));
+
+        let per_row = match plan.expr {
+            SExpression::TempGroupBy { .. } | SExpression::Window { .. } => false,
+            _ => true
+        };
+
+        if per_row {
+            Ok(plan)
+        } else {
+            Err(QueryPlanCompileError::AggregateInUpdate)
+        }
+    }
 }
 
-pub fn compile_ast_expression<'a, DB: DatabaseInfo>(db: &'a DB, expr: ast::Expression)
+pub fn compile_ast_expression<'a, DB: DatabaseInfo>(db: &'a DB, expr: ast::Expression, max_depth: u32)
 -> Result<SExpression<'a, DB>, QueryPlanCompileError>
 where <DB as DatabaseInfo>::Table: 'a
 {
@@ -123,6 +292,11 @@ where <DB as DatabaseInfo>::Table: 'a
 
     let mut compiler = QueryCompiler {
         query_id: 0,
+        depth: 0,
+        max_depth: max_depth,
+        windows: None,
+        group_by: None,
+        origins: HashMap::new(),
         db: db,
         source_id_to_query_id: &mut source_id_to_query_id,
         query_to_aggregated_source_id: &mut query_to_aggregated_source_id,
@@ -133,6 +307,52 @@ where <DB as DatabaseInfo>::Table: 'a
     compiler.ast_expression_to_sexpression(expr, &scope, &mut groups_info)
 }
 
+/// Compiles an expression that's evaluated for each row of `table`, such as
+/// the WHERE of a DELETE. The expression can refer to the table's columns,
+/// which are source 0; see `ExecuteQueryPlan::execute_expression_with_row`.
+pub fn compile_row_expression<'a, DB: DatabaseInfo>(db: &'a DB, table: &'a <DB as DatabaseInfo>::Table,
+    expr: ast::Expression, max_depth: u32)
+-> Result<SExpression<'a, DB>, QueryPlanCompileError>
+where <DB as DatabaseInfo>::Table: 'a
+{
+    let tables = vec![TableOrSubquery {
+        source_id: 0,
+        out_column_names: table.get_column_names()
+    }];
+    let scope = SourceScope::new(None, tables, vec![table.get_name().clone()]);
+
+    let mut source_id_to_query_id = HashMap::new();
+    source_id_to_query_id.insert(0, 0);
+    let mut query_to_aggregated_source_id = HashMap::new();
+    let mut next_source_id = 1;
+    let mut next_query_id = 1;
+
+    let mut groups_info = GroupsInfo::new();
+
+    let mut compiler = QueryCompiler {
+        query_id: 0,
+        depth: 0,
+        max_depth: max_depth,
+        windows: None,
+        group_by: None,
+        origins: HashMap::new(),
+        db: db,
+        source_id_to_query_id: &mut source_id_to_query_id,
+        query_to_aggregated_source_id: &mut query_to_aggregated_source_id,
+        next_source_id: &mut next_source_id,
+        next_query_id: &mut next_query_id
+    };
+
+    let expr = try!(compiler.ast_expression_to_sexpression(expr, &scope, &mut groups_info));
+
+    // An aggregate function outside of a subquery would aggregate the table
+    if compiler.query_to_aggregated_source_id.contains_key(&0) {
+        return Err(QueryPlanCompileError::AggregateOutsideQuery);
+    }
+
+    Ok(expr)
+}
+
 #[derive(Debug)]
 struct GroupsInfo {
     innermost_nonaggregated_query: Option<u32>
@@ -162,6 +382,18 @@ struct QueryCompiler<'a, 'z, DB: DatabaseInfo>
 where DB: 'a, <DB as DatabaseInfo>::Table: 'a
 {
     query_id: u32,
+    /// How many expressions and subqueries are being compiled
+    depth: u32,
+    max_depth: u32,
+    /// The window functions found in the result columns. None while
+    /// compiling anything else, where window functions aren't allowed.
+    windows: Option<Windows<'a, DB>>,
+    /// The GROUP BY expressions, while compiling the result columns and
+    /// HAVING of a grouped query. None while compiling anything else.
+    group_by: Option<GroupBy>,
+    /// The origins of the columns of each table and subquery in FROM, by
+    /// source id
+    origins: HashMap<u32, Vec<Option<ColumnOrigin<'a, <DB as DatabaseInfo>::Table>>>>,
     db: &'a DB,
     source_id_to_query_id: &'z mut HashMap<u32, u32>,
     query_to_aggregated_source_id: &'z mut HashMap<u32, u32>,
@@ -169,13 +401,57 @@ where DB: 'a, <DB as DatabaseInfo>::Table: 'a
     next_query_id: &'z mut u32
 }
 
+struct Windows<'a, DB: DatabaseInfo>
+where <DB as DatabaseInfo>::Table: 'a
+{
+    /// The source of the window's rows, once there's a window function
+    source_id: Option<u32>,
+    /// The offset of the first window function's result in the window's
+    /// rows. The query's columns come before it.
+    column_offset: u32,
+    functions: Vec<WindowFunction<'a, DB>>
+}
+
+struct GroupBy {
+    source_id: u32,
+    /// The offset of the first GROUP BY value in the group's rows. The
+    /// query's columns come before it.
+    column_offset: u32,
+    exprs: Vec<ast::Expression>
+}
+
 struct FromWhere<'a, DB: DatabaseInfo>
 where <DB as DatabaseInfo>::Table: 'a
 {
-    tables: Vec<FromWhereTableOrSubquery<'a, DB>>,
+    /// The first table is scanned in the outermost loop
+    joins: Vec<FromWhereJoin<'a, DB>>,
     where_expr: Option<SExpression<'a, DB>>
 }
 
+/// A step of the nested loops that FROM is evaluated with. The steps can
+/// only refer to the tables before them.
+enum FromWhereJoin<'a, DB: DatabaseInfo>
+where <DB as DatabaseInfo>::Table: 'a
+{
+    /// The rows of a table, for each row of the tables before it
+    Table {
+        table: FromWhereTableOrSubquery<'a, DB>,
+        column_count: u32
+    },
+    /// Filters the rows of the tables before it, for the ON of an INNER JOIN
+    Filter(SExpression<'a, DB>),
+    /// A LEFT JOIN of a table, or of joins in parentheses. Their columns are
+    /// NULL in the rows of the tables before them that none of their rows
+    /// matched.
+    Left {
+        /// The table's own source, or a new one whose rows are the joined
+        /// rows of the joins
+        source_id: u32,
+        joins: Vec<FromWhereJoin<'a, DB>>,
+        on: Option<SExpression<'a, DB>>
+    }
+}
+
 enum FromWhereTableOrSubquery<'a, DB: DatabaseInfo>
 where <DB as DatabaseInfo>::Table: 'a
 {
@@ -186,13 +462,90 @@ where <DB as DatabaseInfo>::Table: 'a
     Subquery {
         source_id: u32,
         expr: SExpression<'a, DB>
+    },
+    Function {
+        source_id: u32,
+        function: Identifier,
+        arguments: Vec<SExpression<'a, DB>>
+    },
+    /// The rows of a hash join's table that match the values
+    HashProbe {
+        source_id: u32,
+        values: Vec<SExpression<'a, DB>>
     }
 }
 
-impl<'a, DB: DatabaseInfo> FromWhere<'a, DB>
+impl<'a, DB: DatabaseInfo> FromWhereTableOrSubquery<'a, DB>
 where <DB as DatabaseInfo>::Table: 'a
+{
+    fn source_id(&self) -> u32 {
+        match self {
+            &FromWhereTableOrSubquery::Table { source_id, .. } |
+            &FromWhereTableOrSubquery::Subquery { source_id, .. } |
+            &FromWhereTableOrSubquery::Function { source_id, .. } |
+            &FromWhereTableOrSubquery::HashProbe { source_id, .. } => source_id
+        }
+    }
+
+    /// Runs `nested_expr` for each row of the table
+    fn evaluate(self, nested_expr: SExpression<'a, DB>) -> SExpression<'a, DB> {
+        match self {
+            FromWhereTableOrSubquery::Subquery { source_id, expr } => {
+                SExpression::Map {
+                    source_id: source_id,
+                    yield_in_fn: Box::new(expr),
+                    yield_out_fn: Box::new(nested_expr)
+                }
+            },
+            FromWhereTableOrSubquery::Table { source_id, table } => {
+                SExpression::Scan {
+                    source_id: source_id,
+                    table: table,
+                    yield_fn: Box::new(nested_expr)
+                }
+            },
+            FromWhereTableOrSubquery::Function { source_id, function, arguments } => {
+                SExpression::TableFunction {
+                    source_id: source_id,
+                    function: function,
+                    arguments: arguments,
+                    yield_fn: Box::new(nested_expr)
+                }
+            },
+            FromWhereTableOrSubquery::HashProbe { source_id, values } => {
+                SExpression::HashProbe {
+                    source_id: source_id,
+                    values: values,
+                    yield_fn: Box::new(nested_expr)
+                }
+            }
+        }
+    }
+}
+
+impl<'a, DB: DatabaseInfo> FromWhere<'a, DB>
+where DB: 'a, <DB as DatabaseInfo>::Table: 'a
 {
     fn evaluate(self, inner_expr: SExpression<'a, DB>) -> SExpression<'a, DB> {
+        let mut tables = Vec::new();
+        join_tables(&self.joins, &mut tables);
+        let source_ids: Vec<u32> = tables.iter().map(|&(source_id, _)| source_id).collect();
+
+        // The tables after the first are looked up by their equalities with
+        // the tables before them, if they have any, instead of being scanned
+        // for each row of those tables
+        let mut hash_keys = Vec::new();
+        let mut conjuncts = Vec::new();
+
+        if let Some(ref where_expr) = self.where_expr {
+            push_conjuncts(where_expr, &mut conjuncts);
+        }
+
+        find_hash_keys(&self.joins, conjuncts, &source_ids, &mut hash_keys);
+
+        let mut hash_joins = Vec::new();
+        let joins = probe_hash_tables(self.joins, &mut hash_keys.into_iter(), &mut hash_joins);
+
         let core_expr = if let Some(where_expr) = self.where_expr {
             SExpression::If {
                 predicate: Box::new(where_expr),
@@ -202,24 +555,271 @@ where <DB as DatabaseInfo>::Table: 'a
             inner_expr
         };
 
-        self.tables.into_iter().fold(core_expr, |nested_expr, x| {
-            match x {
-                FromWhereTableOrSubquery::Subquery { source_id, expr } => {
-                    SExpression::Map {
-                        source_id: source_id,
-                        yield_in_fn: Box::new(expr),
-                        yield_out_fn: Box::new(nested_expr)
+        let expr = evaluate_joins(joins, core_expr);
+
+        hash_joins.into_iter().fold(expr, |expr, (source_id, keys, rows)| {
+            SExpression::HashJoin {
+                source_id: source_id,
+                keys: keys,
+                yield_in_fn: Box::new(rows),
+                yield_out_fn: Box::new(expr)
+            }
+        })
+    }
+}
+
+/// Adds the source and column count of each table of the joins, including
+/// the tables of LEFT JOINs, in the order they're joined in
+fn join_tables<'a, DB: DatabaseInfo>(joins: &[FromWhereJoin<'a, DB>], tables: &mut Vec<(u32, u32)>)
+where <DB as DatabaseInfo>::Table: 'a
+{
+    for join in joins {
+        match join {
+            &FromWhereJoin::Table { ref table, column_count } => tables.push((table.source_id(), column_count)),
+            &FromWhereJoin::Filter(_) => (),
+            &FromWhereJoin::Left { ref joins, .. } => join_tables(joins, tables)
+        }
+    }
+}
+
+/// Finds the hash join keys of each table of the joins, in the order of
+/// `join_tables`. `conjuncts` are the conditions that the joined rows must
+/// meet, besides the filters among the joins.
+fn find_hash_keys<'a, 'e, DB: DatabaseInfo>(joins: &'e [FromWhereJoin<'a, DB>], mut conjuncts: Vec<&'e SExpression<'a, DB>>,
+    source_ids: &[u32], hash_keys: &mut Vec<Option<(Vec<SExpression<'a, DB>>, Vec<SExpression<'a, DB>>)>>)
+where <DB as DatabaseInfo>::Table: 'a
+{
+    for join in joins {
+        if let &FromWhereJoin::Filter(ref on) = join {
+            push_conjuncts(on, &mut conjuncts);
+        }
+    }
+
+    for join in joins {
+        match join {
+            &FromWhereJoin::Table { .. } => {
+                let i = hash_keys.len();
+
+                hash_keys.push(if i == 0 {
+                    None
+                } else {
+                    hash_join_keys(&conjuncts, source_ids[i], &source_ids[i..])
+                });
+            },
+            &FromWhereJoin::Filter(_) => (),
+            &FromWhereJoin::Left { ref joins, ref on, .. } => {
+                // WHERE and the other filters apply after the LEFT JOIN pads
+                // its rows with NULLs, so only ON can be used
+                let mut on_conjuncts = Vec::new();
+
+                if let &Some(ref on) = on {
+                    push_conjuncts(on, &mut on_conjuncts);
+                }
+
+                find_hash_keys(joins, on_conjuncts, source_ids, hash_keys);
+            }
+        }
+    }
+}
+
+/// Replaces the tables that have hash join keys with probes of their hash
+/// tables. The hash join reads all of the table's rows once, and the probe
+/// takes the table's place in the loop.
+fn probe_hash_tables<'a, DB: DatabaseInfo, I>(joins: Vec<FromWhereJoin<'a, DB>>, hash_keys: &mut I,
+    hash_joins: &mut Vec<(u32, Vec<SExpression<'a, DB>>, SExpression<'a, DB>)>)
+-> Vec<FromWhereJoin<'a, DB>>
+where <DB as DatabaseInfo>::Table: 'a, I: Iterator<Item=Option<(Vec<SExpression<'a, DB>>, Vec<SExpression<'a, DB>>)>>
+{
+    let mut probed = Vec::new();
+
+    for join in joins {
+        probed.push(match join {
+            FromWhereJoin::Table { table, column_count } => match hash_keys.next().unwrap() {
+                Some((keys, values)) => {
+                    let source_id = table.source_id();
+                    let rows = table.evaluate(SExpression::Yield { fields: column_fields(source_id, column_count) });
+                    hash_joins.push((source_id, keys, rows));
+
+                    FromWhereJoin::Table {
+                        table: FromWhereTableOrSubquery::HashProbe {
+                            source_id: source_id,
+                            values: values
+                        },
+                        column_count: column_count
                     }
                 },
-                FromWhereTableOrSubquery::Table { source_id, table } => {
-                    SExpression::Scan {
-                        source_id: source_id,
-                        table: table,
-                        yield_fn: Box::new(nested_expr)
-                    }
+                None => FromWhereJoin::Table { table: table, column_count: column_count }
+            },
+            FromWhereJoin::Left { source_id, joins, on } => FromWhereJoin::Left {
+                source_id: source_id,
+                joins: probe_hash_tables(joins, hash_keys, hash_joins),
+                on: on
+            },
+            join => join
+        });
+    }
+
+    probed
+}
+
+/// Nests the loops of the joins, the first outermost, around `inner_expr`
+fn evaluate_joins<'a, DB: DatabaseInfo>(joins: Vec<FromWhereJoin<'a, DB>>, inner_expr: SExpression<'a, DB>)
+-> SExpression<'a, DB>
+where DB: 'a, <DB as DatabaseInfo>::Table: 'a
+{
+    joins.into_iter().rev().fold(inner_expr, |nested_expr, join| match join {
+        FromWhereJoin::Table { table, .. } => table.evaluate(nested_expr),
+        FromWhereJoin::Filter(on) => SExpression::If {
+            predicate: Box::new(on),
+            yield_fn: Box::new(nested_expr)
+        },
+        FromWhereJoin::Left { source_id, joins, on } => {
+            let mut tables = Vec::new();
+            join_tables(&joins, &mut tables);
+
+            // The rows that match are yielded to the LEFT JOIN, which gives
+            // them to the rest of the query as rows of its source
+            let fields = tables.iter().flat_map(|&(source_id, column_count)| column_fields(source_id, column_count)).collect();
+            let matched = SExpression::Yield { fields: fields };
+
+            let matched = match on {
+                Some(on) => SExpression::If {
+                    predicate: Box::new(on),
+                    yield_fn: Box::new(matched)
+                },
+                None => matched
+            };
+
+            // Joins in parentheses are read as columns of the new source
+            let mut nested_expr = nested_expr;
+            let mut column_count = 0;
+
+            let mapping: HashMap<_, _> = tables.iter().map(|&(table_source_id, table_column_count)| {
+                let m = Mapping {
+                    source_id: source_id,
+                    column_offset: column_count
+                };
+
+                column_count += table_column_count;
+                (table_source_id, m)
+            }).collect();
+
+            if tables.len() > 1 {
+                remap_columns_in_sexpression(&mut nested_expr, &mapping);
+            }
+
+            SExpression::LeftJoin {
+                source_id: source_id,
+                column_count: column_count,
+                yield_in_fn: Box::new(evaluate_joins(joins, matched)),
+                yield_out_fn: Box::new(nested_expr)
+            }
+        }
+    })
+}
+
+fn column_fields<'a, DB: DatabaseInfo>(source_id: u32, column_count: u32) -> Vec<SExpression<'a, DB>>
+where <DB as DatabaseInfo>::Table: 'a
+{
+    (0..column_count).map(|column_offset| SExpression::ColumnField {
+        source_id: source_id,
+        column_offset: column_offset
+    }).collect()
+}
+
+/// Adds the expressions that `expr` ANDs together
+fn push_conjuncts<'a, 'e, DB: DatabaseInfo>(expr: &'e SExpression<'a, DB>, conjuncts: &mut Vec<&'e SExpression<'a, DB>>)
+where <DB as DatabaseInfo>::Table: 'a
+{
+    match expr {
+        &SExpression::BinaryOp { op: BinaryOp::And, ref lhs, ref rhs } => {
+            push_conjuncts(lhs, conjuncts);
+            push_conjuncts(rhs, conjuncts);
+        },
+        expr => conjuncts.push(expr)
+    }
+}
+
+/// Finds the equalities between expressions of a table's columns, the keys,
+/// and values that don't depend on the table or the tables after it. The
+/// table's rows can be looked up by those values in a hash table of their
+/// keys. None if there are no such equalities.
+fn hash_join_keys<'a, DB: DatabaseInfo>(conjuncts: &[&SExpression<'a, DB>], source_id: u32, later_source_ids: &[u32])
+-> Option<(Vec<SExpression<'a, DB>>, Vec<SExpression<'a, DB>>)>
+where <DB as DatabaseInfo>::Table: 'a
+{
+    let mut keys = Vec::new();
+    let mut values = Vec::new();
+
+    for conjunct in conjuncts {
+        if let &&SExpression::BinaryOp { op: BinaryOp::Equal, ref lhs, ref rhs } = conjunct {
+            for &(key, value) in [(lhs, rhs), (rhs, lhs)].iter() {
+                // A key that reads no columns is the same for every row
+                let is_key = reads_only_columns(key, &|id| id == source_id) && !reads_only_columns(key, &|_| false);
+                let is_value = reads_only_columns(value, &|id| !later_source_ids.contains(&id));
+
+                if is_key && is_value {
+                    keys.push(copy_column_expression(key));
+                    values.push(copy_column_expression(value));
+                    break;
                 }
             }
-        })
+        }
+    }
+
+    if keys.is_empty() {
+        None
+    } else {
+        Some((keys, values))
+    }
+}
+
+/// Copies an expression that `reads_only_columns` accepts. The predicate it
+/// came from stays in the plan, so the copy is what's looked up.
+fn copy_column_expression<'a, DB: DatabaseInfo>(expr: &SExpression<'a, DB>) -> SExpression<'a, DB>
+where <DB as DatabaseInfo>::Table: 'a
+{
+    match expr {
+        &SExpression::ColumnField { source_id, column_offset } => SExpression::ColumnField {
+            source_id: source_id,
+            column_offset: column_offset
+        },
+        &SExpression::Value(ref v) => SExpression::Value(v.clone()),
+        &SExpression::UnaryOp { op, ref expr } => SExpression::UnaryOp {
+            op: op,
+            expr: Box::new(copy_column_expression(expr))
+        },
+        &SExpression::BinaryOp { op, ref lhs, ref rhs } => SExpression::BinaryOp {
+            op: op,
+            lhs: Box::new(copy_column_expression(lhs)),
+            rhs: Box::new(copy_column_expression(rhs))
+        },
+        &SExpression::FunctionOp { op, ref arguments } => SExpression::FunctionOp {
+            op: op,
+            arguments: arguments.iter().map(copy_column_expression).collect()
+        },
+        _ => unreachable!()
+    }
+}
+
+/// Whether the expression only reads the columns of the sources that
+/// `source` is true for, and always has the same value for the same
+/// columns. Subqueries, sequences and session variables are never used as
+/// hash join keys.
+fn reads_only_columns<'a, DB: DatabaseInfo, F: Fn(u32) -> bool>(expr: &SExpression<'a, DB>, source: &F) -> bool
+where <DB as DatabaseInfo>::Table: 'a
+{
+    match expr {
+        &SExpression::ColumnField { source_id, .. } => source(source_id),
+        &SExpression::Value(_) => true,
+        &SExpression::UnaryOp { ref expr, .. } => reads_only_columns(expr, source),
+        &SExpression::BinaryOp { ref lhs, ref rhs, .. } => {
+            reads_only_columns(lhs, source) && reads_only_columns(rhs, source)
+        },
+        &SExpression::FunctionOp { ref arguments, .. } => {
+            arguments.iter().all(|argument| reads_only_columns(argument, source))
+        },
+        _ => false
     }
 }
 
@@ -255,6 +855,13 @@ where DB: 'a, <DB as DatabaseInfo>::Table: 'a
         }
     }
 
+    /// The table column that a column of a source is, if it's known
+    fn column_origin(&self, source_id: u32, column_offset: u32)
+    -> Option<ColumnOrigin<'a, <DB as DatabaseInfo>::Table>>
+    {
+        self.origins.get(&source_id).and_then(|origins| origins[column_offset as usize].clone())
+    }
+
     fn get_query_id_from_source_id(&self, source_id: u32) -> u32 {
         *self.source_id_to_query_id.get(&source_id).unwrap()
     }
@@ -262,24 +869,54 @@ where DB: 'a, <DB as DatabaseInfo>::Table: 'a
     fn compile<'b>(mut self, stmt: ast::SelectStatement, outer_scope: &'b SourceScope<'b>, groups_info: &mut GroupsInfo)
     -> Result<QueryPlan<'a, DB>, QueryPlanCompileError>
     {
-        // Unimplemented syntaxes: ORDER BY
-        // TODO - implement them!
-        if !stmt.order_by.is_empty() { unimplemented!() }
-
         // FROM and WHERE are compiled together.
         // This makes sense for INNER and OUTER joins, which also
         // contain ON (conditional) expressions.
 
-        let (new_scope, from_where) = try!(self.from_where(stmt.from, stmt.where_expr, outer_scope, groups_info));
+        // Without FROM, the query has a single row: the cross join of no tables
+        let from = stmt.from.unwrap_or(ast::From::Cross(Vec::new()));
+
+        let (new_scope, from_where) = try!(self.from_where(from, stmt.where_expr, outer_scope, groups_info));
 
-        let (mut group_by_values, having_predicate) = if !stmt.group_by.is_empty() {
+        let column_offset = new_scope.tables().iter().fold(0, |c, table| c + table.out_column_names.len() as u32);
+
+        // HAVING without GROUP BY groups every row into one group
+        let (mut group_by_values, having_predicate) = if !stmt.group_by.is_empty() || stmt.having.is_some() {
             let query_id = self.query_id;
-            self.new_aggregated_source_id(query_id);
+            let source_id = self.new_aggregated_source_id(query_id);
+
+            let result_columns = &stmt.result_columns;
 
-            let group_by_values = try!(stmt.group_by.into_iter().map(|expr| {
-                self.ast_expression_to_sexpression(expr, &new_scope, groups_info)
+            let group_by: Vec<_> = try!(stmt.group_by.into_iter().map(|expr| {
+                group_by_expression(expr, result_columns, &new_scope)
             }).collect());
 
+            let mut group_by_values: Vec<_> = try!(group_by.iter().map(|expr| {
+                self.ast_expression_to_sexpression(expr.clone(), &new_scope, groups_info)
+            }).collect());
+
+            if group_by_values.iter_mut().any(|expr| aggregates_source(expr, source_id)) {
+                return Err(QueryPlanCompileError::AggregateInGroupBy);
+            }
+
+            // Each of the group's rows has its GROUP BY values after the
+            // query's columns
+            // A result column that's a GROUP BY value has the value's origin
+            let mut group_origins: Vec<_> = (0..column_offset).map(|_| None).collect();
+
+            group_origins.extend(group_by_values.iter().map(|expr| match expr {
+                &SExpression::ColumnField { source_id, column_offset } => self.column_origin(source_id, column_offset),
+                _ => None
+            }));
+
+            self.origins.insert(source_id, group_origins);
+
+            self.group_by = Some(GroupBy {
+                source_id: source_id,
+                column_offset: column_offset,
+                exprs: group_by
+            });
+
             let having_predicate = if let Some(having) = stmt.having {
                 Some(try!(self.ast_expression_to_sexpression(having, &new_scope, groups_info)))
             } else {
@@ -291,40 +928,42 @@ where DB: 'a, <DB as DatabaseInfo>::Table: 'a
             (vec![], None)
         };
 
-        let (column_names, select_exprs) = try!(self.select(stmt.result_columns, &new_scope, groups_info));
+        self.windows = Some(Windows {
+            source_id: None,
+            column_offset: column_offset,
+            functions: Vec::new()
+        });
+
+        // ORDER BY is compiled along with the result columns, so that it can
+        // sort by aggregate and window functions too
+        let order_by = stmt.order_by;
+
+        let select = self.select(stmt.result_columns, &new_scope, groups_info).and_then(|(column_names, mut select_exprs)| {
+            let order_by = try!(self.order_by(order_by, &column_names, &mut select_exprs, &new_scope, groups_info));
+            Ok((column_names, select_exprs, order_by))
+        });
+        let windows = self.windows.take().unwrap();
+        self.group_by = None;
+        let (column_names, mut select_exprs, mut order_by) = try!(select);
+
+        if stmt.distinct {
+            try!(order_by_result_columns(&mut order_by, &mut select_exprs, column_names.len()));
+        }
+
+        let column_origins = select_exprs[..column_names.len()].iter().map(|expr| match expr {
+            &SExpression::ColumnField { source_id, column_offset } => self.column_origin(source_id, column_offset),
+            _ => None
+        }).collect();
 
         let grouped_source_id = self.query_to_aggregated_source_id.get(&self.query_id).cloned();
 
         let expr = if let Some(source_id) = grouped_source_id {
-            let yield_every_column = SExpression::Yield {
-                fields: new_scope.tables().iter().flat_map(|table| {
-                    let source_id = table.source_id;
-
-                    (0..table.out_column_names.len() as u32).map(move |column_offset| {
-                        SExpression::ColumnField {
-                            source_id: source_id,
-                            column_offset: column_offset
-                        }
-                    })
-                }).collect()
-            };
-
-            let yield_in_fn = from_where.evaluate(yield_every_column);
-
-            let mapping = {
-                let mut c = 0;
-
-                new_scope.tables().iter().map(|table| {
-                    let m = Mapping {
-                        source_id: source_id,
-                        column_offset: c
-                    };
-
-                    c += table.out_column_names.len() as u32;
+            if !windows.functions.is_empty() {
+                return Err(QueryPlanCompileError::WindowFunctionWithGroupBy);
+            }
 
-                    (table.source_id, m)
-                }).collect()
-            };
+            let yield_in_fn = from_where.evaluate(yield_every_column(&new_scope));
+            let mapping = mapping_to_source(&new_scope, source_id);
 
             let mut yield_out_fn = SExpression::Yield { fields: select_exprs };
 
@@ -347,13 +986,66 @@ where DB: 'a, <DB as DatabaseInfo>::Table: 'a
                 group_by_values: group_by_values,
                 yield_out_fn: Box::new(yield_out_fn)
             }
+        } else if let Some(source_id) = windows.source_id {
+            let yield_in_fn = from_where.evaluate(yield_every_column(&new_scope));
+            let mapping = mapping_to_source(&new_scope, source_id);
+
+            let mut yield_out_fn = SExpression::Yield { fields: select_exprs };
+            remap_columns_in_sexpression(&mut yield_out_fn, &mapping);
+
+            let mut functions = windows.functions;
+
+            for function in &mut functions {
+                iter_mut_expressions_in_window_function(function, |e| remap_columns_in_sexpression(e, &mapping));
+            }
+
+            SExpression::Window {
+                source_id: source_id,
+                yield_in_fn: Box::new(yield_in_fn),
+                functions: functions,
+                yield_out_fn: Box::new(yield_out_fn)
+            }
         } else {
             from_where.evaluate(SExpression::Yield { fields: select_exprs })
         };
 
+        let expr = count_rows_without_scan(expr);
+
+        // Duplicates are left out before sorting, so that LIMIT counts
+        // distinct rows
+        let expr = if stmt.distinct {
+            SExpression::Distinct {
+                yield_in_fn: Box::new(expr),
+                column_count: column_names.len() as u32
+            }
+        } else {
+            expr
+        };
+
+        let expr = if order_by.is_empty() {
+            expr
+        } else {
+            SExpression::Sort {
+                yield_in_fn: Box::new(expr),
+                column_count: column_names.len() as u32,
+                order_by: order_by
+            }
+        };
+
+        let expr = if let Some(limit) = stmt.limit {
+            SExpression::Limit {
+                yield_in_fn: Box::new(expr),
+                count: limit.count,
+                offset: limit.offset.unwrap_or(0)
+            }
+        } else {
+            expr
+        };
+
         Ok(QueryPlan {
             expr: expr,
-            out_column_names: column_names
+            out_column_names: column_names,
+            out_column_origins: column_origins
         })
     }
 
@@ -362,144 +1054,520 @@ where DB: 'a, <DB as DatabaseInfo>::Table: 'a
     {
         // TODO - avoid naive nested scans when indices are available
 
-        // All FROM subqueries are nested, never correlated.
-        let ast_cross_tables = match from {
-            ast::From::Cross(v) => v,
-            ast::From::Join {..} => unimplemented!()
+        let mut source_tables = Vec::new();
+        let mut table_aliases = Vec::new();
+        let mut joins = Vec::new();
+
+        match from {
+            ast::From::Cross(tables) => {
+                for ast_table_or_subquery in tables {
+                    let tree = ast::JoinTree::Table(ast_table_or_subquery);
+                    try!(self.from_join_tree(tree, &mut joins, &mut source_tables, &mut table_aliases, scope, groups_info));
+                }
+            },
+            ast::From::Join(join) => {
+                let tree = ast::JoinTree::Join(join);
+                try!(self.from_join_tree(tree, &mut joins, &mut source_tables, &mut table_aliases, scope, groups_info));
+            }
+        }
+
+        let new_scope = SourceScope::new(Some(scope), source_tables, table_aliases);
+
+        let where_expr = if let Some(where_expr) = where_expr {
+            Some(try!(self.ast_expression_to_sexpression(where_expr, &new_scope, groups_info)))
+        } else {
+            None
         };
 
-        let a: Vec<_> = try!(ast_cross_tables.into_iter().map(|ast_table_or_subquery| {
-            match ast_table_or_subquery {
-                ast::TableOrSubquery::Subquery { subquery, alias } => {
-                    let plan = {
-                        let compiler = QueryCompiler {
-                            query_id: self.new_query_id(),
-                            db: self.db,
-                            source_id_to_query_id: self.source_id_to_query_id,
-                            query_to_aggregated_source_id: self.query_to_aggregated_source_id,
-                            next_source_id: self.next_source_id,
-                            next_query_id: self.next_query_id
-                        };
-
-                        try!(compiler.compile(*subquery, scope, groups_info))
-                    };
-                    let alias_identifier = try!(new_identifier(&alias));
+        Ok((new_scope, FromWhere {
+            joins: joins,
+            where_expr: where_expr
+        }))
+    }
 
-                    let source_id = self.new_source_id();
+    /// Adds the steps of a table or a join to `joins`, and its tables to
+    /// `source_tables`. ON can see the tables before it, as well as the
+    /// ones it joins.
+    fn from_join_tree<'b>(&mut self, tree: ast::JoinTree, joins: &mut Vec<FromWhereJoin<'a, DB>>,
+        source_tables: &mut Vec<TableOrSubquery>, table_aliases: &mut Vec<Identifier>, scope: &'b SourceScope<'b>,
+        groups_info: &mut GroupsInfo)
+    -> Result<(), QueryPlanCompileError>
+    {
+        let join = match tree {
+            ast::JoinTree::Table(ast_table_or_subquery) => {
+                let (s, t, alias) = try!(self.from_table(ast_table_or_subquery, scope, groups_info));
+
+                joins.push(FromWhereJoin::Table {
+                    table: t,
+                    column_count: s.out_column_names.len() as u32
+                });
+                source_tables.push(s);
+                table_aliases.push(alias);
+                return Ok(());
+            },
+            ast::JoinTree::Join(join) => join
+        };
 
-                    let s = TableOrSubquery {
-                        source_id: source_id,
-                        out_column_names: plan.out_column_names
-                    };
+        try!(self.from_join_tree(*join.lhs, joins, source_tables, table_aliases, scope, groups_info));
 
-                    let t = FromWhereTableOrSubquery::Subquery {
-                        source_id: source_id,
-                        expr: plan.expr
-                    };
+        if join.operator != ast::JoinOperator::Left {
+            try!(self.from_join_tree(*join.rhs, joins, source_tables, table_aliases, scope, groups_info));
+
+            if let Some(on) = join.on {
+                let on = try!(self.join_condition(on, source_tables, table_aliases, scope, groups_info));
+                joins.push(FromWhereJoin::Filter(on));
+            }
+
+            return Ok(());
+        }
+
+        let first_table = source_tables.len();
+        let mut left_joins = Vec::new();
+        try!(self.from_join_tree(*join.rhs, &mut left_joins, source_tables, table_aliases, scope, groups_info));
+
+        let on = match join.on {
+            Some(on) => Some(try!(self.join_condition(on, source_tables, table_aliases, scope, groups_info))),
+            None => None
+        };
+
+        // The columns are NULL where no row matched
+        for table in &source_tables[first_table..] {
+            if let Some(origins) = self.origins.get_mut(&table.source_id) {
+                for origin in origins.iter_mut() {
+                    if let &mut Some(ref mut origin) = origin {
+                        origin.outer_joined = true;
+                    }
+                }
+            }
+        }
+
+        let source_id = if source_tables.len() - first_table == 1 {
+            source_tables[first_table].source_id
+        } else {
+            self.new_source_id()
+        };
 
-                    Ok(((s, t), alias_identifier))
+        joins.push(FromWhereJoin::Left {
+            source_id: source_id,
+            joins: left_joins,
+            on: on
+        });
+
+        Ok(())
+    }
+
+    /// Compiles the ON of a join with the tables so far
+    fn join_condition<'b>(&mut self, on: ast::Expression, source_tables: &mut Vec<TableOrSubquery>,
+        table_aliases: &mut Vec<Identifier>, scope: &'b SourceScope<'b>, groups_info: &mut GroupsInfo)
+    -> Result<SExpression<'a, DB>, QueryPlanCompileError>
+    {
+        let tables = mem::replace(source_tables, Vec::new());
+        let aliases = mem::replace(table_aliases, Vec::new());
+
+        let join_scope = SourceScope::new(Some(scope), tables, aliases);
+        let on = self.ast_expression_to_sexpression(on, &join_scope, groups_info);
+        let (tables, aliases) = join_scope.into_tables();
+
+        *source_tables = tables;
+        *table_aliases = aliases;
+        on
+    }
+
+    /// Compiles a table, subquery or table function of FROM. All FROM
+    /// subqueries are nested, never correlated.
+    fn from_table<'b>(&mut self, ast_table_or_subquery: ast::TableOrSubquery, scope: &'b SourceScope<'b>,
+        groups_info: &mut GroupsInfo)
+    -> Result<(TableOrSubquery, FromWhereTableOrSubquery<'a, DB>, Identifier), QueryPlanCompileError>
+    {
+        match ast_table_or_subquery {
+            ast::TableOrSubquery::Subquery { subquery, alias } => {
+                let plan = try!(self.compile_subquery(*subquery, scope, groups_info));
+                let alias_identifier = try!(new_identifier(&alias));
+
+                let source_id = self.new_source_id();
+                self.origins.insert(source_id, plan.out_column_origins);
+
+                let s = TableOrSubquery {
+                    source_id: source_id,
+                    out_column_names: plan.out_column_names
+                };
+
+                let t = FromWhereTableOrSubquery::Subquery {
+                    source_id: source_id,
+                    expr: plan.expr
+                };
+
+                Ok((s, t, alias_identifier))
+            },
+            ast::TableOrSubquery::Table { table, alias } => {
+                let table_name_identifier = try!(new_identifier(&table.table_name));
+                let table = match self.db.find_table_by_name(&table_name_identifier) {
+                    Some(table) => table,
+                    None => return Err(QueryPlanCompileError::TableDoesNotExist(table_name_identifier))
+                };
+
+                let alias_identifier = if let Some(alias) = alias {
+                    try!(new_identifier(&alias))
+                } else {
+                    table_name_identifier
+                };
+
+                let source_id = self.new_source_id();
+
+                self.origins.insert(source_id, (0..table.get_column_count()).map(|column_offset| {
+                    Some(ColumnOrigin {
+                        table: table,
+                        column_offset: column_offset,
+                        outer_joined: false
+                    })
+                }).collect());
+
+                let s = TableOrSubquery {
+                    source_id: source_id,
+                    out_column_names: table.get_column_names()
+                };
+
+                let t = FromWhereTableOrSubquery::Table {
+                    source_id: source_id,
+                    table: table
+                };
+
+                Ok((s, t, alias_identifier))
+            },
+            ast::TableOrSubquery::Function { function, arguments, alias } => {
+                let function_identifier = try!(new_identifier(&function));
+                let column_names = match self.db.get_table_function_columns(&function_identifier) {
+                    Some(column_names) => column_names,
+                    None => return Err(QueryPlanCompileError::TableFunctionDoesNotExist(function_identifier))
+                };
+
+                // Like subqueries in FROM, the arguments can't refer to
+                // the tables beside the function
+                let arguments = try!(arguments.into_iter().map(|argument| {
+                    self.ast_expression_to_sexpression(argument, scope, groups_info)
+                }).collect());
+
+                let alias_identifier = if let Some(alias) = alias {
+                    try!(new_identifier(&alias))
+                } else {
+                    function_identifier.clone()
+                };
+
+                let source_id = self.new_source_id();
+
+                let s = TableOrSubquery {
+                    source_id: source_id,
+                    out_column_names: column_names
+                };
+
+                let t = FromWhereTableOrSubquery::Function {
+                    source_id: source_id,
+                    function: function_identifier,
+                    arguments: arguments
+                };
+
+                Ok((s, t, alias_identifier))
+            }
+        }
+    }
+
+    fn select<'b>(&mut self, result_columns: Vec<ast::SelectColumn>, scope: &'b SourceScope<'b>, groups_info: &mut GroupsInfo)
+    -> Result<(Vec<Identifier>, Vec<SExpression<'a, DB>>), QueryPlanCompileError>
+    {
+        let mut arbitrary_column_count = 0;
+
+        let mut arbitrary_column_name = || {
+            let s = format!("_{}", arbitrary_column_count);
+            arbitrary_column_count += 1;
+
+            Identifier::new(&s).unwrap()
+        };
+
+        let mut a: Vec<_> = Vec::new();
+
+        for c in result_columns {
+            match c {
+                ast::SelectColumn::AllColumns => {
+                    if scope.tables().is_empty() {
+                        return Err(QueryPlanCompileError::AllColumnsWithoutFrom);
+                    }
+
+                    groups_info.add_query_id(self.query_id);
+
+                    a.extend(scope.tables().iter().flat_map(|table| {
+                        let source_id = table.source_id;
+
+                        table.out_column_names.iter().enumerate().map(move |(i, name)| {
+                            (name.clone(), SExpression::ColumnField {
+                                source_id: source_id,
+                                column_offset: i as u32
+                            })
+                        })
+                    }));
                 },
-                ast::TableOrSubquery::Table { table, alias } => {
-                    let table_name_identifier = try!(new_identifier(&table.table_name));
-                    let table = match self.db.find_table_by_name(&table_name_identifier) {
-                        Some(table) => table,
-                        None => return Err(QueryPlanCompileError::TableDoesNotExist(table_name_identifier))
-                    };
+                ast::SelectColumn::Expr { expr, alias } => {
+                    let case_folding = self.db.get_identifier_case_folding();
 
-                    let alias_identifier = if let Some(alias) = alias {
-                        try!(new_identifier(&alias))
+                    let column_name = if let Some(alias) = alias {
+                        try!(new_identifier_with_case_folding(&alias, case_folding))
                     } else {
-                        table_name_identifier
+                        // if the expression is a simple identifier, make that
+                        // the column name. else, assign an arbitrary name.
+                        if let &ast::Expression::Ident(ref n) = &expr {
+                            try!(new_identifier_with_case_folding(n, case_folding))
+                        } else {
+                            arbitrary_column_name()
+                        }
                     };
 
-                    let source_id = self.new_source_id();
+                    let e = try!(self.ast_expression_to_sexpression(expr, &scope, groups_info));
+                    a.push((column_name, e));
+                }
+            }
+        }
 
-                    let s = TableOrSubquery {
-                        source_id: source_id,
-                        out_column_names: table.get_column_names()
-                    };
+        Ok(a.into_iter().unzip())
+    }
 
-                    let t = FromWhereTableOrSubquery::Table {
-                        source_id: source_id,
-                        table: table
-                    };
+    /// Compiles the ORDER BY terms into the offsets of their values in the
+    /// query's rows, and whether they're descending. A term can be the
+    /// position or the alias of a result column. Any other expression is
+    /// added to `select_exprs`, after the result columns.
+    fn order_by<'b>(&mut self, order_by: Vec<ast::OrderingTerm>, column_names: &[Identifier],
+        select_exprs: &mut Vec<SExpression<'a, DB>>, scope: &'b SourceScope<'b>, groups_info: &mut GroupsInfo)
+    -> Result<Vec<(u32, bool)>, QueryPlanCompileError>
+    {
+        let mut a = Vec::new();
+
+        for term in order_by {
+            let descending = term.order == ast::Order::Descending;
+
+            let column_offset = match term.expr {
+                ast::Expression::Number(ast::NumberLiteral::Integer(n)) => {
+                    if n >= 1 && n <= column_names.len() as u64 {
+                        Some(n as usize - 1)
+                    } else {
+                        return Err(QueryPlanCompileError::OrderByPosition(n));
+                    }
+                },
+                // A column of the FROM tables comes before an alias
+                ast::Expression::Ident(ref s) => {
+                    let identifier = try!(new_identifier(s));
+
+                    if scope.get_column_offset(&identifier).is_some() {
+                        None
+                    } else {
+                        column_names.iter().position(|name| name == &identifier)
+                    }
+                },
+                _ => None
+            };
+
+            let column_offset = match column_offset {
+                Some(column_offset) => column_offset,
+                None => {
+                    let e = try!(self.ast_expression_to_sexpression(term.expr, scope, groups_info));
+                    select_exprs.push(e);
+                    select_exprs.len() - 1
+                }
+            };
+
+            a.push((column_offset as u32, descending));
+        }
+
+        Ok(a)
+    }
+
+    /// Compiles a subquery of an expression or a FROM clause. The subquery
+    /// may refer to the columns in `scope`.
+    fn compile_subquery<'b>(&mut self, subquery: ast::SelectStatement, scope: &'b SourceScope<'b>,
+        groups_info: &mut GroupsInfo)
+    -> Result<QueryPlan<'a, DB>, QueryPlanCompileError>
+    {
+        if self.depth >= self.max_depth {
+            return Err(QueryPlanCompileError::NestedTooDeeply(self.max_depth));
+        }
+
+        let compiler = QueryCompiler {
+            query_id: self.new_query_id(),
+            depth: self.depth + 1,
+            max_depth: self.max_depth,
+            windows: None,
+            group_by: None,
+            origins: HashMap::new(),
+            db: self.db,
+            source_id_to_query_id: self.source_id_to_query_id,
+            query_to_aggregated_source_id: self.query_to_aggregated_source_id,
+            next_source_id: self.next_source_id,
+            next_query_id: self.next_query_id
+        };
+
+        compiler.compile(subquery, scope, groups_info)
+    }
+
+    fn window_function<'b>(&mut self, name: Identifier, arguments: Vec<ast::Expression>, window: ast::Window,
+        scope: &'b SourceScope<'b>, groups_info: &mut GroupsInfo)
+    -> Result<WindowFunction<'a, DB>, QueryPlanCompileError>
+    {
+        let op = match &name as &str {
+            "row_number" => WindowOp::RowNumber,
+            "rank" => WindowOp::Rank,
+            "dense_rank" => WindowOp::DenseRank,
+            "lag" => WindowOp::Lag,
+            "lead" => WindowOp::Lead,
+            "count" => WindowOp::Aggregate(AggregateOp::Count),
+            "avg" => WindowOp::Aggregate(AggregateOp::Avg),
+            "sum" => WindowOp::Aggregate(AggregateOp::Sum),
+            "min" => WindowOp::Aggregate(AggregateOp::Min),
+            "max" => WindowOp::Aggregate(AggregateOp::Max),
+            _ => return Err(QueryPlanCompileError::UnknownFunctionName(name))
+        };
+
+        if !op.accepts_argument_count(arguments.len()) {
+            return Err(QueryPlanCompileError::WindowFunctionArgumentCount(name));
+        }
+
+        let arguments = try!(arguments.into_iter().map(|expr| {
+            self.ast_expression_to_sexpression(expr, scope, groups_info)
+        }).collect());
+
+        let partition_by = try!(window.partition_by.into_iter().map(|expr| {
+            self.ast_expression_to_sexpression(expr, scope, groups_info)
+        }).collect());
+
+        let order_by = try!(window.order_by.into_iter().map(|term| {
+            let descending = match term.order {
+                ast::Order::Ascending => false,
+                ast::Order::Descending => true
+            };
 
-                    Ok(((s, t), alias_identifier))
-                }
-            }
+            self.ast_expression_to_sexpression(term.expr, scope, groups_info).map(|expr| (expr, descending))
         }).collect());
 
-        let (tables, table_aliases): (Vec<_>, _) = a.into_iter().unzip();
+        let frame = match window.frame {
+            Some(frame) => Some(try!(window_frame(frame))),
+            None => None
+        };
 
-        let (source_tables, fromwhere_tables) = tables.into_iter().unzip();
+        Ok(WindowFunction {
+            op: op,
+            arguments: arguments,
+            partition_by: partition_by,
+            order_by: order_by,
+            frame: frame
+        })
+    }
 
-        let new_scope = SourceScope::new(Some(scope), source_tables, table_aliases);
+    /// `arguments` must be the sequence's name, as a string literal.
+    fn sequence_op(&self, op: SequenceOp, function_name: Identifier, mut arguments: Vec<ast::Expression>)
+    -> Result<SExpression<'a, DB>, QueryPlanCompileError>
+    {
+        match arguments.pop() {
+            Some(ast::Expression::StringLiteral(ref name)) if arguments.is_empty() => {
+                let sequence = try!(new_identifier(name));
+
+                if self.db.has_sequence(&sequence) {
+                    Ok(SExpression::SequenceOp {
+                        op: op,
+                        sequence: sequence
+                    })
+                } else {
+                    Err(QueryPlanCompileError::SequenceDoesNotExist(sequence))
+                }
+            },
+            _ => Err(QueryPlanCompileError::SequenceFunctionRequiresName(function_name))
+        }
+    }
 
-        let where_expr = if let Some(where_expr) = where_expr {
-            Some(try!(self.ast_expression_to_sexpression(where_expr, &new_scope, groups_info)))
-        } else {
-            None
-        };
+    fn function_op<'b>(&mut self, op: FunctionOp, function_name: Identifier, arguments: Vec<ast::Expression>,
+        scope: &'b SourceScope<'b>, groups_info: &mut GroupsInfo)
+    -> Result<SExpression<'a, DB>, QueryPlanCompileError>
+    {
+        if !op.accepts_argument_count(arguments.len()) {
+            return Err(QueryPlanCompileError::FunctionArgumentCount(function_name));
+        }
 
-        Ok((new_scope, FromWhere {
-            tables: fromwhere_tables,
-            where_expr: where_expr
-        }))
+        let arguments: Result<Vec<_>, _> = arguments.into_iter().map(|argument| {
+            self.ast_expression_to_sexpression(argument, scope, groups_info)
+        }).collect();
+
+        Ok(SExpression::FunctionOp {
+            op: op,
+            arguments: try!(arguments)
+        })
     }
 
-    fn select<'b>(&mut self, result_columns: Vec<ast::SelectColumn>, scope: &'b SourceScope<'b>, groups_info: &mut GroupsInfo)
-    -> Result<(Vec<Identifier>, Vec<SExpression<'a, DB>>), QueryPlanCompileError>
+    /// Compiles a comparison of two rows of the same size, such as
+    /// `(a, b) > (1, 2)`
+    fn row_comparison<'b>(&mut self, lhs: ast::Expression, rhs: ast::Expression, op: ast::BinaryOp,
+        scope: &'b SourceScope<'b>, groups_info: &mut GroupsInfo)
+    -> Result<SExpression<'a, DB>, QueryPlanCompileError>
     {
-        let mut arbitrary_column_count = 0;
+        let op = ast_binaryop_to_sexpression_binaryop(op);
 
-        let mut arbitrary_column_name = || {
-            let s = format!("_{}", arbitrary_column_count);
-            arbitrary_column_count += 1;
+        if !op.is_comparison() {
+            return Err(QueryPlanCompileError::RowValueNotAllowed);
+        }
 
-            Identifier::new(&s).unwrap()
+        let (lhs, rhs) = match (lhs, rhs) {
+            (ast::Expression::Row(lhs), ast::Expression::Row(rhs)) => (lhs, rhs),
+            _ => return Err(QueryPlanCompileError::RowValueNotAllowed)
         };
 
-        let mut a: Vec<_> = Vec::new();
+        if lhs.len() != rhs.len() {
+            return Err(QueryPlanCompileError::RowValueSize(lhs.len(), rhs.len()));
+        }
 
-        for c in result_columns {
-            match c {
-                ast::SelectColumn::AllColumns => {
-                    groups_info.add_query_id(self.query_id);
+        let lhs = try!(self.row_values(lhs, scope, groups_info));
+        let rhs = try!(self.row_values(rhs, scope, groups_info));
 
-                    a.extend(scope.tables().iter().flat_map(|table| {
-                        let source_id = table.source_id;
+        Ok(SExpression::RowCompare {
+            op: op,
+            lhs: lhs,
+            rhs: rhs
+        })
+    }
 
-                        table.out_column_names.iter().enumerate().map(move |(i, name)| {
-                            (name.clone(), SExpression::ColumnField {
-                                source_id: source_id,
-                                column_offset: i as u32
-                            })
-                        })
-                    }));
-                },
-                ast::SelectColumn::Expr { expr, alias } => {
-                    let column_name = if let Some(alias) = alias {
-                        try!(new_identifier(&alias))
-                    } else {
-                        // if the expression is a simple identifier, make that
-                        // the column name. else, assign an arbitrary name.
-                        if let &ast::Expression::Ident(ref n) = &expr {
-                            try!(new_identifier(n))
-                        } else {
-                            arbitrary_column_name()
-                        }
-                    };
+    fn row_values<'b>(&mut self, exprs: Vec<ast::Expression>, scope: &'b SourceScope<'b>,
+        groups_info: &mut GroupsInfo)
+    -> Result<Vec<SExpression<'a, DB>>, QueryPlanCompileError>
+    {
+        exprs.into_iter().map(|expr| self.ast_expression_to_sexpression(expr, scope, groups_info)).collect()
+    }
 
-                    let e = try!(self.ast_expression_to_sexpression(expr, &scope, groups_info));
-                    a.push((column_name, e));
-                }
-            }
+    fn ast_expression_to_sexpression<'b>(&mut self, ast: ast::Expression, scope: &'b SourceScope<'b>,
+        groups_info: &mut GroupsInfo)
+    -> Result<SExpression<'a, DB>, QueryPlanCompileError>
+    {
+        // The parser limits nesting too, but a long chain of left-associative
+        // operators is deep without being nested
+        if self.depth >= self.max_depth {
+            return Err(QueryPlanCompileError::NestedTooDeeply(self.max_depth));
         }
 
-        Ok(a.into_iter().unzip())
+        // An expression that's also in GROUP BY is the group's value
+        let group_by_value = self.group_by.as_ref().and_then(|group_by| {
+            group_by.exprs.iter().position(|expr| expr == &ast).map(|i| {
+                (group_by.source_id, group_by.column_offset + i as u32)
+            })
+        });
+
+        if let Some((source_id, column_offset)) = group_by_value {
+            groups_info.add_query_id(self.query_id);
+
+            return Ok(SExpression::ColumnField {
+                source_id: source_id,
+                column_offset: column_offset
+            });
+        }
+
+        self.depth += 1;
+        let result = self.ast_expression_to_sexpression_nested(ast, scope, groups_info);
+        self.depth -= 1;
+        result
     }
 
-    fn ast_expression_to_sexpression<'b>(&mut self, ast: ast::Expression, scope: &'b SourceScope<'b>,
+    fn ast_expression_to_sexpression_nested<'b>(&mut self, ast: ast::Expression, scope: &'b SourceScope<'b>,
         groups_info: &mut GroupsInfo)
     -> Result<SExpression<'a, DB>, QueryPlanCompileError>
     {
@@ -546,6 +1614,15 @@ where DB: 'a, <DB as DatabaseInfo>::Table: 'a
                 })
             },
             ast::Expression::BinaryOp { lhs, rhs, op } => {
+                let is_row = match (&*lhs, &*rhs) {
+                    (&ast::Expression::Row(_), _) | (_, &ast::Expression::Row(_)) => true,
+                    _ => false
+                };
+
+                if is_row {
+                    return self.row_comparison(*lhs, *rhs, op, scope, groups_info);
+                }
+
                 let l = try!(self.ast_expression_to_sexpression(*lhs, scope, groups_info));
                 let r = try!(self.ast_expression_to_sexpression(*rhs, scope, groups_info));
 
@@ -561,28 +1638,26 @@ where DB: 'a, <DB as DatabaseInfo>::Table: 'a
                     Err(s) => Err(QueryPlanCompileError::BadStringLiteral(s.into_owned()))
                 }
             },
-            ast::Expression::Number(s) => {
-                match DB::ColumnValue::from_number_literal(s.into_cow()) {
-                    Ok(value) => Ok(SExpression::Value(value)),
-                    Err(s) => Err(QueryPlanCompileError::BadNumberLiteral(s.into_owned()))
+            ast::Expression::Number(ast::NumberLiteral::Integer(n)) => {
+                // Integers are signed unless they're too large
+                if n <= i64::MAX as u64 {
+                    Ok(SExpression::Value(DB::ColumnValue::from_i64(n as i64)))
+                } else {
+                    Ok(SExpression::Value(DB::ColumnValue::from_u64(n)))
                 }
             },
+            ast::Expression::Number(ast::NumberLiteral::Float(n)) => {
+                Ok(SExpression::Value(DB::ColumnValue::from_f64(n)))
+            },
             ast::Expression::Null => {
                 Ok(SExpression::Value(ColumnValueOpsExt::null()))
             },
+            ast::Expression::Placeholder(_) => {
+                Err(QueryPlanCompileError::UnboundPlaceholder)
+            },
             ast::Expression::Subquery(subquery) => {
                 let source_id = self.new_source_id();
-
-                let compiler = QueryCompiler {
-                    query_id: self.new_query_id(),
-                    db: self.db,
-                    source_id_to_query_id: self.source_id_to_query_id,
-                    query_to_aggregated_source_id: self.query_to_aggregated_source_id,
-                    next_source_id: self.next_source_id,
-                    next_query_id: self.next_query_id
-                };
-
-                let plan = try!(compiler.compile(*subquery, scope, groups_info));
+                let plan = try!(self.compile_subquery(*subquery, scope, groups_info));
 
                 Ok(SExpression::Map {
                     source_id: source_id,
@@ -593,7 +1668,39 @@ where DB: 'a, <DB as DatabaseInfo>::Table: 'a
                     })
                 })
             },
-            ast::Expression::FunctionCall { name, arguments } => {
+            ast::Expression::Exists { subquery, negated } => {
+                let plan = try!(self.compile_subquery(*subquery, scope, groups_info));
+
+                Ok(SExpression::SemiJoin {
+                    anti: negated,
+                    values: Vec::new(),
+                    query: Box::new(plan.expr)
+                })
+            },
+            ast::Expression::InSubquery { expr, subquery, negated } => {
+                // A row is compared with all of the subquery's columns
+                let exprs = match *expr {
+                    ast::Expression::Row(exprs) => exprs,
+                    expr => vec![expr]
+                };
+
+                let values = try!(self.row_values(exprs, scope, groups_info));
+                let plan = try!(self.compile_subquery(*subquery, scope, groups_info));
+
+                if plan.out_column_names.len() != values.len() {
+                    return Err(QueryPlanCompileError::InSubqueryColumnCount(values.len(), plan.out_column_names.len()));
+                }
+
+                Ok(SExpression::SemiJoin {
+                    anti: negated,
+                    values: values,
+                    query: Box::new(plan.expr)
+                })
+            },
+            ast::Expression::Row(_) => {
+                Err(QueryPlanCompileError::RowValueNotAllowed)
+            },
+            ast::Expression::FunctionCall { name, arguments, distinct } => {
                 let ident = try!(new_identifier(&name));
 
                 macro_rules! aggregate {
@@ -615,6 +1722,7 @@ where DB: 'a, <DB as DatabaseInfo>::Table: 'a
 
                                     Ok(SExpression::AggregateOp {
                                         op: $op,
+                                        distinct: distinct,
                                         source_id: source_id,
                                         value: Box::new(value)
                                     })
@@ -636,9 +1744,57 @@ where DB: 'a, <DB as DatabaseInfo>::Table: 'a
                     "sum" => aggregate!(AggregateOp::Sum),
                     "min" => aggregate!(AggregateOp::Min),
                     "max" => aggregate!(AggregateOp::Max),
+                    _ if distinct => Err(QueryPlanCompileError::DistinctNotAggregate(ident)),
+                    "nextval" => self.sequence_op(SequenceOp::NextVal, ident, arguments),
+                    "currval" => self.sequence_op(SequenceOp::CurrVal, ident, arguments),
+                    "current_setting" => current_setting(arguments),
+                    "length" => self.function_op(FunctionOp::Length, ident, arguments, scope, groups_info),
+                    "hex" => self.function_op(FunctionOp::Hex, ident, arguments, scope, groups_info),
+                    "unhex" => self.function_op(FunctionOp::Unhex, ident, arguments, scope, groups_info),
+                    "coalesce" => self.function_op(FunctionOp::Coalesce, ident, arguments, scope, groups_info),
+                    "nullif" => self.function_op(FunctionOp::NullIf, ident, arguments, scope, groups_info),
+                    "ifnull" => self.function_op(FunctionOp::IfNull, ident, arguments, scope, groups_info),
                     _ => Err(QueryPlanCompileError::UnknownFunctionName(ident))
                 }
             },
+            ast::Expression::WindowFunctionCall { name, arguments, window } => {
+                let ident = try!(new_identifier(&name));
+
+                // Window functions can't be nested in each other
+                let mut windows = match self.windows.take() {
+                    Some(windows) => windows,
+                    None => return Err(QueryPlanCompileError::WindowFunctionNotAllowed(ident))
+                };
+
+                let function = self.window_function(ident, arguments, *window, scope, groups_info);
+
+                let result = function.map(|function| {
+                    let source_id = match windows.source_id {
+                        Some(source_id) => source_id,
+                        None => {
+                            let source_id = self.new_source_id();
+                            windows.source_id = Some(source_id);
+                            source_id
+                        }
+                    };
+
+                    let column_offset = windows.column_offset + windows.functions.len() as u32;
+                    windows.functions.push(function);
+
+                    SExpression::ColumnField {
+                        source_id: source_id,
+                        column_offset: column_offset
+                    }
+                });
+
+                self.windows = Some(windows);
+
+                if result.is_ok() {
+                    groups_info.add_query_id(self.query_id);
+                }
+
+                result
+            },
             ast::Expression::FunctionCallAggregateAll { name } => {
                 let ident = try!(new_identifier(&name));
 
@@ -658,12 +1814,209 @@ where DB: 'a, <DB as DatabaseInfo>::Table: 'a
     }
 }
 
+/// Yields every column of the tables in the scope, in order
+fn yield_every_column<'a, DB>(scope: &SourceScope) -> SExpression<'a, DB>
+where DB: DatabaseInfo + 'a, <DB as DatabaseInfo>::Table: 'a
+{
+    SExpression::Yield {
+        fields: scope.tables().iter().flat_map(|table| {
+            let source_id = table.source_id;
+
+            (0..table.out_column_names.len() as u32).map(move |column_offset| {
+                SExpression::ColumnField {
+                    source_id: source_id,
+                    column_offset: column_offset
+                }
+            })
+        }).collect()
+    }
+}
+
+/// Maps the columns of the tables in the scope to the columns of a source
+/// whose rows were yielded by `yield_every_column`.
+fn mapping_to_source(scope: &SourceScope, source_id: u32) -> HashMap<u32, Mapping> {
+    let mut c = 0;
+
+    scope.tables().iter().map(|table| {
+        let m = Mapping {
+            source_id: source_id,
+            column_offset: c
+        };
+
+        c += table.out_column_names.len() as u32;
+
+        (table.source_id, m)
+    }).collect()
+}
+
 #[derive(Debug)]
 struct Mapping {
     source_id: u32,
     column_offset: u32
 }
 
+/// Whether the expression has an aggregate function over the source
+fn aggregates_source<'a, DB>(expr: &mut SExpression<'a, DB>, source_id: u32) -> bool
+where DB: DatabaseInfo + 'a, <DB as DatabaseInfo>::Table: 'a
+{
+    match expr {
+        &mut SExpression::AggregateOp { source_id: s, .. } |
+        &mut SExpression::CountAll { source_id: s } if s == source_id => true,
+        _ => {
+            let mut found = false;
+            iter_mut_expressions_in_expression(expr, |e| found = found || aggregates_source(e, source_id));
+            found
+        }
+    }
+}
+
+/// Points the ORDER BY terms of a `SELECT DISTINCT` that name a result
+/// column's table column at that result column, and drops the expressions
+/// that were added for them. Any other term is an error, because the rows
+/// left out as duplicates may have had other values for it.
+fn order_by_result_columns<'a, DB>(order_by: &mut [(u32, bool)], select_exprs: &mut Vec<SExpression<'a, DB>>,
+    column_count: usize)
+-> Result<(), QueryPlanCompileError>
+where DB: DatabaseInfo + 'a, <DB as DatabaseInfo>::Table: 'a
+{
+    for term in order_by.iter_mut() {
+        let offset = term.0 as usize;
+
+        if offset < column_count {
+            continue;
+        }
+
+        let result_column = match &select_exprs[offset] {
+            &SExpression::ColumnField { source_id, column_offset } => {
+                select_exprs[..column_count].iter().position(|expr| match expr {
+                    &SExpression::ColumnField { source_id: s, column_offset: c } => {
+                        s == source_id && c == column_offset
+                    },
+                    _ => false
+                })
+            },
+            _ => None
+        };
+
+        match result_column {
+            Some(result_column) => term.0 = result_column as u32,
+            None => return Err(QueryPlanCompileError::DistinctOrderBy)
+        }
+    }
+
+    select_exprs.truncate(column_count);
+
+    Ok(())
+}
+
+/// `SELECT count(*) FROM t` doesn't need to scan `t`, because tables know
+/// how many rows they have. This rewrites the plan for such queries.
+/// Filters, joins, groups and other aggregates still need a scan.
+fn count_rows_without_scan<'a, DB>(expr: SExpression<'a, DB>) -> SExpression<'a, DB>
+where DB: DatabaseInfo + 'a, <DB as DatabaseInfo>::Table: 'a
+{
+    let count_table = match &expr {
+        &SExpression::TempGroupBy { source_id, ref yield_in_fn, ref group_by_values, ref yield_out_fn } => {
+            let table = match &**yield_in_fn {
+                &SExpression::Scan { table, ref yield_fn, .. } => match &**yield_fn {
+                    &SExpression::Yield { .. } => Some(table),
+                    _ => None
+                },
+                _ => None
+            };
+
+            let count_all_fields = match &**yield_out_fn {
+                &SExpression::Yield { ref fields } => {
+                    let all = fields.iter().all(|field| match field {
+                        &SExpression::CountAll { source_id: s } => s == source_id,
+                        _ => false
+                    });
+
+                    if all { Some(fields.len()) } else { None }
+                },
+                _ => None
+            };
+
+            match (table, count_all_fields) {
+                (Some(table), Some(n)) if group_by_values.is_empty() => Some((table, n)),
+                _ => None
+            }
+        },
+        _ => None
+    };
+
+    match count_table {
+        Some((table, field_count)) => SExpression::Yield {
+            fields: (0..field_count).map(|_| SExpression::RowCount { table: table }).collect()
+        },
+        None => expr
+    }
+}
+
+/// A GROUP BY position (`GROUP BY 1`) or the alias of a result column is
+/// replaced with that column's expression. A column of the FROM tables takes
+/// precedence over an alias of the same name.
+/// `arguments` must be the variable's name, as a string literal. The variable
+/// doesn't need to be set until the expression is evaluated.
+fn current_setting<'a, DB>(mut arguments: Vec<ast::Expression>) -> Result<SExpression<'a, DB>, QueryPlanCompileError>
+where DB: DatabaseInfo + 'a, <DB as DatabaseInfo>::Table: 'a
+{
+    match arguments.pop() {
+        Some(ast::Expression::StringLiteral(ref name)) if arguments.is_empty() => {
+            Ok(SExpression::SessionVariable {
+                name: try!(new_identifier(name))
+            })
+        },
+        _ => Err(QueryPlanCompileError::CurrentSettingRequiresName)
+    }
+}
+
+fn group_by_expression(expr: ast::Expression, result_columns: &[ast::SelectColumn], scope: &SourceScope)
+-> Result<ast::Expression, QueryPlanCompileError>
+{
+    match expr {
+        ast::Expression::Number(ast::NumberLiteral::Integer(n)) => {
+            // Positions after a `*` would depend on the tables' columns
+            let column = if n >= 1 && n <= result_columns.len() as u64 {
+                let columns = &result_columns[0..n as usize];
+
+                if columns.iter().any(|column| column == &ast::SelectColumn::AllColumns) {
+                    None
+                } else {
+                    columns.last()
+                }
+            } else {
+                None
+            };
+
+            match column {
+                Some(&ast::SelectColumn::Expr { ref expr, .. }) => Ok(expr.clone()),
+                _ => Err(QueryPlanCompileError::GroupByPosition(n))
+            }
+        },
+        ast::Expression::Ident(s) => {
+            let identifier = try!(new_identifier(&s));
+
+            if scope.get_column_offset(&identifier).is_some() {
+                return Ok(ast::Expression::Ident(s));
+            }
+
+            let aliased = result_columns.iter().filter_map(|column| match column {
+                &ast::SelectColumn::Expr { ref expr, alias: Some(ref alias) } => {
+                    match Identifier::new(alias) {
+                        Some(ref alias) if alias == &identifier => Some(expr.clone()),
+                        _ => None
+                    }
+                },
+                _ => None
+            }).nth(0);
+
+            Ok(aliased.unwrap_or(ast::Expression::Ident(s)))
+        },
+        expr => Ok(expr)
+    }
+}
+
 fn remap_columns_in_sexpression<'a, DB>(expr: &mut SExpression<'a, DB>, mapping: &HashMap<u32, Mapping>)
 where DB: DatabaseInfo + 'a, <DB as DatabaseInfo>::Table: 'a
 {
@@ -687,7 +2040,14 @@ where DB: DatabaseInfo + 'a, <DB as DatabaseInfo>::Table: 'a, F: FnMut(&mut SExp
         &mut SExpression::Scan { ref mut yield_fn, .. } => {
             cb(yield_fn);
         },
-        &mut SExpression::Map { ref mut yield_in_fn, ref mut yield_out_fn, .. } => {
+        &mut SExpression::TableFunction { ref mut arguments, ref mut yield_fn, .. } => {
+            for v in arguments {
+                cb(v);
+            }
+            cb(yield_fn);
+        },
+        &mut SExpression::Map { ref mut yield_in_fn, ref mut yield_out_fn, .. } |
+        &mut SExpression::LeftJoin { ref mut yield_in_fn, ref mut yield_out_fn, .. } => {
             cb(yield_in_fn);
             cb(yield_out_fn);
         },
@@ -698,6 +2058,31 @@ where DB: DatabaseInfo + 'a, <DB as DatabaseInfo>::Table: 'a, F: FnMut(&mut SExp
             }
             cb(yield_out_fn);
         },
+        &mut SExpression::Window { ref mut yield_in_fn, ref mut functions, ref mut yield_out_fn, .. } => {
+            cb(yield_in_fn);
+            for function in functions {
+                iter_mut_expressions_in_window_function(function, &mut cb);
+            }
+            cb(yield_out_fn);
+        },
+        &mut SExpression::HashJoin { ref mut keys, ref mut yield_in_fn, ref mut yield_out_fn, .. } => {
+            cb(yield_in_fn);
+            for v in keys {
+                cb(v);
+            }
+            cb(yield_out_fn);
+        },
+        &mut SExpression::HashProbe { ref mut values, ref mut yield_fn, .. } => {
+            for v in values {
+                cb(v);
+            }
+            cb(yield_fn);
+        },
+        &mut SExpression::Distinct { ref mut yield_in_fn, .. } |
+        &mut SExpression::Sort { ref mut yield_in_fn, .. } |
+        &mut SExpression::Limit { ref mut yield_in_fn, .. } => {
+            cb(yield_in_fn);
+        },
         &mut SExpression::Yield { ref mut fields } => {
             for v in fields {
                 cb(v);
@@ -717,15 +2102,49 @@ where DB: DatabaseInfo + 'a, <DB as DatabaseInfo>::Table: 'a, F: FnMut(&mut SExp
             cb(lhs);
             cb(rhs);
         },
+        &mut SExpression::UnaryOp { ref mut expr, .. } => {
+            cb(expr);
+        },
+        &mut SExpression::FunctionOp { ref mut arguments, .. } => {
+            for v in arguments {
+                cb(v);
+            }
+        },
         &mut SExpression::AggregateOp {
             ref mut value, ..
         } => {
             cb(value);
         },
+        &mut SExpression::RowCompare { ref mut lhs, ref mut rhs, .. } => {
+            for v in lhs.iter_mut().chain(rhs.iter_mut()) {
+                cb(v);
+            }
+        },
+        &mut SExpression::SemiJoin {
+            ref mut values,
+            ref mut query, ..
+        } => {
+            for v in values {
+                cb(v);
+            }
+            cb(query);
+        },
         _ => ()
     }
 }
 
+fn iter_mut_expressions_in_window_function<'a, DB, F>(function: &mut WindowFunction<'a, DB>, mut cb: F)
+where DB: DatabaseInfo + 'a, <DB as DatabaseInfo>::Table: 'a, F: FnMut(&mut SExpression<'a, DB>)
+{
+    for v in function.arguments.iter_mut().chain(function.partition_by.iter_mut()) {
+        cb(v);
+    }
+
+    for &mut (ref mut v, _) in function.order_by.iter_mut() {
+        cb(v);
+    }
+}
+
 fn ast_binaryop_to_sexpression_binaryop(ast: ast::BinaryOp) -> BinaryOp {
     match ast {
         ast::BinaryOp::Equal => BinaryOp::Equal,
@@ -752,6 +2171,30 @@ fn ast_unaryop_to_sexpression_unaryop(ast: ast::UnaryOp) -> UnaryOp {
     }
 }
 
+fn window_frame(frame: ast::Frame) -> Result<WindowFrame, QueryPlanCompileError> {
+    fn bound(bound: ast::FrameBound) -> Result<FrameBound, QueryPlanCompileError> {
+        let rows = |rows: String| rows.parse().map_err(|_| QueryPlanCompileError::WindowFrameOffset(rows.clone()));
+
+        Ok(match bound {
+            ast::FrameBound::UnboundedPreceding => FrameBound::UnboundedPreceding,
+            ast::FrameBound::Preceding(n) => FrameBound::Preceding(try!(rows(n))),
+            ast::FrameBound::CurrentRow => FrameBound::CurrentRow,
+            ast::FrameBound::Following(n) => FrameBound::Following(try!(rows(n))),
+            ast::FrameBound::UnboundedFollowing => FrameBound::UnboundedFollowing
+        })
+    }
+
+    match (try!(bound(frame.start)), try!(bound(frame.end))) {
+        (FrameBound::UnboundedFollowing, _) | (_, FrameBound::UnboundedPreceding) => {
+            Err(QueryPlanCompileError::WindowFrameBounds)
+        },
+        (start, end) => Ok(WindowFrame {
+            start: start,
+            end: end
+        })
+    }
+}
+
 impl<'a, DB: DatabaseInfo> fmt::Display for QueryPlan<'a, DB>
 where <DB as DatabaseInfo>::Table: 'a
 {
@@ -763,3 +2206,45 @@ where <DB as DatabaseInfo>::Table: 'a
         self.expr.fmt(f)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::{group_by_expression, QueryPlanCompileError};
+    use super::source::SourceScope;
+    use sqlsyntax::ast::{SelectColumn, SelectStatement, Statement};
+    use sqlsyntax::parse_statement;
+
+    fn select(query: &str) -> SelectStatement {
+        match parse_statement(query).unwrap() {
+            Statement::Select(select) => select,
+            _ => panic!()
+        }
+    }
+
+    /// The GROUP BY expressions of a query as positions in `SELECT a + 1, b`,
+    /// or the bad GROUP BY positions
+    fn group_by(query: &str) -> Vec<Result<Option<usize>, u64>> {
+        let scope = SourceScope::new(None, Vec::new(), Vec::new());
+        let stmt = select(query);
+        let expected = select("SELECT a + 1, b").result_columns;
+        let result_columns = &stmt.result_columns;
+
+        stmt.group_by.into_iter().map(|expr| {
+            match group_by_expression(expr, result_columns, &scope) {
+                Ok(expr) => Ok(expected.iter().position(|column| match column {
+                    &SelectColumn::Expr { expr: ref e, .. } => e == &expr,
+                    _ => false
+                })),
+                Err(QueryPlanCompileError::GroupByPosition(n)) => Err(n),
+                Err(e) => panic!("{}", e)
+            }
+        }).collect()
+    }
+
+    #[test]
+    fn test_group_by_expression() {
+        assert_eq!(group_by("SELECT a + 1 AS x, b FROM t GROUP BY 2, 1, x, b"), vec![Ok(Some(1)), Ok(Some(0)), Ok(Some(0)), Ok(Some(1))]);
+        assert_eq!(group_by("SELECT a + 1 AS x FROM t GROUP BY 0, 2, y, a + 1"), vec![Err(0), Err(2), Ok(None), Ok(Some(0))]);
+        assert_eq!(group_by("SELECT *, a + 1 FROM t GROUP BY 2"), vec![Err(2)]);
+    }
+}
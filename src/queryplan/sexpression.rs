@@ -1,4 +1,5 @@
 use databaseinfo::{DatabaseInfo, TableInfo};
+use identifier::Identifier;
 
 use std::fmt;
 
@@ -11,17 +12,85 @@ where <DB as DatabaseInfo>::Table: 'a
         source_id: u32,
         yield_fn: Box<SExpression<'a, DB>>
     },
+    /// Gives each row of a table function, such as `generate_series`, to
+    /// `yield_fn` as a row of `source_id`
+    TableFunction {
+        function: Identifier,
+        source_id: u32,
+        arguments: Vec<SExpression<'a, DB>>,
+        yield_fn: Box<SExpression<'a, DB>>
+    },
     Map {
         source_id: u32,
         yield_in_fn: Box<SExpression<'a, DB>>,
         yield_out_fn: Box<SExpression<'a, DB>>
     },
+    /// Runs `yield_out_fn` for each row of `yield_in_fn`, as a row of
+    /// `source_id`, like `Map`. If `yield_in_fn` yields no rows,
+    /// `yield_out_fn` runs once with a row of `column_count` NULLs, for
+    /// LEFT JOIN.
+    LeftJoin {
+        source_id: u32,
+        column_count: u32,
+        yield_in_fn: Box<SExpression<'a, DB>>,
+        yield_out_fn: Box<SExpression<'a, DB>>
+    },
+    /// Buffers the rows of `yield_in_fn` in a hash table, by the values of
+    /// `keys` for each of them as a row of `source_id`, then runs
+    /// `yield_out_fn`. Rows with a NULL key are left out, as they can't be
+    /// equal to anything.
+    HashJoin {
+        source_id: u32,
+        keys: Vec<SExpression<'a, DB>>,
+        yield_in_fn: Box<SExpression<'a, DB>>,
+        yield_out_fn: Box<SExpression<'a, DB>>
+    },
+    /// Runs `yield_fn` for each row in the hash table of the `HashJoin` of
+    /// `source_id` whose keys equal `values`, as a row of `source_id`. It
+    /// takes the place of a scan of the table.
+    HashProbe {
+        source_id: u32,
+        values: Vec<SExpression<'a, DB>>,
+        yield_fn: Box<SExpression<'a, DB>>
+    },
     TempGroupBy {
         source_id: u32,
         yield_in_fn: Box<SExpression<'a, DB>>,
         group_by_values: Vec<SExpression<'a, DB>>,
         yield_out_fn: Box<SExpression<'a, DB>>
     },
+    /// Buffers the rows of `yield_in_fn`, and computes each window function
+    /// over them. Each row is then given to `yield_out_fn` as a row of
+    /// `source_id`: the row's columns, followed by the window functions'
+    /// results for it.
+    Window {
+        source_id: u32,
+        yield_in_fn: Box<SExpression<'a, DB>>,
+        functions: Vec<WindowFunction<'a, DB>>,
+        yield_out_fn: Box<SExpression<'a, DB>>
+    },
+    /// Yields the rows of `yield_in_fn` whose first `column_count` columns
+    /// aren't equal to those of an earlier row, for SELECT DISTINCT. NULLs
+    /// are equal to each other here, as they are in GROUP BY.
+    Distinct {
+        yield_in_fn: Box<SExpression<'a, DB>>,
+        column_count: u32
+    },
+    /// Buffers the rows of `yield_in_fn` and yields them sorted, without the
+    /// columns after the first `column_count`. Each of `order_by` is the
+    /// offset of a sort key in the row, and whether it's descending.
+    Sort {
+        yield_in_fn: Box<SExpression<'a, DB>>,
+        column_count: u32,
+        order_by: Vec<(u32, bool)>
+    },
+    /// Yields the rows of `yield_in_fn` after skipping the first `offset`,
+    /// and stops it once `count` rows have been yielded.
+    Limit {
+        yield_in_fn: Box<SExpression<'a, DB>>,
+        count: u64,
+        offset: u64
+    },
     Yield {
         fields: Vec<SExpression<'a, DB>>
     },
@@ -42,14 +111,52 @@ where <DB as DatabaseInfo>::Table: 'a
         lhs: Box<SExpression<'a, DB>>,
         rhs: Box<SExpression<'a, DB>>
     },
+    /// Compares two rows of the same size. `=` and `<>` compare each pair
+    /// of values. The other operators order the rows by their first pair of
+    /// values that aren't equal, and are NULL if that pair has a NULL.
+    RowCompare {
+        op: BinaryOp,
+        lhs: Vec<SExpression<'a, DB>>,
+        rhs: Vec<SExpression<'a, DB>>
+    },
+    /// An aggregate function over the group of `source_id`. If `distinct`,
+    /// each value is only fed to it once.
     AggregateOp {
         op: AggregateOp,
+        distinct: bool,
         source_id: u32,
         value: Box<SExpression<'a, DB>>
     },
     CountAll {
         source_id: u32
     },
+    /// The amount of rows in a table, without scanning it
+    RowCount {
+        table: &'a <DB as DatabaseInfo>::Table
+    },
+    SequenceOp {
+        op: SequenceOp,
+        sequence: Identifier
+    },
+    /// The value of a session variable when the expression is evaluated
+    SessionVariable {
+        name: Identifier
+    },
+    FunctionOp {
+        op: FunctionOp,
+        arguments: Vec<SExpression<'a, DB>>
+    },
+    /// Whether the query yields a row, for EXISTS. If there are values,
+    /// whether the query's columns have those values, for IN.
+    ///
+    /// The query stops at the first match. An anti-join is the opposite:
+    /// NOT EXISTS or NOT IN. Like comparisons, IN and NOT IN are NULL if
+    /// there's no match but the value or any of the query's values is NULL.
+    SemiJoin {
+        anti: bool,
+        values: Vec<SExpression<'a, DB>>,
+        query: Box<SExpression<'a, DB>>
+    },
     Value(<DB as DatabaseInfo>::ColumnValue)
 }
 
@@ -81,6 +188,15 @@ where <DB as DatabaseInfo>::Table: 'a
                 try!(yield_fn.format(f, indent + 1));
                 write!(f, ")")
             },
+            &SExpression::TableFunction { ref function, source_id, ref arguments, ref yield_fn } => {
+                try!(writeln!(f, "(table-function `{}` :source-id {}", function, source_id));
+                for argument in arguments {
+                    try!(argument.format(f, indent + 1));
+                    try!(writeln!(f, ""));
+                }
+                try!(yield_fn.format(f, indent + 1));
+                write!(f, ")")
+            },
             &SExpression::Map { source_id, ref yield_in_fn, ref yield_out_fn } => {
                 try!(writeln!(f, "(map :source-id {}", source_id));
                 try!(yield_in_fn.format(f, indent + 1));
@@ -88,6 +204,36 @@ where <DB as DatabaseInfo>::Table: 'a
                 try!(yield_out_fn.format(f, indent + 1));
                 write!(f, ")")
             },
+            &SExpression::LeftJoin { source_id, column_count, ref yield_in_fn, ref yield_out_fn } => {
+                try!(writeln!(f, "(left-join :source-id {} :column-count {}", source_id, column_count));
+                try!(yield_in_fn.format(f, indent + 1));
+                try!(writeln!(f, ""));
+                try!(yield_out_fn.format(f, indent + 1));
+                write!(f, ")")
+            },
+            &SExpression::HashJoin { source_id, ref keys, ref yield_in_fn, ref yield_out_fn } => {
+                try!(writeln!(f, "(hash-join :source-id {}", source_id));
+                try!(yield_in_fn.format(f, indent + 1));
+                try!(writeln!(f, ""));
+                write_indent!(indent+1);
+                try!(write!(f, "(keys"));
+                for key in keys {
+                    try!(writeln!(f, ""));
+                    try!(key.format(f, indent + 2));
+                }
+                try!(writeln!(f, ")"));
+                try!(yield_out_fn.format(f, indent + 1));
+                write!(f, ")")
+            },
+            &SExpression::HashProbe { source_id, ref values, ref yield_fn } => {
+                try!(writeln!(f, "(hash-probe :source-id {}", source_id));
+                for value in values {
+                    try!(value.format(f, indent + 1));
+                    try!(writeln!(f, ""));
+                }
+                try!(yield_fn.format(f, indent + 1));
+                write!(f, ")")
+            },
             &SExpression::TempGroupBy { source_id, ref yield_in_fn, ref group_by_values, ref yield_out_fn } => {
                 try!(writeln!(f, "(temp-group-by :source-id {}", source_id));
                 try!(yield_in_fn.format(f, indent + 1));
@@ -102,6 +248,39 @@ where <DB as DatabaseInfo>::Table: 'a
                 try!(yield_out_fn.format(f, indent + 1));
                 write!(f, ")")
             },
+            &SExpression::Window { source_id, ref yield_in_fn, ref functions, ref yield_out_fn } => {
+                try!(writeln!(f, "(window :source-id {}", source_id));
+                try!(yield_in_fn.format(f, indent + 1));
+                try!(writeln!(f, ""));
+                for function in functions {
+                    try!(function.format(f, indent + 1));
+                    try!(writeln!(f, ""));
+                }
+                try!(yield_out_fn.format(f, indent + 1));
+                write!(f, ")")
+            },
+            &SExpression::Distinct { ref yield_in_fn, column_count } => {
+                try!(writeln!(f, "(distinct :column-count {}", column_count));
+                try!(yield_in_fn.format(f, indent + 1));
+                write!(f, ")")
+            },
+            &SExpression::Sort { ref yield_in_fn, column_count, ref order_by } => {
+                try!(write!(f, "(sort :column-count {} :order-by (", column_count));
+                for (i, &(column_offset, descending)) in order_by.iter().enumerate() {
+                    if i > 0 {
+                        try!(write!(f, " "));
+                    }
+                    try!(write!(f, "{}{}", column_offset, if descending { " :desc" } else { "" }));
+                }
+                try!(writeln!(f, ")"));
+                try!(yield_in_fn.format(f, indent + 1));
+                write!(f, ")")
+            },
+            &SExpression::Limit { ref yield_in_fn, count, offset } => {
+                try!(writeln!(f, "(limit :count {} :offset {}", count, offset));
+                try!(yield_in_fn.format(f, indent + 1));
+                write!(f, ")")
+            },
             &SExpression::Yield { ref fields } => {
                 try!(write!(f, "(yield"));
                 for field in fields {
@@ -127,19 +306,59 @@ where <DB as DatabaseInfo>::Table: 'a
                 try!(rhs.format(f, indent + 1));
                 write!(f, ")")
             },
+            &SExpression::RowCompare { ref op, ref lhs, ref rhs } => {
+                try!(write!(f, "(row-compare {}", op.sigil()));
+                for row in [lhs, rhs].iter() {
+                    try!(writeln!(f, ""));
+                    write_indent!(indent + 1);
+                    try!(write!(f, "(row"));
+                    for value in row.iter() {
+                        try!(writeln!(f, ""));
+                        try!(value.format(f, indent + 2));
+                    }
+                    try!(write!(f, ")"));
+                }
+                write!(f, ")")
+            },
             &SExpression::UnaryOp { ref op, ref expr } => {
                 try!(writeln!(f, "({} ", op.name()));
                 try!(expr.format(f, indent + 1));
                 write!(f, ")")
             },
-            &SExpression::AggregateOp { ref op, source_id, ref value } => {
-                try!(writeln!(f, "({} :source-id {} ", op.name(), source_id));
+            &SExpression::AggregateOp { ref op, distinct, source_id, ref value } => {
+                try!(writeln!(f, "({} :source-id {}{} ", op.name(), source_id, if distinct { " :distinct" } else { "" }));
                 try!(value.format(f, indent + 1));
                 write!(f, ")")
             },
             &SExpression::CountAll { source_id } => {
                 write!(f, "(count-all :source-id {})", source_id)
             },
+            &SExpression::RowCount { table } => {
+                write!(f, "(row-count `{}`)", table.get_name())
+            },
+            &SExpression::SequenceOp { ref op, ref sequence } => {
+                write!(f, "({} `{}`)", op.name(), sequence)
+            },
+            &SExpression::SessionVariable { ref name } => {
+                write!(f, "(current-setting `{}`)", name)
+            },
+            &SExpression::FunctionOp { ref op, ref arguments } => {
+                try!(write!(f, "({}", op.name()));
+                for argument in arguments {
+                    try!(writeln!(f, ""));
+                    try!(argument.format(f, indent + 1));
+                }
+                write!(f, ")")
+            },
+            &SExpression::SemiJoin { anti, ref values, ref query } => {
+                try!(writeln!(f, "({}", if anti { "anti-join" } else { "semi-join" }));
+                for value in values {
+                    try!(value.format(f, indent + 1));
+                    try!(writeln!(f, ""));
+                }
+                try!(query.format(f, indent + 1));
+                write!(f, ")")
+            },
             &SExpression::Value(ref v) => {
                 write!(f, "{}", v)
             }
@@ -147,6 +366,143 @@ where <DB as DatabaseInfo>::Table: 'a
     }
 }
 
+/// A window function, such as `rank() OVER (PARTITION BY a ORDER BY b)`.
+/// Its expressions are evaluated for each row of the window's source.
+#[derive(Clone)]
+pub struct WindowFunction<'a, DB: DatabaseInfo>
+where <DB as DatabaseInfo>::Table: 'a
+{
+    pub op: WindowOp,
+    pub arguments: Vec<SExpression<'a, DB>>,
+    pub partition_by: Vec<SExpression<'a, DB>>,
+    /// Each value to order by, and whether it's in descending order
+    pub order_by: Vec<(SExpression<'a, DB>, bool)>,
+    /// Only used by aggregate functions. Without a frame, they're computed
+    /// over the whole partition, or if it's ordered, up to the current row
+    /// and its peers.
+    pub frame: Option<WindowFrame>
+}
+
+impl<'a, DB: DatabaseInfo> WindowFunction<'a, DB>
+where <DB as DatabaseInfo>::Table: 'a
+{
+    fn format(&self, f: &mut fmt::Formatter, indent: usize) -> Result<(), fmt::Error> {
+        macro_rules! write_indent {
+            ($i:expr) => (
+                for _ in 0..$i {
+                    try!(write!(f, "  "));
+                }
+            )
+        }
+
+        write_indent!(indent);
+        try!(write!(f, "({}", self.op.name()));
+
+        for argument in &self.arguments {
+            try!(writeln!(f, ""));
+            try!(argument.format(f, indent + 1));
+        }
+
+        if !self.partition_by.is_empty() {
+            try!(writeln!(f, ""));
+            write_indent!(indent + 1);
+            try!(write!(f, "(partition-by"));
+            for value in &self.partition_by {
+                try!(writeln!(f, ""));
+                try!(value.format(f, indent + 2));
+            }
+            try!(write!(f, ")"));
+        }
+
+        if !self.order_by.is_empty() {
+            try!(writeln!(f, ""));
+            write_indent!(indent + 1);
+            try!(write!(f, "(order-by"));
+            for &(ref value, descending) in &self.order_by {
+                try!(writeln!(f, ""));
+                try!(value.format(f, indent + 2));
+                if descending {
+                    try!(write!(f, " :desc"));
+                }
+            }
+            try!(write!(f, ")"));
+        }
+
+        if let Some(ref frame) = self.frame {
+            try!(writeln!(f, ""));
+            write_indent!(indent + 1);
+            try!(write!(f, "(rows :start {} :end {})", frame.start, frame.end));
+        }
+
+        write!(f, ")")
+    }
+}
+
+#[derive(Copy, Clone)]
+pub enum WindowOp {
+    RowNumber,
+    Rank,
+    DenseRank,
+    Lag,
+    Lead,
+    Aggregate(AggregateOp)
+}
+
+impl WindowOp {
+    fn name(&self) -> &'static str {
+        use self::WindowOp::*;
+
+        match self {
+            &RowNumber => "row-number",
+            &Rank => "rank",
+            &DenseRank => "dense-rank",
+            &Lag => "lag",
+            &Lead => "lead",
+            &Aggregate(ref op) => op.name()
+        }
+    }
+
+    /// LAG and LEAD take a value, an optional offset and an optional default
+    /// value.
+    pub fn accepts_argument_count(&self, count: usize) -> bool {
+        use self::WindowOp::*;
+
+        match self {
+            &RowNumber | &Rank | &DenseRank => count == 0,
+            &Lag | &Lead => count >= 1 && count <= 3,
+            &Aggregate(_) => count == 1
+        }
+    }
+}
+
+/// The rows from `start` to `end` inclusive, relative to the current row
+#[derive(Copy, Clone)]
+pub struct WindowFrame {
+    pub start: FrameBound,
+    pub end: FrameBound
+}
+
+#[derive(Copy, Clone)]
+pub enum FrameBound {
+    UnboundedPreceding,
+    Preceding(u64),
+    CurrentRow,
+    Following(u64),
+    UnboundedFollowing
+}
+
+impl fmt::Display for FrameBound {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match self {
+            &FrameBound::UnboundedPreceding => write!(f, "unbounded-preceding"),
+            &FrameBound::Preceding(rows) => write!(f, "(preceding {})", rows),
+            &FrameBound::CurrentRow => write!(f, "current-row"),
+            &FrameBound::Following(rows) => write!(f, "(following {})", rows),
+            &FrameBound::UnboundedFollowing => write!(f, "unbounded-following")
+        }
+    }
+}
+
 #[derive(Copy, Clone)]
 pub enum BinaryOp {
     Equal,
@@ -188,6 +544,16 @@ impl BinaryOp {
             &Concatenate => "concat"
         }
     }
+
+    /// Whether the operator compares its operands, and can compare rows
+    pub fn is_comparison(&self) -> bool {
+        use self::BinaryOp::*;
+
+        match self {
+            &Equal | &NotEqual | &LessThan | &LessThanOrEqual | &GreaterThan | &GreaterThanOrEqual => true,
+            _ => false
+        }
+    }
 }
 
 #[derive(Copy, Clone)]
@@ -227,3 +593,59 @@ impl AggregateOp {
         }
     }
 }
+
+#[derive(Copy, Clone)]
+pub enum SequenceOp {
+    NextVal,
+    CurrVal
+}
+
+impl SequenceOp {
+    fn name(&self) -> &'static str {
+        use self::SequenceOp::*;
+
+        match self {
+            &NextVal => "nextval",
+            &CurrVal => "currval"
+        }
+    }
+}
+
+#[derive(Copy, Clone)]
+pub enum FunctionOp {
+    Length,
+    Hex,
+    Unhex,
+    /// The first argument that isn't NULL. The arguments after it aren't
+    /// evaluated.
+    Coalesce,
+    /// NULL if the arguments are equal, otherwise the first argument
+    NullIf,
+    /// `Coalesce` of two arguments
+    IfNull
+}
+
+impl FunctionOp {
+    fn name(&self) -> &'static str {
+        use self::FunctionOp::*;
+
+        match self {
+            &Length => "length",
+            &Hex => "hex",
+            &Unhex => "unhex",
+            &Coalesce => "coalesce",
+            &NullIf => "nullif",
+            &IfNull => "ifnull"
+        }
+    }
+
+    pub fn accepts_argument_count(&self, count: usize) -> bool {
+        use self::FunctionOp::*;
+
+        match self {
+            &Coalesce => count >= 1,
+            &NullIf | &IfNull => count == 2,
+            &Length | &Hex | &Unhex => count == 1
+        }
+    }
+}
@@ -39,17 +39,21 @@ impl<'a> SourceScope<'a> {
 
     pub fn tables(&self) -> &[TableOrSubquery] { &self.tables }
 
-    pub fn get_column_offset(&self, column_name: &Identifier) -> Option<(u32, u32)> {
-        let mut candidates = get_candidates(self.tables.iter(), column_name);
+    /// The tables and their aliases, to add more tables to
+    pub fn into_tables(self) -> (Vec<TableOrSubquery>, Vec<Identifier>) {
+        (self.tables, self.table_aliases)
+    }
 
-        candidates.extend(self.parent.and_then(|parent| {
-            parent.get_column_offset(column_name)
-        }).into_iter());
+    /// A column of this scope's tables hides the columns of outer scopes
+    /// with the same name. None if there's no such column, or if more than
+    /// one of the innermost scope's tables have it.
+    pub fn get_column_offset(&self, column_name: &Identifier) -> Option<(u32, u32)> {
+        let candidates = get_candidates(self.tables.iter(), column_name);
 
-        if candidates.len() == 1 {
-            Some(candidates[0])
-        } else {
-            None
+        match candidates.len() {
+            0 => self.parent.and_then(|parent| parent.get_column_offset(column_name)),
+            1 => Some(candidates[0]),
+            _ => None
         }
     }
 
@@ -59,13 +63,13 @@ impl<'a> SourceScope<'a> {
             else { None }
         });
 
-        let mut candidates = get_candidates(tables, column_name);
-
-        candidates.extend(self.parent.and_then(|parent| {
-            parent.get_table_column_offset(table_name, column_name)
-        }).into_iter());
+        let candidates = get_candidates(tables, column_name);
 
-        if candidates.len() == 1 {
+        // A table of this scope hides the tables of outer scopes with the
+        // same name, even if it lacks the column
+        if !self.table_aliases.iter().any(|name| name == table_name) {
+            self.parent.and_then(|parent| parent.get_table_column_offset(table_name, column_name))
+        } else if candidates.len() == 1 {
             Some(candidates[0])
         } else {
             None
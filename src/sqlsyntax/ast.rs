@@ -1,9 +1,9 @@
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum UnaryOp {
     Negate
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum BinaryOp {
     Equal,
     NotEqual,
@@ -22,17 +22,37 @@ pub enum BinaryOp {
     Concatenate,
 }
 
-#[derive(Debug, PartialEq)]
+/// The value of a number literal.
+/// The literal's format determines its type, not its magnitude.
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum NumberLiteral {
+    /// Written without a decimal point or exponent, e.g. `42`
+    Integer(u64),
+    /// Written with a decimal point or exponent, e.g. `4.2` or `42e-1`.
+    /// Integers too large for a u64 are also floats.
+    Float(f64)
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum Expression {
     Ident(String),
     IdentMember(String, String),
     StringLiteral(String),
-    Number(String),
+    Number(NumberLiteral),
     Null,
-    /// name(argument1, argument2, argument3...)
-    FunctionCall { name: String, arguments: Vec<Expression> },
+    /// `?` is None. `$n` is Some(n).
+    Placeholder(Option<u32>),
+    /// name(argument1, argument2, argument3...), or name(DISTINCT argument...)
+    /// for an aggregate function over the distinct values
+    FunctionCall { name: String, arguments: Vec<Expression>, distinct: bool },
     /// name(*)
     FunctionCallAggregateAll { name: String },
+    /// name(argument1, argument2...) OVER (window)
+    WindowFunctionCall {
+        name: String,
+        arguments: Vec<Expression>,
+        window: Box<Window>
+    },
     UnaryOp {
         expr: Box<Expression>,
         op: UnaryOp
@@ -43,16 +63,57 @@ pub enum Expression {
         rhs: Box<Expression>,
         op: BinaryOp
     },
-    Subquery(Box<SelectStatement>)
+    Subquery(Box<SelectStatement>),
+    /// EXISTS (subquery), or NOT EXISTS (subquery) if negated
+    Exists {
+        subquery: Box<SelectStatement>,
+        negated: bool
+    },
+    /// expr IN (subquery), or expr NOT IN (subquery) if negated
+    InSubquery {
+        expr: Box<Expression>,
+        subquery: Box<SelectStatement>,
+        negated: bool
+    },
+    /// (expr1, expr2...), a row value of two or more expressions
+    Row(Vec<Expression>)
 }
 
-#[derive(Debug, PartialEq)]
+/// The rows a window function is computed over: the rows in the same
+/// partition as the current row, in the given order.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Window {
+    pub partition_by: Vec<Expression>,
+    pub order_by: Vec<OrderingTerm>,
+    pub frame: Option<Frame>
+}
+
+/// ROWS BETWEEN start AND end: the rows of the partition that aggregate
+/// window functions are computed over, relative to the current row
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frame {
+    pub start: FrameBound,
+    pub end: FrameBound
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FrameBound {
+    UnboundedPreceding,
+    /// n PRECEDING
+    Preceding(String),
+    CurrentRow,
+    /// n FOLLOWING
+    Following(String),
+    UnboundedFollowing
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct Table {
     pub database_name: Option<String>,
     pub table_name: String
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum TableOrSubquery {
     Subquery {
         subquery: Box<SelectStatement>,
@@ -61,10 +122,17 @@ pub enum TableOrSubquery {
     Table {
         table: Table,
         alias: Option<String>
+    },
+    /// A table function, such as `generate_series(1, 10)`, whose rows are
+    /// computed from its arguments
+    Function {
+        function: String,
+        arguments: Vec<Expression>,
+        alias: Option<String>
     }
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum SelectColumn {
     AllColumns,
     Expr {
@@ -73,70 +141,158 @@ pub enum SelectColumn {
     }
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct SelectStatement {
+    /// `SELECT DISTINCT` leaves out duplicate rows
+    pub distinct: bool,
     pub result_columns: Vec<SelectColumn>,
-    pub from: From,
+    /// None if there's no FROM clause, in which case there's a single row
+    pub from: Option<From>,
     pub where_expr: Option<Expression>,
     pub group_by: Vec<Expression>,
     pub having: Option<Expression>,
-    pub order_by: Vec<OrderingTerm>
+    pub order_by: Vec<OrderingTerm>,
+    pub limit: Option<Limit>
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum From {
     Cross(Vec<TableOrSubquery>),
-    Join {
-        table: TableOrSubquery,
-        joins: Vec<Join>
-    }
+    Join(Join)
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum JoinOperator {
     Left,
-    Inner
+    Inner,
+    Cross
 }
 
-#[derive(Debug, PartialEq)]
+/// lhs operator rhs ON on. Joins without parentheses are nested in the left
+/// side of the joins after them: `a JOIN b JOIN c` is `(a JOIN b) JOIN c`.
+#[derive(Debug, Clone, PartialEq)]
 pub struct Join {
     pub operator: JoinOperator,
-    pub table: TableOrSubquery,
-    pub on: Expression
+    pub lhs: Box<JoinTree>,
+    pub rhs: Box<JoinTree>,
+    /// None for CROSS JOIN, which has no ON
+    pub on: Option<Expression>
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
+pub enum JoinTree {
+    Table(TableOrSubquery),
+    Join(Join)
+}
+
+impl Join {
+    /// The tables of the join, and the ON conditions of it and the joins in
+    /// it, each in the order they're written in
+    pub fn parts(&self) -> (Vec<&TableOrSubquery>, Vec<&Expression>) {
+        fn add<'a>(join: &'a Join, tables: &mut Vec<&'a TableOrSubquery>, constraints: &mut Vec<&'a Expression>) {
+            for tree in vec![&*join.lhs, &*join.rhs] {
+                match tree {
+                    &JoinTree::Table(ref table) => tables.push(table),
+                    &JoinTree::Join(ref join) => add(join, tables, constraints)
+                }
+            }
+
+            constraints.extend(join.on.iter());
+        }
+
+        let mut tables = Vec::new();
+        let mut constraints = Vec::new();
+        add(self, &mut tables, &mut constraints);
+        (tables, constraints)
+    }
+
+    pub fn parts_mut(&mut self) -> (Vec<&mut TableOrSubquery>, Vec<&mut Expression>) {
+        fn add<'a>(join: &'a mut Join, tables: &mut Vec<&'a mut TableOrSubquery>, constraints: &mut Vec<&'a mut Expression>) {
+            for tree in vec![&mut *join.lhs, &mut *join.rhs] {
+                match tree {
+                    &mut JoinTree::Table(ref mut table) => tables.push(table),
+                    &mut JoinTree::Join(ref mut join) => add(join, tables, constraints)
+                }
+            }
+
+            constraints.extend(join.on.iter_mut());
+        }
+
+        let mut tables = Vec::new();
+        let mut constraints = Vec::new();
+        add(self, &mut tables, &mut constraints);
+        (tables, constraints)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum Order {
     Ascending,
     Descending
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct OrderingTerm {
     pub expr: Expression,
     pub order: Order
 }
 
-#[derive(Debug)]
+/// LIMIT count [OFFSET offset]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Limit {
+    pub count: u64,
+    pub offset: Option<u64>
+}
+
+#[derive(Debug, Clone)]
 pub struct InsertStatement {
     pub table: Table,
     pub into_columns: Option<Vec<String>>,
     pub source: InsertSource
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum InsertSource {
     Values(Vec<Vec<Expression>>),
+    /// `DEFAULT VALUES`: a single row where every column takes its default
+    DefaultValues,
     Select(Box<SelectStatement>)
 }
 
-#[derive(Debug)]
+/// UPDATE table SET column = expr [, ...] [FROM ...] [WHERE expr]
+#[derive(Debug, Clone)]
+pub struct UpdateStatement {
+    pub table: Table,
+    pub assignments: Vec<Assignment>,
+    /// Tables joined with the updated one, whose columns the assignments and
+    /// WHERE can use. None if there are none.
+    pub from: Option<From>,
+    /// None if every row is updated
+    pub where_expr: Option<Expression>
+}
+
+/// `column = expr` in the SET of an UPDATE
+#[derive(Debug, Clone)]
+pub struct Assignment {
+    pub column: String,
+    pub expr: Expression
+}
+
+/// DELETE FROM table [WHERE expr]
+#[derive(Debug, Clone)]
+pub struct DeleteStatement {
+    pub table: Table,
+    /// None if every row is deleted
+    pub where_expr: Option<Expression>
+}
+
+#[derive(Debug, Clone)]
 pub struct CreateTableColumnConstraint {
     pub name: Option<String>,
     pub constraint: CreateTableColumnConstraintType
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum CreateTableColumnConstraintType {
     PrimaryKey,
     Unique,
@@ -147,7 +303,7 @@ pub enum CreateTableColumnConstraintType {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct CreateTableColumn {
     pub column_name: String,
     pub type_name: String,
@@ -159,26 +315,140 @@ pub struct CreateTableColumn {
     pub constraints: Vec<CreateTableColumnConstraint>
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct CreateTableStatement {
+    /// CREATE TEMPORARY TABLE
+    pub temporary: bool,
+    pub table: Table,
+    pub source: CreateTableSource
+}
+
+#[derive(Debug, Clone)]
+pub enum CreateTableSource {
+    /// (column, column...)
+    Columns(Vec<CreateTableColumn>),
+    /// AS SELECT ...: the columns are inferred from the query's results
+    Query(Box<SelectStatement>)
+}
+
+/// CREATE MATERIALIZED VIEW view AS SELECT ...
+#[derive(Debug, Clone)]
+pub struct CreateMaterializedViewStatement {
+    pub view: Table,
+    pub query: SelectStatement
+}
+
+/// CREATE SEQUENCE sequence [START [WITH] start] [INCREMENT [BY] increment]
+#[derive(Debug, Clone)]
+pub struct CreateSequenceStatement {
+    pub sequence: Table,
+    pub start: Option<Expression>,
+    pub increment: Option<Expression>
+}
+
+/// CREATE VIRTUAL TABLE table [(columns)] USING module(arguments)
+#[derive(Debug, Clone)]
+pub struct CreateVirtualTableStatement {
     pub table: Table,
-    pub columns: Vec<CreateTableColumn>
+    /// None if the module infers the columns
+    pub columns: Option<Vec<CreateTableColumn>>,
+    pub module: String,
+    pub arguments: Vec<Expression>
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum CreateStatement {
-    Table(CreateTableStatement)
+    Table(CreateTableStatement),
+    MaterializedView(CreateMaterializedViewStatement),
+    Sequence(CreateSequenceStatement),
+    VirtualTable(CreateVirtualTableStatement)
+}
+
+/// ALTER TABLE table action
+#[derive(Debug, Clone)]
+pub struct AlterTableStatement {
+    pub table: Table,
+    pub action: AlterTableAction
+}
+
+#[derive(Debug, Clone)]
+pub enum AlterTableAction {
+    /// RENAME TO new_name
+    RenameTable { new_name: String },
+    /// RENAME [COLUMN] column TO new_name
+    RenameColumn { column: String, new_name: String },
+    /// ADD [COLUMN] column
+    AddColumn(CreateTableColumn),
+    /// ALTER [COLUMN] column TYPE type, where the type is written as in
+    /// CREATE TABLE
+    AlterColumnType {
+        column: String,
+        type_name: String,
+        type_size: Option<String>,
+        type_array_size: Option<Option<String>>
+    }
+}
+
+/// COMMENT ON target IS 'comment'
+#[derive(Debug, Clone)]
+pub struct CommentStatement {
+    pub target: CommentTarget,
+    /// None if the comment is being removed: `IS NULL`
+    pub comment: Option<String>
+}
+
+#[derive(Debug, Clone)]
+pub enum CommentTarget {
+    /// TABLE table
+    Table(Table),
+    /// COLUMN table.column
+    Column { table: Table, column: String }
 }
 
-#[derive(Debug)]
+/// REFRESH MATERIALIZED VIEW view
+#[derive(Debug, Clone)]
+pub struct RefreshStatement {
+    pub view: Table
+}
+
+#[derive(Debug, Clone)]
 pub enum ExplainStatement {
     Select(SelectStatement)
 }
 
-#[derive(Debug)]
+/// PRAGMA name [= value]
+#[derive(Debug, Clone)]
+pub struct PragmaStatement {
+    pub name: String,
+    /// None if the setting is being read
+    pub value: Option<Expression>
+}
+
+/// SET name = value, which sets a session variable
+#[derive(Debug, Clone)]
+pub struct SetStatement {
+    pub name: String,
+    pub value: Expression
+}
+
+/// SHOW name
+#[derive(Debug, Clone)]
+pub struct ShowStatement {
+    pub name: String
+}
+
+#[derive(Debug, Clone)]
 pub enum Statement {
     Select(SelectStatement),
     Insert(InsertStatement),
+    Update(UpdateStatement),
+    Delete(DeleteStatement),
     Create(CreateStatement),
-    Explain(ExplainStatement)
+    AlterTable(AlterTableStatement),
+    Comment(CommentStatement),
+    Refresh(RefreshStatement),
+    Explain(ExplainStatement),
+    Pragma(PragmaStatement),
+    Set(SetStatement),
+    Show(ShowStatement)
 }
@@ -0,0 +1,441 @@
+//! Builds SELECT statements in Rust, without formatting SQL text.
+//!
+//! Values become literals in the AST rather than text, so they can't change
+//! the meaning of the statement the way values pasted into SQL can. The
+//! statement is executed like a parsed one.
+//!
+//! ```
+//! use llamadb::sqlsyntax::builder::{Select, col, val};
+//!
+//! let query = Select::from("person")
+//!     .columns(vec![col("name"), col("age")])
+//!     .filter(col("name").eq(val("Bobby'); --")))
+//!     .order_by(col("age"))
+//!     .build();
+//!
+//! assert_eq!(query.to_string(), "SELECT name, age FROM person WHERE name = 'Bobby\\'); --' ORDER BY age");
+//! ```
+
+use super::ast;
+
+pub struct Select {
+    stmt: ast::SelectStatement
+}
+
+impl Select {
+    /// A query without a FROM clause, which has a single row
+    pub fn new() -> Select {
+        Select {
+            stmt: ast::SelectStatement {
+                distinct: false,
+                result_columns: Vec::new(),
+                from: None,
+                where_expr: None,
+                group_by: Vec::new(),
+                having: None,
+                order_by: Vec::new(),
+                limit: None
+            }
+        }
+    }
+
+    pub fn from(table: &str) -> Select {
+        let mut select = Select::new();
+        select.stmt.from = Some(ast::From::Cross(vec![table_or_subquery(table, None)]));
+        select
+    }
+
+    /// FROM table AS alias
+    pub fn from_as(table: &str, alias: &str) -> Select {
+        let mut select = Select::new();
+        select.stmt.from = Some(ast::From::Cross(vec![table_or_subquery(table, Some(alias))]));
+        select
+    }
+
+    /// FROM (subquery) AS alias
+    pub fn from_subquery(subquery: Select, alias: &str) -> Select {
+        let mut select = Select::new();
+        select.stmt.from = Some(ast::From::Cross(vec![ast::TableOrSubquery::Subquery {
+            subquery: Box::new(subquery.build()),
+            alias: alias.to_string()
+        }]));
+        select
+    }
+
+    /// Adds a result column. If no columns are added, all columns are
+    /// selected.
+    pub fn column(mut self, expr: Expr) -> Select {
+        self.stmt.result_columns.push(ast::SelectColumn::Expr {
+            expr: expr.expr,
+            alias: None
+        });
+        self
+    }
+
+    /// Adds a result column with a name
+    pub fn column_as(mut self, expr: Expr, alias: &str) -> Select {
+        self.stmt.result_columns.push(ast::SelectColumn::Expr {
+            expr: expr.expr,
+            alias: Some(alias.to_string())
+        });
+        self
+    }
+
+    pub fn columns(self, exprs: Vec<Expr>) -> Select {
+        exprs.into_iter().fold(self, |select, expr| select.column(expr))
+    }
+
+    /// SELECT DISTINCT, which leaves out duplicate rows
+    pub fn distinct(mut self) -> Select {
+        self.stmt.distinct = true;
+        self
+    }
+
+    /// INNER JOIN table ON on. Joins can only follow a single table or
+    /// other joins; otherwise they panic.
+    pub fn join(self, table: &str, on: Expr) -> Select {
+        self.add_join(ast::JoinOperator::Inner, table, Some(on))
+    }
+
+    /// LEFT JOIN table ON on
+    pub fn left_join(self, table: &str, on: Expr) -> Select {
+        self.add_join(ast::JoinOperator::Left, table, Some(on))
+    }
+
+    /// CROSS JOIN table
+    pub fn cross_join(self, table: &str) -> Select {
+        self.add_join(ast::JoinOperator::Cross, table, None)
+    }
+
+    fn add_join(mut self, operator: ast::JoinOperator, table: &str, on: Option<Expr>) -> Select {
+        let lhs = match self.stmt.from.take() {
+            Some(ast::From::Join(join)) => ast::JoinTree::Join(join),
+            Some(ast::From::Cross(ref mut tables)) if tables.len() == 1 => ast::JoinTree::Table(tables.pop().unwrap()),
+            _ => panic!("a join must follow a single table")
+        };
+
+        self.stmt.from = Some(ast::From::Join(ast::Join {
+            operator: operator,
+            lhs: Box::new(lhs),
+            rhs: Box::new(ast::JoinTree::Table(table_or_subquery(table, None))),
+            on: on.map(|on| on.expr)
+        }));
+        self
+    }
+
+    /// Adds a WHERE condition. Conditions added more than once are ANDed.
+    pub fn filter(mut self, condition: Expr) -> Select {
+        self.stmt.where_expr = Some(and_with(self.stmt.where_expr.take(), condition));
+        self
+    }
+
+    pub fn group_by(mut self, expr: Expr) -> Select {
+        self.stmt.group_by.push(expr.expr);
+        self
+    }
+
+    /// Adds a HAVING condition. Conditions added more than once are ANDed.
+    pub fn having(mut self, condition: Expr) -> Select {
+        self.stmt.having = Some(and_with(self.stmt.having.take(), condition));
+        self
+    }
+
+    pub fn order_by(mut self, expr: Expr) -> Select {
+        self.stmt.order_by.push(ast::OrderingTerm {
+            expr: expr.expr,
+            order: ast::Order::Ascending
+        });
+        self
+    }
+
+    pub fn order_by_desc(mut self, expr: Expr) -> Select {
+        self.stmt.order_by.push(ast::OrderingTerm {
+            expr: expr.expr,
+            order: ast::Order::Descending
+        });
+        self
+    }
+
+    /// LIMIT count OFFSET offset
+    pub fn limit(mut self, count: u64, offset: u64) -> Select {
+        self.stmt.limit = Some(ast::Limit {
+            count: count,
+            offset: if offset > 0 { Some(offset) } else { None }
+        });
+        self
+    }
+
+    pub fn build(mut self) -> ast::SelectStatement {
+        if self.stmt.result_columns.is_empty() {
+            self.stmt.result_columns.push(ast::SelectColumn::AllColumns);
+        }
+        self.stmt
+    }
+
+    /// The query as a statement, to pass to `TempDb::execute_statement`
+    pub fn statement(self) -> ast::Statement {
+        ast::Statement::Select(self.build())
+    }
+}
+
+fn table_or_subquery(table: &str, alias: Option<&str>) -> ast::TableOrSubquery {
+    ast::TableOrSubquery::Table {
+        table: ast::Table {
+            database_name: None,
+            table_name: table.to_string()
+        },
+        alias: alias.map(|alias| alias.to_string())
+    }
+}
+
+fn and_with(existing: Option<ast::Expression>, condition: Expr) -> ast::Expression {
+    match existing {
+        Some(existing) => Expr::new(existing).and(condition).expr,
+        None => condition.expr
+    }
+}
+
+/// An expression, built with `col`, `val` and the functions and methods
+/// below.
+#[derive(Debug, Clone)]
+pub struct Expr {
+    expr: ast::Expression
+}
+
+/// A column, by name
+pub fn col(name: &str) -> Expr {
+    Expr::new(ast::Expression::Ident(name.to_string()))
+}
+
+/// A column of a table or alias: `table.name`
+pub fn qualified(table: &str, name: &str) -> Expr {
+    Expr::new(ast::Expression::IdentMember(table.to_string(), name.to_string()))
+}
+
+/// A literal value
+pub fn val<T: Literal>(value: T) -> Expr {
+    Expr::new(value.into_expression())
+}
+
+pub fn null() -> Expr {
+    Expr::new(ast::Expression::Null)
+}
+
+/// A placeholder, numbered from 1, whose value is bound when the statement
+/// is executed
+pub fn placeholder(number: u32) -> Expr {
+    Expr::new(ast::Expression::Placeholder(Some(number)))
+}
+
+/// A call of a function or aggregate function
+pub fn func(name: &str, arguments: Vec<Expr>) -> Expr {
+    Expr::new(ast::Expression::FunctionCall {
+        name: name.to_string(),
+        arguments: arguments.into_iter().map(|argument| argument.expr).collect(),
+        distinct: false
+    })
+}
+
+/// A call of an aggregate function over the distinct values of its
+/// arguments, e.g. count(DISTINCT a)
+pub fn func_distinct(name: &str, arguments: Vec<Expr>) -> Expr {
+    Expr::new(ast::Expression::FunctionCall {
+        name: name.to_string(),
+        arguments: arguments.into_iter().map(|argument| argument.expr).collect(),
+        distinct: true
+    })
+}
+
+/// count(*)
+pub fn count_all() -> Expr {
+    Expr::new(ast::Expression::FunctionCallAggregateAll {
+        name: "count".to_string()
+    })
+}
+
+/// A row value, `(a, b)`, to compare with another row or with the rows of
+/// a subquery
+pub fn row(exprs: Vec<Expr>) -> Expr {
+    Expr::new(ast::Expression::Row(exprs.into_iter().map(|expr| expr.expr).collect()))
+}
+
+/// A subquery that returns one value
+pub fn subquery(query: Select) -> Expr {
+    Expr::new(ast::Expression::Subquery(Box::new(query.build())))
+}
+
+pub fn exists(query: Select) -> Expr {
+    Expr::new(ast::Expression::Exists {
+        subquery: Box::new(query.build()),
+        negated: false
+    })
+}
+
+pub fn not_exists(query: Select) -> Expr {
+    Expr::new(ast::Expression::Exists {
+        subquery: Box::new(query.build()),
+        negated: true
+    })
+}
+
+impl Expr {
+    pub fn new(expr: ast::Expression) -> Expr {
+        Expr {
+            expr: expr
+        }
+    }
+
+    pub fn into_ast(self) -> ast::Expression {
+        self.expr
+    }
+
+    fn binary(self, op: ast::BinaryOp, rhs: Expr) -> Expr {
+        Expr::new(ast::Expression::BinaryOp {
+            lhs: Box::new(self.expr),
+            rhs: Box::new(rhs.expr),
+            op: op
+        })
+    }
+
+    pub fn eq(self, rhs: Expr) -> Expr { self.binary(ast::BinaryOp::Equal, rhs) }
+    pub fn ne(self, rhs: Expr) -> Expr { self.binary(ast::BinaryOp::NotEqual, rhs) }
+    pub fn lt(self, rhs: Expr) -> Expr { self.binary(ast::BinaryOp::LessThan, rhs) }
+    pub fn le(self, rhs: Expr) -> Expr { self.binary(ast::BinaryOp::LessThanOrEqual, rhs) }
+    pub fn gt(self, rhs: Expr) -> Expr { self.binary(ast::BinaryOp::GreaterThan, rhs) }
+    pub fn ge(self, rhs: Expr) -> Expr { self.binary(ast::BinaryOp::GreaterThanOrEqual, rhs) }
+    pub fn and(self, rhs: Expr) -> Expr { self.binary(ast::BinaryOp::And, rhs) }
+    pub fn or(self, rhs: Expr) -> Expr { self.binary(ast::BinaryOp::Or, rhs) }
+    pub fn add(self, rhs: Expr) -> Expr { self.binary(ast::BinaryOp::Add, rhs) }
+    pub fn sub(self, rhs: Expr) -> Expr { self.binary(ast::BinaryOp::Subtract, rhs) }
+    pub fn mul(self, rhs: Expr) -> Expr { self.binary(ast::BinaryOp::Multiply, rhs) }
+    pub fn div(self, rhs: Expr) -> Expr { self.binary(ast::BinaryOp::Divide, rhs) }
+    pub fn concat(self, rhs: Expr) -> Expr { self.binary(ast::BinaryOp::Concatenate, rhs) }
+
+    pub fn neg(self) -> Expr {
+        Expr::new(ast::Expression::UnaryOp {
+            expr: Box::new(self.expr),
+            op: ast::UnaryOp::Negate
+        })
+    }
+
+    pub fn in_subquery(self, query: Select) -> Expr {
+        Expr::new(ast::Expression::InSubquery {
+            expr: Box::new(self.expr),
+            subquery: Box::new(query.build()),
+            negated: false
+        })
+    }
+
+    pub fn not_in_subquery(self, query: Select) -> Expr {
+        Expr::new(ast::Expression::InSubquery {
+            expr: Box::new(self.expr),
+            subquery: Box::new(query.build()),
+            negated: true
+        })
+    }
+}
+
+/// Rust values that can be written as SQL literals
+pub trait Literal {
+    fn into_expression(self) -> ast::Expression;
+}
+
+impl<'a> Literal for &'a str {
+    fn into_expression(self) -> ast::Expression {
+        ast::Expression::StringLiteral(self.to_string())
+    }
+}
+
+impl Literal for String {
+    fn into_expression(self) -> ast::Expression {
+        ast::Expression::StringLiteral(self)
+    }
+}
+
+impl Literal for u64 {
+    fn into_expression(self) -> ast::Expression {
+        ast::Expression::Number(ast::NumberLiteral::Integer(self))
+    }
+}
+
+impl Literal for u32 {
+    fn into_expression(self) -> ast::Expression {
+        (self as u64).into_expression()
+    }
+}
+
+impl Literal for i64 {
+    fn into_expression(self) -> ast::Expression {
+        if self >= 0 {
+            (self as u64).into_expression()
+        } else {
+            // The magnitude of i64::MIN doesn't fit in an i64
+            negate(ast::Expression::Number(ast::NumberLiteral::Integer((self as u64).wrapping_neg())))
+        }
+    }
+}
+
+impl Literal for i32 {
+    fn into_expression(self) -> ast::Expression {
+        (self as i64).into_expression()
+    }
+}
+
+impl Literal for f64 {
+    fn into_expression(self) -> ast::Expression {
+        if self < 0.0 {
+            negate(ast::Expression::Number(ast::NumberLiteral::Float(-self)))
+        } else {
+            ast::Expression::Number(ast::NumberLiteral::Float(self))
+        }
+    }
+}
+
+fn negate(expr: ast::Expression) -> ast::Expression {
+    Expr::new(expr).neg().expr
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Select, col, count_all, func, func_distinct, qualified, row, val};
+    use super::super::{ast, parse_statement};
+
+    fn parse_select(sql: &str) -> ast::SelectStatement {
+        match parse_statement(sql).unwrap() {
+            ast::Statement::Select(select) => select,
+            _ => panic!()
+        }
+    }
+
+    #[test]
+    fn test_builder() {
+        assert_eq!(Select::from("t").build(), parse_select("SELECT * FROM t"));
+
+        let query = Select::from_as("t", "x")
+            .column(qualified("x", "a"))
+            .column_as(func("max", vec![col("b")]), "m")
+            .join("u", qualified("x", "a").eq(qualified("u", "a")))
+            .filter(col("c").gt(val(-3)))
+            .filter(col("d").ne(val("it's")).or(col("e").le(val(1.5))))
+            .group_by(qualified("x", "a"))
+            .having(count_all().ge(val(2u64)))
+            .order_by_desc(col("m"))
+            .limit(10, 5)
+            .build();
+
+        assert_eq!(query, parse_select("SELECT x.a, max(b) AS m FROM t AS x INNER JOIN u ON x.a = u.a \
+            WHERE c > -3 AND (d <> 'it\\'s' OR e <= 1.5) GROUP BY x.a HAVING count(*) >= 2 ORDER BY m DESC LIMIT 10 OFFSET 5"));
+
+        let query = Select::from("t").left_join("u", qualified("t", "a").eq(qualified("u", "a"))).cross_join("v").build();
+        assert_eq!(query, parse_select("SELECT * FROM t LEFT JOIN u ON t.a = u.a CROSS JOIN v"));
+
+        let query = Select::new().column(col("a").in_subquery(Select::from("t").column(col("a")))).build();
+        assert_eq!(query, parse_select("SELECT a IN (SELECT a FROM t)"));
+
+        let query = Select::from("t").filter(row(vec![col("a"), col("b")]).gt(row(vec![val(1), val("x")]))).build();
+        assert_eq!(query, parse_select("SELECT * FROM t WHERE (a, b) > (1, 'x')"));
+
+        let query = Select::from("t").distinct().column(col("a")).column(func_distinct("count", vec![col("b")])).build();
+        assert_eq!(query, parse_select("SELECT DISTINCT a, count(DISTINCT b) FROM t"));
+    }
+}
@@ -1,18 +1,31 @@
 /// Disclaimer: The lexer is basically spaghetti. What did you expect?
 
+use std::collections::VecDeque;
+use std::fmt;
+use std::io::{self, BufRead};
+use std::mem;
+
+use super::SqlMode;
+
 #[derive(Clone, Debug, PartialEq)]
 pub enum Token {
     // Words
     Select, From, Where, Group, Having, By, Limit,
     Distinct,
     Order, Asc, Desc,
-    As, Join, Inner, Outer, Left, Right, On,
-    Insert, Into, Values, Update, Delete,
+    Over, Partition, Rows, Unbounded, Preceding, Following, Current, Row,
+    As, Join, Inner, Outer, Left, Right, Cross, On,
+    Insert, Into, Values, Default, Update, Set, Delete,
     Create, Table, Index, Constraint,
+    Alter, Rename, To, Column, Comment,
+    Materialized, View, Refresh, Temporary,
+    Sequence, Start, Increment, With,
+    Virtual, Using,
+    Pragma,
     Primary, Key, Unique, References,
     And, Or,
     Between, In,
-    Is, Not, Null,
+    Is, Not, Null, Exists,
     Explain,
 
     // Non-letter tokens
@@ -39,11 +52,72 @@ pub enum Token {
     PreparedStatementPlaceholder,
 
     // Tokens with values
+    /// $n, the numbered prepared statement placeholder
+    NumberedPlaceholder(u32),
     Number(String),
     Ident(String),
     StringLiteral(String)
 }
 
+/// The location of a token in the source text.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Span {
+    /// Byte offset of the token's first character
+    pub start: usize,
+    /// Byte offset one past the token's last character
+    pub end: usize,
+    /// Line of the token's first character, starting at 1
+    pub line: u32,
+    /// Column of the token's first character, starting at 1
+    pub column: u32
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum LexerError {
+    /// A character that can't begin a token
+    UnexpectedCharacter(char, Span),
+    /// The input ended inside a `quoted` identifier
+    UnterminatedIdentifier(Span),
+    /// The input ended inside a string literal
+    UnterminatedStringLiteral(Span),
+    /// A number's exponent has no digits, e.g. `1e` or `1e+x`,
+    /// or a placeholder's number is too large
+    MalformedNumber(Span)
+}
+
+impl LexerError {
+    pub fn span(&self) -> Span {
+        match self {
+            &LexerError::UnexpectedCharacter(_, span) => span,
+            &LexerError::UnterminatedIdentifier(span) => span,
+            &LexerError::UnterminatedStringLiteral(span) => span,
+            &LexerError::MalformedNumber(span) => span
+        }
+    }
+}
+
+impl fmt::Display for LexerError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        let span = self.span();
+
+        try!(match self {
+            &LexerError::UnexpectedCharacter(c, _) => write!(f, "Unexpected character {:?}", c),
+            &LexerError::UnterminatedIdentifier(_) => write!(f, "Unterminated quoted identifier"),
+            &LexerError::UnterminatedStringLiteral(_) => write!(f, "Unterminated string literal"),
+            &LexerError::MalformedNumber(_) => write!(f, "Malformed number")
+        });
+
+        write!(f, " at line {}, column {}", span.line, span.column)
+    }
+}
+
+#[derive(Copy, Clone)]
+struct Position {
+    offset: usize,
+    line: u32,
+    column: u32
+}
+
 fn character_to_token(c: char) -> Option<Token> {
     use self::Token::*;
 
@@ -89,6 +163,7 @@ fn word_to_token(word: String) -> Token {
         "desc" => Desc,
         "as" => As,
         "join" => Join,
+        "cross" => Cross,
         "inner" => Inner,
         "outer" => Outer,
         "left" => Left,
@@ -97,12 +172,30 @@ fn word_to_token(word: String) -> Token {
         "insert" => Insert,
         "into" => Into,
         "values" => Values,
+        "default" => Default,
         "update" => Update,
+        "set" => Set,
         "delete" => Delete,
         "create" => Create,
         "table" => Table,
         "index" => Index,
         "constraint" => Constraint,
+        "alter" => Alter,
+        "rename" => Rename,
+        "to" => To,
+        "column" => Column,
+        "comment" => Comment,
+        "materialized" => Materialized,
+        "view" => View,
+        "refresh" => Refresh,
+        "temp" | "temporary" => Temporary,
+        "sequence" => Sequence,
+        "start" => Start,
+        "increment" => Increment,
+        "with" => With,
+        "virtual" => Virtual,
+        "using" => Using,
+        "pragma" => Pragma,
         "primary" => Primary,
         "key" => Key,
         "unique" => Unique,
@@ -114,7 +207,16 @@ fn word_to_token(word: String) -> Token {
         "is" => Is,
         "not" => Not,
         "null" => Null,
+        "exists" => Exists,
         "explain" => Explain,
+        "over" => Over,
+        "partition" => Partition,
+        "rows" => Rows,
+        "unbounded" => Unbounded,
+        "preceding" => Preceding,
+        "following" => Following,
+        "current" => Current,
+        "row" => Row,
         _ => Ident(word)
     }
 }
@@ -123,8 +225,17 @@ enum LexerState {
     NoState,
     Word,
     Backtick,
+    /// A double-quoted identifier or string, depending on the mode
+    /// `closing` is true after a double quote, which ends the token unless
+    /// it's followed by another one
+    DoubleQuote { closing: bool },
     Apostrophe { escaping: bool },
     Number { decimal: bool },
+    /// The exponent of a number in scientific notation.
+    /// `digits` is false until the first digit of the exponent is fed.
+    Exponent { digits: bool },
+    /// The digits of a `$n` placeholder.
+    Dollar { digits: bool },
     /// Disambiguate an operator sequence.
     OperatorDisambiguate { first: char },
     LineComment,
@@ -133,20 +244,42 @@ enum LexerState {
 
 pub struct Lexer {
     pub tokens: Vec<Token>,
+    /// The span of each token; `spans[i]` is the location of `tokens[i]`.
+    pub spans: Vec<Span>,
 
+    mode: SqlMode,
     state: LexerState,
-    string_buffer: String
+    string_buffer: String,
+    /// Position of the character currently being fed
+    position: Position,
+    /// Position of the first character of the token being built
+    token_start: Position
 }
 
 impl Lexer {
     pub fn new() -> Lexer {
+        Lexer::with_mode(SqlMode::Permissive)
+    }
+
+    pub fn with_mode(mode: SqlMode) -> Lexer {
+        let start = Position { offset: 0, line: 1, column: 1 };
+
         Lexer {
             tokens: Vec::new(),
+            spans: Vec::new(),
+            mode: mode,
             state: LexerState::NoState,
-            string_buffer: String::new()
+            string_buffer: String::new(),
+            position: start,
+            token_start: start
         }
     }
 
+    /// Changes what double quotes mean for the tokens that follow
+    pub fn set_mode(&mut self, mode: SqlMode) {
+        self.mode = mode;
+    }
+
     pub fn is_no_state(&self) -> bool {
         match self.state {
             LexerState::NoState => true,
@@ -154,15 +287,40 @@ impl Lexer {
         }
     }
 
-    fn no_state(&mut self, c: char) -> Result<LexerState, char> {
+    /// The span from `token_start` to the byte offset `end`.
+    fn span_to(&self, end: usize) -> Span {
+        let start = self.token_start;
+
+        Span {
+            start: start.offset,
+            end: end,
+            line: start.line,
+            column: start.column
+        }
+    }
+
+    /// Pushes a token that started at `token_start` and ends at the byte offset `end`.
+    fn push_token(&mut self, token: Token, end: usize) {
+        let span = self.span_to(end);
+
+        self.tokens.push(token);
+        self.spans.push(span);
+    }
+
+    fn no_state(&mut self, c: char) -> Result<LexerState, LexerError> {
+        self.token_start = self.position;
+
         match c {
-            'a'...'z' | 'A'...'Z' | '_' => {
+            c if c.is_alphabetic() || c == '_' => {
                 self.string_buffer.push(c);
                 Ok(LexerState::Word)
             },
             '`' => {
                 Ok(LexerState::Backtick)
             }
+            '"' => {
+                Ok(LexerState::DoubleQuote { closing: false })
+            }
             '\'' => {
                 // string literal
                 Ok(LexerState::Apostrophe { escaping: false })
@@ -171,6 +329,9 @@ impl Lexer {
                 self.string_buffer.push(c);
                 Ok(LexerState::Number { decimal: false })
             },
+            '$' => {
+                Ok(LexerState::Dollar { digits: false })
+            },
             ' ' | '\t' | '\n' => {
                 // whitespace
                 Ok(LexerState::NoState)
@@ -183,47 +344,95 @@ impl Lexer {
                         Ok(LexerState::OperatorDisambiguate { first: c })
                     },
                     Some(token) => {
-                        self.tokens.push(token);
+                        let end = self.position.offset + c.len_utf8();
+                        self.push_token(token, end);
                         Ok(LexerState::NoState)
                     },
                     None => {
                         // unknown character
-                        Err(c)
+                        let end = self.position.offset + c.len_utf8();
+                        Err(LexerError::UnexpectedCharacter(c, self.span_to(end)))
                     }
                 }
             }
         }
     }
 
+    /// Pushes the `$n` placeholder in the string buffer.
+    fn push_numbered_placeholder(&mut self, end: usize) -> Result<(), LexerError> {
+        let buffer = self.move_string_buffer();
+
+        match buffer.parse() {
+            Ok(n) => {
+                self.push_token(Token::NumberedPlaceholder(n), end);
+                Ok(())
+            },
+            Err(_) => Err(LexerError::MalformedNumber(self.span_to(end)))
+        }
+    }
+
     fn move_string_buffer(&mut self) -> String {
         use std::mem;
         mem::replace(&mut self.string_buffer, String::new())
     }
 
-    pub fn feed_character(&mut self, c: Option<char>) {
-        self.state = match self.state {
+    /// Feeds the next character of the input, or None at the end of the input.
+    ///
+    /// On error, the partially lexed token is discarded and the lexer is
+    /// ready to accept new input.
+    pub fn feed_character(&mut self, c: Option<char>) -> Result<(), LexerError> {
+        let result = self.next_state(c);
+
+        if let Some(c) = c {
+            self.position.offset += c.len_utf8();
+
+            if c == '\n' {
+                self.position.line += 1;
+                self.position.column = 1;
+            } else {
+                self.position.column += 1;
+            }
+        }
+
+        match result {
+            Ok(state) => {
+                self.state = state;
+                Ok(())
+            },
+            Err(e) => {
+                self.state = LexerState::NoState;
+                self.string_buffer.clear();
+                Err(e)
+            }
+        }
+    }
+
+    fn next_state(&mut self, c: Option<char>) -> Result<LexerState, LexerError> {
+        let offset = self.position.offset;
+
+        Ok(match self.state {
             LexerState::NoState => {
                 match c {
-                    Some(c) => self.no_state(c).unwrap(),
+                    Some(c) => try!(self.no_state(c)),
                     None => LexerState::NoState
                 }
             },
             LexerState::Word => {
                 match c {
                     Some(c) => match c {
-                        'a'...'z' | 'A'...'Z' | '_' | '0'...'9' => {
+                        c if c.is_alphanumeric() || c == '_' => {
                             self.string_buffer.push(c);
                             LexerState::Word
                         }
                         c => {
                             let buffer = self.move_string_buffer();
-                            self.tokens.push(word_to_token(buffer));
-                            self.no_state(c).unwrap()
+                            self.push_token(word_to_token(buffer), offset);
+                            try!(self.no_state(c))
                         }
                     },
                     None => {
                         let buffer = self.move_string_buffer();
-                        self.tokens.push(word_to_token(buffer));
+                        self.push_token(word_to_token(buffer), offset);
                         LexerState::NoState
                     }
                 }
@@ -232,7 +441,7 @@ impl Lexer {
                 match c {
                     Some('`') => {
                         let buffer = self.move_string_buffer();
-                        self.tokens.push(Token::Ident(buffer));
+                        self.push_token(Token::Ident(buffer), offset + 1);
                         LexerState::NoState
                     },
                     Some(c) => {
@@ -240,8 +449,42 @@ impl Lexer {
                         LexerState::Backtick
                     },
                     None => {
-                        // error: backtick did not finish
-                        unimplemented!()
+                        return Err(LexerError::UnterminatedIdentifier(self.span_to(offset)));
+                    }
+                }
+            },
+            LexerState::DoubleQuote { closing: false } => {
+                match (c, self.mode) {
+                    (Some('"'), _) => LexerState::DoubleQuote { closing: true },
+                    (Some(c), _) => {
+                        self.string_buffer.push(c);
+                        LexerState::DoubleQuote { closing: false }
+                    },
+                    (None, SqlMode::Strict) => {
+                        return Err(LexerError::UnterminatedIdentifier(self.span_to(offset)));
+                    },
+                    (None, SqlMode::Permissive) => {
+                        return Err(LexerError::UnterminatedStringLiteral(self.span_to(offset)));
+                    }
+                }
+            },
+            LexerState::DoubleQuote { closing: true } => {
+                if c == Some('"') {
+                    // `""` is an escaped double quote
+                    self.string_buffer.push('"');
+                    LexerState::DoubleQuote { closing: false }
+                } else {
+                    let buffer = self.move_string_buffer();
+
+                    let token = match self.mode {
+                        SqlMode::Strict => Token::Ident(buffer),
+                        SqlMode::Permissive => Token::StringLiteral(buffer)
+                    };
+                    self.push_token(token, offset);
+
+                    match c {
+                        Some(c) => try!(self.no_state(c)),
+                        None => LexerState::NoState
                     }
                 }
             },
@@ -251,7 +494,7 @@ impl Lexer {
                         (false, '\'') => {
                             // unescaped apostrophe
                             let buffer = self.move_string_buffer();
-                            self.tokens.push(Token::StringLiteral(buffer));
+                            self.push_token(Token::StringLiteral(buffer), offset + 1);
                             LexerState::NoState
                         },
                         (false, '\\') => {
@@ -264,8 +507,7 @@ impl Lexer {
                         }
                     }
                 } else {
-                    // error: apostrophe did not finish
-                    unimplemented!()
+                    return Err(LexerError::UnterminatedStringLiteral(self.span_to(offset)));
                 }
             },
             LexerState::Number { decimal } => {
@@ -280,37 +522,88 @@ impl Lexer {
                             self.string_buffer.push(c);
                             LexerState::Number { decimal: true }
                         },
+                        'e' | 'E' => {
+                            // Scientific notation, e.g. 1.5e10
+                            self.string_buffer.push('e');
+                            LexerState::Exponent { digits: false }
+                        },
                         c => {
                             let buffer = self.move_string_buffer();
-                            self.tokens.push(Token::Number(buffer));
-                            self.no_state(c).unwrap()
+                            self.push_token(Token::Number(buffer), offset);
+                            try!(self.no_state(c))
                         }
                     }
                 } else {
                     let buffer = self.move_string_buffer();
-                    self.tokens.push(Token::Number(buffer));
+                    self.push_token(Token::Number(buffer), offset);
                     LexerState::NoState
                 }
             },
+            LexerState::Exponent { digits } => {
+                match (c, digits) {
+                    (Some(c @ '0'...'9'), _) => {
+                        self.string_buffer.push(c);
+                        LexerState::Exponent { digits: true }
+                    },
+                    (Some(c @ '+'), false) | (Some(c @ '-'), false) if self.string_buffer.ends_with('e') => {
+                        // The exponent's sign must immediately follow the e
+                        self.string_buffer.push(c);
+                        LexerState::Exponent { digits: false }
+                    },
+                    (Some(c), true) => {
+                        let buffer = self.move_string_buffer();
+                        self.push_token(Token::Number(buffer), offset);
+                        try!(self.no_state(c))
+                    },
+                    (None, true) => {
+                        let buffer = self.move_string_buffer();
+                        self.push_token(Token::Number(buffer), offset);
+                        LexerState::NoState
+                    },
+                    (_, false) => {
+                        return Err(LexerError::MalformedNumber(self.span_to(offset)));
+                    }
+                }
+            },
+            LexerState::Dollar { digits } => {
+                match (c, digits) {
+                    (Some(c @ '0'...'9'), _) => {
+                        self.string_buffer.push(c);
+                        LexerState::Dollar { digits: true }
+                    },
+                    (Some(c), true) => {
+                        try!(self.push_numbered_placeholder(offset));
+                        try!(self.no_state(c))
+                    },
+                    (None, true) => {
+                        try!(self.push_numbered_placeholder(offset));
+                        LexerState::NoState
+                    },
+                    (_, false) => {
+                        // A lone $ isn't a token
+                        return Err(LexerError::UnexpectedCharacter('$', self.span_to(offset)));
+                    }
+                }
+            },
             LexerState::OperatorDisambiguate { first } => {
                 use self::Token::*;
 
                 if let Some(c) = c {
                     match (first, c) {
                         ('<', '>') => {
-                            self.tokens.push(NotEqual);
+                            self.push_token(NotEqual, offset + 1);
                             LexerState::NoState
                         },
                         ('<', '=') => {
-                            self.tokens.push(LessThanOrEqual);
+                            self.push_token(LessThanOrEqual, offset + 1);
                             LexerState::NoState
                         },
                         ('>', '=') => {
-                            self.tokens.push(GreaterThanOrEqual);
+                            self.push_token(GreaterThanOrEqual, offset + 1);
                             LexerState::NoState
                         },
                         ('|', '|') => {
-                            self.tokens.push(DoublePipe);
+                            self.push_token(DoublePipe, offset + 1);
                             LexerState::NoState
                         },
                         ('-', '-') => {
@@ -320,12 +613,12 @@ impl Lexer {
                             LexerState::BlockComment { was_prev_char_asterisk: false }
                         },
                         _ => {
-                            self.tokens.push(character_to_token(first).unwrap());
-                            self.no_state(c).unwrap()
+                            self.push_token(character_to_token(first).unwrap(), offset);
+                            try!(self.no_state(c))
                         }
                     }
                 } else {
-                    self.tokens.push(character_to_token(first).unwrap());
+                    self.push_token(character_to_token(first).unwrap(), offset);
                     LexerState::NoState
                 }
             },
@@ -342,30 +635,127 @@ impl Lexer {
                     LexerState::BlockComment { was_prev_char_asterisk: c == Some('*') }
                 }
             }
-        };
+        })
     }
 
-    pub fn feed_characters<I>(&mut self, iter: I)
+    /// Feeds characters until the first error.
+    pub fn feed_characters<I>(&mut self, iter: I) -> Result<(), LexerError>
     where I: Iterator<Item=char>
     {
         for c in iter {
-            self.feed_character(Some(c));
+            try!(self.feed_character(Some(c)));
+        }
+
+        Ok(())
+    }
+}
+
+pub fn parse(sql: &str) -> Result<Vec<Token>, LexerError> {
+    parse_with_spans(sql).map(|(tokens, _)| tokens)
+}
+
+/// Like `parse`, but also returns the location of each token.
+pub fn parse_with_spans(sql: &str) -> Result<(Vec<Token>, Vec<Span>), LexerError> {
+    parse_with_spans_and_mode(sql, SqlMode::Permissive)
+}
+
+/// Like `parse_with_spans`, but double quotes mean what they do in `mode`.
+pub fn parse_with_spans_and_mode(sql: &str, mode: SqlMode) -> Result<(Vec<Token>, Vec<Span>), LexerError> {
+    let mut lexer = Lexer::with_mode(mode);
+
+    try!(lexer.feed_characters(sql.chars()));
+    try!(lexer.feed_character(None));
+
+    Ok((lexer.tokens, lexer.spans))
+}
+
+#[derive(Debug)]
+pub enum TokenStreamError {
+    Io(io::Error),
+    Lexer(LexerError)
+}
+
+impl fmt::Display for TokenStreamError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match self {
+            &TokenStreamError::Io(ref e) => write!(f, "{}", e),
+            &TokenStreamError::Lexer(ref e) => write!(f, "{}", e)
         }
     }
 }
 
-pub fn parse(sql: &str) -> Vec<Token> {
-    let mut lexer = Lexer::new();
+/// Lexes tokens lazily from a reader, one line at a time.
+///
+/// Only the current line and the tokens lexed from it are held in memory.
+/// The stream ends after the first error.
+pub struct TokenStream<R> {
+    reader: R,
+    lexer: Lexer,
+    line: String,
+    pending: VecDeque<(Token, Span)>,
+    /// Yielded once the tokens before it are exhausted
+    error: Option<TokenStreamError>,
+    finished: bool
+}
+
+impl<R: BufRead> TokenStream<R> {
+    pub fn new(reader: R) -> TokenStream<R> {
+        TokenStream {
+            reader: reader,
+            lexer: Lexer::new(),
+            line: String::new(),
+            pending: VecDeque::new(),
+            error: None,
+            finished: false
+        }
+    }
+
+    /// Lexes the next line, or the end of input.
+    fn feed_line(&mut self) -> Result<(), TokenStreamError> {
+        self.line.clear();
+        let length = try!(self.reader.read_line(&mut self.line).map_err(TokenStreamError::Io));
 
-    lexer.feed_characters(sql.chars());
-    lexer.feed_character(None);
+        let result = if length == 0 {
+            self.finished = true;
+            self.lexer.feed_character(None)
+        } else {
+            self.lexer.feed_characters(self.line.chars())
+        };
 
-    lexer.tokens
+        // Keep the tokens that were lexed before any error
+        let tokens = mem::replace(&mut self.lexer.tokens, Vec::new());
+        let spans = mem::replace(&mut self.lexer.spans, Vec::new());
+        self.pending.extend(tokens.into_iter().zip(spans.into_iter()));
+
+        result.map_err(TokenStreamError::Lexer)
+    }
+}
+
+impl<R: BufRead> Iterator for TokenStream<R> {
+    type Item = Result<(Token, Span), TokenStreamError>;
+
+    fn next(&mut self) -> Option<Result<(Token, Span), TokenStreamError>> {
+        while self.pending.is_empty() && !self.finished {
+            if let Err(e) = self.feed_line() {
+                self.finished = true;
+                self.error = Some(e);
+            }
+        }
+
+        match self.pending.pop_front() {
+            Some(token) => Some(Ok(token)),
+            None => self.error.take().map(Err)
+        }
+    }
 }
 
 #[cfg(test)]
 mod test {
-    use super::parse;
+    use super::Token;
+
+    fn parse(sql: &str) -> Vec<Token> {
+        super::parse(sql).unwrap()
+    }
 
     fn id(value: &str) -> super::Token {
         super::Token::Ident(value.to_string())
@@ -389,6 +779,39 @@ mod test {
         assert_eq!(parse(r"'\''"), vec![StringLiteral("'".to_string())]);
     }
 
+    #[test]
+    fn test_sql_lexer_double_quotes() {
+        use super::Token::*;
+        use super::super::SqlMode;
+
+        assert_eq!(parse("\"a b\" = 'c'"), vec![StringLiteral("a b".to_string()), Equal, StringLiteral("c".to_string())]);
+
+        let (tokens, _) = super::parse_with_spans_and_mode("SELECT \"a b\" FROM t", SqlMode::Strict).unwrap();
+        assert_eq!(tokens, vec![Select, id("a b"), From, id("t")]);
+
+        // A doubled quote is part of the name or string
+        let (tokens, _) = super::parse_with_spans_and_mode("\"a\"\"b\".\"\"\"\"", SqlMode::Strict).unwrap();
+        assert_eq!(tokens, vec![id("a\"b"), Dot, id("\"")]);
+        assert_eq!(parse("\"\"\"\",\"\""), vec![StringLiteral("\"".to_string()), Comma, StringLiteral("".to_string())]);
+
+        assert!(super::parse_with_spans_and_mode("\"a\"\"", SqlMode::Strict).is_err());
+        assert!(super::parse_with_spans_and_mode("\"a", SqlMode::Strict).is_err());
+        assert!(super::parse("\"a").is_err());
+    }
+
+    #[test]
+    fn test_sql_lexer_unicode() {
+        use super::Token::*;
+
+        assert_eq!(parse("SELECT größe, имя2 FROM café WHERE ß = 'naïve ☃';"), vec![
+            Select, id("größe"), Comma, id("имя2"), From, id("café"),
+            Where, id("ß"), Equal, StringLiteral("naïve ☃".to_string()), Semicolon
+        ]);
+
+        // Symbols are still not part of a word
+        assert!(super::parse("a☃").is_err());
+    }
+
     #[test]
     fn test_sql_lexer_numbers() {
         use super::Token::*;
@@ -400,6 +823,26 @@ mod test {
         assert_eq!(parse("- 0.25 - -0.25"), vec![Minus, number("0.25"), Minus, Minus, number("0.25")]);
         assert_eq!(parse("- 0.25 --0.25"), vec![Minus, number("0.25")]);
         assert_eq!(parse("0.25 -0.25"), vec![number("0.25"), Minus, number("0.25")]);
+
+        assert_eq!(parse("1e10"), vec![number("1e10")]);
+        assert_eq!(parse("3.14E-2"), vec![number("3.14e-2")]);
+        assert_eq!(parse("2e+3-1"), vec![number("2e+3"), Minus, number("1")]);
+        assert!(super::parse("1e").is_err());
+        assert!(super::parse("1e+ 2").is_err());
+        assert!(super::parse("1e-+2").is_err());
+    }
+
+    #[test]
+    fn test_sql_lexer_placeholders() {
+        use super::Token::*;
+
+        assert_eq!(parse("? = $1 AND $23<?"), vec![
+            PreparedStatementPlaceholder, Equal, NumberedPlaceholder(1), And,
+            NumberedPlaceholder(23), LessThan, PreparedStatementPlaceholder
+        ]);
+        assert_eq!(parse("$7"), vec![NumberedPlaceholder(7)]);
+        assert!(super::parse("$").is_err());
+        assert!(super::parse("$99999999999").is_err());
     }
 
     #[test]
@@ -453,6 +896,71 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_sql_lexer_spans() {
+        use super::{parse_with_spans, Span};
+
+        fn span(start: usize, end: usize, line: u32, column: u32) -> Span {
+            Span { start: start, end: end, line: line, column: column }
+        }
+
+        let (tokens, spans) = parse_with_spans("SELECT a,\n  'b' <> 12;").unwrap();
+        assert_eq!(tokens.len(), spans.len());
+        assert_eq!(spans, vec![
+            span(0, 6, 1, 1), span(7, 8, 1, 8), span(8, 9, 1, 9),
+            span(12, 15, 2, 3), span(16, 18, 2, 7), span(19, 21, 2, 10), span(21, 22, 2, 12)
+        ]);
+
+        // Tokens that end the input are still given a span
+        let (_, spans) = parse_with_spans("-- comment\n`x` y").unwrap();
+        assert_eq!(spans, vec![span(11, 14, 2, 1), span(15, 16, 2, 5)]);
+    }
+
+    #[test]
+    fn test_sql_lexer_token_stream() {
+        use std::io::Cursor;
+        use super::{parse_with_spans, TokenStream, TokenStreamError};
+
+        let sql = "SELECT a, 'multi\nline' FROM t; /* block\ncomment */\nINSERT INTO t VALUES (1.5e3);";
+        let (tokens, spans) = parse_with_spans(sql).unwrap();
+
+        let streamed: Vec<_> = TokenStream::new(Cursor::new(sql)).map(|r| r.unwrap()).collect();
+        assert_eq!(streamed, tokens.into_iter().zip(spans.into_iter()).collect::<Vec<_>>());
+
+        // Tokens before the error are still yielded
+        let mut stream = TokenStream::new(Cursor::new("SELECT a\nFROM # t"));
+        assert_eq!(stream.next().unwrap().unwrap().0, Token::Select);
+        assert_eq!(stream.next().unwrap().unwrap().0, Token::Ident("a".to_string()));
+        assert_eq!(stream.next().unwrap().unwrap().0, Token::From);
+        match stream.next() {
+            Some(Err(TokenStreamError::Lexer(e))) => assert_eq!(e.span().line, 2),
+            _ => panic!()
+        }
+        assert!(stream.next().is_none());
+    }
+
+    #[test]
+    fn test_sql_lexer_errors() {
+        use super::{Lexer, LexerError, Span};
+
+        assert_eq!(super::parse("a\n  $b"), Err(LexerError::UnexpectedCharacter('$', Span {
+            start: 4, end: 5, line: 2, column: 3
+        })));
+        assert_eq!(super::parse("a 'bc"), Err(LexerError::UnterminatedStringLiteral(Span {
+            start: 2, end: 5, line: 1, column: 3
+        })));
+        assert_eq!(super::parse("`bc"), Err(LexerError::UnterminatedIdentifier(Span {
+            start: 0, end: 3, line: 1, column: 1
+        })));
+
+        // The lexer can keep going after an error
+        let mut lexer = Lexer::new();
+        assert!(lexer.feed_characters("a $ b".chars()).is_err());
+        lexer.feed_characters("c".chars()).unwrap();
+        lexer.feed_character(None).unwrap();
+        assert_eq!(lexer.tokens, vec![id("a"), id("c")]);
+    }
+
     #[test]
     fn test_sql_lexer_blockcomment() {
         use super::Token::*;
@@ -2,28 +2,192 @@
 /// As a consequence, the lexer and parser are both written by hand.
 
 pub mod ast;
+pub mod builder;
 pub mod lexer;
 pub mod parser;
+pub mod placeholders;
+mod printer;
 
-pub fn parse_statement(query: &str) -> ast::Statement {
-    let tokens = lexer::parse(query);
-    parser::parse_statement(&tokens).unwrap()
+use std::fmt;
+
+pub use self::lexer::Span;
+
+/// How strictly SQL is interpreted.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum SqlMode {
+    /// `"text"` is a string literal, and inserted values are converted to
+    /// their column's type even if they're truncated or change kind.
+    Permissive,
+    /// ANSI SQL: `"name"` is a quoted identifier, and a value stored in a
+    /// column must fit its type exactly. Expressions are evaluated as in
+    /// permissive mode.
+    Strict
+}
+
+/// An error from either lexing or parsing SQL text.
+#[derive(Debug)]
+pub enum ParseError {
+    Lexer(lexer::LexerError),
+    Parser(parser::RuleError)
+}
+
+impl ParseError {
+    /// The location of the error in the SQL text.
+    /// Returns None if the error is at the end of the text.
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            &ParseError::Lexer(ref e) => Some(e.span()),
+            &ParseError::Parser(ref e) => e.span()
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match self {
+            &ParseError::Lexer(ref e) => write!(f, "{}", e),
+            &ParseError::Parser(ref e) => write!(f, "{}", e)
+        }
+    }
+}
+
+/// Parses a single statement. A trailing semicolon is optional.
+///
+/// Like `parse_statements`, this only involves the lexer and parser.
+pub fn parse_statement(query: &str) -> Result<ast::Statement, ParseError> {
+    let (tokens, spans) = try!(lexer::parse_with_spans(query).map_err(ParseError::Lexer));
+    parser::parse_statement(&tokens, &spans).map_err(ParseError::Parser)
+}
+
+/// Like `parse_statement`, but with a limit other than
+/// `parser::DEFAULT_MAX_DEPTH` on how deeply expressions and subqueries can
+/// be nested.
+pub fn parse_statement_with_max_depth(query: &str, max_depth: u32) -> Result<ast::Statement, ParseError> {
+    let (tokens, spans) = try!(lexer::parse_with_spans(query).map_err(ParseError::Lexer));
+    parser::parse_statement_with_max_depth(&tokens, &spans, max_depth).map_err(ParseError::Parser)
+}
+
+/// Like `parse_statement`, but double quotes mean what they do in `mode`.
+pub fn parse_statement_with_mode(query: &str, mode: SqlMode) -> Result<ast::Statement, ParseError> {
+    let (tokens, spans) = try!(lexer::parse_with_spans_and_mode(query, mode).map_err(ParseError::Lexer));
+    parser::parse_statement(&tokens, &spans).map_err(ParseError::Parser)
+}
+
+/// Parses a series of statements, each terminated by a semicolon.
+///
+/// This is the entry point for tools that want LlamaDB's SQL dialect
+/// without the storage engine. Errors carry the location of the offending
+/// token, when there is one.
+///
+/// ```
+/// use llamadb::sqlsyntax::parse_statements;
+///
+/// let statements = parse_statements("SELECT a FROM t; INSERT INTO t VALUES (1);").unwrap();
+/// assert_eq!(statements.len(), 2);
+///
+/// let error = parse_statements("SELECT a\nFROM WHERE;").unwrap_err();
+/// assert_eq!(error.span().unwrap().line, 2);
+/// ```
+pub fn parse_statements(query: &str) -> Result<Vec<ast::Statement>, ParseError> {
+    let (tokens, spans) = try!(lexer::parse_with_spans(query).map_err(ParseError::Lexer));
+    parser::parse_statements(&tokens, &spans).map_err(ParseError::Parser)
+}
+
+/// Parses a series of statements and formats them consistently: keywords in
+/// upper case, and each clause of a query on its own line. Each statement is
+/// terminated by a semicolon and a line break. Comments aren't kept.
+///
+/// ```
+/// use llamadb::sqlsyntax::format_sql;
+///
+/// let sql = format_sql("select a, b from t where a > 1 and b < 2;").unwrap();
+/// assert_eq!(sql, "SELECT a, b\nFROM t\nWHERE a > 1\n  AND b < 2;\n");
+/// ```
+pub fn format_sql(query: &str) -> Result<String, ParseError> {
+    format_sql_with_mode(query, SqlMode::Permissive)
+}
+
+/// Like `format_sql`, but double quotes mean what they do in `mode`.
+/// Quoted identifiers are formatted with backticks.
+pub fn format_sql_with_mode(query: &str, mode: SqlMode) -> Result<String, ParseError> {
+    let statements = try!(parse_statements_with_mode(query, mode));
+    let mut formatted = String::new();
+
+    for statement in &statements {
+        formatted.push_str(&printer::format_statement(statement));
+        formatted.push_str(";\n");
+    }
+
+    Ok(formatted)
+}
+
+/// Parses the first statement of `query`, and returns it along with the text
+/// after its semicolon, like the tail of SQLite's `sqlite3_prepare`. The tail
+/// isn't lexed, so a statement that changes the SQL mode applies to it.
+/// Returns None if there's no statement left, only whitespace, comments or
+/// semicolons.
+///
+/// ```
+/// use llamadb::sqlsyntax::parse_first_statement;
+///
+/// let (_, tail) = parse_first_statement("SELECT 1; SELECT 'it''s';").unwrap().unwrap();
+/// assert_eq!(tail, " SELECT 'it''s';");
+/// assert!(parse_first_statement(" ; -- done").unwrap().is_none());
+/// ```
+pub fn parse_first_statement(query: &str) -> Result<Option<(ast::Statement, &str)>, ParseError> {
+    parse_first_statement_with_mode(query, SqlMode::Permissive)
+}
+
+/// Like `parse_first_statement`, but double quotes mean what they do in
+/// `mode`.
+pub fn parse_first_statement_with_mode(query: &str, mode: SqlMode)
+-> Result<Option<(ast::Statement, &str)>, ParseError>
+{
+    let mut lexer = lexer::Lexer::with_mode(mode);
+
+    for c in query.chars() {
+        try!(lexer.feed_character(Some(c)).map_err(ParseError::Lexer));
+
+        if lexer.tokens.last() == Some(&lexer::Token::Semicolon) {
+            if lexer.tokens.len() > 1 {
+                break;
+            }
+
+            // An empty statement
+            lexer.tokens.clear();
+            lexer.spans.clear();
+        }
+    }
+
+    try!(lexer.feed_character(None).map_err(ParseError::Lexer));
+
+    let end = match lexer.spans.last() {
+        Some(span) => span.end,
+        None => return Ok(None)
+    };
+
+    let statement = try!(parser::parse_statement(&lexer.tokens, &lexer.spans).map_err(ParseError::Parser));
+    Ok(Some((statement, &query[end..])))
 }
 
-pub fn parse_statements(query: &str) -> Vec<ast::Statement> {
-    let tokens = lexer::parse(query);
-    parser::parse_statements(&tokens).unwrap()
+/// Like `parse_statements`, but double quotes mean what they do in `mode`.
+pub fn parse_statements_with_mode(query: &str, mode: SqlMode) -> Result<Vec<ast::Statement>, ParseError> {
+    let (tokens, spans) = try!(lexer::parse_with_spans_and_mode(query, mode).map_err(ParseError::Lexer));
+    parser::parse_statements(&tokens, &spans).map_err(ParseError::Parser)
 }
 
 #[cfg(test)]
 mod test {
-    use super::parse_statement as parse;
+    fn parse(query: &str) -> super::ast::Statement {
+        super::parse_statement(query).unwrap()
+    }
 
     #[test]
     fn test_sql_parser() {
         parse("SELECT *, (name + 4), count(*) AS amount FROM (SELECT * FROM foo) subq, table1 GROUP BY name HAVING count(*) > 5;");
         parse("SELECT * FROM foo INNER JOIN bar ON foo.id = bar.fooId ORDER BY a DESC, b;");
         parse("SELECT avg(milliseconds) / 1000 seconds FROM track;");
+        parse("SELECT 1 + 1;");
 
         parse("INSERT INTO table1 VALUES (1, 2), (3, 4), (5, 6);");
         parse("INSERT INTO table1 (a, b) VALUES ('foo' || 'bar', 2);");
@@ -35,4 +199,136 @@ mod test {
             data    BYTE[32] NULL UNIQUE
         );");
     }
+
+    #[test]
+    fn test_sql_parser_number_literals() {
+        use super::ast::{Expression, NumberLiteral, SelectColumn, Statement};
+
+        let numbers = match parse("SELECT 12, 1.5, 2e3, 18446744073709551615, 18446744073709551616 FROM t") {
+            Statement::Select(select) => select.result_columns.into_iter().map(|column| match column {
+                SelectColumn::Expr { expr: Expression::Number(n), .. } => n,
+                _ => panic!()
+            }).collect::<Vec<_>>(),
+            _ => panic!()
+        };
+
+        assert_eq!(numbers, vec![
+            NumberLiteral::Integer(12),
+            NumberLiteral::Float(1.5),
+            NumberLiteral::Float(2000.0),
+            NumberLiteral::Integer(18446744073709551615),
+            NumberLiteral::Float(18446744073709551616.0)
+        ]);
+    }
+
+    #[test]
+    fn test_sql_parser_limit() {
+        use super::ast::{Limit, Statement};
+        use super::parse_statement;
+
+        match parse("SELECT a FROM t LIMIT 18446744073709551615 OFFSET 2") {
+            Statement::Select(select) => assert_eq!(select.limit, Some(Limit {
+                count: 18446744073709551615,
+                offset: Some(2)
+            })),
+            _ => panic!()
+        }
+
+        // The number of rows must be a non-negative integer
+        assert!(parse_statement("SELECT a FROM t LIMIT 1.5").is_err());
+        assert!(parse_statement("SELECT a FROM t LIMIT 18446744073709551616").is_err());
+        assert!(parse_statement("SELECT a FROM t LIMIT 1 OFFSET 2e3").is_err());
+        assert!(parse_statement("SELECT a FROM t LIMIT -1").is_err());
+    }
+
+    #[test]
+    fn test_sql_parser_error_location() {
+        use super::lexer;
+        use super::parser;
+
+        let (tokens, spans) = lexer::parse_with_spans("SELECT a\nFROM t\nWHERE b = ;").unwrap();
+        let e = parser::parse_statement(&tokens, &spans).unwrap_err();
+        let span = e.span().unwrap();
+        assert_eq!((span.line, span.column), (3, 11));
+        assert_eq!(e.to_string(), "Expected identifier or number; got Semicolon at line 3, column 11");
+
+        // Without spans, no location is reported
+        let e = parser::parse_statement(&tokens, &[]).unwrap_err();
+        assert!(e.span().is_none());
+    }
+
+    #[test]
+    fn test_sql_parse_error() {
+        use super::{parse_statements, ParseError};
+
+        match parse_statements("SELECT a FROM t;\nSELECT # FROM t;") {
+            Err(e @ ParseError::Lexer(_)) => assert_eq!(e.span().unwrap().line, 2),
+            _ => panic!()
+        }
+
+        match parse_statements("SELECT a FROM t") {
+            Err(e @ ParseError::Parser(_)) => assert!(e.span().is_none()),
+            _ => panic!()
+        }
+    }
+
+    #[test]
+    fn test_sql_parser_max_depth() {
+        use super::{parse_statement, parse_statement_with_max_depth};
+        use std::iter::repeat;
+
+        let nest = |open: &str, close: &str, n| -> String {
+            let opens: String = repeat(open).take(n).collect();
+            let closes: String = repeat(close).take(n).collect();
+            format!("SELECT {}1{}", opens, closes)
+        };
+
+        let parens = |n| nest("(", ")", n);
+        assert!(parse_statement(&parens(98)).is_ok());
+
+        let e = parse_statement(&parens(100)).unwrap_err();
+        assert_eq!(e.to_string(), "Expressions and subqueries can't be nested more than 100 deep at line 1, column 107");
+
+        // A subquery is nested in an expression as well as a SELECT
+        let subqueries = |n| nest("(SELECT ", ")", n);
+        assert!(parse_statement_with_max_depth(&subqueries(4), 10).is_ok());
+        assert!(parse_statement_with_max_depth(&subqueries(5), 10).is_err());
+
+        // Each operator of a chain nests the operators before it
+        let chain = |n| format!("SELECT 1{}", repeat(" + 1").take(n).collect::<String>());
+        assert!(parse_statement_with_max_depth(&chain(5), 10).is_ok());
+        assert!(parse_statement_with_max_depth(&chain(10), 10).is_err());
+        assert!(parse_statement(&chain(100000)).is_err());
+        assert!(parse_statement_with_max_depth("SELECT 1 IN (SELECT 1) = 1 AND 2", 4).is_err());
+
+        // A chain of ANDs or ORs is balanced, so it doesn't nest as deeply as
+        // it's long
+        let conditions = |n: u32| (1..n).fold("SELECT a FROM t WHERE a = 0".to_string(), |sql, i| format!("{} OR a = {}", sql, i));
+        assert!(parse_statement(&conditions(99)).is_ok());
+        assert!(parse_statement_with_max_depth(&conditions(10000), 10).is_ok());
+    }
+
+    #[test]
+    fn test_sql_parser_recovery() {
+        use super::lexer;
+        use super::parser;
+
+        let (tokens, spans) = lexer::parse_with_spans("
+            SELECT a FROM t;
+            SELECT FROM WHERE;
+            ;
+            INSERT INTO t VALUES (1);
+            SELECT b FROM t SELECT c FROM t;
+            SELECT d FROM t;
+            SELECT e FROM
+        ").unwrap();
+
+        let (statements, errors) = parser::parse_statements_recovering(&tokens, &spans);
+        assert_eq!(statements.len(), 3);
+        assert_eq!(errors.len(), 3);
+
+        let lines: Vec<u32> = errors.iter().map(|e| e.span().map_or(0, |s| s.line)).collect();
+        // The last error ran out of tokens, so it has no location
+        assert_eq!(lines, vec![3, 6, 0]);
+    }
 }
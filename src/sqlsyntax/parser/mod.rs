@@ -3,15 +3,47 @@
 use std::marker::{PhantomData, Sized};
 use std::fmt;
 
-use super::lexer::Token;
+use super::lexer::{Span, Token};
 use super::ast::*;
 
 mod tokens;
 use self::tokens::Tokens;
 
+/// Converts the text of a number token into its value.
+/// The lexer guarantees the text is a well-formed number.
+fn parse_number_literal(s: &str) -> NumberLiteral {
+    let is_integer = s.chars().all(|c| match c {
+        '0'...'9' => true,
+        _ => false
+    });
+
+    match (is_integer, s.parse()) {
+        (true, Ok(n)) => NumberLiteral::Integer(n),
+        _ => NumberLiteral::Float(s.parse().unwrap())
+    }
+}
+
+/// Expressions and subqueries can be nested this deeply by default.
+pub const DEFAULT_MAX_DEPTH: u32 = 100;
+
+/// The last field is the location of the unexpected token, if known.
 pub enum RuleError {
-    ExpectingFirst(&'static str, Option<Token>),
-    Expecting(&'static str, Option<Token>)
+    ExpectingFirst(&'static str, Option<Token>, Option<Span>),
+    Expecting(&'static str, Option<Token>, Option<Span>),
+    /// Expressions or subqueries are nested more deeply than the limit
+    TooDeep(u32, Option<Span>)
+}
+
+impl RuleError {
+    /// The location of the unexpected token.
+    /// Returns None if the tokens ran out, or if the token locations weren't provided.
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            &RuleError::ExpectingFirst(_, _, span) => span,
+            &RuleError::Expecting(_, _, span) => span,
+            &RuleError::TooDeep(_, span) => span
+        }
+    }
 }
 
 impl fmt::Display for RuleError {
@@ -19,10 +51,18 @@ impl fmt::Display for RuleError {
         use self::RuleError::*;
 
         match self {
-            &ExpectingFirst(s, Some(ref token)) => write!(f, "Expected {}; got {:?}", s, token),
-            &Expecting(s, Some(ref token)) => write!(f, "Expected {}; got {:?}", s, token),
-            &ExpectingFirst(s, None) => write!(f, "Expected {}; got no more tokens", s),
-            &Expecting(s, None) => write!(f, "Expected {}; got no more tokens", s)
+            &ExpectingFirst(s, ref token, _) | &Expecting(s, ref token, _) => match token {
+                &Some(ref token) => try!(write!(f, "Expected {}; got {:?}", s, token)),
+                &None => try!(write!(f, "Expected {}; got no more tokens", s))
+            },
+            &TooDeep(max_depth, _) => {
+                try!(write!(f, "Expressions and subqueries can't be nested more than {} deep", max_depth))
+            }
+        }
+
+        match self.span() {
+            Some(span) => write!(f, " at line {}, column {}", span.line, span.column),
+            None => Ok(())
         }
     }
 }
@@ -39,7 +79,7 @@ fn rule_result_not_first<T>(rule_result: RuleResult<T>) -> RuleResult<T> {
     use self::RuleError::*;
 
     match rule_result {
-        Err(ExpectingFirst(s, t)) => Err(Expecting(s, t)),
+        Err(ExpectingFirst(s, t, span)) => Err(Expecting(s, t, span)),
         value => value
     }
 }
@@ -173,7 +213,7 @@ impl Rule for BinaryOp {
 }
 
 impl UnaryOp {
-    fn precedence(&self) -> u8 {
+    pub fn precedence(&self) -> u8 {
         use super::ast::UnaryOp::*;
 
         match self {
@@ -184,7 +224,7 @@ impl UnaryOp {
 
 impl BinaryOp {
     /// Operators with a higher precedence have a higher number.
-    fn precedence(&self) -> u8 {
+    pub fn precedence(&self) -> u8 {
         use super::ast::BinaryOp::*;
 
         match self {
@@ -199,6 +239,42 @@ impl BinaryOp {
     }
 }
 
+/// Adds the operands of a chain of `op` to `terms`. A parenthesized chain
+/// of the same operator is split up too, so that it's balanced with the rest
+/// and prints the same way it's parsed.
+fn push_chain_terms(expr: Expression, op: &BinaryOp, terms: &mut Vec<Expression>) {
+    let same_op = match &expr {
+        &Expression::BinaryOp { op: ref expr_op, .. } => expr_op == op,
+        _ => false
+    };
+
+    if !same_op {
+        terms.push(expr);
+    } else if let Expression::BinaryOp { lhs, rhs, .. } = expr {
+        push_chain_terms(*lhs, op, terms);
+        push_chain_terms(*rhs, op, terms);
+    }
+}
+
+/// Joins the next `n` of `terms` with `op`. The left side gets the extra
+/// term, so that a chain of three is left associative as usual.
+fn balanced_chain<I>(terms: &mut I, n: usize, op: &BinaryOp) -> Expression
+where I: Iterator<Item=Expression>
+{
+    if n == 1 {
+        return terms.next().unwrap();
+    }
+
+    let lhs = balanced_chain(terms, (n + 1) / 2, op);
+    let rhs = balanced_chain(terms, n / 2, op);
+
+    Expression::BinaryOp {
+        lhs: Box::new(lhs),
+        rhs: Box::new(rhs),
+        op: op.clone()
+    }
+}
+
 impl Rule for Expression {
     type Output = Expression;
     fn parse(tokens: &mut Tokens) -> RuleResult<Expression> {
@@ -213,15 +289,45 @@ impl Expression {
     /// so this is simple a method that doesn't involve creating different
     /// rules for different precedence levels.
     fn parse_precedence(tokens: &mut Tokens, min_precedence: u8) -> RuleResult<Expression> {
+        tokens.nested(|tokens| Expression::parse_precedence_nested(tokens, min_precedence))
+    }
+
+    fn parse_precedence_nested(tokens: &mut Tokens, min_precedence: u8) -> RuleResult<Expression> {
         let mut expr = try!(Expression::parse_beginning(tokens));
         let mut prev_tokens = *tokens;
 
         // Test for after-expression tokens
-        while let Some(binary_op) = try_notfirst!(BinaryOp::parse_lookahead(tokens)) {
+        loop {
+            // IN binds like the comparison operators
+            if BinaryOp::Equal.precedence() >= min_precedence {
+                if let Some(negated) = Expression::pop_if_in(tokens) {
+                    expr = try_notfirst!(Expression::parse_in(tokens, expr, negated));
+                    prev_tokens = *tokens;
+                    continue;
+                }
+            }
+
+            let binary_op = match try_notfirst!(BinaryOp::parse_lookahead(tokens)) {
+                Some(binary_op) => binary_op,
+                None => break
+            };
+
             let binary_op_precedence = binary_op.precedence();
 
             if binary_op_precedence >= min_precedence {
+                let is_chain = match binary_op {
+                    BinaryOp::And | BinaryOp::Or => true,
+                    _ => false
+                };
+
+                if is_chain {
+                    expr = try_notfirst!(Expression::parse_chain(tokens, expr, binary_op));
+                    prev_tokens = *tokens;
+                    continue;
+                }
+
                 // Assuming left associative
+                try_notfirst!(tokens.deepen());
                 let q = binary_op_precedence + 1;
                 let rhs = try_notfirst!(Expression::parse_precedence(tokens, q));
 
@@ -245,8 +351,80 @@ impl Expression {
         Ok(expr)
     }
 
+    /// Parses the rest of a chain of ANDs or ORs after `first` and the
+    /// first `op`. The chain is built into a tree that's only about log2(n)
+    /// deep, so unlike other operators, these don't count as nesting, and
+    /// long generated conditions can be parsed. AND and OR are associative,
+    /// so the tree means the same as the chain.
+    fn parse_chain(tokens: &mut Tokens, first: Expression, op: BinaryOp) -> RuleResult<Expression> {
+        let q = op.precedence() + 1;
+        let mut terms = Vec::new();
+        push_chain_terms(first, &op, &mut terms);
+
+        loop {
+            let rhs = try_notfirst!(Expression::parse_precedence(tokens, q));
+            push_chain_terms(rhs, &op, &mut terms);
+
+            let mut tokens_copy = *tokens;
+            match try_notfirst!(BinaryOp::parse_lookahead(&mut tokens_copy)) {
+                Some(ref next_op) if next_op == &op => *tokens = tokens_copy,
+                _ => break
+            }
+        }
+
+        let n = terms.len();
+        Ok(balanced_chain(&mut terms.into_iter(), n, &op))
+    }
+
+    /// Parses the subquery after `expr IN`. This is kept out of
+    /// parse_precedence_nested, which every level of nesting goes through,
+    /// so that its stack frame stays small.
+    fn parse_in(tokens: &mut Tokens, expr: Expression, negated: bool) -> RuleResult<Expression> {
+        try!(tokens.deepen());
+        try!(tokens.pop_token_expecting(&Token::LeftParen, "( after IN"));
+        let subquery = try_notfirst!(SelectStatement::parse(tokens));
+        try_notfirst!(tokens.pop_token_expecting(&Token::RightParen, ") after subquery"));
+
+        Ok(Expression::InSubquery {
+            expr: Box::new(expr),
+            subquery: Box::new(subquery),
+            negated: negated
+        })
+    }
+
+    /// Pops `IN` or `NOT IN`. Returns whether the IN is negated.
+    fn pop_if_in(tokens: &mut Tokens) -> Option<bool> {
+        let mut tokens_copy = *tokens;
+
+        if tokens_copy.pop_if_token(&Token::In) {
+            *tokens = tokens_copy;
+            Some(false)
+        } else if tokens_copy.pop_if_token(&Token::Not) && tokens_copy.pop_if_token(&Token::In) {
+            *tokens = tokens_copy;
+            Some(true)
+        } else {
+            None
+        }
+    }
+
     fn parse_beginning(tokens: &mut Tokens) -> RuleResult<Expression> {
-        if tokens.pop_if_token(&Token::Plus) {
+        let negated = tokens.pop_if_token(&Token::Not);
+
+        if negated || tokens.pop_if_token(&Token::Exists) {
+            if negated {
+                // NOT is only supported before EXISTS
+                try_notfirst!(tokens.pop_token_expecting(&Token::Exists, "EXISTS after NOT"));
+            }
+
+            try_notfirst!(tokens.pop_token_expecting(&Token::LeftParen, "( after EXISTS"));
+            let subquery = try_notfirst!(SelectStatement::parse(tokens));
+            try_notfirst!(tokens.pop_token_expecting(&Token::RightParen, ") after subquery"));
+
+            Ok(Expression::Exists {
+                subquery: Box::new(subquery),
+                negated: negated
+            })
+        } else if tokens.pop_if_token(&Token::Plus) {
             // Unary, positive
 
             // There's no point in making a Positive unary operator, so we'll "cheat" and use negate's precedence.
@@ -264,6 +442,14 @@ impl Expression {
                 try!(tokens.pop_token_expecting(&Token::RightParen, ") after subquery"));
                 Ok(Expression::Subquery(Box::new(subquery)))
             } else if let Some(encased_expression) = try!(Expression::parse_lookahead(tokens)) {
+                if tokens.pop_if_token(&Token::Comma) {
+                    // Expression is a row value.
+                    let mut exprs = vec![encased_expression];
+                    exprs.extend(try_notfirst!(Expression::parse_comma_delimited(tokens)));
+                    try_notfirst!(tokens.pop_token_expecting(&Token::RightParen, ") after row value"));
+                    return Ok(Expression::Row(exprs));
+                }
+
                 // Expression is surrounded in parens for precedence.
                 try!(tokens.pop_token_expecting(&Token::RightParen, ") after expression"));
                 Ok(encased_expression)
@@ -279,12 +465,28 @@ impl Expression {
                     try_notfirst!(tokens.pop_token_expecting(&Token::RightParen, ") after aggregate asterisk. e.g. (*)"));
 
                     Ok(Expression::FunctionCallAggregateAll { name: ident })
-                } else {
+                } else if tokens.pop_if_token(&Token::Distinct) {
                     let arguments = try_notfirst!(Expression::parse_comma_delimited(tokens));
-
                     try_notfirst!(tokens.pop_token_expecting(&Token::RightParen, ") after function arguments"));
 
-                    Ok(Expression::FunctionCall { name: ident, arguments: arguments })
+                    // Window functions can't be DISTINCT, so OVER isn't looked for
+                    Ok(Expression::FunctionCall { name: ident, arguments: arguments, distinct: true })
+                } else {
+                    let arguments = if tokens.pop_if_token(&Token::RightParen) {
+                        Vec::new()
+                    } else {
+                        let arguments = try_notfirst!(Expression::parse_comma_delimited(tokens));
+                        try_notfirst!(tokens.pop_token_expecting(&Token::RightParen, ") after function arguments"));
+                        arguments
+                    };
+
+                    if tokens.pop_if_token(&Token::Over) {
+                        let window = try_notfirst!(Window::parse(tokens));
+
+                        Ok(Expression::WindowFunctionCall { name: ident, arguments: arguments, window: Box::new(window) })
+                    } else {
+                        Ok(Expression::FunctionCall { name: ident, arguments: arguments, distinct: false })
+                    }
                 }
             } else if tokens.pop_if_token(&Token::Dot) {
                 let ident2 = try_notfirst!(tokens.pop_ident_expecting("ident after ."));
@@ -296,7 +498,9 @@ impl Expression {
         } else if let Some(string) = tokens.pop_if_string_literal() {
             Ok(Expression::StringLiteral(string))
         } else if let Some(number) = tokens.pop_if_number() {
-            Ok(Expression::Number(number))
+            Ok(Expression::Number(parse_number_literal(&number)))
+        } else if let Some(placeholder) = tokens.pop_if_placeholder() {
+            Ok(Expression::Placeholder(placeholder))
         } else {
             Err(tokens.expecting("identifier or number"))
         }
@@ -342,13 +546,32 @@ impl Rule for TableOrSubquery {
                 alias: alias
             })
         } else if let Some(table) = try!(Table::parse_lookahead(tokens)) {
-            // Table
-            let alias = try_notfirst!(AsAlias::parse_lookahead(tokens));
+            if tokens.pop_if_token(&Token::LeftParen) {
+                // Table function
+                let arguments = if tokens.pop_if_token(&Token::RightParen) {
+                    Vec::new()
+                } else {
+                    let arguments = try_notfirst!(Expression::parse_comma_delimited(tokens));
+                    try_notfirst!(tokens.pop_token_expecting(&Token::RightParen, ") after table function arguments"));
+                    arguments
+                };
 
-            Ok(TableOrSubquery::Table {
-                table: table,
-                alias: alias
-            })
+                let alias = try_notfirst!(AsAlias::parse_lookahead(tokens));
+
+                Ok(TableOrSubquery::Function {
+                    function: table.table_name,
+                    arguments: arguments,
+                    alias: alias
+                })
+            } else {
+                // Table
+                let alias = try_notfirst!(AsAlias::parse_lookahead(tokens));
+
+                Ok(TableOrSubquery::Table {
+                    table: table,
+                    alias: alias
+                })
+            }
         } else {
             Err(tokens.expecting("subquery or table name"))
         }
@@ -377,10 +600,17 @@ impl Rule for SelectStatement {
     type Output = SelectStatement;
     fn parse(tokens: &mut Tokens) -> RuleResult<SelectStatement> {
         try!(tokens.pop_token_expecting(&Token::Select, "SELECT"));
+        tokens.nested(SelectStatement::parse_nested)
+    }
+}
+
+impl SelectStatement {
+    fn parse_nested(tokens: &mut Tokens) -> RuleResult<SelectStatement> {
+        let distinct = tokens.pop_if_token(&Token::Distinct);
 
         let result_columns: Vec<SelectColumn> = try_notfirst!(SelectColumn::parse_comma_delimited(tokens));
 
-        let from = try_notfirst!(From::parse(tokens));
+        let from = try_notfirst!(From::parse_lookahead(tokens));
 
         let where_expr = if tokens.pop_if_token(&Token::Where) {
             Some(try_notfirst!(Expression::parse(tokens)))
@@ -388,19 +618,19 @@ impl Rule for SelectStatement {
             None
         };
 
-        let (group_by, having) = if tokens.pop_if_token(&Token::Group) {
+        let group_by = if tokens.pop_if_token(&Token::Group) {
             try_notfirst!(tokens.pop_token_expecting(&Token::By, "BY after GROUP"));
 
-            let group_exprs = try_notfirst!(Expression::parse_comma_delimited(tokens));
+            try_notfirst!(Expression::parse_comma_delimited(tokens))
+        } else {
+            Vec::new()
+        };
 
-            if tokens.pop_if_token(&Token::Having) {
-                let having_expr = try_notfirst!(Expression::parse(tokens));
-                (group_exprs, Some(having_expr))
-            } else {
-                (group_exprs, None)
-            }
+        // Without GROUP BY, HAVING treats every row as one group
+        let having = if tokens.pop_if_token(&Token::Having) {
+            Some(try_notfirst!(Expression::parse(tokens)))
         } else {
-            (Vec::new(), None)
+            None
         };
 
         let order_by = if tokens.pop_if_token(&Token::Order) {
@@ -411,13 +641,38 @@ impl Rule for SelectStatement {
             Vec::new()
         };
 
+        let limit = try_notfirst!(Limit::parse_lookahead(tokens));
+
         Ok(SelectStatement {
+            distinct: distinct,
             result_columns: result_columns,
             from: from,
             where_expr: where_expr,
             group_by: group_by,
             having: having,
-            order_by: order_by
+            order_by: order_by,
+            limit: limit
+        })
+    }
+}
+
+impl Rule for Limit {
+    type Output = Limit;
+    fn parse(tokens: &mut Tokens) -> RuleResult<Limit> {
+        try!(tokens.pop_token_expecting(&Token::Limit, "LIMIT"));
+
+        let count = try_notfirst!(tokens.pop_integer_expecting("number of rows after LIMIT"));
+
+        // OFFSET isn't a keyword, so it can still be a name elsewhere
+        let offset = if tokens.pop_if_word("offset") {
+            Some(try_notfirst!(tokens.pop_integer_expecting("number of rows after OFFSET")))
+        } else {
+            None
+        };
+
+        Ok(Limit {
+            count: count,
+            offset: offset
         })
     }
 }
@@ -427,26 +682,72 @@ impl Rule for From {
     fn parse(tokens: &mut Tokens) -> RuleResult<From> {
         try!(tokens.pop_token_expecting(&Token::From, "FROM"));
 
-        let tables = try_notfirst!(TableOrSubquery::parse_comma_delimited(tokens));
+        // Subqueries can nest deeply, so the joins are parsed here rather
+        // than through JoinTree::parse, to keep the stack shallow.
+        let first = try_notfirst!(JoinTree::parse_operand(tokens));
 
-        if tables.len() == 1 {
-            // Could add a JOIN clause
-            let joins = try_notfirst!(Join::parse_series_star(tokens));
+        match try_notfirst!(JoinTree::parse_joins(tokens, first)) {
+            JoinTree::Join(join) => Ok(From::Join(join)),
+            JoinTree::Table(table) => {
+                // Tables separated by commas can't be joined
+                let mut tables = vec![table];
 
-            if joins.len() > 0 {
-                let table = tables.into_iter().nth(0).unwrap();
+                while tokens.pop_if_token(&Token::Comma) {
+                    tables.push(try_notfirst!(TableOrSubquery::parse(tokens)));
+                }
 
-                Ok(From::Join {
-                    table: table,
-                    joins: joins
-                })
-            } else {
                 Ok(From::Cross(tables))
             }
+        }
+    }
+}
+
+impl Rule for JoinTree {
+    type Output = JoinTree;
+    fn parse(tokens: &mut Tokens) -> RuleResult<JoinTree> {
+        let first = try!(JoinTree::parse_operand(tokens));
+        JoinTree::parse_joins(tokens, first)
+    }
+}
+
+impl JoinTree {
+    /// A table, or joins in parentheses
+    fn parse_operand(tokens: &mut Tokens) -> RuleResult<JoinTree> {
+        // A subquery starts with a parenthesis too
+        let mut lookahead = *tokens;
+
+        if lookahead.pop_if_token(&Token::LeftParen) && !lookahead.pop_if_token(&Token::Select) {
+            try!(tokens.pop_token_expecting(&Token::LeftParen, "("));
+            let tree = try_notfirst!(tokens.nested(JoinTree::parse));
+            try_notfirst!(tokens.pop_token_expecting(&Token::RightParen, ") after joins"));
+            Ok(tree)
         } else {
-            Ok(From::Cross(tables))
+            TableOrSubquery::parse(tokens).map(JoinTree::Table)
         }
     }
+
+    /// Parses any joins after `tree`, which becomes their left side
+    fn parse_joins(tokens: &mut Tokens, mut tree: JoinTree) -> RuleResult<JoinTree> {
+        while let Some(operator) = try!(JoinOperator::parse_lookahead(tokens)) {
+            let rhs = try_notfirst!(JoinTree::parse_operand(tokens));
+
+            let on = if operator == JoinOperator::Cross {
+                None
+            } else {
+                try_notfirst!(tokens.pop_token_expecting(&Token::On, "ON"));
+                Some(try_notfirst!(Expression::parse(tokens)))
+            };
+
+            tree = JoinTree::Join(Join {
+                operator: operator,
+                lhs: Box::new(tree),
+                rhs: Box::new(rhs),
+                on: on
+            });
+        }
+
+        Ok(tree)
+    }
 }
 
 impl Rule for JoinOperator {
@@ -461,28 +762,102 @@ impl Rule for JoinOperator {
         } else if tokens.pop_if_token(&Token::Inner) {
             try_notfirst!(tokens.pop_token_expecting(&Token::Join, "JOIN after INNER"));
             Ok(JoinOperator::Inner)
+        } else if tokens.pop_if_token(&Token::Cross) {
+            try_notfirst!(tokens.pop_token_expecting(&Token::Join, "JOIN after CROSS"));
+            Ok(JoinOperator::Cross)
+        } else if tokens.pop_if_token(&Token::Join) {
+            Ok(JoinOperator::Inner)
         } else {
-            Err(tokens.expecting("Join operator (LEFT or INNER)"))
+            Err(tokens.expecting("Join operator (JOIN, LEFT, INNER or CROSS)"))
         }
     }
 }
 
-impl Rule for Join {
-    type Output = Join;
-    fn parse(tokens: &mut Tokens) -> RuleResult<Join> {
-        let operator = try!(JoinOperator::parse(tokens));
-        let table = try_notfirst!(TableOrSubquery::parse(tokens));
-        try_notfirst!(tokens.pop_token_expecting(&Token::On, "ON"));
-        let on = try_notfirst!(Expression::parse(tokens));
+impl Rule for Window {
+    type Output = Window;
+    fn parse(tokens: &mut Tokens) -> RuleResult<Window> {
+        try!(tokens.pop_token_expecting(&Token::LeftParen, "( after OVER"));
 
-        Ok(Join {
-            operator: operator,
-            table: table,
-            on: on
+        let partition_by = if tokens.pop_if_token(&Token::Partition) {
+            try_notfirst!(tokens.pop_token_expecting(&Token::By, "BY after PARTITION"));
+            try_notfirst!(Expression::parse_comma_delimited(tokens))
+        } else {
+            Vec::new()
+        };
+
+        let order_by = if tokens.pop_if_token(&Token::Order) {
+            try_notfirst!(tokens.pop_token_expecting(&Token::By, "BY after ORDER"));
+            try_notfirst!(OrderingTerm::parse_comma_delimited(tokens))
+        } else {
+            Vec::new()
+        };
+
+        let frame = if tokens.pop_if_token(&Token::Rows) {
+            Some(try_notfirst!(Frame::parse(tokens)))
+        } else {
+            None
+        };
+
+        try_notfirst!(tokens.pop_token_expecting(&Token::RightParen, ") after window"));
+
+        Ok(Window {
+            partition_by: partition_by,
+            order_by: order_by,
+            frame: frame
         })
     }
 }
 
+impl Rule for Frame {
+    type Output = Frame;
+    fn parse(tokens: &mut Tokens) -> RuleResult<Frame> {
+        if tokens.pop_if_token(&Token::Between) {
+            let start = try!(FrameBound::parse(tokens));
+            try_notfirst!(tokens.pop_token_expecting(&Token::And, "AND after frame start"));
+            let end = try_notfirst!(FrameBound::parse(tokens));
+
+            Ok(Frame {
+                start: start,
+                end: end
+            })
+        } else {
+            // ROWS start is short for ROWS BETWEEN start AND CURRENT ROW
+            let start = try!(FrameBound::parse(tokens));
+
+            Ok(Frame {
+                start: start,
+                end: FrameBound::CurrentRow
+            })
+        }
+    }
+}
+
+impl Rule for FrameBound {
+    type Output = FrameBound;
+    fn parse(tokens: &mut Tokens) -> RuleResult<FrameBound> {
+        if tokens.pop_if_token(&Token::Unbounded) {
+            if tokens.pop_if_token(&Token::Preceding) {
+                Ok(FrameBound::UnboundedPreceding)
+            } else {
+                try_notfirst!(tokens.pop_token_expecting(&Token::Following, "PRECEDING or FOLLOWING after UNBOUNDED"));
+                Ok(FrameBound::UnboundedFollowing)
+            }
+        } else if tokens.pop_if_token(&Token::Current) {
+            try_notfirst!(tokens.pop_token_expecting(&Token::Row, "ROW after CURRENT"));
+            Ok(FrameBound::CurrentRow)
+        } else if let Some(rows) = tokens.pop_if_number() {
+            if tokens.pop_if_token(&Token::Preceding) {
+                Ok(FrameBound::Preceding(rows))
+            } else {
+                try_notfirst!(tokens.pop_token_expecting(&Token::Following, "PRECEDING or FOLLOWING after number of rows"));
+                Ok(FrameBound::Following(rows))
+            }
+        } else {
+            Err(tokens.expecting("UNBOUNDED, CURRENT ROW or number of rows"))
+        }
+    }
+}
+
 impl Rule for OrderingTerm {
     type Output = OrderingTerm;
     fn parse(tokens: &mut Tokens) -> RuleResult<OrderingTerm> {
@@ -524,16 +899,79 @@ impl Rule for InsertStatement {
     }
 }
 
+impl Rule for UpdateStatement {
+    type Output = UpdateStatement;
+    fn parse(tokens: &mut Tokens) -> RuleResult<UpdateStatement> {
+        try!(tokens.pop_token_expecting(&Token::Update, "UPDATE"));
+
+        let table = try_notfirst!(Table::parse(tokens));
+        try_notfirst!(tokens.pop_token_expecting(&Token::Set, "SET"));
+        let assignments = try_notfirst!(Assignment::parse_comma_delimited(tokens));
+        let from = try_notfirst!(From::parse_lookahead(tokens));
+
+        let where_expr = if tokens.pop_if_token(&Token::Where) {
+            Some(try_notfirst!(Expression::parse(tokens)))
+        } else {
+            None
+        };
+
+        Ok(UpdateStatement {
+            table: table,
+            assignments: assignments,
+            from: from,
+            where_expr: where_expr
+        })
+    }
+}
+
+impl Rule for Assignment {
+    type Output = Assignment;
+    fn parse(tokens: &mut Tokens) -> RuleResult<Assignment> {
+        let column = try!(tokens.pop_ident_expecting("column name"));
+        try_notfirst!(tokens.pop_token_expecting(&Token::Equal, "="));
+        let expr = try_notfirst!(Expression::parse(tokens));
+
+        Ok(Assignment {
+            column: column,
+            expr: expr
+        })
+    }
+}
+
+impl Rule for DeleteStatement {
+    type Output = DeleteStatement;
+    fn parse(tokens: &mut Tokens) -> RuleResult<DeleteStatement> {
+        try!(tokens.pop_token_expecting(&Token::Delete, "DELETE"));
+        try_notfirst!(tokens.pop_token_expecting(&Token::From, "FROM"));
+
+        let table = try_notfirst!(Table::parse(tokens));
+
+        let where_expr = if tokens.pop_if_token(&Token::Where) {
+            Some(try_notfirst!(Expression::parse(tokens)))
+        } else {
+            None
+        };
+
+        Ok(DeleteStatement {
+            table: table,
+            where_expr: where_expr
+        })
+    }
+}
+
 impl Rule for InsertSource {
     type Output = InsertSource;
     fn parse(tokens: &mut Tokens) -> RuleResult<InsertSource> {
         if tokens.pop_if_token(&Token::Values) {
             let values = try_notfirst!(CommaDelimitedRule::<ParensCommaDelimitedRule<Expression>>::parse(tokens));
             Ok(InsertSource::Values(values))
+        } else if tokens.pop_if_token(&Token::Default) {
+            try_notfirst!(tokens.pop_token_expecting(&Token::Values, "VALUES after DEFAULT"));
+            Ok(InsertSource::DefaultValues)
         } else if let Some(select) = try!(SelectStatement::parse_lookahead(tokens)) {
             Ok(InsertSource::Select(Box::new(select)))
         } else {
-            Err(tokens.expecting("VALUES or SELECT"))
+            Err(tokens.expecting("VALUES, DEFAULT VALUES or SELECT"))
         }
     }
 }
@@ -585,32 +1023,40 @@ impl Rule for CreateTableColumnConstraintType {
     }
 }
 
+/// A column's type name, size and array size, as in `VARCHAR(256)` or
+/// `BYTE[32]`
+fn parse_column_type(tokens: &mut Tokens) -> RuleResult<(String, Option<String>, Option<Option<String>>)> {
+    let type_name = try!(tokens.pop_ident_expecting("type name"));
+
+    let type_size = if tokens.pop_if_token(&Token::LeftParen) {
+        let x = try!(tokens.pop_number_expecting("column type size"));
+        try!(tokens.pop_token_expecting(&Token::RightParen, ")"));
+        Some(x)
+    } else {
+        None
+    };
+
+    let type_array_size = if tokens.pop_if_token(&Token::LeftBracket) {
+        if tokens.pop_if_token(&Token::RightBracket) {
+            // Dynamic array
+            Some(None)
+        } else {
+            let x = try!(tokens.pop_number_expecting("column array size"));
+            try!(tokens.pop_token_expecting(&Token::RightBracket, "]"));
+            Some(Some(x))
+        }
+    } else {
+        None
+    };
+
+    Ok((type_name, type_size, type_array_size))
+}
+
 impl Rule for CreateTableColumn {
     type Output = CreateTableColumn;
     fn parse(tokens: &mut Tokens) -> RuleResult<CreateTableColumn> {
         let column_name = try!(tokens.pop_ident_expecting("column name"));
-        let type_name = try_notfirst!(tokens.pop_ident_expecting("type name"));
-
-        let type_size = if tokens.pop_if_token(&Token::LeftParen) {
-            let x = try!(tokens.pop_number_expecting("column type size"));
-            try!(tokens.pop_token_expecting(&Token::RightParen, ")"));
-            Some(x)
-        } else {
-            None
-        };
-
-        let type_array_size = if tokens.pop_if_token(&Token::LeftBracket) {
-            if tokens.pop_if_token(&Token::RightBracket) {
-                // Dynamic array
-                Some(None)
-            } else {
-                let x = try!(tokens.pop_number_expecting("column array size"));
-                try!(tokens.pop_token_expecting(&Token::RightBracket, "]"));
-                Some(Some(x))
-            }
-        } else {
-            None
-        };
+        let (type_name, type_size, type_array_size) = try_notfirst!(parse_column_type(tokens));
 
         let constraints = try_notfirst!(CreateTableColumnConstraint::parse_series_star(tokens));
 
@@ -627,17 +1073,114 @@ impl Rule for CreateTableColumn {
 impl Rule for CreateTableStatement {
     type Output = CreateTableStatement;
     fn parse(tokens: &mut Tokens) -> RuleResult<CreateTableStatement> {
-        try!(tokens.pop_token_expecting(&Token::Table, "TABLE"));
+        let temporary = tokens.pop_if_token(&Token::Temporary);
+
+        if temporary {
+            try_notfirst!(tokens.pop_token_expecting(&Token::Table, "TABLE after TEMPORARY"));
+        } else {
+            try!(tokens.pop_token_expecting(&Token::Table, "TABLE"));
+        }
 
         let table = try_notfirst!(Table::parse(tokens));
 
-        try_notfirst!(tokens.pop_token_expecting(&Token::LeftParen, "( after table name"));
-        let columns = try_notfirst!(CreateTableColumn::parse_comma_delimited(tokens));
-        try_notfirst!(tokens.pop_token_expecting(&Token::RightParen, ") after table columns and constraints"));
+        let source = if tokens.pop_if_token(&Token::As) {
+            let query = try_notfirst!(SelectStatement::parse(tokens));
+            CreateTableSource::Query(Box::new(query))
+        } else {
+            try_notfirst!(tokens.pop_token_expecting(&Token::LeftParen, "( or AS after table name"));
+            let columns = try_notfirst!(CreateTableColumn::parse_comma_delimited(tokens));
+            try_notfirst!(tokens.pop_token_expecting(&Token::RightParen, ") after table columns and constraints"));
+            CreateTableSource::Columns(columns)
+        };
 
         Ok(CreateTableStatement {
+            temporary: temporary,
+            table: table,
+            source: source
+        })
+    }
+}
+
+impl Rule for CreateMaterializedViewStatement {
+    type Output = CreateMaterializedViewStatement;
+    fn parse(tokens: &mut Tokens) -> RuleResult<CreateMaterializedViewStatement> {
+        try!(tokens.pop_token_expecting(&Token::Materialized, "MATERIALIZED"));
+        try_notfirst!(tokens.pop_token_expecting(&Token::View, "VIEW after MATERIALIZED"));
+
+        let view = try_notfirst!(Table::parse(tokens));
+
+        try_notfirst!(tokens.pop_token_expecting(&Token::As, "AS after view name"));
+        let query = try_notfirst!(SelectStatement::parse(tokens));
+
+        Ok(CreateMaterializedViewStatement {
+            view: view,
+            query: query
+        })
+    }
+}
+
+impl Rule for CreateSequenceStatement {
+    type Output = CreateSequenceStatement;
+    fn parse(tokens: &mut Tokens) -> RuleResult<CreateSequenceStatement> {
+        try!(tokens.pop_token_expecting(&Token::Sequence, "SEQUENCE"));
+
+        let sequence = try_notfirst!(Table::parse(tokens));
+
+        let start = if tokens.pop_if_token(&Token::Start) {
+            tokens.pop_if_token(&Token::With);
+            Some(try_notfirst!(Expression::parse(tokens)))
+        } else {
+            None
+        };
+
+        let increment = if tokens.pop_if_token(&Token::Increment) {
+            tokens.pop_if_token(&Token::By);
+            Some(try_notfirst!(Expression::parse(tokens)))
+        } else {
+            None
+        };
+
+        Ok(CreateSequenceStatement {
+            sequence: sequence,
+            start: start,
+            increment: increment
+        })
+    }
+}
+
+impl Rule for CreateVirtualTableStatement {
+    type Output = CreateVirtualTableStatement;
+    fn parse(tokens: &mut Tokens) -> RuleResult<CreateVirtualTableStatement> {
+        try!(tokens.pop_token_expecting(&Token::Virtual, "VIRTUAL"));
+        try_notfirst!(tokens.pop_token_expecting(&Token::Table, "TABLE after VIRTUAL"));
+
+        let table = try_notfirst!(Table::parse(tokens));
+
+        let columns = if tokens.pop_if_token(&Token::LeftParen) {
+            let columns = try_notfirst!(CreateTableColumn::parse_comma_delimited(tokens));
+            try_notfirst!(tokens.pop_token_expecting(&Token::RightParen, ") after table columns"));
+            Some(columns)
+        } else {
+            None
+        };
+
+        try_notfirst!(tokens.pop_token_expecting(&Token::Using, "USING"));
+        let module = try_notfirst!(tokens.pop_ident_expecting("module name"));
+
+        try_notfirst!(tokens.pop_token_expecting(&Token::LeftParen, "( after module name"));
+        let arguments = if tokens.pop_if_token(&Token::RightParen) {
+            Vec::new()
+        } else {
+            let arguments = try_notfirst!(Expression::parse_comma_delimited(tokens));
+            try_notfirst!(tokens.pop_token_expecting(&Token::RightParen, ") after module arguments"));
+            arguments
+        };
+
+        Ok(CreateVirtualTableStatement {
             table: table,
-            columns: columns
+            columns: columns,
+            module: module,
+            arguments: arguments
         })
     }
 }
@@ -649,12 +1192,130 @@ impl Rule for CreateStatement {
 
         if let Some(stmt) = try_notfirst!(CreateTableStatement::parse_lookahead(tokens)) {
             Ok(CreateStatement::Table(stmt))
+        } else if let Some(stmt) = try_notfirst!(CreateMaterializedViewStatement::parse_lookahead(tokens)) {
+            Ok(CreateStatement::MaterializedView(stmt))
+        } else if let Some(stmt) = try_notfirst!(CreateSequenceStatement::parse_lookahead(tokens)) {
+            Ok(CreateStatement::Sequence(stmt))
+        } else if let Some(stmt) = try_notfirst!(CreateVirtualTableStatement::parse_lookahead(tokens)) {
+            Ok(CreateStatement::VirtualTable(stmt))
         } else {
-            Err(tokens.expecting("TABLE"))
+            Err(tokens.expecting("TABLE, MATERIALIZED VIEW, SEQUENCE, or VIRTUAL TABLE"))
         }
     }
 }
 
+impl Rule for AlterTableStatement {
+    type Output = AlterTableStatement;
+    fn parse(tokens: &mut Tokens) -> RuleResult<AlterTableStatement> {
+        try!(tokens.pop_token_expecting(&Token::Alter, "ALTER"));
+        try_notfirst!(tokens.pop_token_expecting(&Token::Table, "TABLE after ALTER"));
+
+        let table = try_notfirst!(Table::parse(tokens));
+
+        let action = if tokens.pop_if_token(&Token::Rename) {
+            if tokens.pop_if_token(&Token::To) {
+                let new_name = try_notfirst!(tokens.pop_ident_expecting("new table name after TO"));
+
+                AlterTableAction::RenameTable {
+                    new_name: new_name
+                }
+            } else {
+                tokens.pop_if_token(&Token::Column);
+
+                let column = try_notfirst!(tokens.pop_ident_expecting("TO or column name after RENAME"));
+                try_notfirst!(tokens.pop_token_expecting(&Token::To, "TO after column name"));
+                let new_name = try_notfirst!(tokens.pop_ident_expecting("new column name after TO"));
+
+                AlterTableAction::RenameColumn {
+                    column: column,
+                    new_name: new_name
+                }
+            }
+        } else if tokens.pop_if_word("add") {
+            tokens.pop_if_token(&Token::Column);
+
+            AlterTableAction::AddColumn(try_notfirst!(CreateTableColumn::parse(tokens)))
+        } else if tokens.pop_if_token(&Token::Alter) {
+            tokens.pop_if_token(&Token::Column);
+
+            let column = try_notfirst!(tokens.pop_ident_expecting("column name after ALTER"));
+
+            if !tokens.pop_if_word("type") {
+                return rule_result_not_first(Err(tokens.expecting("TYPE after column name")));
+            }
+
+            let (type_name, type_size, type_array_size) = try_notfirst!(parse_column_type(tokens));
+
+            AlterTableAction::AlterColumnType {
+                column: column,
+                type_name: type_name,
+                type_size: type_size,
+                type_array_size: type_array_size
+            }
+        } else {
+            return rule_result_not_first(Err(tokens.expecting("RENAME, ADD or ALTER after table name")));
+        };
+
+        Ok(AlterTableStatement {
+            table: table,
+            action: action
+        })
+    }
+}
+
+impl Rule for CommentStatement {
+    type Output = CommentStatement;
+    fn parse(tokens: &mut Tokens) -> RuleResult<CommentStatement> {
+        try!(tokens.pop_token_expecting(&Token::Comment, "COMMENT"));
+        try_notfirst!(tokens.pop_token_expecting(&Token::On, "ON after COMMENT"));
+
+        let target = if tokens.pop_if_token(&Token::Table) {
+            CommentTarget::Table(try_notfirst!(Table::parse(tokens)))
+        } else if tokens.pop_if_token(&Token::Column) {
+            let table = try_notfirst!(Table::parse(tokens));
+            try_notfirst!(tokens.pop_token_expecting(&Token::Dot, ". after table name"));
+            let column = try_notfirst!(tokens.pop_ident_expecting("column name after ."));
+
+            CommentTarget::Column {
+                table: table,
+                column: column
+            }
+        } else {
+            return Err(tokens.expecting("TABLE or COLUMN after ON"));
+        };
+
+        try_notfirst!(tokens.pop_token_expecting(&Token::Is, "IS"));
+
+        let comment = if tokens.pop_if_token(&Token::Null) {
+            None
+        } else if let Some(comment) = tokens.pop_if_string_literal() {
+            Some(comment)
+        } else {
+            return Err(tokens.expecting("string or NULL after IS"));
+        };
+
+        Ok(CommentStatement {
+            target: target,
+            comment: comment
+        })
+    }
+}
+
+impl Rule for RefreshStatement {
+    type Output = RefreshStatement;
+    fn parse(tokens: &mut Tokens) -> RuleResult<RefreshStatement> {
+        try!(tokens.pop_token_expecting(&Token::Refresh, "REFRESH"));
+        try_notfirst!(tokens.pop_token_expecting(&Token::Materialized, "MATERIALIZED after REFRESH"));
+        try_notfirst!(tokens.pop_token_expecting(&Token::View, "VIEW after MATERIALIZED"));
+
+        let view = try_notfirst!(Table::parse(tokens));
+
+        Ok(RefreshStatement {
+            view: view
+        })
+    }
+}
+
 impl Rule for ExplainStatement {
     type Output = ExplainStatement;
     fn parse(tokens: &mut Tokens) -> RuleResult<ExplainStatement> {
@@ -668,6 +1329,72 @@ impl Rule for ExplainStatement {
     }
 }
 
+impl Rule for PragmaStatement {
+    type Output = PragmaStatement;
+    fn parse(tokens: &mut Tokens) -> RuleResult<PragmaStatement> {
+        try!(tokens.pop_token_expecting(&Token::Pragma, "PRAGMA"));
+
+        let name = try_notfirst!(tokens.pop_ident_expecting("setting name"));
+
+        let value = if tokens.pop_if_token(&Token::Equal) {
+            // ON is a keyword, but it's a common setting value
+            if tokens.pop_if_token(&Token::On) {
+                Some(Expression::Ident("on".to_string()))
+            } else {
+                Some(try_notfirst!(Expression::parse(tokens)))
+            }
+        } else {
+            None
+        };
+
+        Ok(PragmaStatement {
+            name: name,
+            value: value
+        })
+    }
+}
+
+impl Rule for SetStatement {
+    type Output = SetStatement;
+    fn parse(tokens: &mut Tokens) -> RuleResult<SetStatement> {
+        try!(tokens.pop_token_expecting(&Token::Set, "SET"));
+
+        let name = try_notfirst!(tokens.pop_ident_expecting("variable name"));
+
+        if !tokens.pop_if_token(&Token::To) {
+            try_notfirst!(tokens.pop_token_expecting(&Token::Equal, "= or TO after variable name"));
+        }
+
+        // Like PRAGMA, ON is a value here
+        let value = if tokens.pop_if_token(&Token::On) {
+            Expression::Ident("on".to_string())
+        } else {
+            try_notfirst!(Expression::parse(tokens))
+        };
+
+        Ok(SetStatement {
+            name: name,
+            value: value
+        })
+    }
+}
+
+impl Rule for ShowStatement {
+    type Output = ShowStatement;
+    fn parse(tokens: &mut Tokens) -> RuleResult<ShowStatement> {
+        // SHOW isn't a keyword, so it can still be a name elsewhere
+        if !tokens.pop_if_word("show") {
+            return Err(tokens.expecting("SHOW"));
+        }
+
+        let name = try_notfirst!(tokens.pop_ident_expecting("variable name"));
+
+        Ok(ShowStatement {
+            name: name
+        })
+    }
+}
+
 impl Rule for Statement {
     type Output = Statement;
     fn parse(tokens: &mut Tokens) -> RuleResult<Statement> {
@@ -675,12 +1402,28 @@ impl Rule for Statement {
             Ok(Statement::Select(select))
         } else if let Some(insert) = try!(InsertStatement::parse_lookahead(tokens)) {
             Ok(Statement::Insert(insert))
+        } else if let Some(update) = try!(UpdateStatement::parse_lookahead(tokens)) {
+            Ok(Statement::Update(update))
+        } else if let Some(delete) = try!(DeleteStatement::parse_lookahead(tokens)) {
+            Ok(Statement::Delete(delete))
         } else if let Some(create) = try!(CreateStatement::parse_lookahead(tokens)) {
             Ok(Statement::Create(create))
+        } else if let Some(alter) = try!(AlterTableStatement::parse_lookahead(tokens)) {
+            Ok(Statement::AlterTable(alter))
+        } else if let Some(comment) = try!(CommentStatement::parse_lookahead(tokens)) {
+            Ok(Statement::Comment(comment))
+        } else if let Some(refresh) = try!(RefreshStatement::parse_lookahead(tokens)) {
+            Ok(Statement::Refresh(refresh))
         } else if let Some(explain) = try!(ExplainStatement::parse_lookahead(tokens)) {
             Ok(Statement::Explain(explain))
+        } else if let Some(pragma) = try!(PragmaStatement::parse_lookahead(tokens)) {
+            Ok(Statement::Pragma(pragma))
+        } else if let Some(set) = try!(SetStatement::parse_lookahead(tokens)) {
+            Ok(Statement::Set(set))
+        } else if let Some(show) = try!(ShowStatement::parse_lookahead(tokens)) {
+            Ok(Statement::Show(show))
         } else {
-            Err(tokens.expecting("SELECT, INSERT, CREATE, or EXPLAIN statement"))
+            Err(tokens.expecting("SELECT, INSERT, UPDATE, DELETE, CREATE, ALTER, COMMENT, REFRESH, EXPLAIN, PRAGMA, SET, or SHOW statement"))
         }
     }
 }
@@ -702,8 +1445,18 @@ impl Rule for Statements {
     }
 }
 
-pub fn parse_statement(tokens_slice: &[Token]) -> Result<Statement, RuleError> {
-    let mut tokens = Tokens::new(tokens_slice);
+/// `spans` holds the location of each token, as produced by the lexer.
+/// It may be empty, in which case errors won't report a location.
+pub fn parse_statement(tokens_slice: &[Token], spans: &[Span]) -> Result<Statement, RuleError> {
+    parse_statement_with_max_depth(tokens_slice, spans, DEFAULT_MAX_DEPTH)
+}
+
+/// Like `parse_statement`, but with a limit other than `DEFAULT_MAX_DEPTH` on
+/// how deeply expressions and subqueries can be nested.
+pub fn parse_statement_with_max_depth(tokens_slice: &[Token], spans: &[Span], max_depth: u32)
+-> Result<Statement, RuleError>
+{
+    let mut tokens = Tokens::new(tokens_slice, spans, max_depth);
     let statement = try!(Statement::parse(&mut tokens));
 
     // Pop a semicolon if it's there
@@ -713,9 +1466,45 @@ pub fn parse_statement(tokens_slice: &[Token]) -> Result<Statement, RuleError> {
     Ok(statement)
 }
 
+/// Parses a series of statements separated by semicolons, recovering from errors.
+///
+/// When a statement fails to parse, everything up to and including the next
+/// semicolon is skipped, and parsing resumes with the statement after it.
+/// Returns every statement that parsed, along with every error encountered.
+pub fn parse_statements_recovering(tokens_slice: &[Token], spans: &[Span]) -> (Vec<Statement>, Vec<RuleError>) {
+    let mut tokens = Tokens::new(tokens_slice, spans, DEFAULT_MAX_DEPTH);
+    let mut statements = Vec::new();
+    let mut errors = Vec::new();
+
+    while !tokens.is_empty() {
+        // Empty statements are harmless
+        if tokens.pop_if_token(&Token::Semicolon) {
+            continue;
+        }
+
+        let statement_start = tokens;
+
+        let result = Statement::parse(&mut tokens).and_then(|stmt| {
+            try_notfirst!(tokens.pop_token_expecting(&Token::Semicolon, "semicolon"));
+            Ok(stmt)
+        });
+
+        match result {
+            Ok(stmt) => statements.push(stmt),
+            Err(e) => {
+                errors.push(e);
+                tokens = statement_start;
+                tokens.skip_past_token(&Token::Semicolon);
+            }
+        }
+    }
+
+    (statements, errors)
+}
+
 /// Parses a series of statements separated by semicolons
-pub fn parse_statements(tokens_slice: &[Token]) -> Result<Vec<Statement>, RuleError> {
-    let mut tokens = Tokens::new(tokens_slice);
+pub fn parse_statements(tokens_slice: &[Token], spans: &[Span]) -> Result<Vec<Statement>, RuleError> {
+    let mut tokens = Tokens::new(tokens_slice, spans, DEFAULT_MAX_DEPTH);
     let statements = try!(Statements::parse(&mut tokens));
     try!(tokens.expect_no_more_tokens());
     Ok(statements)
@@ -1,9 +1,14 @@
-use super::super::lexer::Token;
+use super::super::lexer::{Span, Token};
 use super::{RuleError, RuleResult};
 
 #[derive(Copy, Clone)]
 pub struct Tokens<'a> {
-    tokens: &'a [Token]
+    tokens: &'a [Token],
+    /// Either empty, or the span of each token in `tokens`
+    spans: &'a [Span],
+    /// How many expressions and subqueries the parser is inside of
+    depth: u32,
+    max_depth: u32
 }
 
 impl<'a> Tokens<'a> {
@@ -15,14 +20,76 @@ impl<'a> Tokens<'a> {
         }
     }
 
-    pub fn new(tokens: &'a [Token]) -> Tokens<'a> {
+    fn peek_span(&self) -> Option<Span> {
+        self.spans.first().cloned()
+    }
+
+    fn advance(&mut self) {
+        self.tokens = &self.tokens[1..];
+
+        if self.spans.len() > 0 {
+            self.spans = &self.spans[1..];
+        }
+    }
+
+    /// `spans` may be empty if the token locations aren't known.
+    pub fn new(tokens: &'a [Token], spans: &'a [Span], max_depth: u32) -> Tokens<'a> {
+        assert!(spans.is_empty() || spans.len() == tokens.len());
+
         Tokens {
-            tokens: tokens
+            tokens: tokens,
+            spans: spans,
+            depth: 0,
+            max_depth: max_depth
+        }
+    }
+
+    /// Parses with one more level of nesting, failing if that's more than
+    /// `max_depth` levels. This keeps deeply nested SQL from overflowing the
+    /// stack.
+    pub fn nested<T, F>(&mut self, f: F) -> RuleResult<T>
+    where F: FnOnce(&mut Tokens<'a>) -> RuleResult<T>
+    {
+        if self.depth >= self.max_depth {
+            return Err(RuleError::TooDeep(self.max_depth, self.peek_span()));
+        }
+
+        let depth = self.depth;
+        self.depth += 1;
+        let result = f(self);
+        self.depth = depth;
+        result
+    }
+
+    /// Adds a level of nesting until the rule that `nested` is parsing
+    /// returns. Each operator of a chain such as `1 + 1 + 1` nests the
+    /// expression before it one level deeper, without any recursion.
+    pub fn deepen(&mut self) -> RuleResult<()> {
+        if self.depth >= self.max_depth {
+            return Err(RuleError::TooDeep(self.max_depth, self.peek_span()));
+        }
+
+        self.depth += 1;
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tokens.is_empty()
+    }
+
+    /// Skips tokens up to and including the next occurance of `token`.
+    /// If there is none, all remaining tokens are skipped.
+    pub fn skip_past_token(&mut self, token: &Token) {
+        while self.tokens.len() > 0 {
+            let found = &self.tokens[0] == token;
+            self.advance();
+
+            if found { break; }
         }
     }
 
     pub fn expecting(&self, expecting_message: &'static str) -> RuleError {
-        RuleError::ExpectingFirst(expecting_message, self.peek_clone())
+        RuleError::ExpectingFirst(expecting_message, self.peek_clone(), self.peek_span())
     }
 
     pub fn expect_no_more_tokens(&self) -> RuleResult<()> {
@@ -41,7 +108,7 @@ impl<'a> Tokens<'a> {
     pub fn pop_if_token(&mut self, token: &Token) -> bool {
         if self.tokens.len() > 0 {
             if &self.tokens[0] == token {
-                self.tokens = &self.tokens[1..];
+                self.advance();
                 true
             } else {
                 false
@@ -58,7 +125,7 @@ impl<'a> Tokens<'a> {
 
             if let &Token::Number(ref s) = token {
                 let ident = s.clone();
-                self.tokens = &self.tokens[1..];
+                self.advance();
                 Some(ident)
             } else {
                 None
@@ -68,6 +135,19 @@ impl<'a> Tokens<'a> {
         }
     }
 
+    /// `?` is Some(None). `$n` is Some(Some(n)).
+    #[must_use]
+    pub fn pop_if_placeholder(&mut self) -> Option<Option<u32>> {
+        let placeholder = match self.tokens.first() {
+            Some(&Token::PreparedStatementPlaceholder) => None,
+            Some(&Token::NumberedPlaceholder(n)) => Some(n),
+            _ => return None
+        };
+
+        self.advance();
+        Some(placeholder)
+    }
+
     #[must_use]
     pub fn pop_if_string_literal(&mut self) -> Option<String> {
         if self.tokens.len() > 0 {
@@ -75,7 +155,7 @@ impl<'a> Tokens<'a> {
 
             if let &Token::StringLiteral(ref s) = token {
                 let ident = s.clone();
-                self.tokens = &self.tokens[1..];
+                self.advance();
                 Some(ident)
             } else {
                 None
@@ -91,7 +171,7 @@ impl<'a> Tokens<'a> {
 
             if let &Token::Ident(ref s) = token {
                 let ident = s.clone();
-                self.tokens = &self.tokens[1..];
+                self.advance();
                 Some(ident)
             } else {
                 None
@@ -101,13 +181,28 @@ impl<'a> Tokens<'a> {
         }
     }
 
+    /// Pops a word that's only a keyword where it's expected, such as
+    /// `TYPE`, so it can still be used as a name elsewhere.
+    pub fn pop_if_word(&mut self, word: &str) -> bool {
+        let matches = match self.tokens.first() {
+            Some(&Token::Ident(ref s)) => s.to_lowercase() == word,
+            _ => false
+        };
+
+        if matches {
+            self.advance();
+        }
+
+        matches
+    }
+
     pub fn pop_ident_expecting(&mut self, expecting_message: &'static str) -> RuleResult<String> {
         if self.tokens.len() > 0 {
             let token = &self.tokens[0];
 
             if let &Token::Ident(ref s) = token {
                 let ident = s.clone();
-                self.tokens = &self.tokens[1..];
+                self.advance();
                 Ok(ident)
             } else {
                 Err(self.expecting(expecting_message))
@@ -123,7 +218,7 @@ impl<'a> Tokens<'a> {
 
             if let &Token::Number(ref s) = token {
                 let ident = s.clone();
-                self.tokens = &self.tokens[1..];
+                self.advance();
                 Ok(ident)
             } else {
                 Err(self.expecting(expecting_message))
@@ -133,10 +228,26 @@ impl<'a> Tokens<'a> {
         }
     }
 
+    /// Pops a number that's a non-negative integer
+    pub fn pop_integer_expecting(&mut self, expecting_message: &'static str) -> RuleResult<u64> {
+        let n = match self.tokens.first() {
+            Some(&Token::Number(ref s)) => s.parse().ok(),
+            _ => None
+        };
+
+        match n {
+            Some(n) => {
+                self.advance();
+                Ok(n)
+            },
+            None => Err(self.expecting(expecting_message))
+        }
+    }
+
     pub fn pop_expecting(&mut self, expecting_message: &'static str) -> RuleResult<&'a Token> {
         if self.tokens.len() > 0 {
             let token = &self.tokens[0];
-            self.tokens = &self.tokens[1..];
+            self.advance();
             Ok(token)
         } else {
             Err(self.expecting(expecting_message))
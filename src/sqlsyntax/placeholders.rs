@@ -0,0 +1,730 @@
+/// Finds the placeholders of a statement, such as `?` and `$1`.
+///
+/// Each placeholder is a parameter that a value is bound to before execution.
+/// Since the values come from a client, the parameters are enumerated and
+/// checked up front, along with hints about the type each one expects.
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+use super::ast::*;
+
+/// Where a placeholder is used. The context is a hint for the placeholder's type.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PlaceholderContext {
+    /// Compared with or inserted into a named column.
+    /// `tables` are the tables the column may belong to, innermost scope first.
+    Column { tables: Vec<String>, column: String },
+    /// Inserted into a table whose columns aren't listed.
+    /// `position` is the offset of the column, starting at 0.
+    ColumnAt { table: String, position: usize }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Parameter {
+    /// Parameters are numbered from 1.
+    /// Placeholders with the same number are the same parameter.
+    pub number: u32,
+    /// The contexts of each use of the parameter, when known.
+    pub contexts: Vec<PlaceholderContext>
+}
+
+#[derive(Debug, PartialEq)]
+pub enum PlaceholderError {
+    /// Both `?` and `$n` placeholders are used
+    MixedStyles,
+    /// `$0` is used; numbers start at 1
+    ZeroNumber,
+    /// A higher number is used, but not this one
+    MissingNumber(u32),
+    /// The amount of values bound isn't the amount of parameters
+    ValueCount { parameters: u32, values: u32 }
+}
+
+impl fmt::Display for PlaceholderError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match self {
+            &PlaceholderError::MixedStyles => write!(f, "cannot mix ? and $n placeholders"),
+            &PlaceholderError::ZeroNumber => write!(f, "placeholder numbers start at $1"),
+            &PlaceholderError::MissingNumber(n) => write!(f, "placeholder ${} is missing", n),
+            &PlaceholderError::ValueCount { parameters, values } => {
+                write!(f, "the statement has {} parameters, but {} values were given", parameters, values)
+            }
+        }
+    }
+}
+
+/// Enumerates the parameters of a statement, ordered by number.
+///
+/// `?` placeholders are numbered in the order they appear.
+/// `$n` placeholders must use every number from 1 up to the highest.
+pub fn find_parameters(stmt: &Statement) -> Result<Vec<Parameter>, PlaceholderError> {
+    let mut finder = Finder {
+        scopes: Vec::new(),
+        uses: Vec::new()
+    };
+
+    match stmt {
+        &Statement::Select(ref select) => finder.select(select),
+        &Statement::Insert(ref insert) => finder.insert(insert),
+        &Statement::Update(ref update) => finder.update(update),
+        &Statement::Delete(ref delete) => finder.delete(delete),
+        &Statement::Create(CreateStatement::Table(ref table)) => {
+            if let CreateTableSource::Query(ref query) = table.source {
+                finder.select(query);
+            }
+        },
+        &Statement::Create(CreateStatement::MaterializedView(ref view)) => finder.select(&view.query),
+        &Statement::Create(CreateStatement::Sequence(ref sequence)) => {
+            for expr in sequence.start.iter().chain(sequence.increment.iter()) {
+                finder.expression(expr);
+            }
+        },
+        &Statement::Create(CreateStatement::VirtualTable(ref table)) => {
+            for expr in table.arguments.iter() {
+                finder.expression(expr);
+            }
+        },
+        &Statement::AlterTable(_) => (),
+        &Statement::Comment(_) => (),
+        &Statement::Refresh(_) => (),
+        &Statement::Pragma(ref pragma) => {
+            if let Some(ref value) = pragma.value {
+                finder.expression(value);
+            }
+        },
+        &Statement::Set(ref set) => finder.expression(&set.value),
+        &Statement::Show(_) => (),
+        &Statement::Explain(ExplainStatement::Select(ref select)) => finder.select(select)
+    }
+
+    let anonymous = finder.uses.iter().filter(|&&(number, _)| number.is_none()).count();
+
+    if anonymous > 0 {
+        if anonymous < finder.uses.len() {
+            return Err(PlaceholderError::MixedStyles);
+        }
+
+        return Ok(finder.uses.into_iter().enumerate().map(|(i, (_, context))| {
+            Parameter {
+                number: i as u32 + 1,
+                contexts: context.into_iter().collect()
+            }
+        }).collect());
+    }
+
+    // Only the numbers used are kept, so a huge number can't allocate a
+    // parameter for each number below it
+    let mut parameters: BTreeMap<u32, Parameter> = BTreeMap::new();
+
+    for (number, context) in finder.uses {
+        let number = match number {
+            Some(0) => return Err(PlaceholderError::ZeroNumber),
+            Some(n) => n,
+            None => unreachable!()
+        };
+
+        parameters.entry(number).or_insert(Parameter { number: number, contexts: Vec::new() })
+            .contexts.extend(context.into_iter());
+    }
+
+    // The numbers are 1 to n, with no gaps
+    for (i, &number) in parameters.keys().enumerate() {
+        if number != i as u32 + 1 {
+            return Err(PlaceholderError::MissingNumber(i as u32 + 1));
+        }
+    }
+
+    Ok(parameters.into_iter().map(|(_, parameter)| parameter).collect())
+}
+
+/// Replaces the placeholders of a statement with values, such as literals.
+/// The values are ordered by parameter number, as `find_parameters`
+/// enumerates them.
+pub fn bind_parameters(stmt: &mut Statement, values: &[Expression]) -> Result<(), PlaceholderError> {
+    let parameter_count = try!(find_parameters(stmt)).len();
+
+    if parameter_count != values.len() {
+        return Err(PlaceholderError::ValueCount {
+            parameters: parameter_count as u32,
+            values: values.len() as u32
+        });
+    }
+
+    let mut binder = Binder {
+        values: values,
+        next: 0
+    };
+
+    match stmt {
+        &mut Statement::Select(ref mut select) => binder.select(select),
+        &mut Statement::Insert(ref mut insert) => {
+            match insert.source {
+                InsertSource::Values(ref mut rows) => {
+                    for expr in rows.iter_mut().flat_map(|row| row.iter_mut()) {
+                        binder.expression(expr);
+                    }
+                },
+                InsertSource::DefaultValues => (),
+                InsertSource::Select(ref mut select) => binder.select(select)
+            }
+        },
+        &mut Statement::Update(ref mut update) => {
+            for assignment in update.assignments.iter_mut() {
+                binder.expression(&mut assignment.expr);
+            }
+
+            if let Some(ref mut from) = update.from {
+                binder.from(from);
+            }
+
+            if let Some(ref mut expr) = update.where_expr {
+                binder.expression(expr);
+            }
+        },
+        &mut Statement::Delete(ref mut delete) => {
+            if let Some(ref mut expr) = delete.where_expr {
+                binder.expression(expr);
+            }
+        },
+        &mut Statement::Create(CreateStatement::Table(ref mut table)) => {
+            if let CreateTableSource::Query(ref mut query) = table.source {
+                binder.select(query);
+            }
+        },
+        &mut Statement::Create(CreateStatement::MaterializedView(ref mut view)) => binder.select(&mut view.query),
+        &mut Statement::Create(CreateStatement::Sequence(ref mut sequence)) => {
+            for expr in sequence.start.iter_mut().chain(sequence.increment.iter_mut()) {
+                binder.expression(expr);
+            }
+        },
+        &mut Statement::Create(CreateStatement::VirtualTable(ref mut table)) => {
+            for expr in table.arguments.iter_mut() {
+                binder.expression(expr);
+            }
+        },
+        &mut Statement::AlterTable(_) => (),
+        &mut Statement::Comment(_) => (),
+        &mut Statement::Refresh(_) => (),
+        &mut Statement::Pragma(ref mut pragma) => {
+            if let Some(ref mut value) = pragma.value {
+                binder.expression(value);
+            }
+        },
+        &mut Statement::Set(ref mut set) => binder.expression(&mut set.value),
+        &mut Statement::Show(_) => (),
+        &mut Statement::Explain(ExplainStatement::Select(ref mut select)) => binder.select(select)
+    }
+
+    Ok(())
+}
+
+/// Walks a statement in the same order as `Finder`, so that `?`
+/// placeholders get the same numbers
+struct Binder<'a> {
+    values: &'a [Expression],
+    /// The index of the value for the next `?`
+    next: usize
+}
+
+impl<'a> Binder<'a> {
+    fn select(&mut self, select: &mut SelectStatement) {
+        for column in select.result_columns.iter_mut() {
+            if let &mut SelectColumn::Expr { ref mut expr, .. } = column {
+                self.expression(expr);
+            }
+        }
+
+        if let Some(ref mut from) = select.from {
+            self.from(from);
+        }
+
+        if let Some(ref mut expr) = select.where_expr {
+            self.expression(expr);
+        }
+
+        for expr in select.group_by.iter_mut() {
+            self.expression(expr);
+        }
+
+        if let Some(ref mut expr) = select.having {
+            self.expression(expr);
+        }
+
+        for term in select.order_by.iter_mut() {
+            self.expression(&mut term.expr);
+        }
+    }
+
+    fn from(&mut self, from: &mut From) {
+        match from {
+            &mut From::Cross(ref mut tables) => {
+                for table in tables.iter_mut() {
+                    self.table_or_subquery(table);
+                }
+            },
+            &mut From::Join(ref mut join) => {
+                let (tables, constraints) = join.parts_mut();
+
+                for table in tables {
+                    self.table_or_subquery(table);
+                }
+
+                for on in constraints {
+                    self.expression(on);
+                }
+            }
+        }
+    }
+
+    fn table_or_subquery(&mut self, table_or_subquery: &mut TableOrSubquery) {
+        match table_or_subquery {
+            &mut TableOrSubquery::Subquery { ref mut subquery, .. } => self.select(subquery),
+            &mut TableOrSubquery::Function { ref mut arguments, .. } => {
+                for argument in arguments.iter_mut() {
+                    self.expression(argument);
+                }
+            },
+            &mut TableOrSubquery::Table { .. } => ()
+        }
+    }
+
+    fn expression(&mut self, expr: &mut Expression) {
+        let placeholder = match *expr {
+            Expression::Placeholder(number) => Some(number),
+            _ => None
+        };
+
+        if let Some(number) = placeholder {
+            let index = match number {
+                Some(n) => n as usize - 1,
+                None => {
+                    self.next += 1;
+                    self.next - 1
+                }
+            };
+
+            *expr = self.values[index].clone();
+            return;
+        }
+
+        match expr {
+            &mut Expression::FunctionCall { ref mut arguments, .. } => {
+                for argument in arguments.iter_mut() {
+                    self.expression(argument);
+                }
+            },
+            &mut Expression::WindowFunctionCall { ref mut arguments, ref mut window, .. } => {
+                for expr in arguments.iter_mut().chain(window.partition_by.iter_mut()) {
+                    self.expression(expr);
+                }
+                for term in window.order_by.iter_mut() {
+                    self.expression(&mut term.expr);
+                }
+            },
+            &mut Expression::UnaryOp { ref mut expr, .. } => self.expression(expr),
+            &mut Expression::BinaryOp { ref mut lhs, ref mut rhs, .. } => {
+                self.expression(lhs);
+                self.expression(rhs);
+            },
+            &mut Expression::Subquery(ref mut select) => self.select(select),
+            &mut Expression::Exists { ref mut subquery, .. } => self.select(subquery),
+            &mut Expression::InSubquery { ref mut expr, ref mut subquery, .. } => {
+                self.expression(expr);
+                self.select(subquery);
+            },
+            &mut Expression::Row(ref mut exprs) => {
+                for expr in exprs.iter_mut() {
+                    self.expression(expr);
+                }
+            },
+            _ => ()
+        }
+    }
+}
+
+/// A table in a FROM clause
+struct ScopeTable<'a> {
+    name: &'a str,
+    alias: Option<&'a str>
+}
+
+struct Finder<'a> {
+    /// The tables of each enclosing SELECT, outermost first
+    scopes: Vec<Vec<ScopeTable<'a>>>,
+    /// Each placeholder in order of appearance
+    uses: Vec<(Option<u32>, Option<PlaceholderContext>)>
+}
+
+impl<'a> Finder<'a> {
+    /// The tables of a FROM, its subqueries and table functions, and its
+    /// join constraints
+    fn from_parts(from: &'a Option<From>)
+    -> (Vec<ScopeTable<'a>>, Vec<&'a TableOrSubquery>, Vec<&'a Expression>)
+    {
+        let mut tables = Vec::new();
+        // Subqueries and table functions
+        let mut subqueries = Vec::new();
+        let mut join_constraints = Vec::new();
+
+        {
+            let mut add = |table_or_subquery: &'a TableOrSubquery| {
+                match table_or_subquery {
+                    &TableOrSubquery::Table { ref table, ref alias } => {
+                        tables.push(ScopeTable {
+                            name: &table.table_name,
+                            alias: alias.as_ref().map(|s| s as &str)
+                        });
+                    },
+                    table_or_subquery => subqueries.push(table_or_subquery)
+                }
+            };
+
+            match from {
+                &Some(From::Cross(ref v)) => {
+                    for t in v { add(t); }
+                },
+                &Some(From::Join(ref join)) => {
+                    let (join_tables, constraints) = join.parts();
+
+                    for t in join_tables { add(t); }
+                    join_constraints = constraints;
+                },
+                &None => ()
+            }
+        }
+
+        (tables, subqueries, join_constraints)
+    }
+
+    /// Subqueries and table functions in FROM can't see the tables beside
+    /// them
+    fn from_subqueries(&mut self, subqueries: Vec<&'a TableOrSubquery>) {
+        let tables = self.scopes.pop().unwrap();
+
+        for subquery in subqueries {
+            match subquery {
+                &TableOrSubquery::Subquery { ref subquery, .. } => self.select(subquery),
+                &TableOrSubquery::Function { ref arguments, .. } => {
+                    for argument in arguments.iter() {
+                        self.expression(argument);
+                    }
+                },
+                &TableOrSubquery::Table { .. } => ()
+            }
+        }
+
+        self.scopes.push(tables);
+    }
+
+    fn select(&mut self, select: &'a SelectStatement) {
+        let (tables, subqueries, join_constraints) = Finder::from_parts(&select.from);
+
+        // Result columns are written before FROM, but can see its tables
+        self.scopes.push(tables);
+
+        for column in select.result_columns.iter() {
+            if let &SelectColumn::Expr { ref expr, .. } = column {
+                self.expression(expr);
+            }
+        }
+
+        self.from_subqueries(subqueries);
+
+        for expr in join_constraints {
+            self.expression(expr);
+        }
+
+        if let Some(ref expr) = select.where_expr {
+            self.expression(expr);
+        }
+
+        for expr in select.group_by.iter() {
+            self.expression(expr);
+        }
+
+        if let Some(ref expr) = select.having {
+            self.expression(expr);
+        }
+
+        for term in select.order_by.iter() {
+            self.expression(&term.expr);
+        }
+
+        self.scopes.pop();
+    }
+
+    fn insert(&mut self, insert: &'a InsertStatement) {
+        let table = &insert.table.table_name;
+
+        match &insert.source {
+            &InsertSource::Values(ref rows) => {
+                for row in rows {
+                    for (position, expr) in row.iter().enumerate() {
+                        if let &Expression::Placeholder(number) = expr {
+                            let context = match insert.into_columns {
+                                Some(ref columns) => columns.get(position).map(|column| {
+                                    PlaceholderContext::Column {
+                                        tables: vec![table.clone()],
+                                        column: column.clone()
+                                    }
+                                }),
+                                None => Some(PlaceholderContext::ColumnAt {
+                                    table: table.clone(),
+                                    position: position
+                                })
+                            };
+
+                            self.uses.push((number, context));
+                        } else {
+                            self.expression(expr);
+                        }
+                    }
+                }
+            },
+            &InsertSource::DefaultValues => (),
+            &InsertSource::Select(ref select) => self.select(select)
+        }
+    }
+
+    /// A placeholder assigned to a column as is takes that column's context,
+    /// as in an INSERT
+    fn update(&mut self, update: &'a UpdateStatement) {
+        let table = &update.table.table_name;
+        let (mut tables, subqueries, join_constraints) = Finder::from_parts(&update.from);
+
+        tables.insert(0, ScopeTable {
+            name: table,
+            alias: None
+        });
+        self.scopes.push(tables);
+
+        for assignment in update.assignments.iter() {
+            if let Expression::Placeholder(number) = assignment.expr {
+                self.uses.push((number, Some(PlaceholderContext::Column {
+                    tables: vec![table.clone()],
+                    column: assignment.column.clone()
+                })));
+            } else {
+                self.expression(&assignment.expr);
+            }
+        }
+
+        self.from_subqueries(subqueries);
+
+        for expr in join_constraints {
+            self.expression(expr);
+        }
+
+        if let Some(ref expr) = update.where_expr {
+            self.expression(expr);
+        }
+
+        self.scopes.pop();
+    }
+
+    fn delete(&mut self, delete: &'a DeleteStatement) {
+        if let Some(ref expr) = delete.where_expr {
+            self.scopes.push(vec![ScopeTable {
+                name: &delete.table.table_name,
+                alias: None
+            }]);
+            self.expression(expr);
+            self.scopes.pop();
+        }
+    }
+
+    fn expression(&mut self, expr: &'a Expression) {
+        match expr {
+            &Expression::Placeholder(number) => self.uses.push((number, None)),
+            &Expression::FunctionCall { ref arguments, .. } => {
+                for argument in arguments {
+                    self.expression(argument);
+                }
+            },
+            &Expression::WindowFunctionCall { ref arguments, ref window, .. } => {
+                for expr in arguments.iter().chain(window.partition_by.iter()) {
+                    self.expression(expr);
+                }
+                for term in &window.order_by {
+                    self.expression(&term.expr);
+                }
+            },
+            &Expression::UnaryOp { ref expr, .. } => self.expression(expr),
+            &Expression::BinaryOp { ref lhs, ref rhs, ref op } => {
+                let is_comparison = match op {
+                    &BinaryOp::Equal | &BinaryOp::NotEqual |
+                    &BinaryOp::LessThan | &BinaryOp::LessThanOrEqual |
+                    &BinaryOp::GreaterThan | &BinaryOp::GreaterThanOrEqual => true,
+                    _ => false
+                };
+
+                // Both sides are walked in order, so ? placeholders are numbered correctly
+                match (&**lhs, &**rhs) {
+                    (&Expression::Row(ref lhs), &Expression::Row(ref rhs)) if is_comparison && lhs.len() == rhs.len() => {
+                        // Rows are compared value by value
+                        for (expr, other) in lhs.iter().zip(rhs.iter()).chain(rhs.iter().zip(lhs.iter())) {
+                            self.compared(expr, other);
+                        }
+                    },
+                    _ if is_comparison => {
+                        self.compared(lhs, rhs);
+                        self.compared(rhs, lhs);
+                    },
+                    _ => {
+                        self.expression(lhs);
+                        self.expression(rhs);
+                    }
+                }
+            },
+            &Expression::Subquery(ref select) => self.select(select),
+            &Expression::Exists { ref subquery, .. } => self.select(subquery),
+            &Expression::InSubquery { ref expr, ref subquery, .. } => {
+                self.expression(expr);
+                self.select(subquery);
+            },
+            &Expression::Row(ref exprs) => {
+                for expr in exprs {
+                    self.expression(expr);
+                }
+            },
+            &Expression::Ident(_) |
+            &Expression::IdentMember(..) |
+            &Expression::StringLiteral(_) |
+            &Expression::Number(_) |
+            &Expression::Null |
+            &Expression::FunctionCallAggregateAll { .. } => ()
+        }
+    }
+
+    /// Walks `expr`, which is compared with `other`. A placeholder compared
+    /// with a column takes the column's context.
+    fn compared(&mut self, expr: &'a Expression, other: &'a Expression) {
+        if let &Expression::Placeholder(number) = expr {
+            let context = self.column_context(other);
+            self.uses.push((number, context));
+        } else {
+            self.expression(expr);
+        }
+    }
+
+    /// The context of a placeholder that's compared with `expr`, if `expr` is a column.
+    fn column_context(&self, expr: &Expression) -> Option<PlaceholderContext> {
+        fn matches(name: &str, other: &str) -> bool {
+            name.to_lowercase() == other.to_lowercase()
+        }
+
+        let (qualifier, column) = match expr {
+            &Expression::Ident(ref column) => (None, column),
+            &Expression::IdentMember(ref qualifier, ref column) => (Some(qualifier), column),
+            _ => return None
+        };
+
+        let tables = self.scopes.iter().rev().flat_map(|scope| scope.iter()).filter(|t| {
+            match qualifier {
+                // An alias hides the table's name
+                Some(q) => matches(t.alias.unwrap_or(t.name), q),
+                None => true
+            }
+        }).map(|t| t.name.to_string()).collect();
+
+        Some(PlaceholderContext::Column {
+            tables: tables,
+            column: column.clone()
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{bind_parameters, find_parameters, Parameter, PlaceholderContext, PlaceholderError};
+    use super::super::ast::{Expression, NumberLiteral};
+    use super::super::parse_statement;
+
+    fn find(query: &str) -> Result<Vec<Parameter>, PlaceholderError> {
+        find_parameters(&parse_statement(query).unwrap())
+    }
+
+    fn column(tables: &[&str], column: &str) -> PlaceholderContext {
+        PlaceholderContext::Column {
+            tables: tables.iter().map(|s| s.to_string()).collect(),
+            column: column.to_string()
+        }
+    }
+
+    #[test]
+    fn test_placeholders() {
+        let numbers = |query: &str| find(query).unwrap().iter().map(|p| p.number).collect::<Vec<_>>();
+
+        assert_eq!(numbers("SELECT a FROM t"), vec![]);
+        assert_eq!(numbers("SELECT ?, a + ? FROM t WHERE b = (SELECT ? FROM u)"), vec![1, 2, 3]);
+        assert_eq!(numbers("SELECT $2, $1 FROM t WHERE a = $2"), vec![1, 2]);
+
+        assert_eq!(find("SELECT ?, $1 FROM t"), Err(PlaceholderError::MixedStyles));
+        assert_eq!(find("SELECT $0 FROM t"), Err(PlaceholderError::ZeroNumber));
+        assert_eq!(find("SELECT $1, $3 FROM t"), Err(PlaceholderError::MissingNumber(2)));
+        assert_eq!(find("SELECT $4294967295 FROM t"), Err(PlaceholderError::MissingNumber(1)));
+        assert_eq!(find("SELECT $1, $4294967295 FROM t"), Err(PlaceholderError::MissingNumber(2)));
+    }
+
+    #[test]
+    fn test_placeholder_contexts() {
+        let contexts = |query: &str| find(query).unwrap().into_iter().map(|p| p.contexts).collect::<Vec<_>>();
+
+        assert_eq!(contexts("SELECT ? FROM t x, u WHERE ? < a AND x.b = ? AND u.c + 1 = ?"), vec![
+            vec![],
+            vec![column(&["t", "u"], "a")],
+            vec![column(&["t"], "b")],
+            vec![]
+        ]);
+
+        // Inner scopes are searched first
+        assert_eq!(contexts("SELECT a FROM t WHERE a = (SELECT b FROM u WHERE c = $1) AND d = $1"), vec![
+            vec![column(&["u", "t"], "c"), column(&["t"], "d")]
+        ]);
+
+        // Rows are compared value by value, and their ? placeholders are
+        // numbered in order
+        assert_eq!(contexts("SELECT a FROM t WHERE (a, ?) > (?, b + 1)"), vec![
+            vec![],
+            vec![column(&["t"], "a")]
+        ]);
+
+        assert_eq!(contexts("INSERT INTO t VALUES (?, 1 + ?)"), vec![
+            vec![PlaceholderContext::ColumnAt { table: "t".to_string(), position: 0 }],
+            vec![]
+        ]);
+        assert_eq!(contexts("INSERT INTO t (b, a) VALUES (1, ?)"), vec![vec![column(&["t"], "a")]]);
+        assert_eq!(contexts("DELETE FROM t WHERE a = ? OR b IN (SELECT c FROM u WHERE d > ?)"), vec![
+            vec![column(&["t"], "a")],
+            vec![column(&["u", "t"], "d")]
+        ]);
+        assert_eq!(contexts("UPDATE t SET b = ?, a = a + ? WHERE c = ?"), vec![
+            vec![column(&["t"], "b")],
+            vec![],
+            vec![column(&["t"], "c")]
+        ]);
+        assert_eq!(contexts("UPDATE t SET b = ? FROM u, (SELECT d FROM v WHERE d = ?) AS w WHERE t.a = u.a AND c = ?"), vec![
+            vec![column(&["t"], "b")],
+            vec![column(&["v"], "d")],
+            vec![column(&["t", "u"], "c")]
+        ]);
+    }
+
+    #[test]
+    fn test_bind_parameters() {
+        let bind = |query: &str, values: &[Expression]| -> Result<String, PlaceholderError> {
+            let mut stmt = parse_statement(query).unwrap();
+            try!(bind_parameters(&mut stmt, values));
+            Ok(stmt.to_string())
+        };
+        let values = [Expression::Number(NumberLiteral::Integer(1)), Expression::StringLiteral("x".to_string())];
+
+        assert_eq!(bind("SELECT ? FROM (SELECT a FROM t WHERE b = ?) AS s", &values).unwrap(),
+            "SELECT 1 FROM (SELECT a FROM t WHERE b = 'x') AS s");
+        assert_eq!(bind("INSERT INTO t VALUES ($2, $1 + $1)", &values).unwrap(), "INSERT INTO t VALUES ('x', 1 + 1)");
+        assert_eq!(bind("SELECT ? FROM generate_series(1, ?)", &values).unwrap(), "SELECT 1 FROM generate_series(1, 'x')");
+        assert_eq!(bind("SELECT a FROM t WHERE (a, b) > (?, ?)", &values).unwrap(), "SELECT a FROM t WHERE (a, b) > (1, 'x')");
+        assert_eq!(bind("SELECT ?", &values), Err(PlaceholderError::ValueCount { parameters: 1, values: 2 }));
+    }
+}
@@ -0,0 +1,1132 @@
+/// Formats the AST back into SQL.
+///
+/// The output can be parsed back into an equivalent AST.
+/// Parentheses are only emitted where precedence requires them.
+
+use std::fmt;
+
+use super::ast::*;
+use super::lexer::{self, Token};
+
+/// Writes an identifier, quoting it with backticks if it isn't a plain word.
+fn write_ident(f: &mut fmt::Formatter, name: &str) -> Result<(), fmt::Error> {
+    let is_word = match name.chars().nth(0) {
+        Some(c) => (c.is_alphabetic() || c == '_') && name.chars().all(|c| c.is_alphanumeric() || c == '_'),
+        None => false
+    };
+
+    // Keywords must be quoted, or they'd be read back as keywords.
+    let is_plain = is_word && match lexer::parse(name) {
+        Ok(tokens) => tokens.len() == 1 && tokens[0] == Token::Ident(name.to_string()),
+        Err(_) => false
+    };
+
+    if is_plain {
+        f.write_str(name)
+    } else {
+        write!(f, "`{}`", name)
+    }
+}
+
+fn write_string_literal(f: &mut fmt::Formatter, s: &str) -> Result<(), fmt::Error> {
+    try!(f.write_str("'"));
+    for c in s.chars() {
+        match c {
+            '\'' | '\\' => try!(write!(f, "\\{}", c)),
+            c => try!(write!(f, "{}", c))
+        }
+    }
+    f.write_str("'")
+}
+
+fn write_comma_delimited<T: fmt::Display>(f: &mut fmt::Formatter, items: &[T]) -> Result<(), fmt::Error> {
+    for (i, item) in items.iter().enumerate() {
+        if i > 0 { try!(f.write_str(", ")); }
+        try!(write!(f, "{}", item));
+    }
+    Ok(())
+}
+
+fn write_idents_in_parens(f: &mut fmt::Formatter, names: &[String]) -> Result<(), fmt::Error> {
+    try!(f.write_str("("));
+    for (i, name) in names.iter().enumerate() {
+        if i > 0 { try!(f.write_str(", ")); }
+        try!(write_ident(f, name));
+    }
+    f.write_str(")")
+}
+
+impl fmt::Display for UnaryOp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match self {
+            &UnaryOp::Negate => f.write_str("-")
+        }
+    }
+}
+
+impl fmt::Display for BinaryOp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        use super::ast::BinaryOp::*;
+
+        f.write_str(match self {
+            &Equal => "=",
+            &NotEqual => "<>",
+            &LessThan => "<",
+            &LessThanOrEqual => "<=",
+            &GreaterThan => ">",
+            &GreaterThanOrEqual => ">=",
+            &And => "AND",
+            &Or => "OR",
+            &Add => "+",
+            &Subtract => "-",
+            &Multiply => "*",
+            &Divide => "/",
+            &BitAnd => "&",
+            &BitOr => "|",
+            &Concatenate => "||"
+        })
+    }
+}
+
+impl Expression {
+    /// The precedence of the expression's outermost operator.
+    /// Expressions without operators bind the tightest.
+    fn precedence(&self) -> u8 {
+        match self {
+            &Expression::UnaryOp { ref op, .. } => op.precedence(),
+            &Expression::BinaryOp { ref op, .. } => op.precedence(),
+            // IN binds like the comparison operators
+            &Expression::InSubquery { .. } => BinaryOp::Equal.precedence(),
+            _ => !0
+        }
+    }
+
+    fn write_operand(&self, f: &mut fmt::Formatter, parens: bool) -> Result<(), fmt::Error> {
+        if parens {
+            write!(f, "({})", self)
+        } else {
+            write!(f, "{}", self)
+        }
+    }
+}
+
+impl fmt::Display for NumberLiteral {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match self {
+            &NumberLiteral::Integer(n) => write!(f, "{}", n),
+            // Larger than any finite f64
+            &NumberLiteral::Float(n) if n.is_infinite() => f.write_str("1e999"),
+            &NumberLiteral::Float(n) => {
+                let s = n.to_string();
+                try!(f.write_str(&s));
+
+                // Keep the decimal point, or the number would be read back as an integer
+                if s.contains('.') {
+                    Ok(())
+                } else {
+                    f.write_str(".0")
+                }
+            }
+        }
+    }
+}
+
+impl fmt::Display for Expression {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match self {
+            &Expression::Ident(ref name) => write_ident(f, name),
+            &Expression::IdentMember(ref table, ref name) => {
+                try!(write_ident(f, table));
+                try!(f.write_str("."));
+                write_ident(f, name)
+            },
+            &Expression::StringLiteral(ref s) => write_string_literal(f, s),
+            &Expression::Number(ref n) => write!(f, "{}", n),
+            &Expression::Null => f.write_str("NULL"),
+            &Expression::Placeholder(None) => f.write_str("?"),
+            &Expression::Placeholder(Some(n)) => write!(f, "${}", n),
+            &Expression::FunctionCall { ref name, ref arguments, distinct } => {
+                try!(write_ident(f, name));
+                try!(f.write_str(if distinct { "(DISTINCT " } else { "(" }));
+                try!(write_comma_delimited(f, arguments));
+                f.write_str(")")
+            },
+            &Expression::FunctionCallAggregateAll { ref name } => {
+                try!(write_ident(f, name));
+                f.write_str("(*)")
+            },
+            &Expression::WindowFunctionCall { ref name, ref arguments, ref window } => {
+                try!(write_ident(f, name));
+                try!(f.write_str("("));
+                try!(write_comma_delimited(f, arguments));
+                write!(f, ") OVER ({})", window)
+            },
+            &Expression::UnaryOp { ref expr, ref op } => {
+                try!(write!(f, "{}", op));
+                // Operands that begin with an operator are parenthesized;
+                // "--" would otherwise begin a comment.
+                let parens = expr.precedence() <= op.precedence();
+                expr.write_operand(f, parens)
+            },
+            &Expression::BinaryOp { ref lhs, ref rhs, ref op } => {
+                // Operators are left associative. AND and OR are also
+                // associative, and the parser balances chains of them.
+                let p = op.precedence();
+                let associative = match op {
+                    &BinaryOp::And | &BinaryOp::Or => true,
+                    _ => false
+                };
+                try!(lhs.write_operand(f, lhs.precedence() < p));
+                try!(write!(f, " {} ", op));
+                rhs.write_operand(f, rhs.precedence() < p || (rhs.precedence() == p && !associative))
+            },
+            &Expression::Subquery(ref subquery) => write!(f, "({})", subquery),
+            &Expression::Exists { ref subquery, negated } => {
+                if negated {
+                    try!(f.write_str("NOT "));
+                }
+                write!(f, "EXISTS ({})", subquery)
+            },
+            &Expression::InSubquery { ref expr, ref subquery, negated } => {
+                try!(expr.write_operand(f, expr.precedence() < self.precedence()));
+                if negated {
+                    try!(f.write_str(" NOT"));
+                }
+                write!(f, " IN ({})", subquery)
+            },
+            &Expression::Row(ref exprs) => {
+                try!(f.write_str("("));
+                try!(write_comma_delimited(f, exprs));
+                f.write_str(")")
+            }
+        }
+    }
+}
+
+impl fmt::Display for Window {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        if !self.partition_by.is_empty() {
+            try!(f.write_str("PARTITION BY "));
+            try!(write_comma_delimited(f, &self.partition_by));
+        }
+
+        if !self.order_by.is_empty() {
+            if !self.partition_by.is_empty() {
+                try!(f.write_str(" "));
+            }
+            try!(f.write_str("ORDER BY "));
+            try!(write_comma_delimited(f, &self.order_by));
+        }
+
+        if let Some(ref frame) = self.frame {
+            if !self.partition_by.is_empty() || !self.order_by.is_empty() {
+                try!(f.write_str(" "));
+            }
+            try!(write!(f, "ROWS BETWEEN {} AND {}", frame.start, frame.end));
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for FrameBound {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match self {
+            &FrameBound::UnboundedPreceding => f.write_str("UNBOUNDED PRECEDING"),
+            &FrameBound::Preceding(ref rows) => write!(f, "{} PRECEDING", rows),
+            &FrameBound::CurrentRow => f.write_str("CURRENT ROW"),
+            &FrameBound::Following(ref rows) => write!(f, "{} FOLLOWING", rows),
+            &FrameBound::UnboundedFollowing => f.write_str("UNBOUNDED FOLLOWING")
+        }
+    }
+}
+
+impl fmt::Display for Table {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        if let Some(ref database_name) = self.database_name {
+            try!(write_ident(f, database_name));
+            try!(f.write_str("."));
+        }
+        write_ident(f, &self.table_name)
+    }
+}
+
+impl fmt::Display for TableOrSubquery {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match self {
+            &TableOrSubquery::Subquery { ref subquery, ref alias } => {
+                try!(write!(f, "({}) AS ", subquery));
+                write_ident(f, alias)
+            },
+            &TableOrSubquery::Table { ref table, ref alias } => {
+                try!(write!(f, "{}", table));
+                if let &Some(ref alias) = alias {
+                    try!(f.write_str(" AS "));
+                    try!(write_ident(f, alias));
+                }
+                Ok(())
+            },
+            &TableOrSubquery::Function { ref function, ref arguments, ref alias } => {
+                try!(write_ident(f, function));
+                try!(f.write_str("("));
+                try!(write_comma_delimited(f, arguments));
+                try!(f.write_str(")"));
+                if let &Some(ref alias) = alias {
+                    try!(f.write_str(" AS "));
+                    try!(write_ident(f, alias));
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl fmt::Display for SelectColumn {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match self {
+            &SelectColumn::AllColumns => f.write_str("*"),
+            &SelectColumn::Expr { ref expr, ref alias } => {
+                try!(write!(f, "{}", expr));
+                if let &Some(ref alias) = alias {
+                    try!(f.write_str(" AS "));
+                    try!(write_ident(f, alias));
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl fmt::Display for SelectStatement {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        try!(f.write_str(if self.distinct { "SELECT DISTINCT " } else { "SELECT " }));
+        try!(write_comma_delimited(f, &self.result_columns));
+        if let Some(ref from) = self.from {
+            try!(write!(f, " FROM {}", from));
+        }
+
+        if let Some(ref where_expr) = self.where_expr {
+            try!(write!(f, " WHERE {}", where_expr));
+        }
+
+        if !self.group_by.is_empty() {
+            try!(f.write_str(" GROUP BY "));
+            try!(write_comma_delimited(f, &self.group_by));
+        }
+
+        if let Some(ref having) = self.having {
+            try!(write!(f, " HAVING {}", having));
+        }
+
+        if !self.order_by.is_empty() {
+            try!(f.write_str(" ORDER BY "));
+            try!(write_comma_delimited(f, &self.order_by));
+        }
+
+        if let Some(ref limit) = self.limit {
+            try!(write!(f, " {}", limit));
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for Limit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        try!(write!(f, "LIMIT {}", self.count));
+        if let Some(ref offset) = self.offset {
+            try!(write!(f, " OFFSET {}", offset));
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for From {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match self {
+            &From::Cross(ref tables) => write_comma_delimited(f, tables),
+            &From::Join(ref join) => write!(f, "{}", join)
+        }
+    }
+}
+
+impl fmt::Display for JoinOperator {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match self {
+            &JoinOperator::Left => f.write_str("LEFT JOIN"),
+            &JoinOperator::Inner => f.write_str("INNER JOIN"),
+            &JoinOperator::Cross => f.write_str("CROSS JOIN")
+        }
+    }
+}
+
+impl fmt::Display for Join {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        // Joins are nested in the left side without parentheses
+        try!(write!(f, "{} {} ", self.lhs, self.operator));
+        match *self.rhs {
+            JoinTree::Table(ref table) => try!(write!(f, "{}", table)),
+            JoinTree::Join(ref join) => try!(write!(f, "({})", join))
+        }
+        if let Some(ref on) = self.on {
+            try!(write!(f, " ON {}", on));
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for JoinTree {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match self {
+            &JoinTree::Table(ref table) => write!(f, "{}", table),
+            &JoinTree::Join(ref join) => write!(f, "{}", join)
+        }
+    }
+}
+
+impl fmt::Display for OrderingTerm {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match self.order {
+            Order::Ascending => write!(f, "{}", self.expr),
+            Order::Descending => write!(f, "{} DESC", self.expr)
+        }
+    }
+}
+
+impl fmt::Display for InsertStatement {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        try!(write!(f, "INSERT INTO {} ", self.table));
+
+        if let Some(ref into_columns) = self.into_columns {
+            try!(write_idents_in_parens(f, into_columns));
+            try!(f.write_str(" "));
+        }
+
+        write!(f, "{}", self.source)
+    }
+}
+
+impl fmt::Display for InsertSource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match self {
+            &InsertSource::Values(ref rows) => {
+                try!(f.write_str("VALUES "));
+                for (i, row) in rows.iter().enumerate() {
+                    if i > 0 { try!(f.write_str(", ")); }
+                    try!(f.write_str("("));
+                    try!(write_comma_delimited(f, row));
+                    try!(f.write_str(")"));
+                }
+                Ok(())
+            },
+            &InsertSource::DefaultValues => f.write_str("DEFAULT VALUES"),
+            &InsertSource::Select(ref select) => write!(f, "{}", select)
+        }
+    }
+}
+
+impl fmt::Display for UpdateStatement {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        try!(write!(f, "UPDATE {} SET ", self.table));
+        try!(write_comma_delimited(f, &self.assignments));
+
+        if let Some(ref from) = self.from {
+            try!(write!(f, " FROM {}", from));
+        }
+
+        if let Some(ref where_expr) = self.where_expr {
+            try!(write!(f, " WHERE {}", where_expr));
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for Assignment {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        try!(write_ident(f, &self.column));
+        write!(f, " = {}", self.expr)
+    }
+}
+
+impl fmt::Display for DeleteStatement {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        try!(write!(f, "DELETE FROM {}", self.table));
+
+        if let Some(ref where_expr) = self.where_expr {
+            try!(write!(f, " WHERE {}", where_expr));
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for CreateTableColumnConstraint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        if let Some(ref name) = self.name {
+            try!(f.write_str("CONSTRAINT "));
+            try!(write_ident(f, name));
+            try!(f.write_str(" "));
+        }
+        write!(f, "{}", self.constraint)
+    }
+}
+
+impl fmt::Display for CreateTableColumnConstraintType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        use super::ast::CreateTableColumnConstraintType::*;
+
+        match self {
+            &PrimaryKey => f.write_str("PRIMARY KEY"),
+            &Unique => f.write_str("UNIQUE"),
+            &Nullable => f.write_str("NULL"),
+            &ForeignKey { ref table, ref columns } => {
+                try!(write!(f, "REFERENCES {}", table));
+                if let &Some(ref columns) = columns {
+                    try!(f.write_str(" "));
+                    try!(write_idents_in_parens(f, columns));
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+fn write_type(f: &mut fmt::Formatter, type_name: &str, type_size: &Option<String>,
+    type_array_size: &Option<Option<String>>) -> Result<(), fmt::Error>
+{
+    try!(write_ident(f, type_name));
+
+    if let &Some(ref type_size) = type_size {
+        try!(write!(f, "({})", type_size));
+    }
+
+    match type_array_size {
+        &Some(Some(ref size)) => write!(f, "[{}]", size),
+        &Some(None) => f.write_str("[]"),
+        &None => Ok(())
+    }
+}
+
+impl fmt::Display for CreateTableColumn {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        try!(write_ident(f, &self.column_name));
+        try!(f.write_str(" "));
+        try!(write_type(f, &self.type_name, &self.type_size, &self.type_array_size));
+
+        for constraint in &self.constraints {
+            try!(write!(f, " {}", constraint));
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for CreateTableStatement {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        if self.temporary {
+            try!(write!(f, "CREATE TEMPORARY TABLE {}", self.table));
+        } else {
+            try!(write!(f, "CREATE TABLE {}", self.table));
+        }
+
+        match self.source {
+            CreateTableSource::Columns(ref columns) => {
+                try!(f.write_str(" ("));
+                try!(write_comma_delimited(f, columns));
+                f.write_str(")")
+            },
+            CreateTableSource::Query(ref query) => write!(f, " AS {}", query)
+        }
+    }
+}
+
+impl fmt::Display for CreateMaterializedViewStatement {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        write!(f, "CREATE MATERIALIZED VIEW {} AS {}", self.view, self.query)
+    }
+}
+
+impl fmt::Display for CreateSequenceStatement {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        try!(write!(f, "CREATE SEQUENCE {}", self.sequence));
+
+        if let Some(ref start) = self.start {
+            try!(write!(f, " START WITH {}", start));
+        }
+
+        if let Some(ref increment) = self.increment {
+            try!(write!(f, " INCREMENT BY {}", increment));
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for CreateVirtualTableStatement {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        try!(write!(f, "CREATE VIRTUAL TABLE {} ", self.table));
+
+        if let Some(ref columns) = self.columns {
+            try!(f.write_str("("));
+            try!(write_comma_delimited(f, columns));
+            try!(f.write_str(") "));
+        }
+
+        try!(f.write_str("USING "));
+        try!(write_ident(f, &self.module));
+        try!(f.write_str("("));
+        try!(write_comma_delimited(f, &self.arguments));
+        f.write_str(")")
+    }
+}
+
+impl fmt::Display for CreateStatement {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match self {
+            &CreateStatement::Table(ref s) => write!(f, "{}", s),
+            &CreateStatement::MaterializedView(ref s) => write!(f, "{}", s),
+            &CreateStatement::Sequence(ref s) => write!(f, "{}", s),
+            &CreateStatement::VirtualTable(ref s) => write!(f, "{}", s)
+        }
+    }
+}
+
+impl fmt::Display for AlterTableStatement {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        try!(write!(f, "ALTER TABLE {} ", self.table));
+
+        match self.action {
+            AlterTableAction::RenameTable { ref new_name } => {
+                try!(f.write_str("RENAME TO "));
+                write_ident(f, new_name)
+            },
+            AlterTableAction::RenameColumn { ref column, ref new_name } => {
+                try!(f.write_str("RENAME COLUMN "));
+                try!(write_ident(f, column));
+                try!(f.write_str(" TO "));
+                write_ident(f, new_name)
+            },
+            AlterTableAction::AddColumn(ref column) => write!(f, "ADD COLUMN {}", column),
+            AlterTableAction::AlterColumnType { ref column, ref type_name, ref type_size, ref type_array_size } => {
+                try!(f.write_str("ALTER COLUMN "));
+                try!(write_ident(f, column));
+                try!(f.write_str(" TYPE "));
+                write_type(f, type_name, type_size, type_array_size)
+            }
+        }
+    }
+}
+
+impl fmt::Display for CommentStatement {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match self.target {
+            CommentTarget::Table(ref table) => try!(write!(f, "COMMENT ON TABLE {} IS ", table)),
+            CommentTarget::Column { ref table, ref column } => {
+                try!(write!(f, "COMMENT ON COLUMN {}.", table));
+                try!(write_ident(f, column));
+                try!(f.write_str(" IS "));
+            }
+        }
+
+        match self.comment {
+            Some(ref comment) => write_string_literal(f, comment),
+            None => f.write_str("NULL")
+        }
+    }
+}
+
+impl fmt::Display for RefreshStatement {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        write!(f, "REFRESH MATERIALIZED VIEW {}", self.view)
+    }
+}
+
+impl fmt::Display for ExplainStatement {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match self {
+            &ExplainStatement::Select(ref s) => write!(f, "EXPLAIN {}", s)
+        }
+    }
+}
+
+impl fmt::Display for PragmaStatement {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        try!(f.write_str("PRAGMA "));
+        try!(write_ident(f, &self.name));
+
+        if let Some(ref value) = self.value {
+            try!(write!(f, " = {}", value));
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for SetStatement {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        try!(f.write_str("SET "));
+        try!(write_ident(f, &self.name));
+        write!(f, " = {}", self.value)
+    }
+}
+
+impl fmt::Display for ShowStatement {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        try!(f.write_str("SHOW "));
+        write_ident(f, &self.name)
+    }
+}
+
+impl fmt::Display for Statement {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match self {
+            &Statement::Select(ref s) => write!(f, "{}", s),
+            &Statement::Insert(ref s) => write!(f, "{}", s),
+            &Statement::Update(ref s) => write!(f, "{}", s),
+            &Statement::Delete(ref s) => write!(f, "{}", s),
+            &Statement::Create(ref s) => write!(f, "{}", s),
+            &Statement::AlterTable(ref s) => write!(f, "{}", s),
+            &Statement::Comment(ref s) => write!(f, "{}", s),
+            &Statement::Refresh(ref s) => write!(f, "{}", s),
+            &Statement::Explain(ref s) => write!(f, "{}", s),
+            &Statement::Pragma(ref s) => write!(f, "{}", s),
+            &Statement::Set(ref s) => write!(f, "{}", s),
+            &Statement::Show(ref s) => write!(f, "{}", s)
+        }
+    }
+}
+
+/// An identifier, quoted if it needs to be
+struct Ident<'a>(&'a str);
+
+impl<'a> fmt::Display for Ident<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        write_ident(f, self.0)
+    }
+}
+
+fn comma_delimited<T: fmt::Display>(items: &[T]) -> String {
+    let mut s = String::new();
+    for (i, item) in items.iter().enumerate() {
+        if i > 0 { s.push_str(", "); }
+        s.push_str(&item.to_string());
+    }
+    s
+}
+
+/// Builds the lines of a formatted statement
+struct Lines {
+    out: String,
+    indent: usize
+}
+
+impl Lines {
+    /// Starts a new line at the current indentation
+    fn line(&mut self, text: &str) {
+        if !self.out.is_empty() {
+            self.out.push('\n');
+        }
+
+        for _ in 0..self.indent {
+            self.out.push_str("  ");
+        }
+
+        self.out.push_str(text);
+    }
+
+    /// Continues the current line
+    fn push(&mut self, text: &str) {
+        self.out.push_str(text);
+    }
+
+    /// Writes a clause whose condition is split at each top-level AND
+    fn conditions(&mut self, keyword: &str, expr: &Expression) {
+        fn and_terms<'a>(expr: &'a Expression, terms: &mut Vec<&'a Expression>) {
+            match expr {
+                &Expression::BinaryOp { op: BinaryOp::And, ref lhs, ref rhs } => {
+                    and_terms(lhs, terms);
+                    and_terms(rhs, terms);
+                },
+                expr => terms.push(expr)
+            }
+        }
+
+        let mut terms = Vec::new();
+        and_terms(expr, &mut terms);
+        let split = terms.len() > 1;
+
+        for (i, term) in terms.into_iter().enumerate() {
+            // OR binds looser than AND
+            let term = if split && term.precedence() < BinaryOp::And.precedence() {
+                format!("({})", term)
+            } else {
+                term.to_string()
+            };
+
+            if i == 0 {
+                self.line(&format!("{} {}", keyword, term));
+            } else {
+                self.indent += 1;
+                self.line(&format!("AND {}", term));
+                self.indent -= 1;
+            }
+        }
+    }
+
+    fn table_or_subquery(&mut self, table: &TableOrSubquery) {
+        match table {
+            &TableOrSubquery::Subquery { ref subquery, ref alias } => {
+                self.push("(");
+                self.indent += 1;
+                self.select(subquery);
+                self.indent -= 1;
+                self.line(&format!(") AS {}", Ident(alias)));
+            },
+            table => self.push(&table.to_string())
+        }
+    }
+
+    /// Each join is on a line of its own, below the tables it joins to. Joins
+    /// in parentheses are indented inside them.
+    fn join(&mut self, join: &Join) {
+        match *join.lhs {
+            JoinTree::Table(ref table) => self.table_or_subquery(table),
+            JoinTree::Join(ref lhs) => self.join(lhs)
+        }
+
+        self.indent += 1;
+        self.line(&format!("{} ", join.operator));
+
+        match *join.rhs {
+            JoinTree::Table(ref table) => self.table_or_subquery(table),
+            JoinTree::Join(ref rhs) => {
+                self.push("(");
+                self.indent += 1;
+                self.line("");
+                self.join(rhs);
+                self.indent -= 1;
+                self.line(")");
+            }
+        }
+
+        if let Some(ref on) = join.on {
+            self.push(&format!(" ON {}", on));
+        }
+        self.indent -= 1;
+    }
+
+    fn from(&mut self, from: &From) {
+        self.line("FROM ");
+
+        match from {
+            &From::Cross(ref tables) => {
+                for (i, table) in tables.iter().enumerate() {
+                    if i > 0 { self.push(", "); }
+                    self.table_or_subquery(table);
+                }
+            },
+            &From::Join(ref join) => self.join(join)
+        }
+    }
+
+    fn select(&mut self, stmt: &SelectStatement) {
+        let select = if stmt.distinct { "SELECT DISTINCT" } else { "SELECT" };
+        self.line(&format!("{} {}", select, comma_delimited(&stmt.result_columns)));
+
+        if let Some(ref from) = stmt.from {
+            self.from(from);
+        }
+
+        if let Some(ref where_expr) = stmt.where_expr {
+            self.conditions("WHERE", where_expr);
+        }
+
+        if !stmt.group_by.is_empty() {
+            self.line(&format!("GROUP BY {}", comma_delimited(&stmt.group_by)));
+        }
+
+        if let Some(ref having) = stmt.having {
+            self.conditions("HAVING", having);
+        }
+
+        if !stmt.order_by.is_empty() {
+            self.line(&format!("ORDER BY {}", comma_delimited(&stmt.order_by)));
+        }
+
+        if let Some(ref limit) = stmt.limit {
+            self.line(&limit.to_string());
+        }
+    }
+
+    fn statement(&mut self, stmt: &Statement) {
+        match stmt {
+            &Statement::Select(ref s) => self.select(s),
+            &Statement::Insert(ref s) => {
+                self.line(&format!("INSERT INTO {}", s.table));
+                if let Some(ref into_columns) = s.into_columns {
+                    let columns: Vec<_> = into_columns.iter().map(|c| Ident(c)).collect();
+                    self.push(&format!(" ({})", comma_delimited(&columns)));
+                }
+
+                match s.source {
+                    InsertSource::Values(ref rows) if rows.len() == 1 => {
+                        self.push(&format!(" VALUES ({})", comma_delimited(&rows[0])));
+                    },
+                    InsertSource::Values(ref rows) => {
+                        self.line("VALUES");
+                        self.indent += 1;
+                        for (i, row) in rows.iter().enumerate() {
+                            let comma = if i + 1 < rows.len() { "," } else { "" };
+                            self.line(&format!("({}){}", comma_delimited(row), comma));
+                        }
+                        self.indent -= 1;
+                    },
+                    InsertSource::DefaultValues => self.push(" DEFAULT VALUES"),
+                    InsertSource::Select(ref select) => self.select(select)
+                }
+            },
+            &Statement::Update(ref s) => {
+                self.line(&format!("UPDATE {}", s.table));
+                self.line(&format!("SET {}", comma_delimited(&s.assignments)));
+
+                if let Some(ref from) = s.from {
+                    self.from(from);
+                }
+
+                if let Some(ref where_expr) = s.where_expr {
+                    self.conditions("WHERE", where_expr);
+                }
+            },
+            &Statement::Delete(ref s) => {
+                self.line(&format!("DELETE FROM {}", s.table));
+
+                if let Some(ref where_expr) = s.where_expr {
+                    self.conditions("WHERE", where_expr);
+                }
+            },
+            &Statement::Create(CreateStatement::Table(ref s)) => {
+                let temporary = if s.temporary { "TEMPORARY " } else { "" };
+                self.line(&format!("CREATE {}TABLE {}", temporary, s.table));
+
+                match s.source {
+                    CreateTableSource::Columns(ref columns) => {
+                        self.push(" (");
+                        self.indent += 1;
+                        for (i, column) in columns.iter().enumerate() {
+                            let comma = if i + 1 < columns.len() { "," } else { "" };
+                            self.line(&format!("{}{}", column, comma));
+                        }
+                        self.indent -= 1;
+                        self.line(")");
+                    },
+                    CreateTableSource::Query(ref query) => {
+                        self.push(" AS");
+                        self.select(query);
+                    }
+                }
+            },
+            &Statement::Create(CreateStatement::MaterializedView(ref s)) => {
+                self.line(&format!("CREATE MATERIALIZED VIEW {} AS", s.view));
+                self.select(&s.query);
+            },
+            &Statement::Explain(ExplainStatement::Select(ref s)) => {
+                self.line("EXPLAIN");
+                self.select(s);
+            },
+            stmt => self.line(&stmt.to_string())
+        }
+    }
+}
+
+/// Formats a statement over several lines: one for each clause of a query,
+/// and one for each column of a table or row of values. Nested queries in
+/// FROM are indented. Like `Display`, keywords are in upper case, and the
+/// output can be parsed back into an equivalent statement.
+pub fn format_statement(stmt: &Statement) -> String {
+    let mut lines = Lines {
+        out: String::new(),
+        indent: 0
+    };
+
+    lines.statement(stmt);
+    lines.out
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::{format_sql, parse_statement, parse_statements};
+
+    fn roundtrip(sql: &str) -> String {
+        let stmt = parse_statement(sql).unwrap();
+        let printed = stmt.to_string();
+
+        // Printing must preserve the statement
+        let reparsed = parse_statement(&printed).unwrap();
+        assert_eq!(format!("{:?}", stmt), format!("{:?}", reparsed));
+
+        printed
+    }
+
+    #[test]
+    fn test_sql_printer() {
+        assert_eq!(roundtrip("select a, b AS c from t where a = 'it\\'s'"), "SELECT a, b AS c FROM t WHERE a = 'it\\'s'");
+        assert_eq!(roundtrip("SELECT (1 + 2) * 3, 1 + 2 * 3, 1 - (2 - 3), (1 - 2) - 3 FROM t"),
+            "SELECT (1 + 2) * 3, 1 + 2 * 3, 1 - (2 - 3), 1 - 2 - 3 FROM t");
+        assert_eq!(roundtrip("SELECT - -a, -(1 + b), count(*) FROM t"), "SELECT -(-a), -(1 + b), count(*) FROM t");
+        assert_eq!(roundtrip("SELECT `select`, `two words` FROM `from`"), "SELECT `select`, `two words` FROM `from`");
+
+        roundtrip("SELECT *, (name + 4), count(*) AS amount FROM (SELECT * FROM foo) subq, table1 GROUP BY name HAVING count(*) > 5;");
+        roundtrip("SELECT count(*) FROM t HAVING count(*) > 1");
+        roundtrip("SELECT * FROM foo INNER JOIN bar ON foo.id = bar.fooId ORDER BY a DESC, b;");
+        assert_eq!(roundtrip("SELECT * FROM a join b on a.id = b.id left outer join c on 1 cross join d"),
+            "SELECT * FROM a INNER JOIN b ON a.id = b.id LEFT JOIN c ON 1 CROSS JOIN d");
+        assert_eq!(roundtrip("SELECT * FROM ((a join b on x) left join c on y)"),
+            "SELECT * FROM a INNER JOIN b ON x LEFT JOIN c ON y");
+        assert_eq!(roundtrip("SELECT * FROM a left join (b join (c cross join d) on x) on y"),
+            "SELECT * FROM a LEFT JOIN (b INNER JOIN (c CROSS JOIN d) ON x) ON y");
+        assert_eq!(roundtrip("SELECT offset FROM t ORDER BY a limit 10 offset 5"),
+            "SELECT offset FROM t ORDER BY a LIMIT 10 OFFSET 5");
+        roundtrip("SELECT a FROM t LIMIT 1");
+        assert_eq!(roundtrip("select distinct a, count(distinct b), sum(distinct b + 1) from t group by a"),
+            "SELECT DISTINCT a, count(DISTINCT b), sum(DISTINCT b + 1) FROM t GROUP BY a");
+        roundtrip("SELECT a FROM t WHERE a = (SELECT max(b) FROM u) OR NOT_A_KEYWORD AND c || 'x' <> NULL");
+        assert_eq!(roundtrip("SELECT a FROM t WHERE a + 1 in (select b from u) and not exists (select * from v)"),
+            "SELECT a FROM t WHERE a + 1 IN (SELECT b FROM u) AND NOT EXISTS (SELECT * FROM v)");
+        assert_eq!(roundtrip("SELECT a FROM t WHERE (a, (b)) >= (1, 2 + 3) AND (a,b) not in (SELECT c, d FROM u)"),
+            "SELECT a FROM t WHERE (a, b) >= (1, 2 + 3) AND (a, b) NOT IN (SELECT c, d FROM u)");
+        assert_eq!(roundtrip("SELECT (a = 1) NOT IN (SELECT b FROM u), EXISTS (SELECT 1) FROM t"),
+            "SELECT a = 1 NOT IN (SELECT b FROM u), EXISTS (SELECT 1) FROM t");
+        assert_eq!(roundtrip("SELECT a FROM t WHERE a and (b and c) and d or (e or f) or g and (h or i)"),
+            "SELECT a FROM t WHERE a AND b AND c AND d OR e OR f OR g AND (h OR i)");
+
+        assert_eq!(roundtrip("INSERT INTO table1 (a, b) VALUES ('foo' || 'bar', 2), (1, 2);"),
+            "INSERT INTO table1 (a, b) VALUES ('foo' || 'bar', 2), (1, 2)");
+        roundtrip("INSERT INTO table1 SELECT * FROM foo;");
+        assert_eq!(roundtrip("insert into table1 default values"), "INSERT INTO table1 DEFAULT VALUES");
+        assert_eq!(roundtrip("delete from t where a = 1 and b in (select c from u)"),
+            "DELETE FROM t WHERE a = 1 AND b IN (SELECT c FROM u)");
+        roundtrip("DELETE FROM t");
+        assert_eq!(roundtrip("update t set a = a + 1, `b c` = 'x' where a < 3"),
+            "UPDATE t SET a = a + 1, `b c` = 'x' WHERE a < 3");
+        roundtrip("UPDATE t SET a = NULL");
+        assert_eq!(roundtrip("update t set a = u.a + v.b from u join v on u.c = v.c where t.b = u.b"),
+            "UPDATE t SET a = u.a + v.b FROM u INNER JOIN v ON u.c = v.c WHERE t.b = u.b");
+        roundtrip("UPDATE t SET a = 1 FROM u, (SELECT b FROM v) AS w");
+
+        assert_eq!(roundtrip("CREATE TABLE test (
+            foo     INT CONSTRAINT pk PRIMARY KEY,
+            bar     VARCHAR(256),
+            data    BYTE[32] NULL UNIQUE,
+            more    byte[] REFERENCES other (id)
+        );"), "CREATE TABLE test (foo INT CONSTRAINT pk PRIMARY KEY, bar VARCHAR(256), data BYTE[32] NULL UNIQUE, more byte[] REFERENCES other (id))");
+        assert_eq!(roundtrip("create temporary table t as select a, b + 1 as c from u"),
+            "CREATE TEMPORARY TABLE t AS SELECT a, b + 1 AS c FROM u");
+
+        roundtrip("EXPLAIN SELECT a FROM b");
+        assert_eq!(roundtrip("CREATE TEMP TABLE t (a U32)"), "CREATE TEMPORARY TABLE t (a U32)");
+        assert_eq!(roundtrip("CREATE SEQUENCE s START 10 INCREMENT BY -2"), "CREATE SEQUENCE s START WITH 10 INCREMENT BY -2");
+        assert_eq!(roundtrip("create virtual table t using csv('t.csv')"), "CREATE VIRTUAL TABLE t USING csv('t.csv')");
+        assert_eq!(roundtrip("pragma case_folding"), "PRAGMA case_folding");
+        assert_eq!(roundtrip("PRAGMA case_folding = upper"), "PRAGMA case_folding = upper");
+        assert_eq!(roundtrip("PRAGMA foreign_keys = ON"), "PRAGMA foreign_keys = `on`");
+        assert_eq!(roundtrip("set tenant to 'acme'"), "SET tenant = 'acme'");
+        assert_eq!(roundtrip("SET tenant = ?"), "SET tenant = ?");
+        assert_eq!(roundtrip("show tenant"), "SHOW tenant");
+        roundtrip("SELECT show FROM t WHERE tenant = current_setting('tenant')");
+        assert_eq!(roundtrip("CREATE VIRTUAL TABLE t (a I32, b STRING NULL) USING csv('t.csv')"),
+            "CREATE VIRTUAL TABLE t (a I32, b STRING NULL) USING csv('t.csv')");
+        roundtrip("SELECT nextval('s'), currval('s') FROM t");
+        assert_eq!(roundtrip("select value from generate_series(1, 10, 2) s, json_each('[]')"),
+            "SELECT value FROM generate_series(1, 10, 2) AS s, json_each('[]')");
+        assert_eq!(roundtrip("select 1 + 1 where 2 > 1"), "SELECT 1 + 1 WHERE 2 > 1");
+        assert_eq!(roundtrip("create materialized view v as select a, count(*) from t group by a"),
+            "CREATE MATERIALIZED VIEW v AS SELECT a, count(*) FROM t GROUP BY a");
+        assert_eq!(roundtrip("refresh materialized view v;"), "REFRESH MATERIALIZED VIEW v");
+        assert_eq!(roundtrip("alter table t rename to u"), "ALTER TABLE t RENAME TO u");
+        assert_eq!(roundtrip("ALTER TABLE t RENAME a TO `to`"), "ALTER TABLE t RENAME COLUMN a TO `to`");
+        assert_eq!(roundtrip("alter table t add b string null"), "ALTER TABLE t ADD COLUMN b string NULL");
+        assert_eq!(roundtrip("ALTER TABLE t ALTER `type` TYPE BYTE[4]"), "ALTER TABLE t ALTER COLUMN type TYPE BYTE[4]");
+        assert_eq!(roundtrip("comment on table t is 'Tom\\'s table'"), "COMMENT ON TABLE t IS 'Tom\\'s table'");
+        assert_eq!(roundtrip("COMMENT ON COLUMN t.`comment` IS NULL"), "COMMENT ON COLUMN t.`comment` IS NULL");
+        assert_eq!(roundtrip("SELECT ? FROM t WHERE a = $12"), "SELECT ? FROM t WHERE a = $12");
+        assert_eq!(roundtrip("select row_number() over (partition by a, b order by c desc), rank() over () from t"),
+            "SELECT row_number() OVER (PARTITION BY a, b ORDER BY c DESC), rank() OVER () FROM t");
+        roundtrip("SELECT dense_rank() OVER (ORDER BY a) FROM t");
+        assert_eq!(roundtrip("select sum(a) over (order by b rows 2 preceding), avg(a) over (rows between current row and unbounded following) from t"),
+            "SELECT sum(a) OVER (ORDER BY b ROWS BETWEEN 2 PRECEDING AND CURRENT ROW), avg(a) OVER (ROWS BETWEEN CURRENT ROW AND UNBOUNDED FOLLOWING) FROM t");
+        roundtrip("SELECT max(a) OVER (PARTITION BY b ROWS BETWEEN UNBOUNDED PRECEDING AND 1 FOLLOWING) FROM t");
+
+        assert_eq!(roundtrip("SELECT 42, 4.20, 1e3, 1.5E-3, 18446744073709551616, 1e999 FROM t"),
+            "SELECT 42, 4.2, 1000.0, 0.0015, 18446744073709552000.0, 1e999 FROM t");
+    }
+
+    #[test]
+    fn test_format_sql() {
+        let sql = "select a, count(*) as n from t inner join (select id from u where x or y) as v on t.id = v.id \
+            where a > 1 and (b = 2 or c = 3) and d group by a having count(*) > 1 order by a desc limit 10;\
+            insert into t (a, b) values (1, 'x'), (2, 'y'); insert into t values (3, 'z');\
+            update t set b = 'z', a = a + 1 where a = 3; update t set b = u.b from u where t.a = u.a; delete from t where a = 1 or b = 'y'; create table t (a U32, b STRING NULL); pragma case_folding;";
+
+        let formatted = format_sql(sql).unwrap();
+        assert_eq!(formatted, "\
+SELECT a, count(*) AS n
+FROM t
+  INNER JOIN (
+    SELECT id
+    FROM u
+    WHERE x OR y
+  ) AS v ON t.id = v.id
+WHERE a > 1
+  AND (b = 2 OR c = 3)
+  AND d
+GROUP BY a
+HAVING count(*) > 1
+ORDER BY a DESC
+LIMIT 10;
+INSERT INTO t (a, b)
+VALUES
+  (1, 'x'),
+  (2, 'y');
+INSERT INTO t VALUES (3, 'z');
+UPDATE t
+SET b = 'z', a = a + 1
+WHERE a = 3;
+UPDATE t
+SET b = u.b
+FROM u
+WHERE t.a = u.a;
+DELETE FROM t
+WHERE a = 1 OR b = 'y';
+CREATE TABLE t (
+  a U32,
+  b STRING NULL
+);
+PRAGMA case_folding;
+");
+
+        // Formatting must preserve the statements, and is idempotent
+        assert_eq!(format!("{:?}", parse_statements(&formatted).unwrap()), format!("{:?}", parse_statements(sql).unwrap()));
+        assert_eq!(format_sql(&formatted).unwrap(), formatted);
+        assert!(format_sql("select from;").is_err());
+        assert!(format_sql("select a from t limit;").is_err());
+        assert!(format_sql("select * from a join b;").is_err());
+        assert!(format_sql("select a from t where (a, b = (1, 2);").is_err());
+        assert!(format_sql("select * from a cross join b on a.id = b.id;").is_err());
+        assert!(format_sql("select count(distinct *) from t;").is_err());
+        assert!(format_sql("select count(distinct) from t;").is_err());
+        assert!(format_sql("select count(distinct a) over () from t;").is_err());
+        assert_eq!(format_sql("select distinct a from t;").unwrap(), "SELECT DISTINCT a\nFROM t;\n");
+        assert!(format_sql("select * from (a join b on x;").is_err());
+        assert!(format_sql("select * from (a join b on x) as c;").is_err());
+        assert!(format_sql("select * from (a join b on x), c;").is_err());
+        assert_eq!(format_sql("select * from a left join (b join c on x) on y;").unwrap(), "\
+SELECT *
+FROM a
+  LEFT JOIN (
+    b
+      INNER JOIN c ON x
+  ) ON y;
+");
+    }
+}
@@ -0,0 +1,364 @@
+//! A log of the changes made to a database, which another database can apply
+//! to become a copy of it.
+//!
+//! Statements that change the schema are logged as SQL, and are executed
+//! again when the log is applied. Inserted rows are logged with the values
+//! they were stored with, so expressions such as `nextval` aren't evaluated
+//! twice, and so are updated rows, along with their rowids. Deleted rows are
+//! logged by rowid, which is the same in a copy that applied the same log.
+//! Entries are numbered from 1 by their log sequence number (LSN).
+
+use identifier::Identifier;
+use types::{DbType, Variant};
+
+use super::snapshot::{SnapshotReader, SnapshotWriter};
+use super::table::Table;
+
+const MAGIC: &'static [u8] = b"llamadb log\0";
+
+const VERSION: u64 = 1;
+
+const KIND_STATEMENT: u8 = 0;
+const KIND_CREATE_TABLE: u8 = 1;
+const KIND_INSERT: u8 = 2;
+const KIND_DELETE: u8 = 3;
+const KIND_UPDATE: u8 = 4;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LogEntry {
+    pub lsn: u64,
+    pub change: Change
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Change {
+    /// A statement that changed the schema, printed as SQL
+    Statement(String),
+    /// A table whose columns were inferred rather than declared, such as by
+    /// `CREATE TABLE ... AS` or a JSON import. The columns are
+    /// `(name, type, nullable, max_length)`.
+    CreateTable {
+        table: Identifier,
+        columns: Vec<(Identifier, DbType, bool, Option<u64>)>
+    },
+    Insert {
+        table: Identifier,
+        rows: Vec<Vec<Variant>>
+    },
+    Delete {
+        table: Identifier,
+        rowids: Vec<u64>
+    },
+    /// The rowid and new values of each updated row
+    Update {
+        table: Identifier,
+        rows: Vec<(u64, Vec<Variant>)>
+    }
+}
+
+impl Change {
+    pub fn create_table(table: &Table) -> Change {
+        Change::CreateTable {
+            table: table.name.clone(),
+            columns: table.columns.iter().map(|c| (c.name.clone(), c.dbtype, c.nullable, c.max_length)).collect()
+        }
+    }
+}
+
+/// The name of an archived segment of the log. Segments are named by the LSN
+/// of their first entry, padded so that they sort in order.
+pub fn segment_name(first_lsn: u64) -> String {
+    format!("{:020}.log", first_lsn)
+}
+
+/// Encodes log entries, so they can be sent to another process.
+pub fn encode_change_log(entries: &[LogEntry]) -> Vec<u8> {
+    let mut writer = SnapshotWriter::with_header(MAGIC, VERSION);
+    writer.write_uvar(entries.len() as u64);
+
+    for entry in entries {
+        writer.write_uvar(entry.lsn);
+
+        match entry.change {
+            Change::Statement(ref sql) => {
+                writer.write_u8(KIND_STATEMENT);
+                writer.write_str(sql);
+            },
+            Change::CreateTable { ref table, ref columns } => {
+                writer.write_u8(KIND_CREATE_TABLE);
+                writer.write_identifier(table);
+                writer.write_uvar(columns.len() as u64);
+
+                for &(ref name, dbtype, nullable, max_length) in columns {
+                    writer.write_identifier(name);
+                    writer.write_dbtype(dbtype);
+                    writer.write_u8(nullable as u8);
+                    // Lengths are at least 1, so 0 means there's none
+                    writer.write_uvar(max_length.unwrap_or(0));
+                }
+            },
+            Change::Insert { ref table, ref rows } => {
+                writer.write_u8(KIND_INSERT);
+                writer.write_identifier(table);
+                writer.write_uvar(rows.len() as u64);
+
+                for row in rows {
+                    writer.write_uvar(row.len() as u64);
+                    for value in row {
+                        writer.write_variant(value);
+                    }
+                }
+            },
+            Change::Delete { ref table, ref rowids } => {
+                writer.write_u8(KIND_DELETE);
+                writer.write_identifier(table);
+                writer.write_uvar(rowids.len() as u64);
+
+                for &rowid in rowids {
+                    writer.write_uvar(rowid);
+                }
+            },
+            Change::Update { ref table, ref rows } => {
+                writer.write_u8(KIND_UPDATE);
+                writer.write_identifier(table);
+                writer.write_uvar(rows.len() as u64);
+
+                for &(rowid, ref row) in rows {
+                    writer.write_uvar(rowid);
+                    writer.write_uvar(row.len() as u64);
+                    for value in row {
+                        writer.write_variant(value);
+                    }
+                }
+            }
+        }
+    }
+
+    writer.buf
+}
+
+/// Decodes log entries encoded by `encode_change_log`.
+pub fn decode_change_log(buf: &[u8]) -> Result<Vec<LogEntry>, String> {
+    let mut reader = try!(SnapshotReader::with_header(buf, MAGIC, VERSION, "change log"));
+    let count = try!(reader.read_uvar());
+    let mut entries = Vec::new();
+
+    for _ in 0..count {
+        let lsn = try!(reader.read_uvar());
+
+        let change = match try!(reader.read_u8()) {
+            KIND_STATEMENT => Change::Statement(try!(reader.read_string())),
+            KIND_CREATE_TABLE => {
+                let table = try!(reader.read_identifier());
+                let column_count = try!(reader.read_uvar());
+                let mut columns = Vec::new();
+
+                for _ in 0..column_count {
+                    let name = try!(reader.read_identifier());
+                    let dbtype = try!(reader.read_dbtype());
+                    let nullable = try!(reader.read_u8()) != 0;
+                    let max_length = match try!(reader.read_uvar()) {
+                        0 => None,
+                        n => Some(n)
+                    };
+                    columns.push((name, dbtype, nullable, max_length));
+                }
+
+                Change::CreateTable {
+                    table: table,
+                    columns: columns
+                }
+            },
+            KIND_INSERT => {
+                let table = try!(reader.read_identifier());
+                let row_count = try!(reader.read_uvar());
+                let mut rows = Vec::new();
+
+                for _ in 0..row_count {
+                    let value_count = try!(reader.read_uvar());
+                    let mut row = Vec::new();
+
+                    for _ in 0..value_count {
+                        row.push(try!(reader.read_variant()));
+                    }
+
+                    rows.push(row);
+                }
+
+                Change::Insert {
+                    table: table,
+                    rows: rows
+                }
+            },
+            KIND_DELETE => {
+                let table = try!(reader.read_identifier());
+                let rowid_count = try!(reader.read_uvar());
+                let rowids = try!((0..rowid_count).map(|_| reader.read_uvar()).collect());
+
+                Change::Delete {
+                    table: table,
+                    rowids: rowids
+                }
+            },
+            KIND_UPDATE => {
+                let table = try!(reader.read_identifier());
+                let row_count = try!(reader.read_uvar());
+                let mut rows = Vec::new();
+
+                for _ in 0..row_count {
+                    let rowid = try!(reader.read_uvar());
+                    let value_count = try!(reader.read_uvar());
+                    let mut row = Vec::new();
+
+                    for _ in 0..value_count {
+                        row.push(try!(reader.read_variant()));
+                    }
+
+                    rows.push((rowid, row));
+                }
+
+                Change::Update {
+                    table: table,
+                    rows: rows
+                }
+            },
+            kind => return Err(format!("change log has an entry of unknown kind {}", kind))
+        };
+
+        entries.push(LogEntry {
+            lsn: lsn,
+            change: change
+        });
+    }
+
+    if !reader.is_at_end() {
+        return Err(format!("change log has trailing bytes"));
+    }
+
+    Ok(entries)
+}
+
+/// Collects the changes made to a database while logging is enabled.
+pub struct ChangeLog {
+    enabled: bool,
+    /// The LSN of the last change logged or applied
+    lsn: u64,
+    entries: Vec<LogEntry>
+}
+
+impl ChangeLog {
+    pub fn new() -> ChangeLog {
+        ChangeLog {
+            enabled: false,
+            lsn: 0,
+            entries: Vec::new()
+        }
+    }
+
+    pub fn enable(&mut self) {
+        self.enabled = true;
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn lsn(&self) -> u64 {
+        self.lsn
+    }
+
+    /// Does nothing unless logging is enabled
+    pub fn record(&mut self, change: Change) {
+        if self.enabled {
+            self.lsn += 1;
+            self.entries.push(LogEntry {
+                lsn: self.lsn,
+                change: change
+            });
+        }
+    }
+
+    /// Checks that an entry from another database's log is the next one to
+    /// apply. Returns false if it has already been applied.
+    pub fn check_next(&self, lsn: u64) -> Result<bool, String> {
+        if lsn <= self.lsn {
+            Ok(false)
+        } else if lsn == self.lsn + 1 {
+            Ok(true)
+        } else {
+            Err(format!("change log is missing entries {} to {}", self.lsn + 1, lsn - 1))
+        }
+    }
+
+    /// Called once an entry from another database's log has been applied,
+    /// and when a database is opened. If logging is enabled, applying an
+    /// entry logged it again with the same LSN, so a replica can itself be
+    /// replicated.
+    pub fn set_lsn(&mut self, lsn: u64) {
+        self.lsn = lsn;
+    }
+
+    pub fn entries(&self) -> &[LogEntry] {
+        &self.entries
+    }
+
+    /// Forgets the entries logged after the first `len`, such as the changes
+    /// of a migration that failed
+    pub fn truncate(&mut self, len: usize) {
+        self.entries.truncate(len);
+    }
+
+    /// Removes and returns the entries logged so far
+    pub fn take(&mut self) -> Vec<LogEntry> {
+        ::std::mem::replace(&mut self.entries, Vec::new())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Change, ChangeLog, decode_change_log, encode_change_log};
+    use identifier::Identifier;
+    use types::{DbType, Variant};
+
+    #[test]
+    fn test_change_log() {
+        let mut log = ChangeLog::new();
+        log.record(Change::Statement("CREATE TABLE t (a U32)".to_string()));
+        assert!(log.take().is_empty());
+
+        log.enable();
+        log.record(Change::Statement("CREATE TABLE t (a U32)".to_string()));
+        log.record(Change::CreateTable {
+            table: Identifier::new("j").unwrap(),
+            columns: vec![
+                (Identifier::new("x").unwrap(), DbType::Null, true, None),
+                (Identifier::new("y").unwrap(), DbType::String, false, Some(2))
+            ]
+        });
+        log.record(Change::Insert {
+            table: Identifier::new("t").unwrap(),
+            rows: vec![vec![Variant::UnsignedInteger(1)], vec![Variant::Null]]
+        });
+        log.record(Change::Delete {
+            table: Identifier::new("t").unwrap(),
+            rowids: vec![2, 300]
+        });
+        log.record(Change::Update {
+            table: Identifier::new("t").unwrap(),
+            rows: vec![(1, vec![Variant::UnsignedInteger(5)])]
+        });
+
+        let entries = log.take();
+        assert_eq!(entries.iter().map(|e| e.lsn).collect::<Vec<_>>(), vec![1, 2, 3, 4, 5]);
+        assert!(log.take().is_empty());
+
+        let buf = encode_change_log(&entries);
+        assert_eq!(decode_change_log(&buf).unwrap(), entries);
+        assert!(decode_change_log(&buf[..buf.len() - 1]).is_err());
+        assert!(decode_change_log(b"llamadb tempdb\0").is_err());
+
+        assert_eq!(log.check_next(2), Ok(false));
+        assert_eq!(log.check_next(6), Ok(true));
+        assert!(log.check_next(8).is_err());
+    }
+}
@@ -0,0 +1,119 @@
+//! Tells subscribers about the rows that statements change, so that they can
+//! keep caches or live queries up to date.
+
+use std::sync::mpsc::{channel, Receiver, Sender};
+
+use identifier::Identifier;
+use types::Variant;
+
+/// A row that was inserted into, updated in or deleted from a table
+#[derive(Debug, Clone, PartialEq)]
+pub struct RowChange {
+    pub table: Identifier,
+    pub op: ChangeOp,
+    /// The row before the change, for updates and deletes
+    pub old: Option<Vec<Variant>>,
+    /// The row after the change, for inserts and updates
+    pub new: Option<Vec<Variant>>
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ChangeOp {
+    Insert,
+    Update,
+    Delete
+}
+
+impl RowChange {
+    pub fn insert(table: &Identifier, row: Vec<Variant>) -> RowChange {
+        RowChange {
+            table: table.clone(),
+            op: ChangeOp::Insert,
+            old: None,
+            new: Some(row)
+        }
+    }
+
+    pub fn update(table: &Identifier, old: Vec<Variant>, new: Vec<Variant>) -> RowChange {
+        RowChange {
+            table: table.clone(),
+            op: ChangeOp::Update,
+            old: Some(old),
+            new: Some(new)
+        }
+    }
+
+    pub fn delete(table: &Identifier, row: Vec<Variant>) -> RowChange {
+        RowChange {
+            table: table.clone(),
+            op: ChangeOp::Delete,
+            old: Some(row),
+            new: None
+        }
+    }
+}
+
+pub struct Subscribers {
+    senders: Vec<Sender<RowChange>>
+}
+
+impl Subscribers {
+    pub fn new() -> Subscribers {
+        Subscribers {
+            senders: Vec::new()
+        }
+    }
+
+    /// Dropping the receiver unsubscribes
+    pub fn subscribe(&mut self) -> Receiver<RowChange> {
+        let (sender, receiver) = channel();
+        self.senders.push(sender);
+        receiver
+    }
+
+    /// Changes don't need to be collected if nobody is subscribed
+    pub fn is_empty(&self) -> bool {
+        self.senders.is_empty()
+    }
+
+    /// Sends the changes to every subscriber, and forgets the subscribers
+    /// whose receivers have been dropped.
+    pub fn send(&mut self, changes: Vec<RowChange>) {
+        self.senders.retain(|sender| {
+            changes.iter().all(|change| sender.send(change.clone()).is_ok())
+        });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{RowChange, Subscribers};
+    use identifier::Identifier;
+    use types::Variant;
+
+    #[test]
+    fn test_subscribers() {
+        let table = Identifier::new("t").unwrap();
+        let mut subscribers = Subscribers::new();
+        assert!(subscribers.is_empty());
+
+        let first = subscribers.subscribe();
+        drop(subscribers.subscribe());
+
+        subscribers.send(vec![
+            RowChange::insert(&table, vec![Variant::UnsignedInteger(1)]),
+            RowChange::delete(&table, vec![Variant::UnsignedInteger(2)])
+        ]);
+
+        assert_eq!(first.try_recv().unwrap().new, Some(vec![Variant::UnsignedInteger(1)]));
+        assert_eq!(first.try_recv().unwrap().old, Some(vec![Variant::UnsignedInteger(2)]));
+        assert!(first.try_recv().is_err());
+
+        // The dropped receiver was forgotten
+        assert_eq!(subscribers.senders.len(), 1);
+
+        drop(first);
+        subscribers.send(vec![RowChange::insert(&table, Vec::new())]);
+        assert!(subscribers.is_empty());
+    }
+}
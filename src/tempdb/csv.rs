@@ -0,0 +1,298 @@
+//! The `csv` virtual table module, which reads rows from a CSV file.
+//!
+//! The file follows RFC 4180. The first record is a header with the column
+//! names. An empty, unquoted field is NULL, and `""` is an empty string.
+
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+
+use identifier::{CaseFolding, Identifier};
+use types::{DbType, F64NoNaN, Variant};
+
+use super::table::Column;
+use super::virtualtable::VirtualTable;
+
+#[derive(Debug)]
+pub struct CsvTable {
+    path: String
+}
+
+/// A record, and the line it starts on
+type Record = (usize, Vec<Option<String>>);
+
+impl CsvTable {
+    pub fn new(path: String) -> CsvTable {
+        CsvTable {
+            path: path
+        }
+    }
+
+    /// Names the columns after the header. A column is an integer or float
+    /// if all of its values are, and a string otherwise.
+    pub fn infer_columns(&self, case_folding: CaseFolding) -> Result<Vec<Column>, String> {
+        let mut records = try!(self.read_records());
+
+        let header = match records.next() {
+            Some(record) => try!(record.map_err(|e| format!("{}: {}", self.path, e))).1,
+            None => return Err(format!("{} has no header", self.path))
+        };
+
+        let integer = DbType::Integer { signed: true, bytes: 8 };
+
+        // Whether every value so far is an integer, and a float
+        let mut integers = vec![true; header.len()];
+        let mut floats = vec![true; header.len()];
+        let mut has_values = vec![false; header.len()];
+        let mut nullable = vec![false; header.len()];
+
+        for record in records {
+            let (_, fields) = try!(record.map_err(|e| format!("{}: {}", self.path, e)));
+
+            for i in 0..header.len() {
+                match fields.get(i) {
+                    Some(&Some(ref v)) => {
+                        has_values[i] = true;
+                        integers[i] = integers[i] && parse_field(v, integer).is_some();
+                        floats[i] = floats[i] && parse_field(v, DbType::F64).is_some();
+                    },
+                    _ => nullable[i] = true
+                }
+            }
+        }
+
+        header.into_iter().enumerate().map(|(i, name)| {
+            let name = name.unwrap_or(String::new());
+            let name = try!(Identifier::new_with_case_folding(&name, case_folding)
+                .ok_or(format!("{} has a bad column name: \"{}\"", self.path, name)));
+
+            let dbtype = if integers[i] {
+                integer
+            } else if floats[i] {
+                DbType::F64
+            } else {
+                DbType::String
+            };
+
+            Ok(Column {
+                offset: i as u32,
+                name: name,
+                // Columns with no values at all are still strings
+                dbtype: if has_values[i] { dbtype } else { DbType::String },
+                nullable: nullable[i],
+                max_length: None,
+                comment: None
+            })
+        }).collect()
+    }
+
+    fn read_records(&self) -> Result<Records<BufReader<File>>, String> {
+        let file = try!(File::open(&self.path).map_err(|e| format!("cannot open {}: {}", self.path, e)));
+        Ok(Records::new(BufReader::new(file)))
+    }
+}
+
+impl VirtualTable for CsvTable {
+    fn read_rows<'a>(&'a self, columns: &'a [Column])
+    -> Result<Box<Iterator<Item=Result<Vec<Variant>, String>> + 'a>, String>
+    {
+        let records = try!(self.read_records());
+
+        // Skip the header
+        Ok(Box::new(records.skip(1).map(move |record| {
+            let (line, fields) = try!(record.map_err(|e| format!("{}: {}", self.path, e)));
+
+            if fields.len() != columns.len() {
+                return Err(format!("{}, line {}: expected {} fields, got {}",
+                    self.path, line, columns.len(), fields.len()));
+            }
+
+            columns.iter().zip(fields.into_iter()).map(|(column, field)| {
+                match field {
+                    Some(text) => parse_field(&text, column.dbtype).ok_or_else(|| {
+                        format!("{}, line {}: \"{}\" is not a valid {:?} for column {}",
+                            self.path, line, text, column.dbtype, column.name)
+                    }),
+                    None if column.nullable => Ok(Variant::Null),
+                    None => Err(format!("{}, line {}: column {} doesn't allow NULL",
+                        self.path, line, column.name))
+                }
+            }).collect()
+        })))
+    }
+
+    fn module(&self) -> &'static str { "csv" }
+
+    fn arguments(&self) -> Vec<Variant> {
+        vec![Variant::StringLiteral(self.path.clone())]
+    }
+}
+
+fn parse_field(text: &str, dbtype: DbType) -> Option<Variant> {
+    match dbtype {
+        DbType::Null => None,
+        DbType::ByteDynamic => Some(Variant::Bytes(text.as_bytes().to_vec())),
+        DbType::ByteFixed(n) => {
+            if text.len() as u64 == n {
+                Some(Variant::Bytes(text.as_bytes().to_vec()))
+            } else {
+                None
+            }
+        },
+        DbType::Integer { signed: true, .. } => text.parse().ok().map(Variant::SignedInteger),
+        DbType::Integer { signed: false, .. } => text.parse().ok().map(Variant::UnsignedInteger),
+        DbType::F64 => text.parse::<f64>().ok().and_then(F64NoNaN::new).map(Variant::Float),
+        DbType::String => Some(Variant::StringLiteral(text.to_string()))
+    }
+}
+
+/// Reads CSV records a line at a time, skipping blank lines. Quoted fields
+/// may contain commas, newlines and doubled quotes.
+struct Records<R> {
+    reader: R,
+    /// The amount of lines read so far
+    line: usize
+}
+
+impl<R: BufRead> Records<R> {
+    fn new(reader: R) -> Records<R> {
+        Records {
+            reader: reader,
+            line: 0
+        }
+    }
+
+    fn next_record(&mut self) -> Result<Option<Record>, String> {
+        let mut fields = Vec::new();
+        let mut field = String::new();
+        let mut quoted = false;
+        let mut in_quotes = false;
+
+        let mut record_line = self.line + 1;
+
+        loop {
+            let mut text = String::new();
+
+            let read = try!(self.reader.read_line(&mut text).map_err(|e| {
+                format!("cannot read line {}: {}", self.line + 1, e)
+            }));
+
+            if read == 0 {
+                if in_quotes {
+                    return Err(format!("line {}: unterminated quoted field", record_line));
+                }
+
+                // The last record may not end with a newline
+                if !field.is_empty() || quoted || !fields.is_empty() {
+                    fields.push(take_field(&mut field, &mut quoted));
+                    return Ok(Some((record_line, fields)));
+                }
+
+                return Ok(None);
+            }
+
+            self.line += 1;
+            let mut chars = text.chars().peekable();
+
+            while let Some(c) = chars.next() {
+                if in_quotes {
+                    // Read up to the closing quote
+                    if c != '"' {
+                        field.push(c);
+                    } else if chars.peek() == Some(&'"') {
+                        chars.next();
+                        field.push('"');
+                    } else {
+                        in_quotes = false;
+
+                        match chars.peek() {
+                            Some(&',') | Some(&'\r') | Some(&'\n') | None => (),
+                            Some(_) => return Err(format!("line {}: unexpected character after quoted field", self.line))
+                        }
+                    }
+
+                    continue;
+                }
+
+                match c {
+                    '"' if field.is_empty() && !quoted => {
+                        quoted = true;
+                        in_quotes = true;
+                    },
+                    ',' => {
+                        fields.push(take_field(&mut field, &mut quoted));
+                    },
+                    '\r' if chars.peek() == Some(&'\n') => (),
+                    '\n' => {
+                        // Blank lines are skipped
+                        if fields.is_empty() && field.is_empty() && !quoted {
+                            record_line = self.line + 1;
+                        } else {
+                            fields.push(take_field(&mut field, &mut quoted));
+                            return Ok(Some((record_line, fields)));
+                        }
+                    },
+                    c => field.push(c)
+                }
+            }
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for Records<R> {
+    type Item = Result<Record, String>;
+
+    fn next(&mut self) -> Option<Result<Record, String>> {
+        match self.next_record() {
+            Ok(Some(record)) => Some(Ok(record)),
+            Ok(None) => None,
+            Err(e) => Some(Err(e))
+        }
+    }
+}
+
+fn take_field(field: &mut String, quoted: &mut bool) -> Option<String> {
+    let value = if field.is_empty() && !*quoted {
+        None
+    } else {
+        Some(field.clone())
+    };
+
+    field.clear();
+    *quoted = false;
+
+    value
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Record, Records};
+
+    fn parse_records(text: &str) -> Result<Vec<Record>, String> {
+        Records::new(text.as_bytes()).collect()
+    }
+
+    #[test]
+    fn test_parse_csv_records() {
+        let s = |v: &str| Some(v.to_string());
+
+        let records = parse_records("a,b,c\r\n1,,\"x, \"\"y\"\"\"\n2,\"\",\"multi\nline\"\n3,4,5").unwrap();
+
+        assert_eq!(records, vec![
+            (1, vec![s("a"), s("b"), s("c")]),
+            (2, vec![s("1"), None, s("x, \"y\"")]),
+            (3, vec![s("2"), s(""), s("multi\nline")]),
+            (5, vec![s("3"), s("4"), s("5")])
+        ]);
+
+        assert_eq!(parse_records("a\n\nb\n").unwrap(), vec![(1, vec![s("a")]), (3, vec![s("b")])]);
+        assert_eq!(parse_records("a,\n").unwrap(), vec![(1, vec![s("a"), None])]);
+        assert!(parse_records("\"a").is_err());
+        assert!(parse_records("\"a\"b").is_err());
+
+        // Records are yielded as they're read, up to a malformed one
+        let mut records = Records::new("a\n1\n\"2\"x\n3\n".as_bytes());
+        assert_eq!(records.next(), Some(Ok((1, vec![s("a")]))));
+        assert_eq!(records.next(), Some(Ok((2, vec![s("1")]))));
+        assert_eq!(records.next(), Some(Err("line 3: unexpected character after quoted field".to_string())));
+    }
+}
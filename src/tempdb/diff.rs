@@ -0,0 +1,428 @@
+//! Compares two databases, and lists the statements that would turn the
+//! first into the second.
+//!
+//! Only changes that SQL can make are written as statements. Others, such as
+//! tables that would have to be dropped, are listed as unresolved. Temporary
+//! tables and settings aren't compared.
+
+use std::fmt;
+use std::iter;
+
+use sqlsyntax::ast;
+use types::{DbType, Variant};
+
+use super::TempDb;
+use super::table::Table;
+
+pub struct Diff {
+    /// Statements that make the first database more like the second, in the
+    /// order they're executed
+    pub statements: Vec<ast::Statement>,
+    /// Differences that no statement can resolve
+    pub unresolved: Vec<String>
+}
+
+impl Diff {
+    pub fn is_empty(&self) -> bool {
+        self.statements.is_empty() && self.unresolved.is_empty()
+    }
+}
+
+/// Writes the statements as a script, followed by the unresolved differences
+/// as comments.
+impl fmt::Display for Diff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        for statement in self.statements.iter() {
+            try!(writeln!(f, "{};", statement));
+        }
+
+        for difference in self.unresolved.iter() {
+            try!(writeln!(f, "-- {}", difference));
+        }
+
+        Ok(())
+    }
+}
+
+pub fn diff(from: &TempDb, to: &TempDb) -> Diff {
+    let mut diff = Diff {
+        statements: Vec::new(),
+        unresolved: Vec::new()
+    };
+
+    for sequence in to.sequences.iter() {
+        match from.sequences.iter().find(|s| s.name == sequence.name) {
+            Some(old) => {
+                if old.increment != sequence.increment || old.peek_next_value() != sequence.peek_next_value() {
+                    diff.unresolved.push(format!("sequence {} would have to be reset", sequence.name));
+                }
+            },
+            None => match sequence.peek_next_value() {
+                Some(next) => {
+                    diff.statements.push(ast::Statement::Create(ast::CreateStatement::Sequence(ast::CreateSequenceStatement {
+                        sequence: table_name(&sequence.name.to_string()),
+                        start: Some(integer_expression(next)),
+                        increment: Some(integer_expression(sequence.increment))
+                    })));
+                },
+                None => diff.unresolved.push(format!("sequence {} is exhausted, and can't be created", sequence.name))
+            }
+        }
+    }
+
+    for sequence in from.sequences.iter().filter(|s| !to.sequences.iter().any(|t| t.name == s.name)) {
+        diff.unresolved.push(format!("sequence {} would have to be dropped", sequence.name));
+    }
+
+    // Tables are listed in the order they were created, so views are created
+    // after the tables they select from
+    for table in to.tables.iter() {
+        match from.tables.iter().find(|t| t.name == table.name) {
+            Some(old) => diff_table(&mut diff, old, table),
+            None => create_table(&mut diff, table)
+        }
+    }
+
+    for table in from.tables.iter().filter(|t| !to.tables.iter().any(|u| u.name == t.name)) {
+        diff.unresolved.push(format!("table {} would have to be dropped", table.name));
+    }
+
+    diff
+}
+
+fn create_table(diff: &mut Diff, table: &Table) {
+    let name = table_name(&table.name.to_string());
+
+    let create = match (&table.view_query, &table.virtual_table) {
+        (&Some(ref query), _) => ast::CreateStatement::MaterializedView(ast::CreateMaterializedViewStatement {
+            view: name,
+            query: query.clone()
+        }),
+        (_, &Some(ref virtual_table)) => {
+            match column_definitions(table) {
+                Some(columns) => {
+                    ast::CreateStatement::VirtualTable(ast::CreateVirtualTableStatement {
+                        table: name,
+                        columns: Some(columns),
+                        module: virtual_table.module().to_string(),
+                        arguments: virtual_table.arguments().iter().map(value_expression).collect()
+                    })
+                },
+                None => {
+                    diff.unresolved.push(format!("virtual table {} has a column of type NULL, which cannot be declared",
+                        table.name));
+                    return;
+                }
+            }
+        },
+        (&None, &None) => {
+            match column_definitions(table) {
+                Some(columns) => ast::CreateStatement::Table(ast::CreateTableStatement {
+                    temporary: false,
+                    table: name,
+                    source: ast::CreateTableSource::Columns(columns)
+                }),
+                None => {
+                    diff.unresolved.push(format!("table {} has a column of type NULL, which cannot be declared",
+                        table.name));
+                    return;
+                }
+            }
+        }
+    };
+
+    diff.statements.push(ast::Statement::Create(create));
+
+    if table.view_query.is_none() && table.virtual_table.is_none() {
+        let rows = table.rowid_index.iter().map(|key| table.decode_row(key)).collect();
+        insert_rows(diff, table, rows);
+    }
+
+    diff_comments(diff, None, table);
+}
+
+fn diff_table(diff: &mut Diff, old: &Table, table: &Table) {
+    let same_columns = old.columns.len() == table.columns.len() &&
+        old.columns.iter().zip(table.columns.iter()).all(|(a, b)| {
+            a.name == b.name && a.dbtype == b.dbtype && a.nullable == b.nullable && a.max_length == b.max_length
+        });
+
+    if !same_columns {
+        diff.unresolved.push(format!("table {} has different columns", table.name));
+        return;
+    }
+
+    match (&old.view_query, &table.view_query, &old.virtual_table, &table.virtual_table) {
+        (&Some(ref old_query), &Some(ref query), _, _) => {
+            if old_query.to_string() != query.to_string() {
+                diff.unresolved.push(format!("materialized view {} has a different query", table.name));
+            } else if sorted_rows(old) != sorted_rows(table) {
+                diff.statements.push(ast::Statement::Refresh(ast::RefreshStatement {
+                    view: table_name(&table.name.to_string())
+                }));
+            }
+        },
+        (_, _, &Some(ref old_virtual_table), &Some(ref virtual_table)) => {
+            if old_virtual_table.module() != virtual_table.module() ||
+                old_virtual_table.arguments() != virtual_table.arguments()
+            {
+                diff.unresolved.push(format!("virtual table {} reads from a different source", table.name));
+            }
+        },
+        (&None, &None, &None, &None) => {
+            let old_rows = sorted_rows(old);
+            let rows = sorted_rows(table);
+
+            // Both lists are sorted, so they're merged a run of equal rows at
+            // a time to count each row's copies
+            let (mut i, mut j) = (0, 0);
+            let mut missing = Vec::new();
+
+            while i < old_rows.len() || j < rows.len() {
+                let row = if j == rows.len() || (i < old_rows.len() && old_rows[i] < rows[j]) {
+                    &old_rows[i]
+                } else {
+                    &rows[j]
+                };
+
+                let old_count = old_rows[i..].iter().take_while(|r| *r == row).count();
+                let count = rows[j..].iter().take_while(|r| *r == row).count();
+
+                if old_count > count {
+                    // DELETE removes every copy of the row, so the copies to
+                    // keep are inserted again
+                    delete_row(diff, table, row);
+                    missing.extend(iter::repeat(row.clone()).take(count));
+                } else {
+                    missing.extend(iter::repeat(row.clone()).take(count - old_count));
+                }
+
+                i += old_count;
+                j += count;
+            }
+
+            insert_rows(diff, table, missing);
+        },
+        _ => {
+            diff.unresolved.push(format!("table {} is a different kind of table", table.name));
+            return;
+        }
+    }
+
+    diff_comments(diff, Some(old), table);
+}
+
+/// Sets the comments of the table and its columns that differ from the old
+/// table's, or that are set if there's no old table.
+fn diff_comments(diff: &mut Diff, old: Option<&Table>, table: &Table) {
+    let name = table_name(&table.name.to_string());
+
+    if old.map_or(table.comment.is_some(), |old| old.comment != table.comment) {
+        diff.statements.push(ast::Statement::Comment(ast::CommentStatement {
+            target: ast::CommentTarget::Table(name.clone()),
+            comment: table.comment.clone()
+        }));
+    }
+
+    for (i, column) in table.columns.iter().enumerate() {
+        let changed = match old {
+            Some(old) => old.columns[i].comment != column.comment,
+            None => column.comment.is_some()
+        };
+
+        if changed {
+            diff.statements.push(ast::Statement::Comment(ast::CommentStatement {
+                target: ast::CommentTarget::Column {
+                    table: name.clone(),
+                    column: column.name.to_string()
+                },
+                comment: column.comment.clone()
+            }));
+        }
+    }
+}
+
+fn insert_rows(diff: &mut Diff, table: &Table, rows: Vec<Vec<Variant>>) {
+    if rows.is_empty() {
+        return;
+    }
+
+    let values = rows.iter().map(|row| row.iter().map(value_expression).collect()).collect();
+
+    diff.statements.push(ast::Statement::Insert(ast::InsertStatement {
+        table: table_name(&table.name.to_string()),
+        into_columns: None,
+        source: ast::InsertSource::Values(values)
+    }));
+}
+
+/// Deletes every copy of the row from the table.
+fn delete_row(diff: &mut Diff, table: &Table, row: &[Variant]) {
+    let binary_op = |lhs, op, rhs| ast::Expression::BinaryOp {
+        lhs: Box::new(lhs),
+        rhs: Box::new(rhs),
+        op: op
+    };
+
+    let conditions = table.columns.iter().zip(row.iter()).map(|(column, value)| {
+        let column = ast::Expression::Ident(column.name.to_string());
+
+        match value {
+            // There's no IS NULL, but `column = column` is only NULL if the
+            // column is
+            &Variant::Null => {
+                let equal = binary_op(column.clone(), ast::BinaryOp::Equal, column);
+                let is_null = ast::Expression::FunctionCall {
+                    name: "ifnull".to_string(),
+                    arguments: vec![equal, integer_expression(0)],
+                    distinct: false
+                };
+
+                binary_op(is_null, ast::BinaryOp::Equal, integer_expression(0))
+            },
+            value => binary_op(column, ast::BinaryOp::Equal, value_expression(value))
+        }
+    });
+
+    let where_expr = conditions.fold(None, |lhs, condition| match lhs {
+        Some(lhs) => Some(binary_op(lhs, ast::BinaryOp::And, condition)),
+        None => Some(condition)
+    });
+
+    diff.statements.push(ast::Statement::Delete(ast::DeleteStatement {
+        table: table_name(&table.name.to_string()),
+        where_expr: where_expr
+    }));
+}
+
+fn sorted_rows(table: &Table) -> Vec<Vec<Variant>> {
+    let mut rows: Vec<_> = table.rowid_index.iter().map(|key| table.decode_row(key)).collect();
+    rows.sort();
+    rows
+}
+
+/// None if a column's type can't be declared
+fn column_definitions(table: &Table) -> Option<Vec<ast::CreateTableColumn>> {
+    table.columns.iter().map(|column| {
+        let (type_name, type_array_size) = match column.dbtype {
+            DbType::Null => return None,
+            DbType::ByteDynamic => ("byte".to_string(), Some(None)),
+            DbType::ByteFixed(n) => ("byte".to_string(), Some(Some(n.to_string()))),
+            DbType::Integer { signed, bytes } => (format!("{}{}", if signed { "i" } else { "u" }, bytes as u32 * 8), None),
+            DbType::F64 => ("f64".to_string(), None),
+            DbType::String => ("string".to_string(), None)
+        };
+
+        let constraints = if column.nullable {
+            vec![ast::CreateTableColumnConstraint {
+                name: None,
+                constraint: ast::CreateTableColumnConstraintType::Nullable
+            }]
+        } else {
+            Vec::new()
+        };
+
+        Some(ast::CreateTableColumn {
+            column_name: column.name.to_string(),
+            type_name: type_name,
+            type_size: column.max_length.map(|n| n.to_string()),
+            type_array_size: type_array_size,
+            constraints: constraints
+        })
+    }).collect()
+}
+
+fn table_name(name: &str) -> ast::Table {
+    ast::Table {
+        database_name: None,
+        table_name: name.to_string()
+    }
+}
+
+fn integer_expression(n: i64) -> ast::Expression {
+    value_expression(&Variant::SignedInteger(n))
+}
+
+/// A literal for the value, or an `unhex()` call for bytes
+fn value_expression(value: &Variant) -> ast::Expression {
+    let negate = |expr| ast::Expression::UnaryOp {
+        expr: Box::new(expr),
+        op: ast::UnaryOp::Negate
+    };
+
+    match value {
+        &Variant::Null => ast::Expression::Null,
+        &Variant::StringLiteral(ref s) => ast::Expression::StringLiteral(s.clone()),
+        &Variant::UnsignedInteger(n) => ast::Expression::Number(ast::NumberLiteral::Integer(n)),
+        &Variant::SignedInteger(n) if n >= 0 => ast::Expression::Number(ast::NumberLiteral::Integer(n as u64)),
+        &Variant::SignedInteger(n) => {
+            negate(ast::Expression::Number(ast::NumberLiteral::Integer((n as u64).wrapping_neg())))
+        },
+        &Variant::Float(n) if *n < 0.0 => negate(ast::Expression::Number(ast::NumberLiteral::Float(-*n))),
+        &Variant::Float(n) => ast::Expression::Number(ast::NumberLiteral::Float(*n)),
+        &Variant::Bytes(ref bytes) => {
+            let hex: String = bytes.iter().map(|b| format!("{:02X}", b)).collect();
+
+            ast::Expression::FunctionCall {
+                name: "unhex".to_string(),
+                arguments: vec![ast::Expression::StringLiteral(hex)],
+                distinct: false
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::TempDb;
+    use sqlsyntax::parse_statements;
+
+    fn database(sql: &str) -> TempDb {
+        let mut db = TempDb::new();
+        for stmt in parse_statements(sql).unwrap() {
+            db.execute_statement(stmt).unwrap();
+        }
+        db
+    }
+
+    #[test]
+    fn test_diff() {
+        let mut from = database("CREATE TABLE t (a U32, b STRING NULL); INSERT INTO t VALUES (1, 'x'), (2, NULL), (4, NULL);
+            CREATE TABLE d (a U8); INSERT INTO d VALUES (1), (1), (1), (2);
+            CREATE TABLE gone (a U8);");
+        let to = database("CREATE TABLE t (a U32, b STRING NULL); INSERT INTO t VALUES (2, NULL), (3, 'it\\'s');
+            COMMENT ON COLUMN t.b IS 'note';
+            CREATE TABLE d (a U8); INSERT INTO d VALUES (1), (2), (2);
+            CREATE TABLE u (x I16, y F64 NULL, z BYTE[]); INSERT INTO u VALUES (-5, -1.5, unhex('00aB'));
+            CREATE MATERIALIZED VIEW v AS SELECT count(*) AS n FROM u;
+            CREATE SEQUENCE s START WITH 10 INCREMENT BY -2;");
+
+        let diff = super::diff(&from, &to);
+        assert_eq!(diff.to_string(), "\
+CREATE SEQUENCE s START WITH 10 INCREMENT BY -2;
+DELETE FROM t WHERE a = 1 AND b = 'x';
+DELETE FROM t WHERE a = 4 AND ifnull(b = b, 0) = 0;
+INSERT INTO t VALUES (3, 'it\\'s');
+COMMENT ON COLUMN t.b IS 'note';
+DELETE FROM d WHERE a = 1;
+INSERT INTO d VALUES (1), (2);
+CREATE TABLE u (x i16, y f64 NULL, z byte[]);
+INSERT INTO u VALUES (-5, -1.5, unhex('00AB'));
+CREATE MATERIALIZED VIEW v AS SELECT count(*) AS n FROM u;
+-- table gone would have to be dropped
+");
+
+        for stmt in diff.statements {
+            from.execute_statement(stmt).unwrap();
+        }
+
+        let diff = super::diff(&from, &to);
+        assert_eq!(diff.statements.len(), 0);
+        assert_eq!(diff.unresolved, vec!["table gone would have to be dropped".to_string()]);
+        assert!(super::diff(&to, &to).is_empty());
+
+        let diff = super::diff(&database("CREATE TABLE t (a VARCHAR(2));"), &database("CREATE TABLE t (a VARCHAR(5));"));
+        assert!(!diff.is_empty());
+        assert_eq!(diff.unresolved, vec!["table t has different columns".to_string()]);
+    }
+}
@@ -0,0 +1,353 @@
+//! A reader for JSON Lines (newline-delimited JSON), where each line is an
+//! object that holds one row.
+//!
+//! Only the top level of each object maps to columns. Nested arrays and
+//! objects are kept as their JSON text. The `json_each` table function
+//! expands them one level at a time with the same parser.
+
+use std::iter::Peekable;
+use std::str::Chars;
+
+#[derive(Debug, PartialEq)]
+pub enum JsonValue {
+    Null,
+    Bool(bool),
+    Integer(i64),
+    Float(f64),
+    String(String),
+    /// An array or object, as JSON text
+    Nested(String)
+}
+
+/// Parses a line holding one JSON object into its fields, in order.
+pub fn parse_object(line: &str) -> Result<Vec<(String, JsonValue)>, String> {
+    let mut parser = Parser { chars: line.chars().peekable() };
+
+    parser.skip_whitespace();
+    try!(parser.expect('{'));
+
+    let mut fields = Vec::new();
+
+    parser.skip_whitespace();
+    if parser.chars.peek() == Some(&'}') {
+        parser.chars.next();
+    } else {
+        loop {
+            parser.skip_whitespace();
+            try!(parser.expect('"'));
+            let name = try!(parser.string());
+
+            parser.skip_whitespace();
+            try!(parser.expect(':'));
+            parser.skip_whitespace();
+
+            let value = try!(parser.value());
+
+            if fields.iter().any(|&(ref n, _)| n == &name) {
+                return Err(format!("duplicate key \"{}\"", name));
+            }
+            fields.push((name, value));
+
+            parser.skip_whitespace();
+            match parser.chars.next() {
+                Some(',') => (),
+                Some('}') => break,
+                c => return Err(unexpected(c))
+            }
+        }
+    }
+
+    parser.skip_whitespace();
+    match parser.chars.next() {
+        None => Ok(fields),
+        c => Err(unexpected(c))
+    }
+}
+
+/// Parses text holding a JSON array into its elements, in order.
+pub fn parse_array(text: &str) -> Result<Vec<JsonValue>, String> {
+    let mut parser = Parser { chars: text.chars().peekable() };
+
+    parser.skip_whitespace();
+    try!(parser.expect('['));
+
+    let mut elements = Vec::new();
+
+    parser.skip_whitespace();
+    if parser.chars.peek() == Some(&']') {
+        parser.chars.next();
+    } else {
+        loop {
+            parser.skip_whitespace();
+            elements.push(try!(parser.value()));
+
+            parser.skip_whitespace();
+            match parser.chars.next() {
+                Some(',') => (),
+                Some(']') => break,
+                c => return Err(unexpected(c))
+            }
+        }
+    }
+
+    parser.skip_whitespace();
+    match parser.chars.next() {
+        None => Ok(elements),
+        c => Err(unexpected(c))
+    }
+}
+
+/// Parses text holding any one JSON value.
+pub fn parse_value(text: &str) -> Result<JsonValue, String> {
+    let mut parser = Parser { chars: text.chars().peekable() };
+
+    parser.skip_whitespace();
+    let value = try!(parser.value());
+
+    parser.skip_whitespace();
+    match parser.chars.next() {
+        None => Ok(value),
+        c => Err(unexpected(c))
+    }
+}
+
+fn unexpected(c: Option<char>) -> String {
+    match c {
+        Some(c) => format!("unexpected character '{}'", c),
+        None => format!("unexpected end of line")
+    }
+}
+
+struct Parser<'a> {
+    chars: Peekable<Chars<'a>>
+}
+
+impl<'a> Parser<'a> {
+    fn skip_whitespace(&mut self) {
+        while let Some(&c) = self.chars.peek() {
+            match c {
+                ' ' | '\t' | '\r' | '\n' => { self.chars.next(); },
+                _ => break
+            }
+        }
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), String> {
+        match self.chars.next() {
+            Some(c) if c == expected => Ok(()),
+            c => Err(unexpected(c))
+        }
+    }
+
+    fn value(&mut self) -> Result<JsonValue, String> {
+        match self.chars.peek().cloned() {
+            Some('"') => {
+                self.chars.next();
+                Ok(JsonValue::String(try!(self.string())))
+            },
+            Some('[') | Some('{') => {
+                let mut text = String::new();
+                try!(self.nested(&mut text));
+                Ok(JsonValue::Nested(text))
+            },
+            Some('-') | Some('0'...'9') => self.number(),
+            Some('a'...'z') => {
+                let mut word = String::new();
+
+                while let Some(&c) = self.chars.peek() {
+                    match c {
+                        'a'...'z' => { word.push(c); self.chars.next(); },
+                        _ => break
+                    }
+                }
+
+                match &word as &str {
+                    "null" => Ok(JsonValue::Null),
+                    "true" => Ok(JsonValue::Bool(true)),
+                    "false" => Ok(JsonValue::Bool(false)),
+                    _ => Err(format!("unexpected word \"{}\"", word))
+                }
+            },
+            c => Err(unexpected(c))
+        }
+    }
+
+    /// Reads the rest of a string whose opening quote has been consumed.
+    fn string(&mut self) -> Result<String, String> {
+        let mut s = String::new();
+
+        loop {
+            match self.chars.next() {
+                Some('"') => return Ok(s),
+                Some('\\') => {
+                    let c = match self.chars.next() {
+                        Some('"') => '"',
+                        Some('\\') => '\\',
+                        Some('/') => '/',
+                        Some('b') => '\x08',
+                        Some('f') => '\x0C',
+                        Some('n') => '\n',
+                        Some('r') => '\r',
+                        Some('t') => '\t',
+                        Some('u') => try!(self.unicode_escape()),
+                        c => return Err(unexpected(c))
+                    };
+                    s.push(c);
+                },
+                Some(c) => s.push(c),
+                None => return Err(unexpected(None))
+            }
+        }
+    }
+
+    /// Reads the hex digits of a \u escape, including a following low
+    /// surrogate if the first is a high surrogate.
+    fn unicode_escape(&mut self) -> Result<char, String> {
+        use std::char;
+
+        let high = try!(self.hex4());
+
+        let code = if high >= 0xD800 && high < 0xDC00 {
+            try!(self.expect('\\'));
+            try!(self.expect('u'));
+            let low = try!(self.hex4());
+
+            if low < 0xDC00 || low >= 0xE000 {
+                return Err(format!("invalid surrogate pair"));
+            }
+
+            0x10000 + ((high - 0xD800) << 10) + (low - 0xDC00)
+        } else {
+            high
+        };
+
+        char::from_u32(code).ok_or(format!("invalid unicode escape"))
+    }
+
+    fn hex4(&mut self) -> Result<u32, String> {
+        let mut value = 0;
+
+        for _ in 0..4 {
+            let digit = match self.chars.next() {
+                Some(c) => try!(c.to_digit(16).ok_or(unexpected(Some(c)))),
+                None => return Err(unexpected(None))
+            };
+            value = value * 16 + digit;
+        }
+
+        Ok(value)
+    }
+
+    fn number(&mut self) -> Result<JsonValue, String> {
+        let mut text = String::new();
+
+        while let Some(&c) = self.chars.peek() {
+            match c {
+                '0'...'9' | '-' | '+' | '.' | 'e' | 'E' => { text.push(c); self.chars.next(); },
+                _ => break
+            }
+        }
+
+        let is_integer = !text.chars().any(|c| c == '.' || c == 'e' || c == 'E');
+
+        if is_integer {
+            if let Ok(n) = text.parse() {
+                return Ok(JsonValue::Integer(n));
+            }
+        }
+
+        // Integers too large for i64 become floats
+        match text.parse() {
+            Ok(n) => Ok(JsonValue::Float(n)),
+            Err(_) => Err(format!("malformed number {}", text))
+        }
+    }
+
+    /// Copies an array or object verbatim, checking only that its brackets
+    /// and strings are balanced.
+    fn nested(&mut self, text: &mut String) -> Result<(), String> {
+        let mut closers = Vec::new();
+
+        loop {
+            let c = match self.chars.next() {
+                Some(c) => c,
+                None => return Err(unexpected(None))
+            };
+            text.push(c);
+
+            match c {
+                '[' => closers.push(']'),
+                '{' => closers.push('}'),
+                ']' | '}' => {
+                    if closers.pop() != Some(c) {
+                        return Err(unexpected(Some(c)));
+                    }
+                    if closers.is_empty() {
+                        return Ok(());
+                    }
+                },
+                '"' => {
+                    // Skip over the string, escapes included
+                    loop {
+                        match self.chars.next() {
+                            Some('"') => { text.push('"'); break; },
+                            Some('\\') => {
+                                text.push('\\');
+                                match self.chars.next() {
+                                    Some(c) => text.push(c),
+                                    None => return Err(unexpected(None))
+                                }
+                            },
+                            Some(c) => text.push(c),
+                            None => return Err(unexpected(None))
+                        }
+                    }
+                },
+                _ => ()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{parse_array, parse_object, parse_value, JsonValue};
+
+    #[test]
+    fn test_parse_json_object() {
+        let fields = parse_object(r#" {"a": 1, "b": -2.5e1, "c": "x\"é🦙", "d": [1, {"e": "]"}], "f": null, "g": true} "#).unwrap();
+
+        assert_eq!(fields, vec![
+            ("a".to_string(), JsonValue::Integer(1)),
+            ("b".to_string(), JsonValue::Float(-25.0)),
+            ("c".to_string(), JsonValue::String("x\"é🦙".to_string())),
+            ("d".to_string(), JsonValue::Nested(r#"[1, {"e": "]"}]"#.to_string())),
+            ("f".to_string(), JsonValue::Null),
+            ("g".to_string(), JsonValue::Bool(true))
+        ]);
+
+        assert_eq!(parse_object("{}").unwrap(), vec![]);
+        assert_eq!(parse_object(r#"{"a": 18446744073709551616}"#).unwrap(), vec![
+            ("a".to_string(), JsonValue::Float(18446744073709551616.0))
+        ]);
+
+        assert!(parse_object(r#"{"a": 1,}"#).is_err());
+        assert!(parse_object(r#"{"a": 1} x"#).is_err());
+        assert!(parse_object(r#"{"a": 1, "a": 2}"#).is_err());
+        assert!(parse_object(r#"{"a": [1}"#).is_err());
+        assert!(parse_object("[1]").is_err());
+
+        assert_eq!(parse_array(r#"[1, "a", [2], {}]"#).unwrap(), vec![
+            JsonValue::Integer(1),
+            JsonValue::String("a".to_string()),
+            JsonValue::Nested("[2]".to_string()),
+            JsonValue::Nested("{}".to_string())
+        ]);
+        assert_eq!(parse_array(" [] ").unwrap(), vec![]);
+        assert!(parse_array("[1,]").is_err());
+
+        assert_eq!(parse_value(" 2.5 ").unwrap(), JsonValue::Float(2.5));
+        assert_eq!(parse_value(r#"{"a": 1}"#).unwrap(), JsonValue::Nested(r#"{"a": 1}"#.to_string()));
+        assert!(parse_value("1 2").is_err());
+    }
+}
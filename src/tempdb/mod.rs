@@ -4,28 +4,161 @@
 //! This module will be removed once the pager and B+Tree are functional.
 
 use std::borrow::Cow;
-use std::collections::BTreeSet;
+use std::cmp;
+use std::collections::{BTreeSet, HashMap};
+use std::fs::{self, File};
+use std::io::{self, BufRead, Read, Write};
+use std::path::Path;
+use std::sync::mpsc::Receiver;
+use std::time::Duration;
+use std::{i64, u32, u64};
 
 use columnvalueops::{ColumnValueOps, ColumnValueOpsExt};
 use databaseinfo::{DatabaseInfo, TableInfo, ColumnInfo};
-use databasestorage::{Group, DatabaseStorage};
-use identifier::Identifier;
+use databasestorage::DatabaseStorage;
+use identifier::{CaseFolding, Identifier};
 use types::{DbType, Variant};
-use sqlsyntax::ast;
+use sqlsyntax::{self, ast, SqlMode};
+use sqlsyntax::placeholders::{self, PlaceholderContext};
 use queryplan::{self, ExecuteQueryPlan, QueryPlan};
 
+mod changelog;
+use self::changelog::ChangeLog;
+pub use self::changelog::{Change, LogEntry, decode_change_log, encode_change_log};
+
+mod changes;
+use self::changes::Subscribers;
+pub use self::changes::{ChangeOp, RowChange};
+
+mod csv;
+
+mod diff;
+pub use self::diff::Diff;
+
+mod jsonlines;
+use self::jsonlines::JsonValue;
+
+mod sequence;
+use self::sequence::Sequence;
+
+mod planformat;
+
+mod rename;
+use self::rename::{Rename, Renamer};
+
+mod settings;
+use self::settings::Settings;
+pub use self::settings::Synchronous;
+
+mod shared;
+pub use self::shared::SharedTempDb;
+
+mod snapshot;
+use self::snapshot::{SnapshotReader, SnapshotWriter};
+
 mod table;
 use self::table::Table;
 
+mod tablefunction;
+
+mod virtualtable;
+
 pub struct TempDb {
-    tables: Vec<Table>
+    tables: Vec<Table>,
+    /// Temporary tables hide permanent tables of the same name
+    temp_tables: Vec<Table>,
+    sequences: Vec<Sequence>,
+    /// A version number for the application's schema, which llamadb saves
+    /// but doesn't otherwise use
+    user_version: u32,
+    settings: Settings,
+    /// Session variables, set with `SET name = value`. Like the settings,
+    /// they belong to the connection and aren't saved.
+    variables: HashMap<Identifier, Variant>,
+    slow_query_sink: Option<Box<FnMut(&SlowQuery) + Send>>,
+    subscribers: Subscribers,
+    change_log: ChangeLog
+}
+
+/// A statement that took at least as long as the slow query threshold
+#[derive(Debug)]
+pub struct SlowQuery {
+    /// The statement, printed as SQL
+    pub sql: String,
+    pub duration: Duration,
+    /// The amount of rows inserted, deleted, refreshed or selected, if any
+    pub rows: Option<u64>,
+    /// The query plan of a SELECT statement
+    pub plan: Option<String>
+}
+
+/// A statement whose parameters have been checked and described.
+pub struct PreparedStatement {
+    statement: ast::Statement,
+    parameters: Vec<ParameterDescription>
+}
+
+impl PreparedStatement {
+    pub fn statement(&self) -> &ast::Statement { &self.statement }
+
+    /// The statement's parameters, ordered by number.
+    pub fn parameters(&self) -> &[ParameterDescription] { &self.parameters }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct ParameterDescription {
+    /// Parameters are numbered from 1
+    pub number: u32,
+    /// The type of the column the parameter is compared with or inserted into.
+    /// None if the type can't be inferred from the statement.
+    pub dbtype: Option<DbType>
+}
+
+/// What's known about a column of a query's results
+#[derive(Clone, Debug, PartialEq)]
+pub struct ColumnMetadata {
+    /// The declared type of the table column it selects, or None if it's
+    /// computed
+    pub dbtype: Option<DbType>,
+    /// The table and column it selects as is, directly or through subqueries
+    /// in FROM
+    pub table: Option<String>,
+    pub column: Option<String>,
+    /// Computed columns are assumed to be nullable
+    pub nullable: bool,
+    /// The table column's maximum length, if it's declared with one
+    pub max_length: Option<u64>
+}
+
+impl ColumnMetadata {
+    fn computed() -> ColumnMetadata {
+        ColumnMetadata {
+            dbtype: None,
+            table: None,
+            column: None,
+            nullable: true,
+            max_length: None
+        }
+    }
 }
 
 pub enum ExecuteStatementResponse<'a> {
     Created,
     Inserted(u64),
+    Updated(u64),
+    Deleted(u64),
+    /// A table or column was renamed
+    Altered,
+    /// A table or column's comment was set or removed
+    Commented,
+    /// A materialized view was recomputed, and now has this many rows
+    Refreshed(u64),
+    /// A setting or session variable was changed
+    Set,
     Select {
         column_names: Box<[String]>,
+        /// The metadata of each column, in the same order as `column_names`
+        columns: Box<[ColumnMetadata]>,
         rows: Box<Iterator<Item=Box<[Variant]>> + 'a>
     },
     Explain(String)
@@ -38,317 +171,3447 @@ impl DatabaseInfo for TempDb {
     type ColumnValue = Variant;
 
     fn find_table_by_name(&self, name: &Identifier) -> Option<&Table> {
-        self.tables.iter().find(|t| &t.name == name)
+        self.temp_tables.iter().chain(self.tables.iter()).find(|t| &t.name == name)
     }
-}
-
-struct ScanGroup<'a> {
-    table: &'a Table
-}
-
-impl<'a> Group for ScanGroup<'a> {
-    type ColumnValue = Variant;
 
-    fn get_any_row<'b>(&'b self) -> Option<Cow<'b, [Variant]>> {
-        self.iter().nth(0)
+    fn has_sequence(&self, name: &Identifier) -> bool {
+        self.find_sequence(name).is_ok()
     }
 
-    fn count(&self) -> u64 {
-        self.table.rowid_index.len() as u64
+    fn get_identifier_case_folding(&self) -> CaseFolding {
+        self.settings.case_folding
     }
 
-    fn iter<'b>(&'b self) -> Box<Iterator<Item=Cow<'b, [Variant]>> + 'b> {
-        let table = self.table;
-        let columns: &'b [self::table::Column] = &table.columns;
-
-        Box::new(table.rowid_index.iter().map(move |key_v| {
-            use byteutils;
-            use std::borrow::IntoCow;
-
-            let raw_key: &[u8] = &key_v;
-            trace!("KEY: {:?}", raw_key);
-
-            let variable_column_count = columns.iter().filter(|column| {
-                column.dbtype.is_variable_length()
-            }).count();
+    fn get_table_function_columns(&self, name: &Identifier) -> Option<Vec<Identifier>> {
+        tablefunction::column_names(name)
+    }
+}
 
-            let variable_lengths: Vec<_> = (0..variable_column_count).map(|i| {
-                let o = raw_key.len() - variable_column_count*8 + i*8;
-                byteutils::read_udbinteger(&raw_key[o..o+8])
-            }).collect();
+impl DatabaseStorage for TempDb {
+    type Info = TempDb;
 
-            trace!("variable lengths: {:?}", variable_lengths);
+    fn scan_table<'a>(&'a self, table: &'a Table)
+    -> Result<Box<Iterator<Item=Result<Cow<'a, [Variant]>, String>> + 'a>, String>
+    {
+        use std::borrow::IntoCow;
 
-            let _rowid: u64 = byteutils::read_udbinteger(&raw_key[0..8]);
+        match table.virtual_table {
+            Some(ref virtual_table) => {
+                let rows = try!(virtual_table.read_rows(&table.columns));
 
-            let mut variable_length_offset = 0;
-            let mut key_offset = 8;
+                Ok(Box::new(rows.map(|row| row.map(|row| row.into_cow()))))
+            },
+            None => Ok(Box::new(table.rowid_index.iter().map(move |key_v| {
+                Ok(table.decode_row(key_v).into_cow())
+            })))
+        }
+    }
 
-            let v: Vec<Variant> = columns.iter().map(|column| {
-                let is_null = if column.nullable {
-                    let flag = raw_key[key_offset];
-                    key_offset += 1;
-                    flag != 0
-                } else {
-                    false
-                };
+    fn count_rows(&self, table: &Table) -> Result<u64, String> {
+        match table.virtual_table {
+            Some(ref virtual_table) => {
+                let mut count = 0;
 
-                if is_null {
-                    ColumnValueOpsExt::null()
-                } else {
-                    let size = match column.dbtype.get_fixed_length() {
-                        Some(l) => l as usize,
-                        None => {
-                            let l = variable_lengths[variable_length_offset];
-                            variable_length_offset += 1;
-                            l as usize
-                        }
-                    };
+                for row in try!(virtual_table.read_rows(&table.columns)) {
+                    try!(row);
+                    count += 1;
+                }
 
-                    let bytes = &raw_key[key_offset..key_offset + size];
+                Ok(count)
+            },
+            None => Ok(table.rowid_index.len() as u64)
+        }
+    }
 
-                    trace!("from bytes: {:?}, {:?}", column.dbtype, bytes);
-                    let value = ColumnValueOps::from_bytes(column.dbtype, bytes.into_cow()).unwrap();
-                    key_offset += size;
-                    value
-                }
-            }).collect();
+    fn next_sequence_value(&self, name: &Identifier) -> Result<Variant, String> {
+        let sequence = try!(self.find_sequence(name));
+        sequence.next_value().map(Variant::from_i64)
+    }
 
-            v.into_cow()
-        }))
+    fn current_sequence_value(&self, name: &Identifier) -> Result<Variant, String> {
+        let sequence = try!(self.find_sequence(name));
+        sequence.current_value().map(Variant::from_i64)
     }
-}
 
-impl DatabaseStorage for TempDb {
-    type Info = TempDb;
+    fn session_variable(&self, name: &Identifier) -> Result<Variant, String> {
+        self.variables.get(name).cloned().ok_or(format!("session variable {} is not set", name))
+    }
 
-    fn scan_table<'a>(&'a self, table: &'a Table)
-    -> Box<Group<ColumnValue=Variant> + 'a>
+    fn call_table_function<'a>(&'a self, name: &Identifier, arguments: Vec<Variant>)
+    -> Result<Box<Iterator<Item=Vec<Variant>> + 'a>, String>
     {
-        Box::new(ScanGroup {
-            table: table
-        })
+        tablefunction::call(name, arguments)
     }
 }
 
 impl TempDb {
     pub fn new() -> TempDb {
         TempDb {
-            tables: Vec::new()
+            tables: Vec::new(),
+            temp_tables: Vec::new(),
+            sequences: Vec::new(),
+            user_version: 0,
+            settings: Settings::new(),
+            variables: HashMap::new(),
+            slow_query_sink: None,
+            subscribers: Subscribers::new(),
+            change_log: ChangeLog::new()
         }
     }
 
-    pub fn execute_statement(&mut self, stmt: ast::Statement) -> ExecuteStatementResult {
-        match stmt {
-            ast::Statement::Create(create_stmt) => {
-                match create_stmt {
-                    ast::CreateStatement::Table(s) => self.create_table(s)
-                }
-            },
-            ast::Statement::Insert(insert_stmt) => self.insert_into(insert_stmt),
-            ast::Statement::Select(select_stmt) => self.select(select_stmt),
-            ast::Statement::Explain(explain_stmt) => self.explain(explain_stmt)
-        }
+    /// Sets how the names of new tables, columns and result columns are
+    /// displayed. Lookups are case-insensitive regardless.
+    pub fn set_identifier_case_folding(&mut self, case_folding: CaseFolding) {
+        self.settings.case_folding = case_folding;
     }
 
-    fn create_table(&mut self, stmt: ast::CreateTableStatement) -> ExecuteStatementResult {
-        if stmt.table.database_name.is_some() {
-            unimplemented!()
-        }
+    /// Reports statements that take at least `threshold_ms` milliseconds to
+    /// `sink`. The threshold can be changed later with
+    /// `PRAGMA slow_query_threshold`. Thresholds too large to time are
+    /// lowered to the largest one, which no statement reaches.
+    ///
+    /// The sink is `Send` so that the database can be shared between threads
+    /// with `SharedTempDb`.
+    pub fn set_slow_query_log(&mut self, threshold_ms: u64, sink: Box<FnMut(&SlowQuery) + Send>) {
+        self.settings.slow_query_threshold = Some(cmp::min(threshold_ms, i64::MAX as u64));
+        self.slow_query_sink = Some(sink);
+    }
 
-        let table_name = Identifier::new(&stmt.table.table_name).unwrap();
+    /// Returns a channel of the rows that are inserted into or deleted from
+    /// tables, including tables created from a query and materialized views
+    /// when they're created or refreshed. A statement's changes are sent once
+    /// it has succeeded, so a failed statement sends none. Dropping the
+    /// receiver unsubscribes.
+    pub fn subscribe(&mut self) -> Receiver<RowChange> {
+        self.subscribers.subscribe()
+    }
 
-        let columns_result: Result<_, String>;
-        columns_result = stmt.columns.into_iter().enumerate().map(|(i, column)| {
-            let name = Identifier::new(&column.column_name).unwrap();
-            let type_name = Identifier::new(&column.type_name).unwrap();
-            let type_array_size = match column.type_array_size {
-                Some(Some(s)) => {
-                    let v = try!(self.parse_number_as_u64(s));
-                    Some(Some(v))
-                },
-                Some(None) => Some(None),
-                None => None
-            };
+    /// Sets how much saving waits for the file to reach the disk. Bulk loads
+    /// that are saved often can turn syncing off, at the risk of losing the
+    /// file in a crash.
+    pub fn set_synchronous(&mut self, synchronous: Synchronous) {
+        self.settings.synchronous = synchronous;
+    }
 
-            let dbtype = try!(DbType::from_identifier(&type_name, type_array_size).ok_or(format!("{} is not a valid column type", type_name)));
+    /// The mode that SQL for this database should be parsed with, which can
+    /// be changed with `PRAGMA sql_mode`
+    pub fn sql_mode(&self) -> SqlMode {
+        self.settings.sql_mode
+    }
 
-            let nullable = column.constraints.iter().any(|c| {
-                c.constraint == ast::CreateTableColumnConstraintType::Nullable
-            });
+    /// Sets what double quotes mean to tools that parse SQL for this
+    /// database, and whether inserted values must fit their columns exactly.
+    pub fn set_sql_mode(&mut self, sql_mode: SqlMode) {
+        self.settings.sql_mode = sql_mode;
+    }
 
-            Ok(table::Column {
-                offset: i as u32,
-                name: name,
-                dbtype: dbtype,
-                nullable: nullable
-            })
-        }).collect();
+    /// The version of the application's schema, or 0 if it was never set.
+    pub fn user_version(&self) -> u32 {
+        self.user_version
+    }
 
-        let columns = try!(columns_result);
+    /// Sets the version of the application's schema, which is saved with the
+    /// database. `PRAGMA user_version = n` does the same.
+    pub fn set_user_version(&mut self, user_version: u32) {
+        self.user_version = user_version;
+        self.change_log.record(Change::Statement(format!("PRAGMA user_version = {}", user_version)));
+    }
 
-        try!(self.add_table(Table {
-            name: table_name,
-            columns: columns,
-            next_rowid: 1,
-            rowid_index: BTreeSet::new()
-        }));
+    /// The value each sequence hands out next, or None for those that are
+    /// exhausted. Sequences are saved, so a query that calls `nextval`
+    /// changes the database even though it changes no rows.
+    pub fn sequence_positions(&self) -> Vec<Option<i64>> {
+        self.sequences.iter().map(|sequence| sequence.peek_next_value()).collect()
+    }
 
-        Ok(ExecuteStatementResponse::Created)
+    /// Brings the schema up to date by executing the scripts in `migrations`
+    /// that haven't been executed yet. The script at index `i` upgrades the
+    /// schema from user version `i` to `i + 1`, so scripts must only ever be
+    /// appended to the list. Returns the new user version.
+    ///
+    /// The user version is set after each script. If a statement fails, the
+    /// database is returned to how it was before that script and the error
+    /// is returned, so the scripts before it stay applied. Changes to
+    /// temporary tables aren't undone, and row changes already sent to
+    /// subscribers aren't taken back.
+    pub fn migrate(&mut self, migrations: &[&str]) -> Result<u32, String> {
+        let current = self.user_version as usize;
+
+        if current > migrations.len() {
+            return Err(format!("the database is at version {}, but there are only {} migrations",
+                current, migrations.len()));
+        }
+
+        for (i, sql) in migrations.iter().enumerate().skip(current) {
+            let version = (i + 1) as u32;
+            let snapshot = self.snapshot();
+            let log_len = self.change_log.entries().len();
+
+            let result = sqlsyntax::parse_statements_with_mode(sql, self.sql_mode()).map_err(|e| format!("{}", e))
+                .and_then(|stmts| {
+                    for stmt in stmts {
+                        try!(self.execute_statement(stmt));
+                    }
+                    Ok(())
+                });
+
+            if let Err(e) = result {
+                self.tables.clear();
+                self.sequences.clear();
+                self.change_log.truncate(log_len);
+                try!(self.load_snapshot(&snapshot));
+
+                return Err(format!("migration to version {} failed: {}", version, e));
+            }
+
+            self.set_user_version(version);
+        }
+
+        Ok(self.user_version)
     }
 
-    fn insert_into(&mut self, stmt: ast::InsertStatement) -> ExecuteStatementResult {
-        trace!("inserting row: {:?}", stmt);
+    /// Starts logging the changes made to permanent tables, so that they can
+    /// be applied to a replica with `apply_change_log`. The replica should
+    /// start out as a copy of this database, such as a new database if
+    /// logging is enabled before anything is created.
+    ///
+    /// Schema changes are logged as statements and executed again by the
+    /// replica, which recomputes materialized views. Inserted rows are
+    /// logged as values. Sequences aren't advanced on the replica.
+    pub fn enable_change_log(&mut self) {
+        self.change_log.enable();
+    }
 
-        let table_name = stmt.table.table_name;
-        let column_types: Vec<(DbType, bool)>;
-        let ast_index_to_column_index: Vec<u32>;
+    /// Removes and returns the changes logged since the last call. Each
+    /// statement's changes are logged once it has succeeded.
+    pub fn take_change_log(&mut self) -> Vec<LogEntry> {
+        self.change_log.take()
+    }
 
-        {
-            let table = try!(self.get_table_mut(&table_name));
+    /// The LSN of the last change this database logged or applied, or 0.
+    pub fn change_log_lsn(&self) -> u64 {
+        self.change_log.lsn()
+    }
 
-            column_types = table.get_columns().iter().map(|c| {
-                (c.dbtype, c.nullable)
-            }).collect();
+    /// Applies changes logged by another database, in order. Entries that
+    /// have already been applied are skipped, so a log can be sent again
+    /// after a failure. Fails if entries are missing.
+    ///
+    /// If this database's own log is enabled, the changes are logged again
+    /// with the same LSNs.
+    pub fn apply_change_log(&mut self, entries: &[LogEntry]) -> Result<(), String> {
+        for entry in entries {
+            if !try!(self.change_log.check_next(entry.lsn)) {
+                continue;
+            }
 
-            ast_index_to_column_index = match stmt.into_columns {
-                // Column names listed; map specified columns
-                Some(v) => try!(v.into_iter().map(|column_name| {
-                    let ident = Identifier::new(&column_name).unwrap();
-                    match table.find_column_by_name(&ident) {
-                        Some(column) => Ok(column.get_offset()),
-                        None => Err(format!("column {} not in table", column_name))
-                    }
-                }).collect()),
-                // No column names are listed; map all columns
-                None => (0..table.get_column_count()).collect()
+            let result = match entry.change {
+                Change::Statement(ref sql) => {
+                    sqlsyntax::parse_statement(sql).map_err(|e| format!("{}", e)).and_then(|stmt| {
+                        self.execute_statement_untimed(stmt).map(|_| ())
+                    })
+                },
+                Change::CreateTable { ref table, ref columns } => {
+                    let columns = columns.iter().enumerate().map(|(i, &(ref name, dbtype, nullable, max_length))| {
+                        table::Column {
+                            offset: i as u32,
+                            name: name.clone(),
+                            dbtype: dbtype,
+                            nullable: nullable,
+                            max_length: max_length,
+                            comment: None
+                        }
+                    }).collect();
+
+                    self.add_table(Table {
+                        name: table.clone(),
+                        columns: columns,
+                        next_rowid: 1,
+                        rowid_index: BTreeSet::new(),
+                        view_query: None,
+                        virtual_table: None,
+                        comment: None
+                    }, false).map(|()| self.change_log.record(entry.change.clone()))
+                },
+                Change::Insert { ref table, ref rows } => self.apply_insert(table, rows),
+                Change::Delete { ref table, ref rowids } => self.apply_delete(table, rowids),
+                Change::Update { ref table, ref rows } => self.apply_update(table, rows)
             };
 
-            trace!("ast_index_to_column_index: {:?}", ast_index_to_column_index);
+            try!(result.map_err(|e| format!("could not apply change {}: {}", entry.lsn, e)));
+            self.change_log.set_lsn(entry.lsn);
         }
 
-        match stmt.source {
-            ast::InsertSource::Values(rows) => {
-                let mut count = 0;
+        Ok(())
+    }
 
-                for row in rows {
-                    if ast_index_to_column_index.len() != row.len() {
-                        return Err(format!("INSERT value contains wrong amount of columns"));
-                    }
+    fn apply_insert(&mut self, table_name: &Identifier, rows: &[Vec<Variant>]) -> Result<(), String> {
+        let count = {
+            let table = match self.tables.iter_mut().find(|t| &t.name == table_name) {
+                Some(table) => table,
+                None => return Err(format!("Could not find table named {}", table_name))
+            };
 
-                    let mut exprs: Vec<Option<ast::Expression>>;
-                    exprs = (0..column_types.len()).map(|_| None).collect();
+            let batch: Vec<Vec<_>> = try!(rows.iter().map(|row| {
+                if row.len() != table.columns.len() {
+                    return Err(format!("row has {} values, but table {} has {} columns",
+                        row.len(), table.name, table.columns.len()));
+                }
 
-                    for (i, expr) in row.into_iter().enumerate() {
-                        exprs[ast_index_to_column_index[i] as usize] = Some(expr);
-                    }
+                table.columns.iter().zip(row.iter()).map(|(column, value)| {
+                    let mut buf = Vec::new();
+                    let is_null = try!(variant_to_data(value.clone(), column.dbtype, column.nullable, column.max_length,
+                        SqlMode::Permissive, &mut buf));
+                    Ok((buf.into_boxed_slice(), is_null))
+                }).collect()
+            }).collect());
 
-                    // TODO: don't allow expressions that SELECT the same table that's being inserted into
-                    let v: Vec<_> = try!({column_types.iter().zip(exprs.into_iter()).map(|(&(dbtype, nullable), expr)| {
-                        match expr {
-                            Some(expr) => {
-                                // TODO - allocate buffer outside of loop
-                                let mut buf = Vec::new();
+            try!(table.insert_many(batch.into_iter().map(|v| v.into_iter())).map_err(|e| format!("{}", e)))
+        };
 
-                                let execute = ExecuteQueryPlan::new(self);
+        self.record_inserted(&table_name.to_string(), count);
+        Ok(())
+    }
 
-                                let sexpr = match queryplan::compile_ast_expression(self, expr).map_err(|e| format!("{}", e)) {
-                                    Ok(v) => v,
-                                    Err(e) => return Err(e)
-                                };
-                                let value = try!(execute.execute_expression(&sexpr));
+    fn apply_delete(&mut self, table_name: &Identifier, rowids: &[u64]) -> Result<(), String> {
+        let deleted = {
+            let table = match self.tables.iter_mut().find(|t| &t.name == table_name) {
+                Some(table) => table,
+                None => return Err(format!("Could not find table named {}", table_name))
+            };
 
-                                let is_null = try!(variant_to_data(value, dbtype, nullable, &mut buf));
-                                Ok((buf.into_boxed_slice(), is_null))
-                            },
-                            None => {
-                                // use default value for column type
-                                let is_null = if nullable { Some(true) } else { None };
-                                Ok((dbtype.get_default().into_owned().into_boxed_slice(), is_null))
-                            }
-                        }
-                    }).collect()});
+            let keys = try!(table.find_row_keys(rowids));
+            table.delete_rows(keys)
+        };
 
-                    let mut table = try!(self.get_table_mut(&table_name));
-                    try!(table.insert_row(v.into_iter()).map_err(|e| format!("{}", e)));
-                    count += 1;
+        self.record_deleted(&table_name.to_string(), deleted);
+        Ok(())
+    }
+
+    fn apply_update(&mut self, table_name: &Identifier, rows: &[(u64, Vec<Variant>)]) -> Result<(), String> {
+        let updated = {
+            let table = match self.tables.iter_mut().find(|t| &t.name == table_name) {
+                Some(table) => table,
+                None => return Err(format!("Could not find table named {}", table_name))
+            };
+
+            let rowids: Vec<u64> = rows.iter().map(|&(rowid, _)| rowid).collect();
+            let keys = try!(table.find_row_keys(&rowids));
+
+            let rows = try!(rows.iter().zip(keys).map(|(&(_, ref row), key)| {
+                if row.len() != table.columns.len() {
+                    return Err(format!("row has {} values, but table {} has {} columns",
+                        row.len(), table.name, table.columns.len()));
                 }
 
-                Ok(ExecuteStatementResponse::Inserted(count))
-            },
-            ast::InsertSource::Select(_s) => unimplemented!()
+                let values = try!(table.columns.iter().zip(row.iter()).map(|(column, value)| {
+                    convert_for_column(value.clone(), column, SqlMode::Permissive)
+                }).collect());
+
+                Ok((key, values))
+            }).collect());
+
+            try!(table.update_rows(rows))
+        };
+
+        self.record_updated(&table_name.to_string(), updated);
+        Ok(())
+    }
+
+    /// Like `take_change_log`, but first writes the entries to a new segment
+    /// file in `directory`, for `restore` to replay. Nothing is written if
+    /// no changes were logged. The entries are still returned, so they can
+    /// also be sent to replicas.
+    pub fn archive_change_log<P: AsRef<Path>>(&mut self, directory: P) -> Result<Vec<LogEntry>, String> {
+        if let Some(first) = self.change_log.entries().first() {
+            let path = directory.as_ref().join(changelog::segment_name(first.lsn));
+            try!(self.write_file(&path, &encode_change_log(self.change_log.entries())));
         }
+
+        Ok(self.change_log.take())
     }
 
-    fn select(&self, stmt: ast::SelectStatement) -> ExecuteStatementResult {
-        let plan = try!(QueryPlan::compile_select(self, stmt).map_err(|e| format!("{}", e)));
-        debug!("{}", plan);
+    /// Recovers a database from a base backup saved by `save_to` and the
+    /// change log segments archived since. Changes are replayed up to and
+    /// including `target_lsn`, or to the end of the archive if it's None.
+    ///
+    /// The base backup must be saved while the change log is enabled, so
+    /// that the archive holds every change made after it.
+    pub fn restore<P: AsRef<Path>, Q: AsRef<Path>>(base: P, archive: Q, target_lsn: Option<u64>)
+    -> Result<TempDb, String>
+    {
+        let archive = archive.as_ref();
+        let mut db = try!(TempDb::open(base));
 
-        let mut rows = Vec::new();
+        if let Some(target_lsn) = target_lsn {
+            if target_lsn < db.change_log.lsn() {
+                return Err(format!("the base backup is already at LSN {}, past LSN {}",
+                    db.change_log.lsn(), target_lsn));
+            }
+        }
 
-        let execute = ExecuteQueryPlan::new(self);
-        try!(execute.execute_query_plan(&plan.expr, &mut |r| {
-            rows.push(r.to_vec().into_boxed_slice());
-            Ok(())
-        }));
+        let read_error = |e| format!("cannot read {}: {}", archive.display(), e);
+        let mut segment_names = Vec::new();
 
-        let column_names: Vec<String> = plan.out_column_names.iter().map(|ident| ident.to_string()).collect();
+        for dir_entry in try!(fs::read_dir(archive).map_err(&read_error)) {
+            let path = try!(dir_entry.map_err(&read_error)).path();
 
-        Ok(ExecuteStatementResponse::Select {
-            column_names: column_names.into_boxed_slice(),
-            rows: Box::new(rows.into_iter())
-        })
-    }
+            match (path.file_name().and_then(|name| name.to_str()), path.extension().and_then(|e| e.to_str())) {
+                (Some(name), Some("log")) => segment_names.push(name.to_string()),
+                _ => ()
+            }
+        }
 
-    fn explain(&self, stmt: ast::ExplainStatement) -> ExecuteStatementResult {
-        use queryplan::QueryPlan;
+        segment_names.sort();
 
-        match stmt {
-            ast::ExplainStatement::Select(select) => {
-                let plan = try!(QueryPlan::compile_select(self, select).map_err(|e| format!("{}", e)));
+        for name in segment_names {
+            let path = archive.join(&name);
+            let mut buf = Vec::new();
 
-                Ok(ExecuteStatementResponse::Explain(plan.to_string()))
+            let mut file = try!(File::open(&path).map_err(|e| format!("cannot open {}: {}", path.display(), e)));
+            try!(file.read_to_end(&mut buf).map_err(|e| format!("cannot read {}: {}", path.display(), e)));
+
+            let entries: Vec<_> = try!(decode_change_log(&buf).map_err(|e| format!("{}: {}", path.display(), e)))
+                .into_iter().filter(|entry| target_lsn.map_or(true, |target_lsn| entry.lsn <= target_lsn)).collect();
+
+            try!(db.apply_change_log(&entries).map_err(|e| format!("{}: {}", path.display(), e)));
+        }
+
+        if let Some(target_lsn) = target_lsn {
+            if db.change_log.lsn() < target_lsn {
+                return Err(format!("the archive ends at LSN {}, before LSN {}", db.change_log.lsn(), target_lsn));
             }
         }
+
+        Ok(db)
     }
 
-    fn add_table(&mut self, table: Table) -> Result<(), String> {
-        if self.tables.iter().any(|t| t.name == table.name) {
-            Err(format!("Table {} already exists", table.name))
-        } else {
-            debug!("adding table: {:?}", table);
-            self.tables.push(table);
+    /// Saves the database to a file, replacing the file if it exists.
+    /// Temporary tables and settings aren't saved.
+    ///
+    /// The database is written to a temporary file that's then renamed over
+    /// `path`, so a failed save leaves the previous file intact. The
+    /// `synchronous` setting decides whether the file is synced to disk.
+    pub fn save_to<P: AsRef<Path>>(&self, path: P) -> Result<(), String> {
+        self.write_file(path.as_ref(), &self.snapshot())
+    }
 
-            Ok(())
+    fn snapshot(&self) -> Vec<u8> {
+        let mut writer = SnapshotWriter::new();
+
+        writer.write_uvar(self.user_version as u64);
+        writer.write_uvar(self.tables.len() as u64);
+
+        for table in self.tables.iter() {
+            writer.write_identifier(&table.name);
+
+            match (&table.view_query, &table.virtual_table) {
+                (&Some(ref query), _) => {
+                    writer.write_u8(snapshot::KIND_MATERIALIZED_VIEW);
+                    writer.write_str(&query.to_string());
+                },
+                (_, &Some(ref virtual_table)) => {
+                    let arguments = virtual_table.arguments();
+
+                    writer.write_u8(snapshot::KIND_VIRTUAL_TABLE);
+                    writer.write_str(virtual_table.module());
+                    writer.write_uvar(arguments.len() as u64);
+
+                    for argument in arguments.iter() {
+                        writer.write_variant(argument);
+                    }
+                },
+                (&None, &None) => writer.write_u8(snapshot::KIND_TABLE)
+            }
+
+            writer.write_optional_str(table.get_comment());
+
+            writer.write_uvar(table.columns.len() as u64);
+
+            for column in table.columns.iter() {
+                writer.write_identifier(&column.name);
+                writer.write_dbtype(column.dbtype);
+                writer.write_u8(if column.nullable { 1 } else { 0 });
+                writer.write_optional_str(column.get_comment());
+                // Lengths are at least 1, so 0 means there's none
+                writer.write_uvar(column.max_length.unwrap_or(0));
+            }
+
+            writer.write_uvar(table.next_rowid);
+            writer.write_uvar(table.rowid_index.len() as u64);
+
+            for key in table.rowid_index.iter() {
+                writer.write_bytes(key);
+            }
         }
-    }
 
-    fn get_table_mut(&mut self, table_name: &str) -> Result<&mut Table, String> {
-        let table_name = try!(Identifier::new(table_name).ok_or(format!("Bad table name: {}", table_name)));
+        writer.write_uvar(self.sequences.len() as u64);
 
-        match self.tables.iter_mut().find(|t| t.name == table_name) {
-            Some(s) => Ok(s),
-            None => Err(format!("Could not find table named {}", table_name))
+        for sequence in self.sequences.iter() {
+            writer.write_identifier(&sequence.name);
+            writer.write_i64(sequence.increment);
+            writer.write_i64(sequence.peek_next_value().unwrap_or(0));
+            writer.write_u8(if sequence.peek_next_value().is_none() { 1 } else { 0 });
         }
-    }
 
-    fn parse_number_as_u64(&self, number: String) -> Result<u64, String> {
-        number.parse().map_err(|_| format!("{} is not a valid number", number))
+        writer.write_uvar(self.change_log.lsn());
+
+        writer.buf
     }
-}
 
-fn variant_to_data(value: Variant, column_type: DbType, nullable: bool, buf: &mut Vec<u8>)
--> Result<Option<bool>, String> {
-    match (value.is_null(), nullable) {
-        (true, true) => Ok(Some(true)),
-        (true, false) => {
-            Err(format!("cannot insert NULL into column that doesn't allow NULL"))
-        },
-        (false, nullable) => {
-            let bytes = value.to_bytes(column_type).unwrap();
-            buf.push_all(&bytes);
+    /// Writes a file through a temporary file that's renamed over `path`,
+    /// syncing it as the `synchronous` setting says.
+    fn write_file(&self, path: &Path, buf: &[u8]) -> Result<(), String> {
+        let mut temp_path = path.as_os_str().to_os_string();
+        temp_path.push(".tmp");
 
-            Ok(if nullable { Some(false) } else { None })
+        let write_error = |e| format!("cannot write {}: {}", path.display(), e);
+
+        {
+            let mut file = try!(File::create(&temp_path).map_err(&write_error));
+            try!(file.write_all(buf).map_err(&write_error));
+
+            if self.settings.synchronous != Synchronous::Off {
+                try!(file.sync_all().map_err(&write_error));
+            }
         }
+
+        try!(fs::rename(&temp_path, path).map_err(&write_error));
+
+        if self.settings.synchronous == Synchronous::Full {
+            try!(sync_directory(path).map_err(&write_error));
+        }
+
+        Ok(())
+    }
+
+    /// Compares this database with another, and returns the statements that
+    /// would make this one match it.
+    pub fn diff(&self, other: &TempDb) -> Diff {
+        diff::diff(self, other)
+    }
+
+    /// Opens a database saved by `save_to`.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<TempDb, String> {
+        let path = path.as_ref();
+        let mut buf = Vec::new();
+
+        let mut file = try!(File::open(path).map_err(|e| format!("cannot open {}: {}", path.display(), e)));
+        try!(file.read_to_end(&mut buf).map_err(|e| format!("cannot read {}: {}", path.display(), e)));
+
+        let mut db = TempDb::new();
+        try!(db.load_snapshot(&buf).map_err(|e| format!("{}: {}", path.display(), e)));
+
+        Ok(db)
+    }
+
+    fn load_snapshot(&mut self, buf: &[u8]) -> Result<(), String> {
+        let mut reader = try!(SnapshotReader::new(buf));
+
+        let user_version = try!(reader.read_uvar());
+
+        if user_version > u32::MAX as u64 {
+            return Err(format!("database file has a bad user version: {}", user_version));
+        }
+
+        self.user_version = user_version as u32;
+
+        let table_count = try!(reader.read_uvar());
+
+        for _ in 0..table_count {
+            let name = try!(reader.read_identifier());
+
+            let mut view_query = None;
+            let mut virtual_definition = None;
+
+            match try!(reader.read_u8()) {
+                snapshot::KIND_TABLE => (),
+                snapshot::KIND_MATERIALIZED_VIEW => {
+                    let sql = try!(reader.read_string());
+
+                    view_query = match sqlsyntax::parse_statement(&sql) {
+                        Ok(ast::Statement::Select(query)) => Some(query),
+                        _ => return Err(format!("materialized view {} has a bad query: {}", name, sql))
+                    };
+                },
+                snapshot::KIND_VIRTUAL_TABLE => {
+                    let module = try!(reader.read_identifier());
+                    let argument_count = try!(reader.read_uvar());
+                    let arguments = try!((0..argument_count).map(|_| reader.read_variant()).collect());
+
+                    virtual_definition = Some((module, arguments));
+                },
+                kind => return Err(format!("table {} has an unknown kind: {}", name, kind))
+            }
+
+            let comment = try!(reader.read_optional_string());
+
+            let column_count = try!(reader.read_uvar());
+            let mut columns = Vec::new();
+
+            for i in 0..column_count {
+                columns.push(table::Column {
+                    offset: i as u32,
+                    name: try!(reader.read_identifier()),
+                    dbtype: try!(reader.read_dbtype()),
+                    nullable: try!(reader.read_u8()) != 0,
+                    comment: try!(reader.read_optional_string()),
+                    max_length: match try!(reader.read_uvar()) {
+                        0 => None,
+                        n => Some(n)
+                    }
+                });
+            }
+
+            // Virtual tables keep their saved columns rather than inferring
+            // them again
+            let (virtual_table, columns) = match virtual_definition {
+                Some((module, arguments)) => {
+                    let (virtual_table, columns) = try!(virtualtable::create(&module, arguments, Some(columns),
+                        self.settings.case_folding));
+                    (Some(virtual_table), columns)
+                },
+                None => (None, columns)
+            };
+
+            let next_rowid = try!(reader.read_uvar());
+            let row_count = try!(reader.read_uvar());
+            let mut rowid_index = BTreeSet::new();
+
+            for _ in 0..row_count {
+                rowid_index.insert(try!(reader.read_bytes()).to_vec());
+            }
+
+            let table = Table {
+                name: name,
+                columns: columns,
+                next_rowid: next_rowid,
+                rowid_index: rowid_index,
+                view_query: view_query,
+                virtual_table: virtual_table,
+                comment: comment
+            };
+
+            for key in table.rowid_index.iter() {
+                try!(table.check_row(key).map_err(|e| format!("table {}: {}", table.name, e)));
+            }
+
+            try!(self.add_table(table, false));
+        }
+
+        let sequence_count = try!(reader.read_uvar());
+
+        for _ in 0..sequence_count {
+            let name = try!(reader.read_identifier());
+            let increment = try!(reader.read_i64());
+            let next = try!(reader.read_i64());
+            let exhausted = try!(reader.read_u8()) != 0;
+
+            self.sequences.push(Sequence::new(name, if exhausted { None } else { Some(next) }, increment));
+        }
+
+        self.change_log.set_lsn(try!(reader.read_uvar()));
+
+        if !reader.is_at_end() {
+            return Err(format!("database file has trailing data"));
+        }
+
+        Ok(())
+    }
+
+    /// The comment set by `COMMENT ON TABLE`, if any.
+    pub fn get_table_comment(&self, table_name: &str) -> Result<Option<&str>, String> {
+        let table = try!(self.find_table(table_name));
+        Ok(table.get_comment())
+    }
+
+    /// The comment set by `COMMENT ON COLUMN`, if any.
+    pub fn get_column_comment(&self, table_name: &str, column_name: &str) -> Result<Option<&str>, String> {
+        let table = try!(self.find_table(table_name));
+        let column_identifier = try!(Identifier::new(column_name).ok_or(format!("Bad column name: {}", column_name)));
+
+        match table.find_column_by_name(&column_identifier) {
+            Some(column) => Ok(column.get_comment()),
+            None => Err(format!("Table {} has no column named {}", table.name, column_identifier))
+        }
+    }
+
+    fn find_table(&self, table_name: &str) -> Result<&Table, String> {
+        let table_identifier = try!(Identifier::new(table_name).ok_or(format!("Bad table name: {}", table_name)));
+
+        match self.find_table_by_name(&table_identifier) {
+            Some(table) => Ok(table),
+            None => Err(format!("Could not find table named {}", table_identifier))
+        }
+    }
+
+    /// Enumerates the parameters of a statement, and infers their types from
+    /// the columns they're used with.
+    ///
+    /// Fails if the placeholders are malformed, or if a parameter is used with
+    /// columns of different types.
+    pub fn prepare(&self, stmt: ast::Statement) -> Result<PreparedStatement, String> {
+        let parameters = try!(placeholders::find_parameters(&stmt).map_err(|e| format!("{}", e)));
+
+        let descriptions = try!(parameters.into_iter().map(|parameter| {
+            let mut dbtype = None;
+
+            for context in parameter.contexts.iter() {
+                let context_dbtype = match self.placeholder_context_column(context) {
+                    Some(column) => column.dbtype,
+                    None => continue
+                };
+
+                match dbtype {
+                    Some(t) if t != context_dbtype => {
+                        return Err(format!("parameter {} is used as both {:?} and {:?}",
+                            parameter.number, t, context_dbtype));
+                    },
+                    _ => dbtype = Some(context_dbtype)
+                }
+            }
+
+            Ok(ParameterDescription {
+                number: parameter.number,
+                dbtype: dbtype
+            })
+        }).collect());
+
+        Ok(PreparedStatement {
+            statement: stmt,
+            parameters: descriptions
+        })
+    }
+
+    /// Parses the first statement of `sql` with the database's SQL mode, and
+    /// prepares it. Returns it along with the SQL after it, or None if
+    /// there's no statement left. Executing each statement before preparing
+    /// the rest lets `PRAGMA sql_mode` apply to the statements after it.
+    pub fn prepare_first<'s>(&self, sql: &'s str) -> Result<Option<(PreparedStatement, &'s str)>, String> {
+        match try!(sqlsyntax::parse_first_statement_with_mode(sql, self.sql_mode()).map_err(|e| format!("{}", e))) {
+            Some((stmt, tail)) => Ok(Some((try!(self.prepare(stmt)), tail))),
+            None => Ok(None)
+        }
+    }
+
+    /// Compiles a query, and encodes its plan so that it can be executed
+    /// later, or by another database with the same tables, without compiling
+    /// it again. The format is described in `planformat`.
+    pub fn encode_select_plan(&self, stmt: ast::SelectStatement) -> Result<Vec<u8>, String> {
+        let plan = try!(QueryPlan::compile_select(self, stmt, self.settings.max_query_depth).map_err(|e| format!("{}", e)));
+        Ok(planformat::encode_plan(&plan))
+    }
+
+    /// Executes a plan encoded by `encode_select_plan`. Fails if a table it
+    /// uses no longer exists or has different columns.
+    pub fn execute_encoded_plan(&self, buf: &[u8]) -> ExecuteStatementResult<'static> {
+        let plan = try!(planformat::decode_plan(self, buf, self.settings.max_plan_depth));
+        debug!("{}", plan);
+
+        self.execute_plan(plan)
+    }
+
+    /// The column a placeholder is used with, if it exists.
+    fn placeholder_context_column(&self, context: &PlaceholderContext) -> Option<&table::Column> {
+        match context {
+            &PlaceholderContext::Column { ref tables, ref column } => {
+                let column = match Identifier::new(column) {
+                    Some(ident) => ident,
+                    None => return None
+                };
+
+                // The column belongs to the first table that has it
+                tables.iter().filter_map(|name| {
+                    Identifier::new(name).and_then(|name| self.find_table_by_name(&name))
+                }).filter_map(|table| table.find_column_by_name(&column)).nth(0)
+            },
+            &PlaceholderContext::ColumnAt { ref table, position } => {
+                Identifier::new(table).and_then(|name| self.find_table_by_name(&name)).and_then(|table| {
+                    table.get_columns().get(position)
+                })
+            }
+        }
+    }
+
+    /// Executes a statement. If it's slower than the slow query threshold,
+    /// it's reported to the slow query sink.
+    pub fn execute_statement(&mut self, stmt: ast::Statement) -> ExecuteStatementResult<'static> {
+        let threshold = match (self.settings.slow_query_threshold, self.slow_query_sink.is_some()) {
+            (Some(ms), true) => Duration::milliseconds(ms as i64),
+            _ => return self.execute_statement_untimed(stmt)
+        };
+
+        let logged_stmt = stmt.clone();
+
+        let mut result = None;
+        let duration = Duration::span(|| {
+            result = Some(self.execute_statement_untimed(stmt));
+        });
+        let mut response = try!(result.unwrap());
+
+        if duration < threshold {
+            return Ok(response);
+        }
+
+        // Rows have already been computed, so counting them is cheap
+        let rows = match response {
+            ExecuteStatementResponse::Inserted(n) | ExecuteStatementResponse::Updated(n) |
+            ExecuteStatementResponse::Deleted(n) | ExecuteStatementResponse::Refreshed(n) => Some(n),
+            ExecuteStatementResponse::Select { column_names, columns, rows } => {
+                let rows: Vec<_> = rows.collect();
+                let count = rows.len() as u64;
+
+                response = ExecuteStatementResponse::Select {
+                    column_names: column_names,
+                    columns: columns,
+                    rows: Box::new(rows.into_iter())
+                };
+
+                Some(count)
+            },
+            _ => None
+        };
+
+        let plan = match logged_stmt {
+            ast::Statement::Select(ref select) => {
+                QueryPlan::compile_select(self, select.clone(), self.settings.max_query_depth).ok().map(|plan| plan.to_string())
+            },
+            _ => None
+        };
+
+        let slow_query = SlowQuery {
+            sql: logged_stmt.to_string(),
+            duration: duration,
+            rows: rows,
+            plan: plan
+        };
+
+        if let Some(ref mut sink) = self.slow_query_sink {
+            sink(&slow_query);
+        }
+
+        Ok(response)
+    }
+
+    fn execute_statement_untimed(&mut self, stmt: ast::Statement) -> ExecuteStatementResult<'static> {
+        let logged_sql = if self.change_log.is_enabled() && self.is_logged_as_statement(&stmt) {
+            Some(stmt.to_string())
+        } else {
+            None
+        };
+
+        let response = try!(match stmt {
+            ast::Statement::Create(create_stmt) => {
+                match create_stmt {
+                    ast::CreateStatement::Table(s) => self.create_table(s),
+                    ast::CreateStatement::MaterializedView(s) => self.create_materialized_view(s),
+                    ast::CreateStatement::Sequence(s) => self.create_sequence(s),
+                    ast::CreateStatement::VirtualTable(s) => self.create_virtual_table(s)
+                }
+            },
+            ast::Statement::AlterTable(alter_stmt) => self.alter_table(alter_stmt),
+            ast::Statement::Comment(comment_stmt) => self.comment(comment_stmt),
+            ast::Statement::Refresh(refresh_stmt) => self.refresh_materialized_view(refresh_stmt),
+            ast::Statement::Insert(insert_stmt) => self.insert_into(insert_stmt),
+            ast::Statement::Update(update_stmt) => self.update(update_stmt),
+            ast::Statement::Delete(delete_stmt) => self.delete_from(delete_stmt),
+            ast::Statement::Select(select_stmt) => self.select(select_stmt),
+            ast::Statement::Explain(explain_stmt) => self.explain(explain_stmt),
+            ast::Statement::Pragma(pragma_stmt) => self.pragma(pragma_stmt),
+            ast::Statement::Set(set_stmt) => self.set_variable(set_stmt),
+            ast::Statement::Show(show_stmt) => self.show_variable(show_stmt)
+        });
+
+        if let Some(sql) = logged_sql {
+            self.change_log.record(Change::Statement(sql));
+        }
+
+        Ok(response)
+    }
+
+    /// Whether the statement changes the schema of permanent tables, and is
+    /// logged as it is. Inserts and `CREATE TABLE ... AS` log their rows
+    /// instead, updates their rowids and new rows, and deletes their rowids.
+    fn is_logged_as_statement(&self, stmt: &ast::Statement) -> bool {
+        match stmt {
+            &ast::Statement::Create(ast::CreateStatement::Table(ref s)) => {
+                match s.source {
+                    ast::CreateTableSource::Columns(_) => !s.temporary,
+                    ast::CreateTableSource::Query(_) => false
+                }
+            },
+            &ast::Statement::Create(_) | &ast::Statement::Refresh(_) => true,
+            &ast::Statement::AlterTable(ref s) => !self.is_temporary(&s.table.table_name),
+            &ast::Statement::Comment(ref s) => {
+                match s.target {
+                    ast::CommentTarget::Table(ref table) |
+                    ast::CommentTarget::Column { ref table, .. } => !self.is_temporary(&table.table_name)
+                }
+            },
+            &ast::Statement::Insert(_) | &ast::Statement::Update(_) | &ast::Statement::Delete(_) |
+            &ast::Statement::Select(_) | &ast::Statement::Explain(_) | &ast::Statement::Pragma(_) |
+            &ast::Statement::Set(_) | &ast::Statement::Show(_) => false
+        }
+    }
+
+    fn is_temporary(&self, table_name: &str) -> bool {
+        match Identifier::new(table_name) {
+            Some(name) => self.temp_tables.iter().any(|t| t.name == name),
+            None => false
+        }
+    }
+
+    fn create_table(&mut self, stmt: ast::CreateTableStatement) -> ExecuteStatementResult<'static> {
+        if stmt.table.database_name.is_some() {
+            unimplemented!()
+        }
+
+        let table_name = Identifier::new_with_case_folding(&stmt.table.table_name, self.settings.case_folding).unwrap();
+
+        let from_query = match stmt.source {
+            ast::CreateTableSource::Query(_) => true,
+            ast::CreateTableSource::Columns(_) => false
+        };
+
+        let table = match stmt.source {
+            ast::CreateTableSource::Columns(columns) => Table {
+                name: table_name,
+                columns: try!(self.columns_from_ast(columns)),
+                next_rowid: 1,
+                rowid_index: BTreeSet::new(),
+                view_query: None,
+                virtual_table: None,
+                comment: None
+            },
+            ast::CreateTableSource::Query(query) => {
+                // The table is filled like a materialized view, but doesn't
+                // keep the query
+                let mut table = try!(self.materialize(table_name, *query));
+                table.view_query = None;
+
+                // Nothing could be stored in a column of NULLs
+                if let Some(column) = table.columns.iter().find(|column| column.dbtype == DbType::Null) {
+                    return Err(format!("cannot tell the type of column {}, since all of its values are NULL",
+                        column.name));
+                }
+
+                table
+            }
+        };
+
+        let name = table.name.to_string();
+        let count = table.rowid_index.len() as u64;
+
+        // The change is only logged once the table is added, which fails if
+        // the name is taken
+        let change = if from_query && !stmt.temporary {
+            Some(Change::create_table(&table))
+        } else {
+            None
+        };
+
+        try!(self.add_table(table, stmt.temporary));
+
+        if let Some(change) = change {
+            self.change_log.record(change);
+        }
+
+        if from_query {
+            self.record_inserted(&name, count);
+        }
+
+        Ok(ExecuteStatementResponse::Created)
+    }
+
+    fn create_virtual_table(&mut self, stmt: ast::CreateVirtualTableStatement) -> ExecuteStatementResult<'static> {
+        if stmt.table.database_name.is_some() {
+            return Err(format!("database names are not supported in CREATE VIRTUAL TABLE"));
+        }
+
+        let table_name = try!(Identifier::new_with_case_folding(&stmt.table.table_name, self.settings.case_folding)
+            .ok_or(format!("Bad table name: {}", stmt.table.table_name)));
+        let module = try!(Identifier::new(&stmt.module).ok_or(format!("Bad module name: {}", stmt.module)));
+
+        let arguments = try!(stmt.arguments.into_iter().map(|expr| self.evaluate_constant(expr)).collect());
+
+        let columns = match stmt.columns {
+            Some(columns) => Some(try!(self.columns_from_ast(columns))),
+            None => None
+        };
+
+        let (virtual_table, columns) = try!(virtualtable::create(&module, arguments, columns, self.settings.case_folding));
+
+        try!(self.add_table(Table {
+            name: table_name,
+            columns: columns,
+            next_rowid: 1,
+            rowid_index: BTreeSet::new(),
+            view_query: None,
+            virtual_table: Some(virtual_table),
+            comment: None
+        }, false));
+
+        Ok(ExecuteStatementResponse::Created)
+    }
+
+    fn columns_from_ast(&self, columns: Vec<ast::CreateTableColumn>) -> Result<Vec<table::Column>, String> {
+        let case_folding = self.settings.case_folding;
+
+        columns.into_iter().enumerate().map(|(i, column)| {
+            let name = Identifier::new_with_case_folding(&column.column_name, case_folding).unwrap();
+            let (dbtype, max_length) = try!(self.dbtype_from_ast(&column.type_name, column.type_size,
+                column.type_array_size));
+
+            let nullable = column.constraints.iter().any(|c| {
+                c.constraint == ast::CreateTableColumnConstraintType::Nullable
+            });
+
+            Ok(table::Column {
+                offset: i as u32,
+                name: name,
+                dbtype: dbtype,
+                nullable: nullable,
+                max_length: max_length,
+                comment: None
+            })
+        }).collect()
+    }
+
+    /// A column's type, and the maximum length of its values if it's a
+    /// string type declared with one, as in `VARCHAR(50)`.
+    fn dbtype_from_ast(&self, type_name: &str, type_size: Option<String>, type_array_size: Option<Option<String>>)
+    -> Result<(DbType, Option<u64>), String>
+    {
+        let type_name = Identifier::new(type_name).unwrap();
+        let type_array_size = match type_array_size {
+            Some(Some(s)) => {
+                let v = try!(self.parse_number_as_u64(s));
+                Some(Some(v))
+            },
+            Some(None) => Some(None),
+            None => None
+        };
+
+        let dbtype = try!(DbType::from_identifier(&type_name, type_array_size)
+            .ok_or(format!("{} is not a valid column type", type_name)));
+
+        let max_length = match type_size {
+            Some(s) => {
+                if dbtype != DbType::String {
+                    return Err(format!("{} cannot be declared with a length", type_name));
+                }
+
+                match try!(self.parse_number_as_u64(s)) {
+                    0 => return Err(format!("{} must have a length of at least 1", type_name)),
+                    n => Some(n)
+                }
+            },
+            None => None
+        };
+
+        Ok((dbtype, max_length))
+    }
+
+    fn create_materialized_view(&mut self, stmt: ast::CreateMaterializedViewStatement) -> ExecuteStatementResult<'static> {
+        if stmt.view.database_name.is_some() {
+            return Err(format!("database names are not supported in CREATE MATERIALIZED VIEW"));
+        }
+
+        let view_name = try!(Identifier::new_with_case_folding(&stmt.view.table_name, self.settings.case_folding)
+            .ok_or(format!("Bad view name: {}", stmt.view.table_name)));
+
+        if self.find_table_by_name(&view_name).is_some() {
+            return Err(format!("Table {} already exists", view_name));
+        }
+
+        try!(self.check_view_query_tables(&view_name, &stmt.query));
+
+        let view = try!(self.materialize(view_name, stmt.query));
+        let count = view.rowid_index.len() as u64;
+        try!(self.add_table(view, false));
+
+        self.record_inserted(&stmt.view.table_name, count);
+
+        Ok(ExecuteStatementResponse::Created)
+    }
+
+    fn create_sequence(&mut self, stmt: ast::CreateSequenceStatement) -> ExecuteStatementResult<'static> {
+        if stmt.sequence.database_name.is_some() {
+            return Err(format!("database names are not supported in CREATE SEQUENCE"));
+        }
+
+        let name = try!(Identifier::new_with_case_folding(&stmt.sequence.table_name, self.settings.case_folding)
+            .ok_or(format!("Bad sequence name: {}", stmt.sequence.table_name)));
+
+        if self.find_sequence(&name).is_ok() {
+            return Err(format!("Sequence {} already exists", name));
+        }
+
+        let start = match stmt.start {
+            Some(expr) => try!(self.evaluate_i64(expr)),
+            None => 1
+        };
+
+        let increment = match stmt.increment {
+            Some(expr) => try!(self.evaluate_i64(expr)),
+            None => 1
+        };
+
+        if increment == 0 {
+            return Err(format!("Sequence increment cannot be zero"));
+        }
+
+        self.sequences.push(Sequence::new(name, Some(start), increment));
+
+        Ok(ExecuteStatementResponse::Created)
+    }
+
+    fn find_sequence(&self, name: &Identifier) -> Result<&Sequence, String> {
+        match self.sequences.iter().find(|s| &s.name == name) {
+            Some(s) => Ok(s),
+            None => Err(format!("Could not find sequence named {}", name))
+        }
+    }
+
+    /// An executor for one query, with the limits from the settings
+    fn query_executor(&self) -> ExecuteQueryPlan<TempDb> {
+        ExecuteQueryPlan::new(self, self.settings.max_plan_depth, self.settings.query_memory_limit)
+    }
+
+    fn evaluate_constant(&self, expr: ast::Expression) -> Result<Variant, String> {
+        let sexpr = try!(queryplan::compile_ast_expression(self, expr, self.settings.max_query_depth).map_err(|e| format!("{}", e)));
+        self.query_executor().execute_expression(&sexpr)
+    }
+
+    /// Evaluates a constant expression as a signed 64-bit integer.
+    fn evaluate_i64(&self, expr: ast::Expression) -> Result<i64, String> {
+        let value = try!(self.evaluate_constant(expr));
+
+        match value.cast(DbType::Integer { signed: true, bytes: 8 }) {
+            Some(Variant::SignedInteger(n)) => Ok(n),
+            _ => Err(format!("expected an integer"))
+        }
+    }
+
+    /// Renames a table or column. The queries of materialized views are
+    /// rewritten to use the new name, so they can still be refreshed.
+    fn alter_table(&mut self, stmt: ast::AlterTableStatement) -> ExecuteStatementResult<'static> {
+        if stmt.table.database_name.is_some() {
+            return Err(format!("database names are not supported in ALTER TABLE"));
+        }
+
+        let case_folding = self.settings.case_folding;
+        let table_name = stmt.table.table_name;
+        let table_name = try!(Identifier::new(&table_name).ok_or(format!("Bad table name: {}", table_name)));
+
+        let temporary = self.temp_tables.iter().any(|t| t.name == table_name);
+
+        match stmt.action {
+            ast::AlterTableAction::RenameTable { new_name } => {
+                let new_identifier = try!(Identifier::new_with_case_folding(&new_name, case_folding)
+                    .ok_or(format!("Bad table name: {}", new_name)));
+
+                // Renaming a table to itself may change how its name is displayed
+                let same_name = try!(self.get_table_mut(&table_name)).name == new_identifier;
+
+                {
+                    let tables = if temporary { &self.temp_tables } else { &self.tables };
+                    if !same_name && tables.iter().any(|t| t.name == new_identifier) {
+                        return Err(format!("Table {} already exists", new_identifier));
+                    }
+                }
+
+                // Views don't refer to temporary tables once they're
+                // reopened, so only the names of permanent tables are
+                // rewritten
+                if !temporary && !same_name {
+                    self.rewrite_view_queries(Rename::Table {
+                        old_name: &table_name,
+                        new_name: &new_name
+                    });
+                }
+
+                try!(self.get_table_mut(&table_name)).name = new_identifier;
+            },
+            ast::AlterTableAction::RenameColumn { column, new_name } => {
+                let column_name = try!(Identifier::new(&column).ok_or(format!("Bad column name: {}", column)));
+                let new_identifier = try!(Identifier::new_with_case_folding(&new_name, case_folding)
+                    .ok_or(format!("Bad column name: {}", new_name)));
+
+                {
+                    let table = try!(self.get_table_mut(&table_name));
+
+                    if table.view_query.is_some() {
+                        return Err(format!("Cannot rename a column of materialized view {}; \
+                            rename it in the view's query instead", table.name));
+                    }
+
+                    if !table.columns.iter().any(|c| c.name == column_name) {
+                        return Err(format!("Table {} has no column named {}", table.name, column_name));
+                    }
+
+                    if column_name != new_identifier && table.columns.iter().any(|c| c.name == new_identifier) {
+                        return Err(format!("Table {} already has a column named {}", table.name, new_identifier));
+                    }
+                }
+
+                self.rename_column(temporary, table_name, column_name, new_identifier);
+            },
+            ast::AlterTableAction::AddColumn(column) => {
+                let column = try!(self.columns_from_ast(vec![column])).pop().unwrap();
+                let table = try!(self.get_table_mut(&table_name));
+                try!(check_columns_alterable(table, "add a column to"));
+
+                if table.columns.iter().any(|c| c.name == column.name) {
+                    return Err(format!("Table {} already has a column named {}", table.name, column.name));
+                }
+
+                table.add_column(column.name, column.dbtype, column.nullable, column.max_length);
+            },
+            ast::AlterTableAction::AlterColumnType { column, type_name, type_size, type_array_size } => {
+                let column_name = try!(Identifier::new(&column).ok_or(format!("Bad column name: {}", column)));
+                let (dbtype, max_length) = try!(self.dbtype_from_ast(&type_name, type_size, type_array_size));
+                let sql_mode = self.settings.sql_mode;
+
+                let table = try!(self.get_table_mut(&table_name));
+                try!(check_columns_alterable(table, "change the type of a column of"));
+
+                let offset = match table.columns.iter().find(|c| c.name == column_name) {
+                    Some(column) => column.offset,
+                    None => return Err(format!("Table {} has no column named {}", table.name, column_name))
+                };
+
+                // The rows are only checked here. Their values are converted
+                // when they're read.
+                for key in table.rowid_index.iter() {
+                    let value = table.decode_row(key).swap_remove(offset as usize);
+
+                    if value.is_null() {
+                        continue;
+                    }
+
+                    if sql_mode == SqlMode::Strict && !fits_exactly(&value, dbtype) {
+                        return Err(format!("value {} doesn't fit the type {:?} without converting it", value, dbtype));
+                    }
+
+                    // Values that are too long aren't truncated in either
+                    // mode, since the rows aren't rewritten
+                    match table::convert(value.clone(), dbtype) {
+                        Some(Variant::StringLiteral(ref s)) if max_length.map_or(false, |n| s.chars().count() as u64 > n) => {
+                            return Err(format!("value {} of column {} is longer than {} characters",
+                                value, column_name, max_length.unwrap()));
+                        },
+                        Some(_) => (),
+                        None => {
+                            return Err(format!("value {} of column {} cannot be converted to {:?}",
+                                value, column_name, dbtype));
+                        }
+                    }
+                }
+
+                try!(table.set_column_type(offset, dbtype));
+                table.columns[offset as usize].max_length = max_length;
+            }
+        }
+
+        Ok(ExecuteStatementResponse::Altered)
+    }
+
+    fn comment(&mut self, stmt: ast::CommentStatement) -> ExecuteStatementResult<'static> {
+        let (table, column) = match stmt.target {
+            ast::CommentTarget::Table(table) => (table, None),
+            ast::CommentTarget::Column { table, column } => (table, Some(column))
+        };
+
+        if table.database_name.is_some() {
+            return Err(format!("database names are not supported in COMMENT"));
+        }
+
+        let table = try!(self.get_table_mut(&table.table_name));
+
+        match column {
+            Some(column) => {
+                let column_name = try!(Identifier::new(&column).ok_or(format!("Bad column name: {}", column)));
+
+                match table.columns.iter_mut().find(|c| c.name == column_name) {
+                    Some(column) => column.comment = stmt.comment,
+                    None => return Err(format!("Table {} has no column named {}", table.name, column_name))
+                }
+            },
+            None => table.comment = stmt.comment
+        }
+
+        Ok(ExecuteStatementResponse::Commented)
+    }
+
+    /// Renames a column, and the columns of materialized views that select
+    /// it with `*`.
+    fn rename_column(&mut self, temporary: bool, table_name: Identifier, column_name: Identifier,
+        new_name: Identifier)
+    {
+        let mut pending = vec![(table_name, column_name, new_name)];
+
+        while let Some((table_name, column_name, new_name)) = pending.pop() {
+            if !temporary {
+                let renamed_view_columns = self.rewrite_view_queries(Rename::Column {
+                    table: &table_name,
+                    old_name: &column_name,
+                    new_name: &new_name.to_string()
+                });
+
+                for (view_name, view_column_name, new_view_column_name) in renamed_view_columns {
+                    let new_view_column_name = Identifier::new_with_case_folding(&new_view_column_name,
+                        self.settings.case_folding).unwrap();
+                    pending.push((view_name, view_column_name, new_view_column_name));
+                }
+            }
+
+            let table = self.get_table_mut(&table_name).unwrap();
+
+            for column in table.columns.iter_mut().filter(|c| c.name == column_name) {
+                column.name = new_name.clone();
+            }
+        }
+    }
+
+    /// Rewrites the queries of materialized views for a rename that hasn't
+    /// been made yet. Returns the view columns whose names change.
+    fn rewrite_view_queries(&mut self, rename: Rename) -> Vec<(Identifier, Identifier, String)> {
+        let mut renamed_columns = Vec::new();
+
+        let queries: Vec<_> = {
+            let table_columns = |name: &Identifier| {
+                self.find_table_by_name(name).map(|t| t.get_column_names())
+            };
+            let renamer = Renamer::new(rename, &table_columns);
+
+            self.tables.iter().enumerate().filter_map(|(i, table)| {
+                table.view_query.as_ref().map(|query| {
+                    let mut query = query.clone();
+
+                    for (column_name, new_name) in renamer.rewrite_query(&mut query) {
+                        renamed_columns.push((table.name.clone(), column_name, new_name));
+                    }
+
+                    (i, query)
+                })
+            }).collect()
+        };
+
+        for (i, query) in queries {
+            self.tables[i].view_query = Some(query);
+        }
+
+        renamed_columns
+    }
+
+    fn refresh_materialized_view(&mut self, stmt: ast::RefreshStatement) -> ExecuteStatementResult<'static> {
+        let (index, mut view) = {
+            let view_name = stmt.view.table_name;
+            let view_name = try!(Identifier::new(&view_name).ok_or(format!("Bad view name: {}", view_name)));
+
+            let index = match self.tables.iter().position(|t| t.name == view_name) {
+                Some(i) => i,
+                None => return Err(format!("Could not find materialized view named {}", view_name))
+            };
+
+            let table = &self.tables[index];
+            let query = match table.view_query {
+                Some(ref query) => query.clone(),
+                None => return Err(format!("{} is not a materialized view", table.name))
+            };
+
+            try!(self.check_view_query_tables(&table.name, &query));
+
+            (index, try!(self.materialize(table.name.clone(), query)))
+        };
+
+        // Comments outlive the view's rows
+        {
+            let old_view = &mut self.tables[index];
+            view.comment = old_view.comment.take();
+
+            for column in view.columns.iter_mut() {
+                if let Some(old_column) = old_view.columns.iter_mut().find(|c| c.name == column.name) {
+                    column.comment = old_column.comment.take();
+                }
+            }
+        }
+
+        if !self.subscribers.is_empty() {
+            let old_view = &self.tables[index];
+
+            let deleted = old_view.rowid_index.iter().map(|key| RowChange::delete(&old_view.name, old_view.decode_row(key)));
+            let inserted = view.rowid_index.iter().map(|key| RowChange::insert(&view.name, view.decode_row(key)));
+
+            self.subscribers.send(deleted.chain(inserted).collect());
+        }
+
+        let count = view.rowid_index.len() as u64;
+        self.tables[index] = view;
+
+        Ok(ExecuteStatementResponse::Refreshed(count))
+    }
+
+    /// Materialized views are saved and replicated, but temporary tables
+    /// aren't, so a view can't read one. That includes a temporary table
+    /// created after the view, which hides the permanent table it reads.
+    fn check_view_query_tables(&self, view_name: &Identifier, query: &ast::SelectStatement) -> Result<(), String> {
+        let mut table_names = Vec::new();
+        query_table_names(query, &mut table_names);
+
+        match table_names.into_iter().find(|name| self.is_temporary(name)) {
+            Some(name) => Err(format!("materialized view {} cannot read temporary table {}", view_name, name)),
+            None => Ok(())
+        }
+    }
+
+    /// Runs the query and stores its rows in a new table.
+    ///
+    /// Query results aren't statically typed, so the type of each column is
+    /// taken from its first non-NULL value.
+    fn materialize(&self, name: Identifier, query: ast::SelectStatement) -> Result<Table, String> {
+        let mut rows = Vec::new();
+
+        let columns: Vec<table::Column> = {
+            let plan = try!(QueryPlan::compile_select(self, query.clone(), self.settings.max_query_depth).map_err(|e| format!("{}", e)));
+
+            let execute = self.query_executor();
+            try!(execute.execute_query_plan(&plan.expr, &mut |r| {
+                try!(execute.reserve_row(r));
+                rows.push(r.to_vec());
+                Ok(())
+            }));
+
+            plan.out_column_names.iter().zip(plan.out_column_origins.iter()).enumerate().map(|(i, (column_name, origin))| {
+                // A table's column keeps its type. Computed columns take the
+                // type of their first value that isn't NULL.
+                let (dbtype, nullable, max_length) = match origin {
+                    &Some(ref origin) => {
+                        let column = origin.table.find_column_by_offset(origin.column_offset).unwrap();
+                        (column.dbtype, column.nullable || origin.outer_joined, column.max_length)
+                    },
+                    &None => {
+                        let dbtype = rows.iter().map(|r| &r[i]).find(|v| !v.is_null()).map_or(DbType::Null, |v| v.get_dbtype());
+                        (dbtype, dbtype == DbType::Null || rows.iter().any(|r| r[i].is_null()), None)
+                    }
+                };
+
+                table::Column {
+                    offset: i as u32,
+                    name: column_name.clone(),
+                    dbtype: dbtype,
+                    nullable: nullable,
+                    max_length: max_length,
+                    comment: None
+                }
+            }).collect()
+        };
+
+        let batch: Vec<Vec<_>> = try!(rows.into_iter().map(|row| {
+            columns.iter().zip(row.into_iter()).map(|(column, value)| -> Result<_, String> {
+                let mut buf = Vec::new();
+                // The column types were taken from the values, or from the
+                // columns the values came from
+                let is_null = try!(variant_to_data(value, column.dbtype, column.nullable, column.max_length,
+                    SqlMode::Permissive, &mut buf));
+                Ok((buf.into_boxed_slice(), is_null))
+            }).collect()
+        }).collect());
+
+        let mut table = Table {
+            name: name,
+            columns: columns,
+            next_rowid: 1,
+            rowid_index: BTreeSet::new(),
+            view_query: Some(query),
+            virtual_table: None,
+            comment: None
+        };
+
+        try!(table.insert_many(batch.into_iter().map(|v| v.into_iter())).map_err(|e| format!("{}", e)));
+
+        Ok(table)
+    }
+
+    fn insert_into(&mut self, stmt: ast::InsertStatement) -> ExecuteStatementResult<'static> {
+        trace!("inserting row: {:?}", stmt);
+
+        let table_name = stmt.table.table_name;
+        let column_types: Vec<(DbType, bool, Option<u64>)>;
+        let ast_index_to_column_index: Vec<u32>;
+
+        // DEFAULT VALUES is a single row that lists no columns
+        let (into_columns, source) = match stmt.source {
+            ast::InsertSource::DefaultValues => {
+                if stmt.into_columns.is_some() {
+                    return Err(format!("DEFAULT VALUES cannot be used with a list of columns"));
+                }
+
+                (Some(Vec::new()), ast::InsertSource::Values(vec![Vec::new()]))
+            },
+            source => (stmt.into_columns, source)
+        };
+
+        {
+            let table = try!(self.get_table_mut(&table_name));
+
+            if table.view_query.is_some() {
+                return Err(format!("cannot insert into materialized view {}", table.name));
+            }
+
+            if table.virtual_table.is_some() {
+                return Err(format!("cannot insert into virtual table {}", table.name));
+            }
+
+            column_types = table.get_columns().iter().map(|c| {
+                (c.dbtype, c.nullable, c.max_length)
+            }).collect();
+
+            ast_index_to_column_index = match into_columns {
+                // Column names listed; map specified columns
+                Some(v) => try!(v.into_iter().map(|column_name| {
+                    let ident = Identifier::new(&column_name).unwrap();
+                    match table.find_column_by_name(&ident) {
+                        Some(column) => Ok(column.get_offset()),
+                        None => Err(format!("column {} not in table", column_name))
+                    }
+                }).collect()),
+                // No column names are listed; map all columns
+                None => (0..table.get_column_count()).collect()
+            };
+
+            trace!("ast_index_to_column_index: {:?}", ast_index_to_column_index);
+        }
+
+        match source {
+            ast::InsertSource::Values(rows) => {
+                let mut batch = Vec::with_capacity(rows.len());
+
+                for row in rows {
+                    if ast_index_to_column_index.len() != row.len() {
+                        return Err(format!("INSERT value contains wrong amount of columns"));
+                    }
+
+                    let mut exprs: Vec<Option<ast::Expression>>;
+                    exprs = (0..column_types.len()).map(|_| None).collect();
+
+                    for (i, expr) in row.into_iter().enumerate() {
+                        exprs[ast_index_to_column_index[i] as usize] = Some(expr);
+                    }
+
+                    // TODO: don't allow expressions that SELECT the same table that's being inserted into
+                    let v: Vec<_> = try!({column_types.iter().zip(exprs.into_iter()).map(|(&(dbtype, nullable, max_length), expr)| {
+                        match expr {
+                            Some(expr) => {
+                                // TODO - allocate buffer outside of loop
+                                let mut buf = Vec::new();
+
+                                let execute = self.query_executor();
+
+                                let sexpr = match queryplan::compile_ast_expression(self, expr, self.settings.max_query_depth).map_err(|e| format!("{}", e)) {
+                                    Ok(v) => v,
+                                    Err(e) => return Err(e)
+                                };
+                                let value = try!(execute.execute_expression(&sexpr));
+
+                                let is_null = try!(variant_to_data(value, dbtype, nullable, max_length, self.settings.sql_mode, &mut buf));
+                                Ok((buf.into_boxed_slice(), is_null))
+                            },
+                            // Omitted nullable columns are NULL
+                            None if nullable => Ok((Vec::new().into_boxed_slice(), Some(true))),
+                            None => {
+                                // use default value for column type
+                                Ok((dbtype.get_default().into_owned().into_boxed_slice(), None))
+                            }
+                        }
+                    }).collect()});
+
+                    batch.push(v);
+                }
+
+                // All rows are evaluated before any are inserted.
+                // The whole batch is then inserted at once.
+                let count = {
+                    let table = try!(self.get_table_mut(&table_name));
+                    try!(table.insert_many(batch.into_iter().map(|v| v.into_iter())).map_err(|e| format!("{}", e)))
+                };
+
+                self.record_inserted(&table_name, count);
+
+                Ok(ExecuteStatementResponse::Inserted(count))
+            },
+            // Turned into a row of VALUES that lists no columns, above
+            ast::InsertSource::DefaultValues => unreachable!(),
+            ast::InsertSource::Select(_s) => unimplemented!()
+        }
+    }
+
+    fn update(&mut self, stmt: ast::UpdateStatement) -> ExecuteStatementResult<'static> {
+        let table_name = stmt.table.table_name.clone();
+        let sql_mode = self.settings.sql_mode;
+        let max_depth = self.settings.max_query_depth;
+
+        let rows: Vec<(Vec<u8>, Vec<Variant>)> = {
+            let table = try!(self.find_table(&table_name));
+
+            if table.view_query.is_some() {
+                return Err(format!("cannot update materialized view {}", table.name));
+            }
+
+            if table.virtual_table.is_some() {
+                return Err(format!("cannot update virtual table {}", table.name));
+            }
+
+            let mut columns: Vec<&table::Column> = Vec::new();
+            let mut exprs = Vec::new();
+
+            for assignment in stmt.assignments {
+                let column = match Identifier::new(&assignment.column).and_then(|ident| table.find_column_by_name(&ident)) {
+                    Some(column) => column,
+                    None => return Err(format!("Table {} has no column named {}", table.name, assignment.column))
+                };
+
+                if columns.iter().any(|c| c.offset == column.offset) {
+                    return Err(format!("column {} is assigned more than once", column.name));
+                }
+
+                columns.push(column);
+                exprs.push(assignment.expr);
+            }
+
+            let assign = |row: &[Variant], values: Vec<Variant>| -> Result<Vec<Variant>, String> {
+                let mut row = row.to_vec();
+
+                for (&column, value) in columns.iter().zip(values.into_iter()) {
+                    row[column.offset as usize] = try!(convert_for_column(value, column, sql_mode)
+                        .map_err(|e| format!("column {}: {}", column.name, e)));
+                }
+
+                Ok(row)
+            };
+
+            let mut rows = Vec::new();
+
+            match stmt.from {
+                Some(from) => {
+                    let joined = try!(self.update_join(stmt.table, table, exprs, from, stmt.where_expr));
+
+                    for key in table.rowid_index.iter() {
+                        let row = table.decode_row(key);
+
+                        if let Some(values) = joined.get(&row) {
+                            rows.push((key.clone(), try!(assign(&row, values.clone()))));
+                        }
+                    }
+                },
+                None => {
+                    let mut compiled = Vec::new();
+
+                    for expr in exprs {
+                        compiled.push(try!(queryplan::compile_row_expression(self, table, expr, max_depth)
+                            .map_err(|e| format!("{}", e))));
+                    }
+
+                    let filter = match stmt.where_expr {
+                        Some(expr) => Some(try!(queryplan::compile_row_expression(self, table, expr, max_depth)
+                            .map_err(|e| format!("{}", e)))),
+                        None => None
+                    };
+
+                    let execute = self.query_executor();
+
+                    for key in table.rowid_index.iter() {
+                        let row = table.decode_row(key);
+
+                        if let Some(ref filter) = filter {
+                            if !try!(execute.execute_expression_with_row(filter, &row)).tests_true() {
+                                continue;
+                            }
+                        }
+
+                        // Every assignment sees the row as it was
+                        let mut values = Vec::new();
+
+                        for expr in compiled.iter() {
+                            values.push(try!(execute.execute_expression_with_row(expr, &row)));
+                        }
+
+                        rows.push((key.clone(), try!(assign(&row, values))));
+                    }
+                }
+            }
+
+            rows
+        };
+
+        // Like a DELETE, every row is computed before any are changed
+        let updated = try!(try!(self.get_table_mut(&table_name)).update_rows(rows));
+        let count = updated.len() as u64;
+
+        self.record_updated(&table_name, updated);
+
+        Ok(ExecuteStatementResponse::Updated(count))
+    }
+
+    /// Joins `table` with the FROM of an UPDATE, and computes the values
+    /// assigned to each of its rows that's joined with any. They're keyed by
+    /// the row, since equal rows are joined with the same rows. A row that's
+    /// joined with several takes the values of the first.
+    fn update_join(&self, target: ast::Table, table: &Table, exprs: Vec<ast::Expression>, from: ast::From,
+        where_expr: Option<ast::Expression>) -> Result<HashMap<Vec<Variant>, Vec<Variant>>, String>
+    {
+        // The query selects the table's columns, then the values
+        let mut result_columns: Vec<_> = table.columns.iter().map(|column| ast::SelectColumn::Expr {
+            expr: ast::Expression::IdentMember(target.table_name.clone(), column.name.to_string()),
+            alias: None
+        }).collect();
+        let column_count = result_columns.len();
+
+        result_columns.extend(exprs.into_iter().map(|expr| ast::SelectColumn::Expr {
+            expr: expr,
+            alias: None
+        }));
+
+        let target = ast::TableOrSubquery::Table {
+            table: target,
+            alias: None
+        };
+
+        let from = match from {
+            ast::From::Cross(mut tables) => {
+                tables.insert(0, target);
+                ast::From::Cross(tables)
+            },
+            ast::From::Join(join) => ast::From::Join(ast::Join {
+                operator: ast::JoinOperator::Cross,
+                lhs: Box::new(ast::JoinTree::Table(target)),
+                rhs: Box::new(ast::JoinTree::Join(join)),
+                on: None
+            })
+        };
+
+        let query = ast::SelectStatement {
+            distinct: false,
+            result_columns: result_columns,
+            from: Some(from),
+            where_expr: where_expr,
+            group_by: Vec::new(),
+            having: None,
+            order_by: Vec::new(),
+            limit: None
+        };
+
+        let plan = try!(QueryPlan::compile_update_join(self, query, self.settings.max_query_depth)
+            .map_err(|e| format!("{}", e)));
+
+        let execute = self.query_executor();
+        let mut joined = HashMap::new();
+
+        try!(execute.execute_query_plan(&plan.expr, &mut |r| {
+            let (row, values) = r.split_at(column_count);
+
+            if !joined.contains_key(row) {
+                try!(execute.reserve_row(r));
+                joined.insert(row.to_vec(), values.to_vec());
+            }
+
+            Ok(())
+        }));
+
+        Ok(joined)
+    }
+
+    fn delete_from(&mut self, stmt: ast::DeleteStatement) -> ExecuteStatementResult<'static> {
+        let table_name = stmt.table.table_name;
+
+        let keys: Vec<Vec<u8>> = {
+            let table = try!(self.find_table(&table_name));
+
+            if table.view_query.is_some() {
+                return Err(format!("cannot delete from materialized view {}", table.name));
+            }
+
+            if table.virtual_table.is_some() {
+                return Err(format!("cannot delete from virtual table {}", table.name));
+            }
+
+            match stmt.where_expr {
+                Some(expr) => {
+                    let filter = try!(queryplan::compile_row_expression(self, table, expr, self.settings.max_query_depth)
+                        .map_err(|e| format!("{}", e)));
+                    let execute = self.query_executor();
+                    let mut keys = Vec::new();
+
+                    for key in table.rowid_index.iter() {
+                        let row = table.decode_row(key);
+
+                        if try!(execute.execute_expression_with_row(&filter, &row)).tests_true() {
+                            keys.push(key.clone());
+                        }
+                    }
+
+                    keys
+                },
+                None => table.rowid_index.iter().cloned().collect()
+            }
+        };
+
+        // Every row is tested before any are deleted, so the WHERE sees the
+        // table as it was
+        let deleted = try!(self.get_table_mut(&table_name)).delete_rows(keys);
+        let count = deleted.len() as u64;
+
+        self.record_deleted(&table_name, deleted);
+
+        Ok(ExecuteStatementResponse::Deleted(count))
+    }
+
+    fn select(&self, stmt: ast::SelectStatement) -> ExecuteStatementResult<'static> {
+        let plan = try!(QueryPlan::compile_select(self, stmt, self.settings.max_query_depth).map_err(|e| format!("{}", e)));
+        debug!("{}", plan);
+
+        self.execute_plan(plan)
+    }
+
+    fn execute_plan(&self, plan: QueryPlan<TempDb>) -> ExecuteStatementResult<'static> {
+        let mut rows = Vec::new();
+
+        let execute = self.query_executor();
+        try!(execute.execute_query_plan(&plan.expr, &mut |r| {
+            try!(execute.reserve_row(r));
+            rows.push(r.to_vec().into_boxed_slice());
+            Ok(())
+        }));
+
+        let column_names: Vec<String> = plan.out_column_names.iter().map(|ident| ident.to_string()).collect();
+
+        let columns: Vec<_> = plan.out_column_origins.iter().map(|origin| match origin {
+            &Some(ref origin) => {
+                let column = origin.table.find_column_by_offset(origin.column_offset).unwrap();
+
+                ColumnMetadata {
+                    dbtype: Some(column.dbtype),
+                    table: Some(origin.table.name.to_string()),
+                    column: Some(column.name.to_string()),
+                    nullable: column.nullable || origin.outer_joined,
+                    max_length: column.max_length
+                }
+            },
+            &None => ColumnMetadata::computed()
+        }).collect();
+
+        Ok(ExecuteStatementResponse::Select {
+            column_names: column_names.into_boxed_slice(),
+            columns: columns.into_boxed_slice(),
+            rows: Box::new(rows.into_iter())
+        })
+    }
+
+    fn explain(&self, stmt: ast::ExplainStatement) -> ExecuteStatementResult<'static> {
+        use queryplan::QueryPlan;
+
+        match stmt {
+            ast::ExplainStatement::Select(select) => {
+                let plan = try!(QueryPlan::compile_select(self, select, self.settings.max_query_depth).map_err(|e| format!("{}", e)));
+
+                Ok(ExecuteStatementResponse::Explain(plan.to_string()))
+            }
+        }
+    }
+
+    /// Imports JSON Lines, where each line is an object that holds one row.
+    /// Returns the amount of rows inserted.
+    ///
+    /// Keys are matched with the table's columns by name. If the table
+    /// doesn't exist, it's created with a column for each key. Each column's
+    /// type is inferred from its values, and if they disagree, it's a string.
+    /// Nested arrays and objects are stored as JSON text.
+    pub fn import_json_lines<R: BufRead>(&mut self, table_name: &str, reader: R) -> Result<u64, String> {
+        let mut records = Vec::new();
+
+        for (i, line) in reader.lines().enumerate() {
+            let line = try!(line.map_err(|e| format!("{}", e)));
+
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let fields = try!(jsonlines::parse_object(&line).map_err(|e| format!("line {}: {}", i + 1, e)));
+            records.push((i + 1, fields));
+        }
+
+        let name = try!(Identifier::new_with_case_folding(table_name, self.settings.case_folding)
+            .ok_or(format!("Bad table name: {}", table_name)));
+
+        // A table that's inferred from the records takes them as they are
+        let mut sql_mode = self.settings.sql_mode;
+
+        if self.find_table_by_name(&name).is_none() {
+            let table = try!(infer_json_table(name, &records, self.settings.case_folding));
+            let change = Change::create_table(&table);
+            try!(self.add_table(table, false));
+            self.change_log.record(change);
+            sql_mode = SqlMode::Permissive;
+        }
+
+        let count = {
+            let table = try!(self.get_table_mut(table_name));
+
+            if table.view_query.is_some() {
+                return Err(format!("cannot insert into materialized view {}", table.name));
+            }
+
+            if table.virtual_table.is_some() {
+                return Err(format!("cannot insert into virtual table {}", table.name));
+            }
+
+            let batch: Vec<Vec<_>> = try!(records.into_iter().map(|(line_number, fields)| {
+                let mut values: Vec<Option<Variant>> = table.columns.iter().map(|_| None).collect();
+
+                for (key, value) in fields {
+                    let column = try!(Identifier::new(&key).and_then(|ident| table.find_column_by_name(&ident))
+                        .ok_or(format!("line {}: table {} has no column {}", line_number, table.name, key)));
+
+                    values[column.offset as usize] = Some(json_to_variant(value));
+                }
+
+                table.columns.iter().zip(values.into_iter()).map(|(column, value)| -> Result<_, String> {
+                    match value {
+                        Some(value) => {
+                            let mut buf = Vec::new();
+                            let is_null = try!(variant_to_data(value, column.dbtype, column.nullable, column.max_length, sql_mode, &mut buf)
+                                .map_err(|e| format!("line {}: column {}: {}", line_number, column.name, e)));
+                            Ok((buf.into_boxed_slice(), is_null))
+                        },
+                        None if column.nullable => Ok((Vec::new().into_boxed_slice(), Some(true))),
+                        None => {
+                            // use default value for column type
+                            Ok((column.dbtype.get_default().into_owned().into_boxed_slice(), None))
+                        }
+                    }
+                }).collect()
+            }).collect());
+
+            try!(table.insert_many(batch.into_iter().map(|v| v.into_iter())).map_err(|e| format!("{}", e)))
+        };
+
+        self.record_inserted(table_name, count);
+
+        Ok(count)
+    }
+
+    fn pragma(&mut self, stmt: ast::PragmaStatement) -> ExecuteStatementResult<'static> {
+        let name = try!(Identifier::new(&stmt.name).ok_or(format!("Bad setting name: {}", stmt.name)));
+
+        // The user version belongs to the database rather than the
+        // connection, so it's saved and logged unlike the settings
+        if &name as &str == "user_version" {
+            return self.pragma_user_version(name, stmt.value);
+        }
+
+        match stmt.value {
+            Some(expr) => {
+                let value = match expr {
+                    // Words such as `upper` or `on` are values, not columns
+                    ast::Expression::Ident(word) => Variant::StringLiteral(word),
+                    expr => try!(self.evaluate_constant(expr))
+                };
+
+                try!(self.settings.set(&name, value));
+
+                Ok(ExecuteStatementResponse::Set)
+            },
+            None => {
+                let value = try!(self.settings.get(&name));
+
+                Ok(ExecuteStatementResponse::Select {
+                    column_names: vec![name.to_string()].into_boxed_slice(),
+                    columns: vec![ColumnMetadata::computed()].into_boxed_slice(),
+                    rows: Box::new(Some(vec![value].into_boxed_slice()).into_iter())
+                })
+            }
+        }
+    }
+
+    fn set_variable(&mut self, stmt: ast::SetStatement) -> ExecuteStatementResult<'static> {
+        let name = try!(Identifier::new(&stmt.name).ok_or(format!("Bad variable name: {}", stmt.name)));
+
+        let value = match stmt.value {
+            ast::Expression::Ident(word) => Variant::StringLiteral(word),
+            expr => try!(self.evaluate_constant(expr))
+        };
+
+        self.variables.insert(name, value);
+
+        Ok(ExecuteStatementResponse::Set)
+    }
+
+    fn show_variable(&mut self, stmt: ast::ShowStatement) -> ExecuteStatementResult<'static> {
+        let name = try!(Identifier::new(&stmt.name).ok_or(format!("Bad variable name: {}", stmt.name)));
+        let value = try!(self.session_variable(&name));
+
+        Ok(ExecuteStatementResponse::Select {
+            column_names: vec![name.to_string()].into_boxed_slice(),
+            columns: vec![ColumnMetadata::computed()].into_boxed_slice(),
+            rows: Box::new(Some(vec![value].into_boxed_slice()).into_iter())
+        })
+    }
+
+    fn pragma_user_version(&mut self, name: Identifier, value: Option<ast::Expression>) -> ExecuteStatementResult<'static> {
+        match value {
+            Some(expr) => {
+                let value = match expr {
+                    ast::Expression::Ident(word) => Variant::StringLiteral(word),
+                    expr => try!(self.evaluate_constant(expr))
+                };
+
+                let user_version = match value {
+                    Variant::UnsignedInteger(n) if n <= u32::MAX as u64 => n as u32,
+                    Variant::SignedInteger(n) if n >= 0 && n <= u32::MAX as i64 => n as u32,
+                    value => return Err(format!("{} is not a valid value for {}; expected an integer from 0 to {}",
+                        value, name, u32::MAX))
+                };
+
+                self.set_user_version(user_version);
+
+                Ok(ExecuteStatementResponse::Set)
+            },
+            None => Ok(ExecuteStatementResponse::Select {
+                column_names: vec![name.to_string()].into_boxed_slice(),
+                columns: vec![ColumnMetadata::computed()].into_boxed_slice(),
+                rows: Box::new(Some(vec![Variant::UnsignedInteger(self.user_version as u64)].into_boxed_slice()).into_iter())
+            })
+        }
+    }
+
+    /// Temporary tables are kept apart from permanent tables, and are
+    /// dropped along with the database.
+    fn add_table(&mut self, table: Table, temporary: bool) -> Result<(), String> {
+        let tables = if temporary { &mut self.temp_tables } else { &mut self.tables };
+
+        if tables.iter().any(|t| t.name == table.name) {
+            Err(format!("Table {} already exists", table.name))
+        } else {
+            debug!("adding table: {:?}", table);
+            tables.push(table);
+
+            Ok(())
+        }
+    }
+
+    fn get_table_mut(&mut self, table_name: &str) -> Result<&mut Table, String> {
+        let table_name = try!(Identifier::new(table_name).ok_or(format!("Bad table name: {}", table_name)));
+
+        match self.temp_tables.iter_mut().chain(self.tables.iter_mut()).find(|t| t.name == table_name) {
+            Some(s) => Ok(s),
+            None => Err(format!("Could not find table named {}", table_name))
+        }
+    }
+
+    /// Sends the rows that were just inserted into a table to subscribers,
+    /// and logs them. The rows of materialized views and temporary tables
+    /// aren't logged.
+    fn record_inserted(&mut self, table_name: &str, count: u64) {
+        let temporary = self.is_temporary(table_name);
+
+        let (name, rows, logged) = match self.find_table(table_name) {
+            Ok(table) => {
+                let logged = self.change_log.is_enabled() && !temporary && table.view_query.is_none() && count > 0;
+
+                if self.subscribers.is_empty() && !logged {
+                    return;
+                }
+
+                // Inserted rows have the largest rowids, so their keys are last
+                let rows: Vec<_> = table.rowid_index.iter().skip(table.rowid_index.len() - count as usize).map(|key| {
+                    table.decode_row(key)
+                }).collect();
+
+                (table.name.clone(), rows, logged)
+            },
+            Err(_) => return
+        };
+
+        if !self.subscribers.is_empty() {
+            self.subscribers.send(rows.iter().map(|row| RowChange::insert(&name, row.clone())).collect());
+        }
+
+        if logged {
+            self.change_log.record(Change::Insert {
+                table: name,
+                rows: rows
+            });
+        }
+    }
+
+    /// Sends the rows that were just updated to subscribers, with their old
+    /// and new values, and logs their rowids and new values.
+    fn record_updated(&mut self, table_name: &str, updated: Vec<(u64, Vec<Variant>, Vec<Variant>)>) {
+        let logged = self.change_log.is_enabled() && !self.is_temporary(table_name) && !updated.is_empty();
+
+        let name = match self.find_table(table_name) {
+            Ok(table) => table.name.clone(),
+            Err(_) => return
+        };
+
+        if !self.subscribers.is_empty() {
+            self.subscribers.send(updated.iter().map(|&(_, ref old, ref new)| {
+                RowChange::update(&name, old.clone(), new.clone())
+            }).collect());
+        }
+
+        if logged {
+            self.change_log.record(Change::Update {
+                table: name,
+                rows: updated.into_iter().map(|(rowid, _, new)| (rowid, new)).collect()
+            });
+        }
+    }
+
+    /// Sends the rows that were just deleted from a table to subscribers, and
+    /// logs their rowids. Like inserts, deletes from temporary tables aren't
+    /// logged.
+    fn record_deleted(&mut self, table_name: &str, deleted: Vec<(u64, Vec<Variant>)>) {
+        let logged = self.change_log.is_enabled() && !self.is_temporary(table_name) && !deleted.is_empty();
+
+        let name = match self.find_table(table_name) {
+            Ok(table) => table.name.clone(),
+            Err(_) => return
+        };
+
+        let (rowids, rows): (Vec<_>, Vec<_>) = deleted.into_iter().unzip();
+
+        if !self.subscribers.is_empty() {
+            self.subscribers.send(rows.into_iter().map(|row| RowChange::delete(&name, row)).collect());
+        }
+
+        if logged {
+            self.change_log.record(Change::Delete {
+                table: name,
+                rowids: rowids
+            });
+        }
+    }
+
+    fn parse_number_as_u64(&self, number: String) -> Result<u64, String> {
+        number.parse().map_err(|_| format!("{} is not a valid number", number))
+    }
+}
+
+/// Creates a table with a column for every key in the records, in the order
+/// they first appear. A column is nullable if any record lacks it or has null.
+fn infer_json_table(name: Identifier, records: &[(usize, Vec<(String, JsonValue)>)], case_folding: CaseFolding)
+-> Result<Table, String> {
+    let mut columns: Vec<table::Column> = Vec::new();
+
+    for &(line_number, ref fields) in records {
+        for &(ref key, ref value) in fields {
+            let dbtype = match value {
+                &JsonValue::Null => DbType::Null,
+                &JsonValue::Bool(_) => DbType::Integer { signed: false, bytes: 1 },
+                &JsonValue::Integer(_) => DbType::Integer { signed: true, bytes: 8 },
+                &JsonValue::Float(_) => DbType::F64,
+                &JsonValue::String(_) | &JsonValue::Nested(_) => DbType::String
+            };
+
+            let ident = try!(Identifier::new_with_case_folding(key, case_folding)
+                .ok_or(format!("line {}: {} is not a valid column name", line_number, key)));
+
+            match columns.iter_mut().find(|c| c.name == ident) {
+                Some(column) => {
+                    column.dbtype = match (column.dbtype, dbtype) {
+                        (a, DbType::Null) => a,
+                        (DbType::Null, b) => b,
+                        (a, b) if a == b => a,
+                        (DbType::Integer { signed: true, .. }, DbType::F64) |
+                        (DbType::F64, DbType::Integer { signed: true, .. }) => DbType::F64,
+                        _ => DbType::String
+                    };
+                },
+                None => columns.push(table::Column {
+                    offset: columns.len() as u32,
+                    name: ident,
+                    dbtype: dbtype,
+                    nullable: false,
+                    max_length: None,
+                    comment: None
+                })
+            }
+        }
+    }
+
+    for column in columns.iter_mut() {
+        column.nullable = column.dbtype == DbType::Null || records.iter().any(|&(_, ref fields)| {
+            !fields.iter().any(|&(ref key, ref value)| {
+                Identifier::new(key).map_or(false, |ident| ident == column.name) && value != &JsonValue::Null
+            })
+        });
+    }
+
+    Ok(Table {
+        name: name,
+        columns: columns,
+        next_rowid: 1,
+        rowid_index: BTreeSet::new(),
+        view_query: None,
+        virtual_table: None,
+        comment: None
+    })
+}
+
+fn json_to_variant(value: JsonValue) -> Variant {
+    match value {
+        JsonValue::Null => Variant::Null,
+        JsonValue::Bool(b) => Variant::UnsignedInteger(if b { 1 } else { 0 }),
+        JsonValue::Integer(n) => Variant::from_i64(n),
+        JsonValue::Float(n) => Variant::from_f64(n),
+        JsonValue::String(s) | JsonValue::Nested(s) => Variant::StringLiteral(s)
+    }
+}
+
+/// Adds the names of the tables a query reads to `names`, including the
+/// tables of its subqueries.
+fn query_table_names<'q>(query: &'q ast::SelectStatement, names: &mut Vec<&'q str>) {
+    let (tables, constraints) = match query.from {
+        Some(ast::From::Cross(ref tables)) => (tables.iter().collect(), Vec::new()),
+        Some(ast::From::Join(ref join)) => join.parts(),
+        None => (Vec::new(), Vec::new())
+    };
+
+    for table in tables {
+        match table {
+            &ast::TableOrSubquery::Subquery { ref subquery, .. } => query_table_names(subquery, names),
+            &ast::TableOrSubquery::Table { ref table, .. } => names.push(&table.table_name),
+            &ast::TableOrSubquery::Function { ref arguments, .. } => {
+                for argument in arguments {
+                    expression_table_names(argument, names);
+                }
+            }
+        }
+    }
+
+    let result_exprs = query.result_columns.iter().filter_map(|column| match column {
+        &ast::SelectColumn::Expr { ref expr, .. } => Some(expr),
+        &ast::SelectColumn::AllColumns => None
+    });
+
+    for expr in constraints.into_iter().chain(result_exprs).chain(query.where_expr.iter())
+        .chain(query.group_by.iter()).chain(query.having.iter()).chain(query.order_by.iter().map(|term| &term.expr))
+    {
+        expression_table_names(expr, names);
+    }
+}
+
+fn expression_table_names<'q>(expr: &'q ast::Expression, names: &mut Vec<&'q str>) {
+    match expr {
+        &ast::Expression::Subquery(ref subquery) |
+        &ast::Expression::Exists { ref subquery, .. } => query_table_names(subquery, names),
+        &ast::Expression::InSubquery { ref expr, ref subquery, .. } => {
+            expression_table_names(expr, names);
+            query_table_names(subquery, names);
+        },
+        &ast::Expression::UnaryOp { ref expr, .. } => expression_table_names(expr, names),
+        &ast::Expression::BinaryOp { ref lhs, ref rhs, .. } => {
+            expression_table_names(lhs, names);
+            expression_table_names(rhs, names);
+        },
+        &ast::Expression::FunctionCall { ref arguments, .. } |
+        &ast::Expression::Row(ref arguments) => {
+            for argument in arguments {
+                expression_table_names(argument, names);
+            }
+        },
+        &ast::Expression::WindowFunctionCall { ref arguments, ref window, .. } => {
+            for expr in arguments.iter().chain(window.partition_by.iter())
+                .chain(window.order_by.iter().map(|term| &term.expr))
+            {
+                expression_table_names(expr, names);
+            }
+        },
+        &ast::Expression::Ident(_) | &ast::Expression::IdentMember(..) | &ast::Expression::StringLiteral(_) |
+        &ast::Expression::Number(_) | &ast::Expression::Null | &ast::Expression::Placeholder(_) |
+        &ast::Expression::FunctionCallAggregateAll { .. } => ()
+    }
+}
+
+/// Only tables that store their own rows can have their columns added to or
+/// changed.
+fn check_columns_alterable(table: &Table, action: &str) -> Result<(), String> {
+    if table.view_query.is_some() {
+        Err(format!("Cannot {} materialized view {}; change the view's query instead", action, table.name))
+    } else if table.virtual_table.is_some() {
+        Err(format!("Cannot {} virtual table {}", action, table.name))
+    } else {
+        Ok(())
+    }
+}
+
+/// In strict mode, the value must fit the column's type exactly. Strings
+/// longer than `max_length` characters are truncated, or rejected in strict
+/// mode.
+fn variant_to_data(value: Variant, column_type: DbType, nullable: bool, max_length: Option<u64>, mode: SqlMode,
+    buf: &mut Vec<u8>) -> Result<Option<bool>, String> {
+    match (value.is_null(), nullable) {
+        (true, true) => Ok(Some(true)),
+        (true, false) => {
+            Err(format!("cannot insert NULL into column that doesn't allow NULL"))
+        },
+        (false, nullable) => {
+            if mode == SqlMode::Strict && !fits_exactly(&value, column_type) {
+                return Err(format!("value {} doesn't fit the column's type, {:?}, without converting it",
+                    value, column_type));
+            }
+
+            // Only string columns have a maximum length, and any value can be
+            // converted to a string
+            let value = match (max_length, value.cast(column_type)) {
+                (Some(n), Some(Variant::StringLiteral(s))) => match s.char_indices().nth(n as usize) {
+                    Some(_) if mode == SqlMode::Strict => {
+                        return Err(format!("value '{}' is longer than the column's maximum length, {}", s, n));
+                    },
+                    Some((end, _)) => Variant::StringLiteral(s[..end].to_string()),
+                    None => Variant::StringLiteral(s)
+                },
+                (_, Some(value)) => value,
+                (_, None) => return Err(format!("value cannot be converted to the column's type, {:?}", column_type))
+            };
+
+            let bytes = try!(value.to_bytes(column_type).map_err(|_| {
+                format!("value cannot be converted to the column's type, {:?}", column_type)
+            }));
+            buf.push_all(&bytes);
+
+            Ok(if nullable { Some(false) } else { None })
+        }
+    }
+}
+
+/// Converts a value as `variant_to_data` does, for a row that's encoded from
+/// its values rather than from their data.
+fn convert_for_column(value: Variant, column: &table::Column, mode: SqlMode) -> Result<Variant, String> {
+    let mut buf = Vec::new();
+
+    match try!(variant_to_data(value, column.dbtype, column.nullable, column.max_length, mode, &mut buf)) {
+        Some(true) => Ok(Variant::Null),
+        _ => Ok(ColumnValueOps::from_bytes(column.dbtype, Cow::Owned(buf)).unwrap())
+    }
+}
+
+/// Whether a value can be stored in a column of a type without being
+/// truncated or changing kind. Integers can be stored as floats.
+fn fits_exactly(value: &Variant, column_type: DbType) -> bool {
+    /// The largest unsigned and signed integers with that many bytes
+    fn max(bytes: u8) -> (u64, u64) {
+        let unsigned = if bytes >= 8 { u64::MAX } else { (1 << (bytes as u64 * 8)) - 1 };
+        (unsigned, unsigned >> 1)
+    }
+
+    match (value, column_type) {
+        (&Variant::Null, _) => true,
+        (&Variant::Bytes(..), DbType::ByteDynamic) => true,
+        (&Variant::Bytes(ref bytes), DbType::ByteFixed(n)) => bytes.len() as u64 == n,
+        (&Variant::StringLiteral(..), DbType::String) => true,
+        (&Variant::UnsignedInteger(n), DbType::Integer { signed, bytes }) => {
+            let (unsigned_max, signed_max) = max(bytes);
+            n <= if signed { signed_max } else { unsigned_max }
+        },
+        (&Variant::SignedInteger(n), DbType::Integer { signed, bytes }) => {
+            let (unsigned_max, signed_max) = max(bytes);
+            if n >= 0 {
+                n as u64 <= if signed { signed_max } else { unsigned_max }
+            } else {
+                // The smallest signed integer is one less than -max
+                signed && n >= -(signed_max as i64) - 1
+            }
+        },
+        (&Variant::UnsignedInteger(..), DbType::F64) |
+        (&Variant::SignedInteger(..), DbType::F64) |
+        (&Variant::Float(..), DbType::F64) => true,
+        _ => false
+    }
+}
+
+/// Syncs the directory that `path` is in, so that a file renamed into it
+/// isn't lost in a crash. Only Unix can sync directories.
+#[cfg(unix)]
+fn sync_directory(path: &Path) -> io::Result<()> {
+    let directory = match path.parent() {
+        Some(parent) if parent != Path::new("") => parent,
+        _ => Path::new(".")
+    };
+
+    File::open(directory).and_then(|directory| directory.sync_all())
+}
+
+#[cfg(not(unix))]
+fn sync_directory(_path: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ChangeOp, ColumnMetadata, ExecuteStatementResponse, TempDb};
+    use columnvalueops::ColumnValueOps;
+    use std::env;
+    use std::fs::{self, File};
+    use std::io::Write;
+    use std::path::PathBuf;
+    use std::sync::{Arc, Mutex};
+    use sqlsyntax::{ast, parse_statement, parse_statements, parse_statements_with_mode};
+    use types::{DbType, Variant};
+
+    fn columns(db: &mut TempDb, sql: &str) -> Vec<ColumnMetadata> {
+        let mut columns = Vec::new();
+
+        for stmt in parse_statements(sql).unwrap() {
+            if let ExecuteStatementResponse::Select { columns: c, .. } = db.execute_statement(stmt).unwrap() {
+                columns = c.into_vec();
+            }
+        }
+
+        columns
+    }
+
+    fn origin(table: &str, column: &str, dbtype: DbType, nullable: bool) -> ColumnMetadata {
+        ColumnMetadata {
+            dbtype: Some(dbtype),
+            table: Some(table.to_string()),
+            column: Some(column.to_string()),
+            nullable: nullable,
+            max_length: None
+        }
+    }
+
+    #[test]
+    fn test_column_metadata() {
+        let mut db = TempDb::new();
+        let a = origin("t", "a", DbType::Integer { signed: false, bytes: 4 }, false);
+        let b = origin("t", "b", DbType::String, true);
+
+        assert_eq!(columns(&mut db, "CREATE TABLE t (a U32, b STRING NULL); SELECT * FROM t;"), vec![a.clone(), b.clone()]);
+        assert_eq!(columns(&mut db, "SELECT b AS x, a + 1, count(*) FROM t GROUP BY a;"),
+            vec![b.clone(), ColumnMetadata::computed(), ColumnMetadata::computed()]);
+        assert_eq!(columns(&mut db, "SELECT a, c FROM (SELECT a, b || 'x' AS c FROM t) s GROUP BY a;"),
+            vec![a.clone(), ColumnMetadata::computed()]);
+        assert_eq!(columns(&mut db, "PRAGMA sql_mode;"), vec![ColumnMetadata::computed()]);
+
+        // The columns of the right side of a LEFT JOIN can be NULL
+        let u_a = origin("u", "a", DbType::Integer { signed: false, bytes: 4 }, true);
+        assert_eq!(columns(&mut db, "CREATE TABLE u (a U32); SELECT t.a, u.a FROM t LEFT JOIN u ON t.a = u.a;"),
+            vec![a.clone(), u_a.clone()]);
+        assert_eq!(columns(&mut db, "SELECT t.a, u.a, v.a FROM t LEFT JOIN (u JOIN t v ON u.a = v.a) ON t.a = u.a;"),
+            vec![a.clone(), u_a, origin("t", "a", DbType::Integer { signed: false, bytes: 4 }, true)]);
+    }
+
+    /// The rows of the last query
+    fn rows(db: &mut TempDb, sql: &str) -> Result<Vec<Vec<Variant>>, String> {
+        let mut rows = Vec::new();
+
+        for stmt in parse_statements_with_mode(sql, db.sql_mode()).unwrap() {
+            if let ExecuteStatementResponse::Select { rows: r, .. } = try!(db.execute_statement(stmt)) {
+                rows = r.map(|row| row.into_vec()).collect();
+            }
+        }
+
+        Ok(rows)
+    }
+
+    /// Executes a statement with its table qualified by a database name,
+    /// which the parser doesn't accept yet
+    fn execute_in_database(db: &mut TempDb, sql: &str) -> Result<(), String> {
+        let mut stmt = parse_statement(sql).unwrap();
+
+        {
+            let table = match stmt {
+                ast::Statement::Create(ast::CreateStatement::MaterializedView(ref mut s)) => &mut s.view,
+                ast::Statement::Create(ast::CreateStatement::Sequence(ref mut s)) => &mut s.sequence,
+                ast::Statement::Create(ast::CreateStatement::VirtualTable(ref mut s)) => &mut s.table,
+                ast::Statement::AlterTable(ref mut s) => &mut s.table,
+                ast::Statement::Comment(ref mut s) => match s.target {
+                    ast::CommentTarget::Table(ref mut table) | ast::CommentTarget::Column { ref mut table, .. } => table
+                },
+                _ => panic!("no table to qualify in {}", sql)
+            };
+            table.database_name = Some("main".to_string());
+        }
+
+        db.execute_statement(stmt).map(|_| ())
+    }
+
+    #[test]
+    fn test_database_names() {
+        let mut db = TempDb::new();
+        rows(&mut db, "CREATE TABLE t (a U32);").unwrap();
+
+        assert!(execute_in_database(&mut db, "CREATE MATERIALIZED VIEW v AS SELECT a FROM t;").is_err());
+        assert!(rows(&mut db, "SELECT * FROM v;").is_err());
+
+        assert!(execute_in_database(&mut db, "CREATE SEQUENCE s;").is_err());
+        assert!(rows(&mut db, "SELECT nextval('s');").is_err());
+
+        assert!(execute_in_database(&mut db, "CREATE VIRTUAL TABLE p USING csv('people.csv');").is_err());
+        assert!(rows(&mut db, "SELECT * FROM p;").is_err());
+
+        assert!(execute_in_database(&mut db, "ALTER TABLE t RENAME TO u;").is_err());
+        assert!(rows(&mut db, "SELECT * FROM t;").is_ok());
+
+        assert!(execute_in_database(&mut db, "COMMENT ON TABLE t IS 'Numbers';").is_err());
+        assert!(execute_in_database(&mut db, "COMMENT ON COLUMN t.a IS 'Numbers';").is_err());
+        assert_eq!(db.get_table_comment("t"), Ok(None));
+    }
+
+    #[test]
+    fn test_alter_table_columns() {
+        let s = |s: &str| Variant::StringLiteral(s.to_string());
+        let mut db = TempDb::new();
+
+        rows(&mut db, "CREATE TABLE t (a U32, b STRING NULL); INSERT INTO t VALUES (1, 'x'), (2, NULL);
+            ALTER TABLE t ADD COLUMN c I32; ALTER TABLE t ADD d STRING NULL;
+            INSERT INTO t VALUES (3, 'y', -3, 'z');").unwrap();
+
+        // The rows stored before the columns were added take their defaults
+        assert_eq!(rows(&mut db, "SELECT * FROM t;").unwrap(), vec![
+            vec![Variant::from_u64(1), s("x"), Variant::from_i64(0), Variant::Null],
+            vec![Variant::from_u64(2), Variant::Null, Variant::from_i64(0), Variant::Null],
+            vec![Variant::from_u64(3), s("y"), Variant::from_i64(-3), s("z")]
+        ]);
+
+        assert_eq!(rows(&mut db, "ALTER TABLE t ALTER COLUMN a TYPE STRING; ALTER TABLE t ALTER c TYPE F64;
+            INSERT INTO t VALUES ('4', NULL, 4.5, NULL); SELECT a, c FROM t;").unwrap(), vec![
+            vec![s("1"), Variant::from_f64(0.0)],
+            vec![s("2"), Variant::from_f64(0.0)],
+            vec![s("3"), Variant::from_f64(-3.0)],
+            vec![s("4"), Variant::from_f64(4.5)]
+        ]);
+
+        // Values are converted from their last type, not from the type
+        // they were first stored with
+        assert_eq!(rows(&mut db, "ALTER TABLE t ALTER c TYPE I8; SELECT c FROM t;").unwrap(), vec![
+            vec![Variant::from_i64(0)], vec![Variant::from_i64(0)], vec![Variant::from_i64(-3)], vec![Variant::from_i64(4)]
+        ]);
+
+        let mut opened = TempDb::new();
+        opened.load_snapshot(&db.snapshot()).unwrap();
+        assert_eq!(rows(&mut opened, "SELECT * FROM t;"), rows(&mut db, "SELECT * FROM t;"));
+
+        assert!(rows(&mut db, "ALTER TABLE t ALTER b TYPE U32;").is_err());
+        assert!(rows(&mut db, "ALTER TABLE t ALTER e TYPE U32;").is_err());
+        assert!(rows(&mut db, "ALTER TABLE t ADD b U32;").is_err());
+        assert!(rows(&mut db, "PRAGMA sql_mode = strict; ALTER TABLE t ALTER c TYPE U8;").is_err());
+        assert!(rows(&mut db, "CREATE MATERIALIZED VIEW v AS SELECT a FROM t; ALTER TABLE v ADD e U32;").is_err());
+    }
+
+    #[test]
+    fn test_group_by_keys() {
+        let s = |s: &str| Variant::StringLiteral(s.to_string());
+        let mut db = TempDb::new();
+
+        rows(&mut db, "CREATE TABLE t (a STRING NULL);
+            INSERT INTO t VALUES ('x'), (NULL), ('y'), (NULL), ('x');").unwrap();
+
+        // NULLs are one group, and the groups are sorted with NULL first
+        assert_eq!(rows(&mut db, "SELECT a, count(*) FROM t GROUP BY a;").unwrap(), vec![
+            vec![Variant::Null, Variant::from_u64(2)],
+            vec![s("x"), Variant::from_u64(2)],
+            vec![s("y"), Variant::from_u64(1)]
+        ]);
+
+        // Equal numbers of different types are one group
+        assert_eq!(rows(&mut db, "SELECT value, count(*) FROM json_each('[2, 1.0, null, 1, -0.5, null]') GROUP BY value;")
+            .unwrap(), vec![
+            vec![Variant::Null, Variant::from_u64(2)],
+            vec![Variant::from_f64(-0.5), Variant::from_u64(1)],
+            vec![Variant::from_f64(1.0), Variant::from_u64(2)],
+            vec![Variant::from_i64(2), Variant::from_u64(1)]
+        ]);
+    }
+
+    #[test]
+    fn test_null_functions() {
+        let s = |s: &str| Variant::StringLiteral(s.to_string());
+        let mut db = TempDb::new();
+
+        rows(&mut db, "CREATE TABLE t (a STRING NULL, b STRING NULL);
+            INSERT INTO t VALUES ('x', 'y'), (NULL, 'y'), (NULL, NULL), ('y', 'y');").unwrap();
+
+        assert_eq!(rows(&mut db, "SELECT coalesce(a, b, 'z'), ifnull(a, b), nullif(a, b) FROM t;").unwrap(), vec![
+            vec![s("x"), s("x"), s("x")],
+            vec![s("y"), s("y"), Variant::Null],
+            vec![s("z"), Variant::Null, Variant::Null],
+            vec![s("y"), s("y"), Variant::Null]
+        ]);
+
+        // The arguments after the first non-NULL one aren't evaluated
+        assert_eq!(rows(&mut db, "SELECT coalesce(1, 1e999 - 1e999), ifnull(2, 1e999 - 1e999);").unwrap(),
+            vec![vec![Variant::from_i64(1), Variant::from_i64(2)]]);
+        assert!(rows(&mut db, "SELECT coalesce(NULL, 1e999 - 1e999);").is_err());
+        assert_eq!(rows(&mut db, "CREATE SEQUENCE s; SELECT coalesce(a, nextval('s')) FROM t; SELECT currval('s');")
+            .unwrap(), vec![vec![Variant::from_i64(2)]]);
+
+        assert!(rows(&mut db, "SELECT coalesce();").is_err());
+        assert!(rows(&mut db, "SELECT ifnull(1);").is_err());
+        assert!(rows(&mut db, "SELECT nullif(1, 2, 3);").is_err());
+    }
+
+    #[test]
+    fn test_min_max() {
+        let s = |s: &str| Variant::StringLiteral(s.to_string());
+        let mut db = TempDb::new();
+
+        rows(&mut db, "CREATE TABLE t (a U32, b STRING NULL);
+            INSERT INTO t VALUES (1, 'pear'), (1, NULL), (2, NULL), (1, 'apple'), (3, 'Zebra');").unwrap();
+
+        assert_eq!(rows(&mut db, "SELECT a, min(b), max(b) FROM t GROUP BY a;").unwrap(), vec![
+            vec![Variant::from_u64(1), s("apple"), s("pear")],
+            vec![Variant::from_u64(2), Variant::Null, Variant::Null],
+            vec![Variant::from_u64(3), s("Zebra"), s("Zebra")]
+        ]);
+
+        // Numbers are compared by value, and come before strings whichever
+        // is fed first
+        assert_eq!(rows(&mut db, "SELECT min(value), max(value) FROM json_each('[3, \"b\", null, 2.5, \"a\", -1]');")
+            .unwrap(), vec![vec![Variant::from_i64(-1), s("b")]]);
+        assert_eq!(rows(&mut db, "SELECT min(value), max(value) FROM json_each('[\"a\", 1]');").unwrap(),
+            rows(&mut db, "SELECT min(value), max(value) FROM json_each('[1, \"a\"]');").unwrap());
+        assert_eq!(rows(&mut db, "SELECT min(value), max(value) FROM json_each('[2, 1.5, 10]');").unwrap(),
+            vec![vec![Variant::from_f64(1.5), Variant::from_i64(10)]]);
+    }
+
+    #[test]
+    fn test_count_without_scan() {
+        let u = Variant::from_u64;
+        let mut db = TempDb::new();
+
+        let explain = |db: &mut TempDb, sql: &str| {
+            let stmt = parse_statements(&format!("EXPLAIN {};", sql)).unwrap().remove(0);
+            match db.execute_statement(stmt).unwrap() {
+                ExecuteStatementResponse::Explain(plan) => plan,
+                _ => panic!()
+            }
+        };
+
+        rows(&mut db, "CREATE TABLE t (a U32);").unwrap();
+        assert_eq!(rows(&mut db, "SELECT count(*) FROM t;").unwrap(), vec![vec![u(0)]]);
+
+        rows(&mut db, "INSERT INTO t VALUES (1), (2), (2);").unwrap();
+
+        for query in ["SELECT count(*) FROM t", "SELECT count(*), count(*) FROM t"].iter() {
+            let plan = explain(&mut db, query);
+            assert!(plan.contains("row-count") && !plan.contains("scan"), "{}", query);
+        }
+
+        assert_eq!(rows(&mut db, "SELECT count(*) FROM t;").unwrap(), vec![vec![u(3)]]);
+        assert_eq!(rows(&mut db, "SELECT count(*), count(*) FROM t;").unwrap(), vec![vec![u(3), u(3)]]);
+
+        // Queries that filter or group the rows still scan them
+        let scanned = [
+            ("SELECT count(*) FROM t WHERE a = 2", vec![vec![u(2)]]),
+            ("SELECT count(*) FROM t GROUP BY a", vec![vec![u(1)], vec![u(2)]]),
+            ("SELECT count(*) FROM t HAVING count(*) > 5", vec![]),
+            ("SELECT count(*), max(a) FROM t", vec![vec![u(3), u(2)]])
+        ];
+
+        for &(query, ref expected) in scanned.iter() {
+            assert!(!explain(&mut db, query).contains("row-count"), "{}", query);
+            assert_eq!(&rows(&mut db, &format!("{};", query)).unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn test_long_conditions() {
+        let u = Variant::from_u64;
+        let mut db = TempDb::new();
+
+        rows(&mut db, "CREATE TABLE t (a U32); INSERT INTO t VALUES (1), (50), (150);").unwrap();
+
+        // Generated conditions are often long chains of ORs
+        let sql = (1..99).fold("SELECT count(*) FROM t WHERE a = 0".to_string(), |sql, i| format!("{} OR a = {}", sql, i));
+        assert_eq!(rows(&mut db, &format!("{};", sql)).unwrap(), vec![vec![u(2)]]);
+    }
+
+    #[test]
+    fn test_order_by() {
+        let s = |s: &str| Variant::StringLiteral(s.to_string());
+        let u = Variant::from_u64;
+        let mut db = TempDb::new();
+
+        rows(&mut db, "CREATE TABLE t (a U32, b STRING NULL);
+            INSERT INTO t VALUES (2, 'x'), (1, NULL), (3, 'y'), (1, 'z'), (2, 'w');").unwrap();
+
+        assert_eq!(rows(&mut db, "SELECT a, b FROM t ORDER BY a, b DESC;").unwrap(), vec![
+            vec![u(1), s("z")], vec![u(1), Variant::Null], vec![u(2), s("x")], vec![u(2), s("w")], vec![u(3), s("y")]
+        ]);
+
+        // NULLs come first, and equal rows keep the order they were in
+        assert_eq!(rows(&mut db, "SELECT b FROM t ORDER BY b;").unwrap(), vec![
+            vec![Variant::Null], vec![s("w")], vec![s("x")], vec![s("y")], vec![s("z")]
+        ]);
+        assert_eq!(rows(&mut db, "SELECT b FROM t ORDER BY a DESC;").unwrap(), vec![
+            vec![s("y")], vec![s("x")], vec![s("w")], vec![Variant::Null], vec![s("z")]
+        ]);
+
+        // Positions, aliases, and expressions that aren't selected
+        assert_eq!(rows(&mut db, "SELECT b, a * 10 AS c FROM t ORDER BY 2 DESC, 1;").unwrap(),
+            rows(&mut db, "SELECT b, a * 10 AS c FROM t ORDER BY c DESC, b;").unwrap());
+        assert_eq!(rows(&mut db, "SELECT b FROM t ORDER BY -a, b;").unwrap(), vec![
+            vec![s("y")], vec![s("w")], vec![s("x")], vec![Variant::Null], vec![s("z")]
+        ]);
+        assert_eq!(rows(&mut db, "SELECT a AS b FROM t ORDER BY b;").unwrap().len(), 5);
+
+        assert_eq!(rows(&mut db, "SELECT a, count(*) FROM t GROUP BY a ORDER BY count(*) DESC, a DESC;").unwrap(), vec![
+            vec![u(2), u(2)], vec![u(1), u(2)], vec![u(3), u(1)]
+        ]);
+        assert_eq!(rows(&mut db, "SELECT b FROM t ORDER BY row_number() OVER (ORDER BY b) DESC;").unwrap(), vec![
+            vec![s("z")], vec![s("y")], vec![s("x")], vec![s("w")], vec![Variant::Null]
+        ]);
+        assert_eq!(rows(&mut db, "SELECT b FROM t WHERE a IN (SELECT c.a FROM t c ORDER BY c.b DESC) ORDER BY b;").unwrap(),
+            rows(&mut db, "SELECT b FROM t ORDER BY b;").unwrap());
+
+        assert!(rows(&mut db, "SELECT a FROM t ORDER BY 2;").is_err());
+        assert!(rows(&mut db, "SELECT * FROM t ORDER BY 0;").is_err());
+    }
+
+    #[test]
+    fn test_limit() {
+        let u = Variant::from_u64;
+        let mut db = TempDb::new();
+
+        rows(&mut db, "CREATE TABLE t (a U32);
+            INSERT INTO t VALUES (3), (1), (4), (1), (5);").unwrap();
+
+        assert_eq!(rows(&mut db, "SELECT a FROM t LIMIT 2;").unwrap(), vec![vec![u(3)], vec![u(1)]]);
+        assert_eq!(rows(&mut db, "SELECT a FROM t ORDER BY a DESC LIMIT 2 OFFSET 1;").unwrap(),
+            vec![vec![u(4)], vec![u(3)]]);
+        assert_eq!(rows(&mut db, "SELECT a FROM t LIMIT 10 OFFSET 3;").unwrap(), vec![vec![u(1)], vec![u(5)]]);
+        assert!(rows(&mut db, "SELECT a FROM t LIMIT 0;").unwrap().is_empty());
+        assert!(rows(&mut db, "SELECT a FROM t LIMIT 1 OFFSET 5;").unwrap().is_empty());
+
+        // The scan stops once the limit is reached, so the sequence is only
+        // advanced for the rows that are yielded
+        rows(&mut db, "CREATE SEQUENCE s;").unwrap();
+        assert_eq!(rows(&mut db, "SELECT nextval('s') FROM t LIMIT 2;").unwrap(),
+            vec![vec![Variant::from_i64(1)], vec![Variant::from_i64(2)]]);
+        assert_eq!(rows(&mut db, "SELECT nextval('s');").unwrap(), vec![vec![Variant::from_i64(3)]]);
+
+        // A limit in a subquery doesn't stop the outer query
+        assert_eq!(rows(&mut db, "SELECT a FROM t WHERE EXISTS (SELECT 1 FROM t u LIMIT 1) LIMIT 3;").unwrap().len(), 3);
+        assert_eq!(rows(&mut db, "SELECT (SELECT u.a FROM t u ORDER BY u.a LIMIT 1) FROM t LIMIT 1;").unwrap(),
+            vec![vec![u(1)]]);
+        assert_eq!(rows(&mut db, "SELECT count(*) FROM (SELECT a FROM t LIMIT 3) x;").unwrap(), vec![vec![u(3)]]);
+    }
+
+    #[test]
+    fn test_slow_query_log() {
+        let reported = Arc::new(Mutex::new(Vec::new()));
+        let sink_reported = reported.clone();
+
+        let mut db = TempDb::new();
+        db.set_slow_query_log(0, Box::new(move |query| {
+            sink_reported.lock().unwrap().push((query.sql.clone(), query.rows, query.plan.clone()));
+        }));
+
+        let sql = "CREATE TABLE t (a U32); INSERT INTO t VALUES (1), (2); SELECT a FROM t WHERE a > 0;";
+        let stmts = parse_statements(sql).unwrap();
+
+        // Reporting a query doesn't use up its rows
+        assert_eq!(rows(&mut db, sql).unwrap().len(), 2);
+        assert!(rows(&mut db, "SELECT b FROM t;").is_err());
+
+        let plan = match db.execute_statement(parse_statements("EXPLAIN SELECT a FROM t WHERE a > 0;").unwrap().remove(0)) {
+            Ok(ExecuteStatementResponse::Explain(plan)) => plan,
+            _ => panic!()
+        };
+
+        // Failed statements aren't reported
+        assert_eq!(&reported.lock().unwrap()[..3], &[
+            (stmts[0].to_string(), None, None),
+            (stmts[1].to_string(), Some(2), None),
+            (stmts[2].to_string(), Some(2), Some(plan))
+        ][..]);
+        assert_eq!(reported.lock().unwrap().len(), 4);
+
+        // A threshold is checked before the statement that changes it, and
+        // one too large to time is rejected
+        assert!(rows(&mut db, "PRAGMA slow_query_threshold = 9223372036854775808;").is_err());
+        rows(&mut db, "PRAGMA slow_query_threshold = 9223372036854775807; SELECT a FROM t;").unwrap();
+        rows(&mut db, "PRAGMA slow_query_threshold = off; SELECT a FROM t;").unwrap();
+        assert_eq!(reported.lock().unwrap().len(), 5);
+    }
+
+    #[test]
+    fn test_session_variables() {
+        let s = |s: &str| Variant::StringLiteral(s.to_string());
+        let mut db = TempDb::new();
+
+        rows(&mut db, "CREATE TABLE t (tenant STRING, a U32);
+            INSERT INTO t VALUES ('acme', 1), ('initech', 2), ('acme', 3);").unwrap();
+
+        assert!(rows(&mut db, "SELECT a FROM t WHERE tenant = current_setting('tenant');").is_err());
+
+        rows(&mut db, "SET tenant = 'acme';").unwrap();
+        assert_eq!(rows(&mut db, "SELECT a FROM t WHERE tenant = current_setting('tenant');").unwrap(),
+            vec![vec![Variant::from_u64(1)], vec![Variant::from_u64(3)]]);
+        assert_eq!(rows(&mut db, "SHOW tenant;").unwrap(), vec![vec![s("acme")]]);
+
+        rows(&mut db, "SET TENANT TO initech;").unwrap();
+        assert_eq!(rows(&mut db, "SELECT a FROM t WHERE tenant = current_setting('Tenant');").unwrap(),
+            vec![vec![Variant::from_u64(2)]]);
+
+        rows(&mut db, "SET n = 1 + 2; SET show = 'x';").unwrap();
+        assert_eq!(rows(&mut db, "SELECT current_setting('n') * 2, current_setting('show');").unwrap(),
+            vec![vec![Variant::from_i64(6), s("x")]]);
+
+        assert!(rows(&mut db, "SHOW missing;").is_err());
+        assert!(rows(&mut db, "SELECT current_setting(tenant) FROM t;").is_err());
+        assert!(rows(&mut db, "SET n = (SELECT a FROM t);").is_err());
+    }
+
+    #[test]
+    fn test_join() {
+        let u = Variant::from_u64;
+        let s = |s: &str| Variant::StringLiteral(s.to_string());
+        let mut db = TempDb::new();
+
+        rows(&mut db, "CREATE TABLE a (id U32, x STRING);
+            CREATE TABLE b (a_id U32, y STRING);
+            INSERT INTO a VALUES (1, 'a1'), (2, 'a2'), (3, 'a3');
+            INSERT INTO b VALUES (1, 'b1'), (3, 'b3'), (3, 'b3x');").unwrap();
+
+        let inner = vec![vec![s("a1"), s("b1")], vec![s("a3"), s("b3")], vec![s("a3"), s("b3x")]];
+        assert_eq!(rows(&mut db, "SELECT a.x, b.y FROM a INNER JOIN b ON a.id = b.a_id;").unwrap(), inner);
+        assert_eq!(rows(&mut db, "SELECT a.x, b.y FROM a JOIN b ON a.id = b.a_id;").unwrap(), inner);
+
+        assert_eq!(rows(&mut db, "SELECT a.x, b.y FROM a LEFT JOIN b ON a.id = b.a_id;").unwrap(), vec![
+            vec![s("a1"), s("b1")],
+            vec![s("a2"), Variant::Null],
+            vec![s("a3"), s("b3")],
+            vec![s("a3"), s("b3x")]
+        ]);
+
+        // ON filters the right side before it's padded, but WHERE filters
+        // the joined rows
+        assert_eq!(rows(&mut db, "SELECT a.x, b.y FROM a LEFT OUTER JOIN b ON a.id = b.a_id AND b.y = 'b3x';").unwrap(), vec![
+            vec![s("a1"), Variant::Null],
+            vec![s("a2"), Variant::Null],
+            vec![s("a3"), s("b3x")]
+        ]);
+        assert_eq!(rows(&mut db, "SELECT a.x FROM a LEFT JOIN b ON a.id = b.a_id WHERE b.y = 'b1';").unwrap(),
+            vec![vec![s("a1")]]);
+
+        assert_eq!(rows(&mut db, "SELECT count(*) FROM a CROSS JOIN b;").unwrap(), vec![vec![u(9)]]);
+        assert_eq!(rows(&mut db, "SELECT a.id, c.id FROM a CROSS JOIN a c WHERE c.id = 2;").unwrap(),
+            vec![vec![u(1), u(2)], vec![u(2), u(2)], vec![u(3), u(2)]]);
+
+        // Joins can be chained
+        assert_eq!(rows(&mut db, "SELECT a.x, c.x FROM a JOIN b ON a.id = b.a_id LEFT JOIN a c ON b.a_id = c.id + 2;").unwrap(), vec![
+            vec![s("a1"), Variant::Null],
+            vec![s("a3"), s("a1")],
+            vec![s("a3"), s("a1")]
+        ]);
+
+        // Parentheses group joins. A LEFT JOIN pads all of the tables in
+        // them with NULLs where the ON matches nothing.
+        assert_eq!(rows(&mut db, "SELECT a.x, c.x FROM (a JOIN b ON a.id = b.a_id) LEFT JOIN a c ON b.a_id = c.id + 2;").unwrap(), vec![
+            vec![s("a1"), Variant::Null],
+            vec![s("a3"), s("a1")],
+            vec![s("a3"), s("a1")]
+        ]);
+        assert_eq!(rows(&mut db, "SELECT c.x, a.x, b.y FROM a c LEFT JOIN (a JOIN b ON a.id = b.a_id) ON c.id = a.id;").unwrap(), vec![
+            vec![s("a1"), s("a1"), s("b1")],
+            vec![s("a2"), Variant::Null, Variant::Null],
+            vec![s("a3"), s("a3"), s("b3")],
+            vec![s("a3"), s("a3"), s("b3x")]
+        ]);
+        assert_eq!(rows(&mut db, "SELECT c.x, a.x, b.y FROM a c LEFT JOIN (a LEFT JOIN b ON a.id = b.a_id) ON c.id = a.id + 1;").unwrap(), vec![
+            vec![s("a1"), Variant::Null, Variant::Null],
+            vec![s("a2"), s("a1"), s("b1")],
+            vec![s("a3"), s("a2"), Variant::Null]
+        ]);
+        assert_eq!(rows(&mut db, "SELECT count(*) FROM ((a) CROSS JOIN (b CROSS JOIN a c));").unwrap(), vec![vec![u(27)]]);
+
+        // ON can only use the tables before it
+        assert!(rows(&mut db, "SELECT a.x FROM a JOIN b ON b.a_id = c.id JOIN a c ON a.id = c.id;").is_err());
+    }
+
+    #[test]
+    fn test_hash_join() {
+        let u = Variant::from_u64;
+        let s = |s: &str| Variant::StringLiteral(s.to_string());
+        let mut db = TempDb::new();
+
+        rows(&mut db, "CREATE TABLE a (id U32, x STRING NULL);
+            CREATE TABLE b (a_id I64 NULL, y STRING);
+            INSERT INTO a VALUES (1, 'p'), (2, NULL), (3, 'q');
+            INSERT INTO b VALUES (1, 'p'), (3, 'q'), (3, 'r'), (NULL, 'n');").unwrap();
+
+        let explain = |db: &mut TempDb, sql: &str| {
+            let stmt = parse_statements(&format!("EXPLAIN {};", sql)).unwrap().remove(0);
+            match db.execute_statement(stmt).unwrap() {
+                ExecuteStatementResponse::Explain(plan) => plan,
+                _ => panic!()
+            }
+        };
+
+        // An equality with the tables before it looks the table's rows up,
+        // whether it's in ON or WHERE, and whichever side it's on
+        let queries = [
+            "SELECT a.id, b.y FROM a JOIN b ON a.id = b.a_id",
+            "SELECT a.id, b.y FROM a, b WHERE b.a_id = a.id",
+            "SELECT a.id, b.y FROM a CROSS JOIN b WHERE b.a_id + 0 = a.id AND b.y <> 'x'"
+        ];
+
+        for query in queries.iter() {
+            assert!(explain(&mut db, query).contains("hash-probe"), "{}", query);
+            assert_eq!(rows(&mut db, &format!("{};", query)).unwrap(), vec![
+                vec![u(1), s("p")],
+                vec![u(3), s("q")],
+                vec![u(3), s("r")]
+            ]);
+        }
+
+        // NULLs match nothing, and keys of different types match by value
+        assert_eq!(rows(&mut db, "SELECT a.id, b.y FROM a JOIN b ON a.x = b.y AND b.a_id = a.id * 1.0;").unwrap(),
+            vec![vec![u(1), s("p")], vec![u(3), s("q")]]);
+        assert_eq!(rows(&mut db, "SELECT a.id, b.y FROM a LEFT JOIN b ON a.x = b.y;").unwrap(),
+            vec![vec![u(1), s("p")], vec![u(2), Variant::Null], vec![u(3), s("q")]]);
+
+        // The rest of the query's tables and outer queries can be used
+        assert_eq!(rows(&mut db, "SELECT a.id, c.id FROM a JOIN b ON a.id = b.a_id JOIN a c ON c.id = b.a_id + 1;").unwrap(),
+            vec![vec![u(1), u(2)]]);
+        assert_eq!(rows(&mut db, "SELECT a.id FROM a WHERE EXISTS (SELECT 1 FROM b, a c WHERE c.id = b.a_id AND b.y = a.x);").unwrap(),
+            vec![vec![u(1)], vec![u(3)]]);
+
+        // Scans are kept where there's nothing to look up by
+        assert!(!explain(&mut db, "SELECT a.id FROM a, b WHERE a.id < b.a_id").contains("hash-probe"));
+        assert!(!explain(&mut db, "SELECT a.id FROM a, b WHERE a.id = b.a_id OR b.y = 'n'").contains("hash-probe"));
+        rows(&mut db, "CREATE SEQUENCE s;").unwrap();
+        assert!(!explain(&mut db, "SELECT a.id FROM a, b WHERE b.a_id = nextval('s')").contains("hash-probe"));
+    }
+
+    #[test]
+    fn test_semi_join() {
+        let u = Variant::from_u64;
+        let mut db = TempDb::new();
+
+        rows(&mut db, "CREATE TABLE z (d U32 NULL); INSERT INTO z VALUES (1), (2), (3), (NULL);
+            CREATE TABLE w (d U32 NULL); INSERT INTO w VALUES (2), (3), (3);").unwrap();
+
+        // A column of the subquery's tables hides the outer column of the
+        // same name. Each row is yielded once, however many rows match it.
+        assert_eq!(rows(&mut db, "SELECT d FROM z WHERE d IN (SELECT d FROM w);").unwrap(), vec![vec![u(2)], vec![u(3)]]);
+        assert_eq!(rows(&mut db, "SELECT d FROM z WHERE d NOT IN (SELECT d FROM w);").unwrap(), vec![vec![u(1)]]);
+        assert_eq!(rows(&mut db, "SELECT count(*) FROM z WHERE d IN (SELECT d FROM z);").unwrap(), vec![vec![u(3)]]);
+
+        assert_eq!(rows(&mut db, "SELECT d FROM z WHERE EXISTS (SELECT 1 FROM w WHERE w.d = z.d);").unwrap(),
+            vec![vec![u(2)], vec![u(3)]]);
+        assert_eq!(rows(&mut db, "SELECT d FROM z WHERE NOT EXISTS (SELECT 1 FROM w WHERE w.d = z.d);").unwrap(),
+            vec![vec![u(1)], vec![Variant::Null]]);
+        assert_eq!(rows(&mut db, "SELECT d FROM z WHERE NOT EXISTS (SELECT 1 FROM w WHERE d > 5);").unwrap().len(), 4);
+
+        // A NULL in the subquery might equal any value, so NOT IN is never
+        // true, while IN still is where a value matches
+        rows(&mut db, "INSERT INTO w VALUES (NULL);").unwrap();
+        assert!(rows(&mut db, "SELECT d FROM z WHERE d NOT IN (SELECT d FROM w);").unwrap().is_empty());
+        assert_eq!(rows(&mut db, "SELECT d FROM z WHERE d IN (SELECT d FROM w);").unwrap(), vec![vec![u(2)], vec![u(3)]]);
+        assert_eq!(rows(&mut db, "SELECT d NOT IN (SELECT d FROM w WHERE d = 2) FROM z;").unwrap(), vec![
+            vec![u(1)], vec![u(0)], vec![u(1)], vec![Variant::Null]
+        ]);
+
+        // Two tables of the same query still make a column ambiguous
+        assert!(rows(&mut db, "SELECT 1 FROM z WHERE 1 IN (SELECT d FROM w, z);").is_err());
+    }
+
+    #[test]
+    fn test_distinct() {
+        let u = Variant::from_u64;
+        let i = Variant::from_i64;
+        let mut db = TempDb::new();
+
+        rows(&mut db, "CREATE TABLE t (a U32, b U32 NULL);
+            INSERT INTO t VALUES (1, 1), (1, 2), (2, 1), (1, 1), (2, NULL), (3, NULL), (2, 1);").unwrap();
+
+        // Rows keep the order they're first seen in, and NULLs are equal
+        assert_eq!(rows(&mut db, "SELECT DISTINCT a, b FROM t;").unwrap(), vec![
+            vec![u(1), u(1)], vec![u(1), u(2)], vec![u(2), u(1)], vec![u(2), Variant::Null], vec![u(3), Variant::Null]
+        ]);
+        assert_eq!(rows(&mut db, "SELECT DISTINCT b FROM t ORDER BY b DESC LIMIT 2;").unwrap(),
+            vec![vec![u(2)], vec![u(1)]]);
+        // Only result columns can be sorted by, since equal rows may differ
+        // in other columns
+        assert_eq!(rows(&mut db, "SELECT DISTINCT a AS x, b FROM t ORDER BY t.b, a DESC;").unwrap(), vec![
+            vec![u(3), Variant::Null], vec![u(2), Variant::Null], vec![u(2), u(1)], vec![u(1), u(1)], vec![u(1), u(2)]
+        ]);
+        assert!(rows(&mut db, "SELECT DISTINCT a FROM t ORDER BY b, a;").is_err());
+        assert!(rows(&mut db, "SELECT DISTINCT a FROM t ORDER BY a + 1;").is_err());
+        assert_eq!(rows(&mut db, "SELECT DISTINCT a + 0 FROM t WHERE b = 1;").unwrap(),
+            vec![vec![i(1)], vec![i(2)]]);
+        assert_eq!(rows(&mut db, "SELECT DISTINCT count(*) FROM t GROUP BY a;").unwrap(),
+            vec![vec![u(3)], vec![u(1)]]);
+
+        // Aggregates over the distinct values, which still skip NULLs
+        assert_eq!(rows(&mut db, "SELECT count(DISTINCT b), sum(DISTINCT a), avg(DISTINCT b), count(b) FROM t;").unwrap(),
+            vec![vec![u(2), u(6), Variant::from_f64(1.5), u(5)]]);
+        assert_eq!(rows(&mut db, "SELECT a, count(DISTINCT b) FROM t GROUP BY a;").unwrap(),
+            vec![vec![u(1), u(2)], vec![u(2), u(1)], vec![u(3), u(0)]]);
+        assert_eq!(rows(&mut db, "SELECT count(DISTINCT a * 1.0) FROM t;").unwrap(), vec![vec![u(3)]]);
+
+        assert!(rows(&mut db, "SELECT length(DISTINCT 'x');").is_err());
+        assert!(rows(&mut db, "SELECT count(DISTINCT a, b) FROM t;").is_err());
+    }
+
+    #[test]
+    fn test_row_values() {
+        let u = Variant::from_u64;
+        let mut db = TempDb::new();
+
+        rows(&mut db, "CREATE TABLE t (a U32, b U32 NULL);
+            INSERT INTO t VALUES (1, 1), (1, 2), (2, 1), (2, NULL), (3, 3);").unwrap();
+
+        assert_eq!(rows(&mut db, "SELECT a, b FROM t WHERE (a, b) = (1, 2);").unwrap(), vec![vec![u(1), u(2)]]);
+        assert_eq!(rows(&mut db, "SELECT a FROM t WHERE (a, b) <> (1, 1);").unwrap(),
+            vec![vec![u(1)], vec![u(2)], vec![u(2)], vec![u(3)]]);
+
+        // Keyset pagination: the page after (1, 2)
+        assert_eq!(rows(&mut db, "SELECT a, b FROM t WHERE (a, b) > (1, 2) ORDER BY a, b LIMIT 2;").unwrap(),
+            vec![vec![u(2), Variant::Null], vec![u(2), u(1)]]);
+        assert_eq!(rows(&mut db, "SELECT a, b FROM t WHERE (a, b) <= (2, 1);").unwrap(),
+            vec![vec![u(1), u(1)], vec![u(1), u(2)], vec![u(2), u(1)]]);
+
+        // Rows are ordered by their first values that aren't equal, so a
+        // NULL only matters if the values before it are equal
+        assert_eq!(rows(&mut db, "SELECT (1, NULL) < (2, 0), (NULL, 1) < (2, 0), (1, NULL) = (2, NULL), \
+            (1, NULL) = (1, 2), (1, 2) < (1, 2), (1, 2) <= (1, 2);").unwrap(), vec![vec![
+                u(1), Variant::Null, u(0), Variant::Null, u(0), u(1)
+            ]]);
+
+        assert_eq!(rows(&mut db, "SELECT a, b FROM t WHERE (a, b) IN (SELECT u.b, u.a FROM t u);").unwrap(),
+            vec![vec![u(1), u(1)], vec![u(1), u(2)], vec![u(2), u(1)], vec![u(3), u(3)]]);
+        assert_eq!(rows(&mut db, "SELECT a, b FROM t WHERE (a, b) NOT IN (SELECT u.a, u.b FROM t u WHERE u.a = 1);").unwrap(),
+            vec![vec![u(2), u(1)], vec![u(2), Variant::Null], vec![u(3), u(3)]]);
+
+        assert!(rows(&mut db, "SELECT (1, 2);").is_err());
+        assert!(rows(&mut db, "SELECT (1, 2) = (1, 2, 3);").is_err());
+        assert!(rows(&mut db, "SELECT (1, 2) + (1, 2);").is_err());
+        assert!(rows(&mut db, "SELECT a FROM t WHERE a = (1, 2);").is_err());
+        assert!(rows(&mut db, "SELECT a FROM t WHERE (a, b) IN (SELECT u.a FROM t u);").is_err());
+        assert!(rows(&mut db, "SELECT a FROM t WHERE a IN (SELECT u.a, u.b FROM t u);").is_err());
+    }
+
+    #[test]
+    fn test_create_table_as() {
+        let mut db = TempDb::new();
+        db.enable_change_log();
+
+        rows(&mut db, "CREATE TABLE t (a U32, b STRING NULL);
+            INSERT INTO t VALUES (1, 'x'), (2, NULL);
+            CREATE TABLE u AS SELECT a + 1 AS c, b FROM t;").unwrap();
+        assert_eq!(rows(&mut db, "SELECT * FROM u;").unwrap(), vec![
+            vec![Variant::from_i64(2), Variant::StringLiteral("x".to_string())],
+            vec![Variant::from_i64(3), Variant::Null]
+        ]);
+
+        // Columns of tables keep their types without any rows, but other
+        // columns need a value to take their type from
+        rows(&mut db, "CREATE TABLE e AS SELECT a, b FROM t WHERE a > 2; INSERT INTO e VALUES (3, 'z');").unwrap();
+        assert_eq!(rows(&mut db, "SELECT * FROM e;").unwrap(),
+            vec![vec![Variant::from_u64(3), Variant::StringLiteral("z".to_string())]]);
+        assert!(rows(&mut db, "CREATE TABLE f AS SELECT a + 1 AS c FROM t WHERE a > 2;").is_err());
+        assert!(rows(&mut db, "CREATE TABLE f AS SELECT NULL AS c;").is_err());
+
+        // A table that isn't created isn't logged
+        assert!(rows(&mut db, "CREATE TABLE u AS SELECT a FROM t;").is_err());
+        let mut replica = TempDb::new();
+        replica.apply_change_log(&db.take_change_log()).unwrap();
+        assert_eq!(rows(&mut replica, "SELECT * FROM u;"), rows(&mut db, "SELECT * FROM u;"));
+
+        // Columns taken from a table keep its maximum lengths on the replica
+        rows(&mut db, "CREATE TABLE vc (s VARCHAR(2)); INSERT INTO vc VALUES ('ab');
+            CREATE TABLE c2 AS SELECT s FROM vc;").unwrap();
+        replica.apply_change_log(&db.take_change_log()).unwrap();
+        assert_eq!(columns(&mut replica, "SELECT s FROM c2;"), columns(&mut db, "SELECT s FROM c2;"));
+        assert!(rows(&mut replica, "PRAGMA sql_mode = strict; INSERT INTO c2 VALUES ('abcdef');").is_err());
+    }
+
+    #[test]
+    fn test_select_without_from() {
+        let mut db = TempDb::new();
+
+        // The result columns are evaluated once, for a single row
+        assert_eq!(rows(&mut db, "SELECT 1 + 1, 'a' || 'b';").unwrap(),
+            vec![vec![Variant::from_i64(2), Variant::StringLiteral("ab".to_string())]]);
+        assert_eq!(rows(&mut db, "CREATE SEQUENCE s; SELECT nextval('s'); SELECT currval('s');").unwrap(),
+            vec![vec![Variant::from_i64(1)]]);
+        assert!(rows(&mut db, "SELECT *;").is_err());
+    }
+
+    #[test]
+    fn test_insert_default_values() {
+        let mut db = TempDb::new();
+        rows(&mut db, "CREATE TABLE t (a U32, b STRING, c F64, d BYTE[2], e I8 NULL);").unwrap();
+
+        match db.execute_statement(parse_statements("INSERT INTO t DEFAULT VALUES;").unwrap().remove(0)) {
+            Ok(ExecuteStatementResponse::Inserted(n)) => assert_eq!(n, 1),
+            _ => panic!()
+        }
+        assert_eq!(rows(&mut db, "SELECT * FROM t;").unwrap(), vec![vec![
+            Variant::from_u64(0), Variant::StringLiteral(String::new()), Variant::from_f64(0.0),
+            Variant::Bytes(vec![0, 0]), Variant::Null
+        ]]);
+
+        assert!(rows(&mut db, "INSERT INTO t (a) DEFAULT VALUES;").is_err());
+    }
+
+    #[test]
+    fn test_delete() {
+        let mut db = TempDb::new();
+        db.enable_change_log();
+        let changes = db.subscribe();
+
+        rows(&mut db, "CREATE TABLE t (a U32, b STRING NULL);
+            INSERT INTO t VALUES (1, 'x'), (2, NULL), (3, 'y'), (4, 'x');").unwrap();
+        let mut replica = TempDb::new();
+        replica.apply_change_log(&db.take_change_log()).unwrap();
+
+        // The subquery sees the rows as they were before any were deleted
+        rows(&mut db, "DELETE FROM t WHERE b = 'x' AND a < (SELECT max(c.a) FROM t c WHERE c.b = 'x');").unwrap();
+        rows(&mut db, "DELETE FROM t WHERE t.b = 'nothing';").unwrap();
+        assert_eq!(rows(&mut db, "SELECT a FROM t;").unwrap(), vec![
+            vec![Variant::from_u64(2)], vec![Variant::from_u64(3)], vec![Variant::from_u64(4)]
+        ]);
+
+        let mut deleted = Vec::new();
+        while let Ok(change) = changes.try_recv() {
+            if change.op == ChangeOp::Delete {
+                deleted.push(change.old.unwrap());
+            }
+        }
+        assert_eq!(deleted, vec![vec![Variant::from_u64(1), Variant::StringLiteral("x".to_string())]]);
+
+        // Deletes are replicated by rowid, so the replica deletes the same row
+        // even where another is equal to it
+        rows(&mut db, "INSERT INTO t VALUES (2, NULL); DELETE FROM t WHERE a = 3;").unwrap();
+        replica.apply_change_log(&db.take_change_log()).unwrap();
+        assert_eq!(rows(&mut replica, "SELECT a FROM t;"), rows(&mut db, "SELECT a FROM t;"));
+
+        match db.execute_statement(parse_statements("DELETE FROM t;").unwrap().remove(0)) {
+            Ok(ExecuteStatementResponse::Deleted(n)) => assert_eq!(n, 3),
+            _ => panic!()
+        }
+        assert!(rows(&mut db, "SELECT a FROM t;").unwrap().is_empty());
+
+        // Rows can be deleted by the rows of another table with IN or EXISTS,
+        // where the other table's columns hide the same names of this one's
+        rows(&mut db, "INSERT INTO t VALUES (1, 'x'), (2, 'y'), (3, 'z'), (4, NULL);
+            CREATE TABLE w (a U32, b STRING); INSERT INTO w VALUES (1, 'x'), (3, 'q'), (4, 'r');").unwrap();
+        rows(&mut db, "DELETE FROM t WHERE a IN (SELECT a FROM w WHERE b = 'x');").unwrap();
+        rows(&mut db, "DELETE FROM t WHERE EXISTS (SELECT 1 FROM w WHERE w.a = t.a AND w.b <> 'r');").unwrap();
+        assert_eq!(rows(&mut db, "SELECT a FROM t;").unwrap(), vec![
+            vec![Variant::from_u64(2)], vec![Variant::from_u64(4)]
+        ]);
+
+        assert!(rows(&mut db, "DELETE FROM t WHERE count(*) > 1;").is_err());
+        assert!(rows(&mut db, "DELETE FROM t WHERE c = 1;").is_err());
+        assert!(rows(&mut db, "DELETE FROM u;").is_err());
+        assert!(rows(&mut db, "CREATE MATERIALIZED VIEW v AS SELECT a FROM t; DELETE FROM v;").is_err());
+    }
+
+    #[test]
+    fn test_update() {
+        let s = |s: &str| Variant::StringLiteral(s.to_string());
+        let mut db = TempDb::new();
+        db.enable_change_log();
+        let changes = db.subscribe();
+
+        rows(&mut db, "CREATE TABLE t (a U32, b VARCHAR(3) NULL);
+            INSERT INTO t VALUES (1, 'x'), (2, NULL), (3, 'y');").unwrap();
+        let mut replica = TempDb::new();
+        replica.apply_change_log(&db.take_change_log()).unwrap();
+
+        // Each assignment sees the row as it was, and the subquery sees the
+        // table as it was
+        match db.execute_statement(parse_statements("UPDATE t SET a = a * 10, b = a || 'abcd'
+            WHERE a < (SELECT max(c.a) FROM t c);").unwrap().remove(0)) {
+            Ok(ExecuteStatementResponse::Updated(n)) => assert_eq!(n, 2),
+            _ => panic!()
+        }
+        assert_eq!(rows(&mut db, "SELECT * FROM t;").unwrap(), vec![
+            vec![Variant::from_u64(10), s("1ab")],
+            vec![Variant::from_u64(20), s("2ab")],
+            vec![Variant::from_u64(3), s("y")]
+        ]);
+
+        let mut updated = Vec::new();
+        while let Ok(change) = changes.try_recv() {
+            if change.op == ChangeOp::Update {
+                updated.push((change.old.unwrap(), change.new.unwrap()));
+            }
+        }
+        assert_eq!(updated[1], (vec![Variant::from_u64(2), Variant::Null], vec![Variant::from_u64(20), s("2ab")]));
+
+        rows(&mut db, "UPDATE t SET b = NULL WHERE a = 3;").unwrap();
+        replica.apply_change_log(&db.take_change_log()).unwrap();
+        assert_eq!(rows(&mut replica, "SELECT * FROM t;"), rows(&mut db, "SELECT * FROM t;"));
+
+        let mut opened = TempDb::new();
+        opened.load_snapshot(&db.snapshot()).unwrap();
+        assert_eq!(rows(&mut opened, "SELECT * FROM t;"), rows(&mut db, "SELECT * FROM t;"));
+
+        // No row is changed if any of them fails
+        assert!(rows(&mut db, "PRAGMA sql_mode = strict; UPDATE t SET b = a || 'xy';").is_err());
+        assert!(rows(&mut db, "UPDATE t SET a = NULL WHERE a = 3;").is_err());
+        assert_eq!(rows(&mut replica, "SELECT * FROM t;"), rows(&mut db, "SELECT * FROM t;"));
+
+        assert!(rows(&mut db, "UPDATE t SET a = 1, a = 2;").is_err());
+        assert!(rows(&mut db, "UPDATE t SET c = 1;").is_err());
+        assert!(rows(&mut db, "UPDATE t SET a = count(*);").is_err());
+        assert!(rows(&mut db, "CREATE MATERIALIZED VIEW v AS SELECT a FROM t; UPDATE v SET a = 1;").is_err());
+    }
+
+    #[test]
+    fn test_update_from() {
+        let s = |s: &str| Variant::StringLiteral(s.to_string());
+        let u = Variant::from_u64;
+        let mut db = TempDb::new();
+        db.enable_change_log();
+
+        rows(&mut db, "CREATE TABLE t (a U32, b STRING NULL);
+            INSERT INTO t VALUES (1, 'x'), (2, NULL), (2, NULL), (3, 'y');
+            CREATE TABLE u (a U32, b STRING);
+            INSERT INTO u VALUES (2, 'two'), (3, 'three'), (3, 'drei'), (4, 'four');").unwrap();
+        let mut replica = TempDb::new();
+        replica.apply_change_log(&db.take_change_log()).unwrap();
+
+        // Only the rows that are joined with any are updated, each with its
+        // first match, and equal rows alike
+        match db.execute_statement(parse_statements("UPDATE t SET b = u.b, a = t.a + u.a FROM u WHERE t.a = u.a;").unwrap().remove(0)) {
+            Ok(ExecuteStatementResponse::Updated(n)) => assert_eq!(n, 3),
+            _ => panic!()
+        }
+        assert_eq!(rows(&mut db, "SELECT * FROM t;").unwrap(), vec![
+            vec![u(1), s("x")], vec![u(4), s("two")], vec![u(4), s("two")], vec![u(6), s("three")]
+        ]);
+
+        rows(&mut db, "UPDATE t SET a = v.n FROM (SELECT a, count(*) AS n FROM u GROUP BY a) AS v
+            INNER JOIN u AS w ON v.a = w.a WHERE t.a = v.a;").unwrap();
+        assert_eq!(rows(&mut db, "SELECT a FROM t;").unwrap(), vec![vec![u(1)], vec![u(1)], vec![u(1)], vec![u(6)]]);
+
+        replica.apply_change_log(&db.take_change_log()).unwrap();
+        assert_eq!(rows(&mut replica, "SELECT * FROM t;"), rows(&mut db, "SELECT * FROM t;"));
+
+        // The table and FROM are one scope, so their column names clash
+        assert!(rows(&mut db, "UPDATE t SET b = b FROM u;").is_err());
+        assert!(rows(&mut db, "UPDATE t SET a = count(*) FROM u;").is_err());
+        assert!(rows(&mut db, "UPDATE t SET a = row_number() OVER () FROM u;").is_err());
+        assert!(rows(&mut db, "UPDATE t SET a = 1 FROM t;").is_err());
+    }
+
+    #[test]
+    fn test_temporary_tables() {
+        let mut db = TempDb::new();
+        db.enable_change_log();
+
+        rows(&mut db, "CREATE TABLE t (x U32); INSERT INTO t VALUES (1);
+            CREATE MATERIALIZED VIEW v AS SELECT x FROM t;
+            CREATE TEMPORARY TABLE tt (x U32); INSERT INTO tt VALUES (2);").unwrap();
+
+        // Views are replicated, so they can't read a temporary table, even
+        // in a subquery or where it hides the permanent table they read
+        assert!(rows(&mut db, "CREATE MATERIALIZED VIEW w AS SELECT x FROM tt;").is_err());
+        assert!(rows(&mut db, "CREATE MATERIALIZED VIEW w AS SELECT x FROM t WHERE x IN (SELECT x FROM tt);").is_err());
+        assert!(rows(&mut db, "CREATE TEMP TABLE t (x U32); REFRESH MATERIALIZED VIEW v;").is_err());
+
+        let mut replica = TempDb::new();
+        replica.apply_change_log(&db.take_change_log()).unwrap();
+        assert_eq!(rows(&mut replica, "SELECT * FROM v;").unwrap(), vec![vec![Variant::from_u64(1)]]);
+
+        // A temporary table hides the permanent table of the same name, and
+        // neither it nor its rows are logged
+        rows(&mut db, "CREATE TABLE p (x U32); INSERT INTO p VALUES (1);
+            CREATE TEMPORARY TABLE p (x U32); INSERT INTO p VALUES (2);
+            ALTER TABLE tt ADD COLUMN y U32; COMMENT ON TABLE tt IS 'scratch';").unwrap();
+        assert_eq!(rows(&mut db, "SELECT x FROM p;").unwrap(), vec![vec![Variant::from_u64(2)]]);
+        assert!(rows(&mut db, "CREATE TABLE p (x U32);").is_err());
+
+        let entries = db.take_change_log();
+        assert_eq!(entries.len(), 2);
+        replica.apply_change_log(&entries).unwrap();
+        assert_eq!(rows(&mut replica, "SELECT x FROM p;").unwrap(), vec![vec![Variant::from_u64(1)]]);
+        assert!(rows(&mut replica, "SELECT * FROM tt;").is_err());
+
+        // Nor are they saved
+        let path = test_directory("temporary-tables").join("db.llamadb");
+        db.save_to(&path).unwrap();
+        let mut opened = TempDb::open(&path).unwrap();
+        assert_eq!(rows(&mut opened, "SELECT x FROM p;").unwrap(), vec![vec![Variant::from_u64(1)]]);
+        assert!(rows(&mut opened, "SELECT * FROM tt;").is_err());
+    }
+
+    #[test]
+    fn test_max_length() {
+        let s = |s: &str| Variant::StringLiteral(s.to_string());
+        let mut db = TempDb::new();
+
+        // Lengths are counted in characters, and values are truncated in
+        // permissive mode
+        assert_eq!(rows(&mut db, "CREATE TABLE t (a VARCHAR(3), b CHAR(2) NULL);
+            INSERT INTO t VALUES ('abcdef', NULL), (12345, 'éèê'), ('ab', 'x'); SELECT * FROM t;").unwrap(), vec![
+            vec![s("abc"), Variant::Null],
+            vec![s("123"), s("éè")],
+            vec![s("ab"), s("x")]
+        ]);
+
+        let mut a = origin("t", "a", DbType::String, false);
+        a.max_length = Some(3);
+        assert_eq!(columns(&mut db, "SELECT a FROM t;"), vec![a]);
+
+        let mut opened = TempDb::new();
+        opened.load_snapshot(&db.snapshot()).unwrap();
+        assert!(rows(&mut opened, "PRAGMA sql_mode = strict; INSERT INTO t VALUES ('abcd', NULL);").is_err());
+        assert!(rows(&mut opened, "INSERT INTO t VALUES ('abc', 'éè');").is_ok());
+
+        // Existing values aren't truncated when the length is shortened
+        assert!(rows(&mut db, "ALTER TABLE t ALTER a TYPE VARCHAR(2);").is_err());
+        assert_eq!(rows(&mut db, "ALTER TABLE t ALTER a TYPE STRING; INSERT INTO t VALUES ('abcd', NULL);
+            SELECT a FROM t;").unwrap(), vec![vec![s("abc")], vec![s("123")], vec![s("ab")], vec![s("abcd")]]);
+
+        assert!(rows(&mut db, "CREATE TABLE u (a I32(5));").is_err());
+        assert!(rows(&mut db, "CREATE TABLE u (a VARCHAR(0));").is_err());
+    }
+
+    /// An empty directory for a test's files
+    fn test_directory(name: &str) -> PathBuf {
+        let path = env::temp_dir().join(format!("llamadb-test-{}", name));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_save_and_open() {
+        let s = |s: &str| Variant::StringLiteral(s.to_string());
+        let directory = test_directory("save-and-open");
+        let csv_path = directory.join("people.csv");
+        File::create(&csv_path).unwrap().write_all(b"name,age\nann,30\nbob,\n").unwrap();
+
+        let mut db = TempDb::new();
+        rows(&mut db, &format!("CREATE TABLE t (a U32, b VARCHAR(3) NULL);
+            INSERT INTO t VALUES (1, 'x'), (2, NULL), (3, 'y');
+            COMMENT ON TABLE t IS 'Numbers'; COMMENT ON COLUMN t.b IS 'Letters';
+            CREATE SEQUENCE s START WITH 10 INCREMENT BY 5; SELECT nextval('s');
+            CREATE SEQUENCE e START WITH 9223372036854775807; SELECT nextval('e');
+            CREATE MATERIALIZED VIEW v AS SELECT a FROM t WHERE a <> 2;
+            CREATE VIRTUAL TABLE people USING csv('{}');
+            CREATE TEMPORARY TABLE scratch (a U32);
+            PRAGMA user_version = 3;", csv_path.display())).unwrap();
+
+        let path = directory.join("db.llamadb");
+        db.save_to(&path).unwrap();
+        let mut opened = TempDb::open(&path).unwrap();
+
+        assert_eq!(rows(&mut opened, "SELECT * FROM t;"), rows(&mut db, "SELECT * FROM t;"));
+        assert_eq!(columns(&mut opened, "SELECT * FROM t;"), columns(&mut db, "SELECT * FROM t;"));
+        assert_eq!(opened.get_table_comment("t"), Ok(Some("Numbers")));
+        assert_eq!(opened.get_column_comment("t", "b"), Ok(Some("Letters")));
+        assert_eq!(opened.user_version(), 3);
+
+        // Sequences carry on from where they were, and an exhausted one
+        // stays exhausted
+        assert_eq!(rows(&mut opened, "SELECT nextval('s');").unwrap(), vec![vec![Variant::from_i64(15)]]);
+        assert!(rows(&mut opened, "SELECT nextval('e');").is_err());
+
+        // The view's query is saved along with its rows, so it can be refreshed
+        rows(&mut opened, "INSERT INTO t VALUES (4, 'z');").unwrap();
+        assert_eq!(rows(&mut opened, "SELECT * FROM v;").unwrap(), vec![vec![Variant::from_u64(1)], vec![Variant::from_u64(3)]]);
+        assert_eq!(rows(&mut opened, "REFRESH MATERIALIZED VIEW v; SELECT * FROM v;").unwrap().len(), 3);
+
+        // Virtual tables read their file again
+        assert_eq!(rows(&mut opened, "SELECT * FROM people;").unwrap(), vec![
+            vec![s("ann"), Variant::from_i64(30)],
+            vec![s("bob"), Variant::Null]
+        ]);
+
+        assert!(rows(&mut opened, "SELECT * FROM scratch;").is_err());
+        assert!(fs::metadata(directory.join("db.llamadb.tmp")).is_err());
+
+        // A corrupt integer width is an error rather than a panic
+        let mut db = TempDb::new();
+        rows(&mut db, "CREATE TABLE w (a U32); INSERT INTO w VALUES (1);").unwrap();
+        let mut buf = db.snapshot();
+        let column = buf.windows(6).position(|w| w == &b"\x01a\x01a\x04\x04"[..]).unwrap();
+
+        for &width in [0, 9].iter() {
+            buf[column + 5] = width;
+            File::create(&path).unwrap().write_all(&buf).unwrap();
+            assert!(TempDb::open(&path).is_err());
+        }
+    }
+
+    #[test]
+    fn test_save_case_folded_names() {
+        use identifier::CaseFolding;
+        use super::{decode_change_log, encode_change_log};
+
+        // Straße folds to STRASSE in upper case, which is a different name
+        // once it's normalized again
+        let mut db = TempDb::new();
+        db.enable_change_log();
+        db.set_identifier_case_folding(CaseFolding::Upper);
+        rows(&mut db, "CREATE TABLE Größe (a U32); INSERT INTO Größe VALUES (1);
+            CREATE TABLE Straße AS SELECT a AS Maß FROM Größe;").unwrap();
+
+        let path = test_directory("save-case-folded-names").join("db.llamadb");
+        db.save_to(&path).unwrap();
+        let mut opened = TempDb::open(&path).unwrap();
+
+        let entries = decode_change_log(&encode_change_log(&db.take_change_log())).unwrap();
+        let mut replica = TempDb::new();
+        replica.apply_change_log(&entries).unwrap();
+
+        for copy in vec![&mut opened, &mut replica].into_iter() {
+            assert_eq!(rows(copy, "SELECT Maß FROM Straße;").unwrap(), vec![vec![Variant::from_u64(1)]]);
+            assert!(rows(copy, "SELECT * FROM strasse;").is_err());
+        }
+    }
+
+    #[test]
+    fn test_restore() {
+        let u = Variant::from_u64;
+        let directory = test_directory("restore");
+        let base = directory.join("base.llamadb");
+        let archive = directory.join("archive");
+        fs::create_dir_all(&archive).unwrap();
+
+        let mut db = TempDb::new();
+        db.enable_change_log();
+        rows(&mut db, "CREATE TABLE t (a U32); INSERT INTO t VALUES (1);").unwrap();
+        db.archive_change_log(&archive).unwrap();
+        db.save_to(&base).unwrap();
+        let base_lsn = db.change_log.lsn();
+
+        rows(&mut db, "INSERT INTO t VALUES (2);").unwrap();
+        rows(&mut db, "INSERT INTO t VALUES (3);").unwrap();
+        let second_lsn = db.change_log.lsn() - 1;
+        db.archive_change_log(&archive).unwrap();
+        rows(&mut db, "DELETE FROM t WHERE a = 1;").unwrap();
+        db.archive_change_log(&archive).unwrap();
+
+        // Nothing is archived when there are no changes
+        db.archive_change_log(&archive).unwrap();
+        assert_eq!(fs::read_dir(&archive).unwrap().count(), 3);
+
+        // Segments from before the base backup are skipped
+        let mut restored = TempDb::restore(&base, &archive, None).unwrap();
+        assert_eq!(rows(&mut restored, "SELECT a FROM t;"), rows(&mut db, "SELECT a FROM t;"));
+        assert_eq!(restored.change_log.lsn(), db.change_log.lsn());
+
+        let mut restored = TempDb::restore(&base, &archive, Some(second_lsn)).unwrap();
+        assert_eq!(rows(&mut restored, "SELECT a FROM t;").unwrap(), vec![vec![u(1)], vec![u(2)]]);
+
+        let mut restored = TempDb::restore(&base, &archive, Some(base_lsn)).unwrap();
+        assert_eq!(rows(&mut restored, "SELECT a FROM t;").unwrap(), vec![vec![u(1)]]);
+
+        assert!(TempDb::restore(&base, &archive, Some(base_lsn - 1)).is_err());
+        assert!(TempDb::restore(&base, &archive, Some(db.change_log.lsn() + 1)).is_err());
     }
 }
@@ -0,0 +1,740 @@
+//! The format used by `TempDb::encode_select_plan`, so that a compiled query
+//! can be cached, or sent to a replica, and executed without compiling it
+//! again.
+//!
+//! A plan starts with a magic string and a format version, followed by its
+//! result columns and its expression. Each result column is its name and its
+//! origin, if it has one. Each expression is a tag followed by its fields, in
+//! the order they're declared in `SExpression`. Tables are written as their
+//! names and column counts, and the plan can only be decoded by a database
+//! whose tables still have those columns.
+
+use databaseinfo::{DatabaseInfo, TableInfo};
+use identifier::Identifier;
+use queryplan::{AggregateOp, BinaryOp, ColumnOrigin, FrameBound, FunctionOp, QueryPlan, SequenceOp, SExpression,
+    UnaryOp, WindowFrame, WindowFunction, WindowOp};
+
+use super::TempDb;
+use super::snapshot::{SnapshotReader, SnapshotWriter};
+use super::table::Table;
+
+const MAGIC: &'static [u8] = b"llamadb plan\0";
+
+/// Version 2 added whether an aggregate function is DISTINCT. Plans of
+/// version 1 can still be decoded.
+const VERSION: u64 = 2;
+
+const TAG_SCAN: u8 = 0;
+const TAG_TABLE_FUNCTION: u8 = 1;
+const TAG_MAP: u8 = 2;
+const TAG_TEMP_GROUP_BY: u8 = 3;
+const TAG_WINDOW: u8 = 4;
+const TAG_YIELD: u8 = 5;
+const TAG_COLUMN_FIELD: u8 = 6;
+const TAG_IF: u8 = 7;
+const TAG_UNARY_OP: u8 = 8;
+const TAG_BINARY_OP: u8 = 9;
+const TAG_AGGREGATE_OP: u8 = 10;
+const TAG_COUNT_ALL: u8 = 11;
+const TAG_ROW_COUNT: u8 = 12;
+const TAG_SEQUENCE_OP: u8 = 13;
+const TAG_FUNCTION_OP: u8 = 14;
+const TAG_SEMI_JOIN: u8 = 15;
+const TAG_VALUE: u8 = 16;
+const TAG_SORT: u8 = 17;
+const TAG_LIMIT: u8 = 18;
+const TAG_SESSION_VARIABLE: u8 = 19;
+const TAG_LEFT_JOIN: u8 = 20;
+const TAG_ROW_COMPARE: u8 = 21;
+const TAG_HASH_JOIN: u8 = 22;
+const TAG_HASH_PROBE: u8 = 23;
+const TAG_DISTINCT: u8 = 24;
+
+/// Defines the codes that an operator enum is written with, and the
+/// functions that convert between them.
+macro_rules! op_codes {
+    ($write:ident, $read:ident, $t:ident, $name:expr, { $($code:tt => $op:ident),* }) => (
+        fn $write(op: $t) -> u8 {
+            match op {
+                $($t::$op => $code),*
+            }
+        }
+
+        fn $read(code: u8) -> Result<$t, String> {
+            match code {
+                $($code => Ok($t::$op),)*
+                code => Err(format!("plan contains an unknown {}: {}", $name, code))
+            }
+        }
+    )
+}
+
+op_codes!(binary_op_code, binary_op_from_code, BinaryOp, "operator", {
+    0 => Equal, 1 => NotEqual, 2 => LessThan, 3 => LessThanOrEqual, 4 => GreaterThan, 5 => GreaterThanOrEqual,
+    6 => And, 7 => Or, 8 => Add, 9 => Subtract, 10 => Multiply, 11 => Divide, 12 => BitAnd, 13 => BitOr,
+    14 => Concatenate
+});
+
+op_codes!(unary_op_code, unary_op_from_code, UnaryOp, "operator", { 0 => Negate });
+
+op_codes!(aggregate_op_code, aggregate_op_from_code, AggregateOp, "aggregate function", {
+    0 => Count, 1 => Avg, 2 => Sum, 3 => Min, 4 => Max
+});
+
+op_codes!(sequence_op_code, sequence_op_from_code, SequenceOp, "sequence function", { 0 => NextVal, 1 => CurrVal });
+
+op_codes!(function_op_code, function_op_from_code, FunctionOp, "function", { 0 => Length, 1 => Hex, 2 => Unhex,
+    3 => Coalesce, 4 => NullIf, 5 => IfNull });
+
+/// An aggregate window function is written as its aggregate's code after 5
+const WINDOW_AGGREGATE: u8 = 5;
+
+pub fn encode_plan(plan: &QueryPlan<TempDb>) -> Vec<u8> {
+    let mut writer = SnapshotWriter::with_header(MAGIC, VERSION);
+    writer.write_uvar(plan.out_column_names.len() as u64);
+
+    for (name, origin) in plan.out_column_names.iter().zip(plan.out_column_origins.iter()) {
+        writer.write_identifier(name);
+
+        match origin {
+            &Some(ref origin) => {
+                writer.write_u8(if origin.outer_joined { 2 } else { 1 });
+                write_table(&mut writer, origin.table);
+                writer.write_uvar(origin.column_offset as u64);
+            },
+            &None => writer.write_u8(0)
+        }
+    }
+
+    write_expression(&mut writer, &plan.expr);
+    writer.buf
+}
+
+fn write_table(writer: &mut SnapshotWriter, table: &Table) {
+    writer.write_identifier(&table.name);
+    writer.write_uvar(table.get_column_count() as u64);
+}
+
+fn write_expressions(writer: &mut SnapshotWriter, exprs: &[SExpression<TempDb>]) {
+    writer.write_uvar(exprs.len() as u64);
+
+    for expr in exprs {
+        write_expression(writer, expr);
+    }
+}
+
+fn write_expression(writer: &mut SnapshotWriter, expr: &SExpression<TempDb>) {
+    match expr {
+        &SExpression::Scan { table, source_id, ref yield_fn } => {
+            writer.write_u8(TAG_SCAN);
+            write_table(writer, table);
+            writer.write_uvar(source_id as u64);
+            write_expression(writer, yield_fn);
+        },
+        &SExpression::TableFunction { ref function, source_id, ref arguments, ref yield_fn } => {
+            writer.write_u8(TAG_TABLE_FUNCTION);
+            writer.write_identifier(function);
+            writer.write_uvar(source_id as u64);
+            write_expressions(writer, arguments);
+            write_expression(writer, yield_fn);
+        },
+        &SExpression::Map { source_id, ref yield_in_fn, ref yield_out_fn } => {
+            writer.write_u8(TAG_MAP);
+            writer.write_uvar(source_id as u64);
+            write_expression(writer, yield_in_fn);
+            write_expression(writer, yield_out_fn);
+        },
+        &SExpression::LeftJoin { source_id, column_count, ref yield_in_fn, ref yield_out_fn } => {
+            writer.write_u8(TAG_LEFT_JOIN);
+            writer.write_uvar(source_id as u64);
+            writer.write_uvar(column_count as u64);
+            write_expression(writer, yield_in_fn);
+            write_expression(writer, yield_out_fn);
+        },
+        &SExpression::HashJoin { source_id, ref keys, ref yield_in_fn, ref yield_out_fn } => {
+            writer.write_u8(TAG_HASH_JOIN);
+            writer.write_uvar(source_id as u64);
+            write_expressions(writer, keys);
+            write_expression(writer, yield_in_fn);
+            write_expression(writer, yield_out_fn);
+        },
+        &SExpression::HashProbe { source_id, ref values, ref yield_fn } => {
+            writer.write_u8(TAG_HASH_PROBE);
+            writer.write_uvar(source_id as u64);
+            write_expressions(writer, values);
+            write_expression(writer, yield_fn);
+        },
+        &SExpression::TempGroupBy { source_id, ref yield_in_fn, ref group_by_values, ref yield_out_fn } => {
+            writer.write_u8(TAG_TEMP_GROUP_BY);
+            writer.write_uvar(source_id as u64);
+            write_expression(writer, yield_in_fn);
+            write_expressions(writer, group_by_values);
+            write_expression(writer, yield_out_fn);
+        },
+        &SExpression::Window { source_id, ref yield_in_fn, ref functions, ref yield_out_fn } => {
+            writer.write_u8(TAG_WINDOW);
+            writer.write_uvar(source_id as u64);
+            write_expression(writer, yield_in_fn);
+            writer.write_uvar(functions.len() as u64);
+
+            for function in functions {
+                write_window_function(writer, function);
+            }
+
+            write_expression(writer, yield_out_fn);
+        },
+        &SExpression::Distinct { ref yield_in_fn, column_count } => {
+            writer.write_u8(TAG_DISTINCT);
+            write_expression(writer, yield_in_fn);
+            writer.write_uvar(column_count as u64);
+        },
+        &SExpression::Sort { ref yield_in_fn, column_count, ref order_by } => {
+            writer.write_u8(TAG_SORT);
+            write_expression(writer, yield_in_fn);
+            writer.write_uvar(column_count as u64);
+            writer.write_uvar(order_by.len() as u64);
+
+            for &(column_offset, descending) in order_by {
+                writer.write_uvar(column_offset as u64);
+                writer.write_u8(descending as u8);
+            }
+        },
+        &SExpression::Limit { ref yield_in_fn, count, offset } => {
+            writer.write_u8(TAG_LIMIT);
+            write_expression(writer, yield_in_fn);
+            writer.write_uvar(count);
+            writer.write_uvar(offset);
+        },
+        &SExpression::Yield { ref fields } => {
+            writer.write_u8(TAG_YIELD);
+            write_expressions(writer, fields);
+        },
+        &SExpression::ColumnField { source_id, column_offset } => {
+            writer.write_u8(TAG_COLUMN_FIELD);
+            writer.write_uvar(source_id as u64);
+            writer.write_uvar(column_offset as u64);
+        },
+        &SExpression::If { ref predicate, ref yield_fn } => {
+            writer.write_u8(TAG_IF);
+            write_expression(writer, predicate);
+            write_expression(writer, yield_fn);
+        },
+        &SExpression::UnaryOp { op, ref expr } => {
+            writer.write_u8(TAG_UNARY_OP);
+            writer.write_u8(unary_op_code(op));
+            write_expression(writer, expr);
+        },
+        &SExpression::BinaryOp { op, ref lhs, ref rhs } => {
+            writer.write_u8(TAG_BINARY_OP);
+            writer.write_u8(binary_op_code(op));
+            write_expression(writer, lhs);
+            write_expression(writer, rhs);
+        },
+        &SExpression::AggregateOp { op, distinct, source_id, ref value } => {
+            writer.write_u8(TAG_AGGREGATE_OP);
+            writer.write_u8(aggregate_op_code(op));
+            writer.write_u8(distinct as u8);
+            writer.write_uvar(source_id as u64);
+            write_expression(writer, value);
+        },
+        &SExpression::CountAll { source_id } => {
+            writer.write_u8(TAG_COUNT_ALL);
+            writer.write_uvar(source_id as u64);
+        },
+        &SExpression::RowCount { table } => {
+            writer.write_u8(TAG_ROW_COUNT);
+            write_table(writer, table);
+        },
+        &SExpression::SequenceOp { op, ref sequence } => {
+            writer.write_u8(TAG_SEQUENCE_OP);
+            writer.write_u8(sequence_op_code(op));
+            writer.write_identifier(sequence);
+        },
+        &SExpression::SessionVariable { ref name } => {
+            writer.write_u8(TAG_SESSION_VARIABLE);
+            writer.write_identifier(name);
+        },
+        &SExpression::FunctionOp { op, ref arguments } => {
+            writer.write_u8(TAG_FUNCTION_OP);
+            writer.write_u8(function_op_code(op));
+            write_expressions(writer, arguments);
+        },
+        &SExpression::RowCompare { op, ref lhs, ref rhs } => {
+            writer.write_u8(TAG_ROW_COMPARE);
+            writer.write_u8(binary_op_code(op));
+            write_expressions(writer, lhs);
+            write_expressions(writer, rhs);
+        },
+        &SExpression::SemiJoin { anti, ref values, ref query } => {
+            writer.write_u8(TAG_SEMI_JOIN);
+            writer.write_u8(anti as u8);
+            // A count of 0 or 1 is written as the byte that once said
+            // whether there was a value, so older plans still decode
+            write_expressions(writer, values);
+            write_expression(writer, query);
+        },
+        &SExpression::Value(ref value) => {
+            writer.write_u8(TAG_VALUE);
+            writer.write_variant(value);
+        }
+    }
+}
+
+fn write_window_function(writer: &mut SnapshotWriter, function: &WindowFunction<TempDb>) {
+    match function.op {
+        WindowOp::RowNumber => writer.write_u8(0),
+        WindowOp::Rank => writer.write_u8(1),
+        WindowOp::DenseRank => writer.write_u8(2),
+        WindowOp::Lag => writer.write_u8(3),
+        WindowOp::Lead => writer.write_u8(4),
+        WindowOp::Aggregate(op) => {
+            writer.write_u8(WINDOW_AGGREGATE);
+            writer.write_u8(aggregate_op_code(op));
+        }
+    }
+
+    write_expressions(writer, &function.arguments);
+    write_expressions(writer, &function.partition_by);
+    writer.write_uvar(function.order_by.len() as u64);
+
+    for &(ref value, descending) in &function.order_by {
+        write_expression(writer, value);
+        writer.write_u8(descending as u8);
+    }
+
+    match function.frame {
+        Some(frame) => {
+            writer.write_u8(1);
+            write_frame_bound(writer, frame.start);
+            write_frame_bound(writer, frame.end);
+        },
+        None => writer.write_u8(0)
+    }
+}
+
+fn write_frame_bound(writer: &mut SnapshotWriter, bound: FrameBound) {
+    match bound {
+        FrameBound::UnboundedPreceding => writer.write_u8(0),
+        FrameBound::Preceding(rows) => {
+            writer.write_u8(1);
+            writer.write_uvar(rows);
+        },
+        FrameBound::CurrentRow => writer.write_u8(2),
+        FrameBound::Following(rows) => {
+            writer.write_u8(3);
+            writer.write_uvar(rows);
+        },
+        FrameBound::UnboundedFollowing => writer.write_u8(4)
+    }
+}
+
+/// Decodes a plan encoded by `encode_plan`, with the tables of `db`.
+/// Expressions can't be nested more deeply than `max_depth`.
+pub fn decode_plan<'a>(db: &'a TempDb, buf: &[u8], max_depth: u32) -> Result<QueryPlan<'a, TempDb>, String> {
+    let mut decoder = Decoder {
+        db: db,
+        reader: try!(SnapshotReader::with_header(buf, MAGIC, VERSION, "query plan")),
+        depth: 0,
+        max_depth: max_depth
+    };
+
+    let column_count = try!(decoder.reader.read_uvar());
+    let mut column_names = Vec::new();
+    let mut column_origins = Vec::new();
+
+    for _ in 0..column_count {
+        column_names.push(try!(decoder.reader.read_identifier()));
+
+        column_origins.push(match try!(decoder.reader.read_u8()) {
+            0 => None,
+            kind => {
+                let table = try!(decoder.read_table());
+                let column_offset = try!(decoder.read_u32());
+
+                if column_offset >= table.get_column_count() {
+                    return Err(format!("plan refers to a column that table {} doesn't have", table.name));
+                }
+
+                Some(ColumnOrigin {
+                    table: table,
+                    column_offset: column_offset,
+                    outer_joined: kind == 2
+                })
+            }
+        });
+    }
+
+    let expr = try!(decoder.read_expression());
+
+    if !decoder.reader.is_at_end() {
+        return Err(format!("query plan has trailing data"));
+    }
+
+    Ok(QueryPlan {
+        expr: expr,
+        out_column_names: column_names,
+        out_column_origins: column_origins
+    })
+}
+
+struct Decoder<'a, 'b> {
+    db: &'a TempDb,
+    reader: SnapshotReader<'b>,
+    depth: u32,
+    max_depth: u32
+}
+
+impl<'a, 'b> Decoder<'a, 'b> {
+    fn read_u32(&mut self) -> Result<u32, String> {
+        let value = try!(self.reader.read_uvar());
+
+        if value > u32::max_value() as u64 {
+            Err(format!("plan contains a number that's too large: {}", value))
+        } else {
+            Ok(value as u32)
+        }
+    }
+
+    fn read_bool(&mut self) -> Result<bool, String> {
+        self.reader.read_u8().map(|value| value != 0)
+    }
+
+    /// The table must still have the columns it had when the plan was
+    /// encoded.
+    fn read_table(&mut self) -> Result<&'a Table, String> {
+        let name = try!(self.reader.read_identifier());
+        let column_count = try!(self.read_u32());
+
+        match self.db.find_table_by_name(&name) {
+            Some(table) if table.get_column_count() == column_count => Ok(table),
+            Some(_) => Err(format!("table {} has changed since the plan was encoded", name)),
+            None => Err(format!("plan refers to a table that doesn't exist: {}", name))
+        }
+    }
+
+    fn read_identifier(&mut self) -> Result<Identifier, String> {
+        self.reader.read_identifier()
+    }
+
+    fn read_expressions(&mut self) -> Result<Vec<SExpression<'a, TempDb>>, String> {
+        let count = try!(self.reader.read_uvar());
+        let mut exprs = Vec::new();
+
+        for _ in 0..count {
+            exprs.push(try!(self.read_expression()));
+        }
+
+        Ok(exprs)
+    }
+
+    fn read_boxed_expression(&mut self) -> Result<Box<SExpression<'a, TempDb>>, String> {
+        self.read_expression().map(Box::new)
+    }
+
+    fn read_expression(&mut self) -> Result<SExpression<'a, TempDb>, String> {
+        if self.depth >= self.max_depth {
+            return Err(format!("query plan is nested more than {} deep", self.max_depth));
+        }
+
+        self.depth += 1;
+        let result = self.read_expression_nested();
+        self.depth -= 1;
+        result
+    }
+
+    fn read_expression_nested(&mut self) -> Result<SExpression<'a, TempDb>, String> {
+        Ok(match try!(self.reader.read_u8()) {
+            TAG_SCAN => SExpression::Scan {
+                table: try!(self.read_table()),
+                source_id: try!(self.read_u32()),
+                yield_fn: try!(self.read_boxed_expression())
+            },
+            TAG_TABLE_FUNCTION => SExpression::TableFunction {
+                function: try!(self.read_identifier()),
+                source_id: try!(self.read_u32()),
+                arguments: try!(self.read_expressions()),
+                yield_fn: try!(self.read_boxed_expression())
+            },
+            TAG_MAP => SExpression::Map {
+                source_id: try!(self.read_u32()),
+                yield_in_fn: try!(self.read_boxed_expression()),
+                yield_out_fn: try!(self.read_boxed_expression())
+            },
+            TAG_LEFT_JOIN => SExpression::LeftJoin {
+                source_id: try!(self.read_u32()),
+                column_count: try!(self.read_u32()),
+                yield_in_fn: try!(self.read_boxed_expression()),
+                yield_out_fn: try!(self.read_boxed_expression())
+            },
+            TAG_HASH_JOIN => SExpression::HashJoin {
+                source_id: try!(self.read_u32()),
+                keys: try!(self.read_expressions()),
+                yield_in_fn: try!(self.read_boxed_expression()),
+                yield_out_fn: try!(self.read_boxed_expression())
+            },
+            TAG_HASH_PROBE => SExpression::HashProbe {
+                source_id: try!(self.read_u32()),
+                values: try!(self.read_expressions()),
+                yield_fn: try!(self.read_boxed_expression())
+            },
+            TAG_TEMP_GROUP_BY => SExpression::TempGroupBy {
+                source_id: try!(self.read_u32()),
+                yield_in_fn: try!(self.read_boxed_expression()),
+                group_by_values: try!(self.read_expressions()),
+                yield_out_fn: try!(self.read_boxed_expression())
+            },
+            TAG_WINDOW => {
+                let source_id = try!(self.read_u32());
+                let yield_in_fn = try!(self.read_boxed_expression());
+                let count = try!(self.reader.read_uvar());
+                let mut functions = Vec::new();
+
+                for _ in 0..count {
+                    functions.push(try!(self.read_window_function()));
+                }
+
+                SExpression::Window {
+                    source_id: source_id,
+                    yield_in_fn: yield_in_fn,
+                    functions: functions,
+                    yield_out_fn: try!(self.read_boxed_expression())
+                }
+            },
+            TAG_DISTINCT => SExpression::Distinct {
+                yield_in_fn: try!(self.read_boxed_expression()),
+                column_count: try!(self.read_u32())
+            },
+            TAG_SORT => {
+                let yield_in_fn = try!(self.read_boxed_expression());
+                let column_count = try!(self.read_u32());
+                let count = try!(self.reader.read_uvar());
+                let mut order_by = Vec::new();
+
+                for _ in 0..count {
+                    let column_offset = try!(self.read_u32());
+                    order_by.push((column_offset, try!(self.read_bool())));
+                }
+
+                SExpression::Sort {
+                    yield_in_fn: yield_in_fn,
+                    column_count: column_count,
+                    order_by: order_by
+                }
+            },
+            TAG_LIMIT => SExpression::Limit {
+                yield_in_fn: try!(self.read_boxed_expression()),
+                count: try!(self.reader.read_uvar()),
+                offset: try!(self.reader.read_uvar())
+            },
+            TAG_YIELD => SExpression::Yield {
+                fields: try!(self.read_expressions())
+            },
+            TAG_COLUMN_FIELD => SExpression::ColumnField {
+                source_id: try!(self.read_u32()),
+                column_offset: try!(self.read_u32())
+            },
+            TAG_IF => SExpression::If {
+                predicate: try!(self.read_boxed_expression()),
+                yield_fn: try!(self.read_boxed_expression())
+            },
+            TAG_UNARY_OP => SExpression::UnaryOp {
+                op: try!(unary_op_from_code(try!(self.reader.read_u8()))),
+                expr: try!(self.read_boxed_expression())
+            },
+            TAG_BINARY_OP => SExpression::BinaryOp {
+                op: try!(binary_op_from_code(try!(self.reader.read_u8()))),
+                lhs: try!(self.read_boxed_expression()),
+                rhs: try!(self.read_boxed_expression())
+            },
+            TAG_AGGREGATE_OP => SExpression::AggregateOp {
+                op: try!(aggregate_op_from_code(try!(self.reader.read_u8()))),
+                distinct: if self.reader.version() >= 2 { try!(self.read_bool()) } else { false },
+                source_id: try!(self.read_u32()),
+                value: try!(self.read_boxed_expression())
+            },
+            TAG_COUNT_ALL => SExpression::CountAll {
+                source_id: try!(self.read_u32())
+            },
+            TAG_ROW_COUNT => SExpression::RowCount {
+                table: try!(self.read_table())
+            },
+            TAG_SEQUENCE_OP => SExpression::SequenceOp {
+                op: try!(sequence_op_from_code(try!(self.reader.read_u8()))),
+                sequence: try!(self.read_identifier())
+            },
+            TAG_SESSION_VARIABLE => SExpression::SessionVariable {
+                name: try!(self.read_identifier())
+            },
+            TAG_FUNCTION_OP => {
+                let op = try!(function_op_from_code(try!(self.reader.read_u8())));
+                let arguments = try!(self.read_expressions());
+
+                if !op.accepts_argument_count(arguments.len()) {
+                    return Err(format!("plan calls a function with the wrong number of arguments"));
+                }
+
+                SExpression::FunctionOp {
+                    op: op,
+                    arguments: arguments
+                }
+            },
+            TAG_ROW_COMPARE => {
+                let op = try!(binary_op_from_code(try!(self.reader.read_u8())));
+                let lhs = try!(self.read_expressions());
+                let rhs = try!(self.read_expressions());
+
+                if !op.is_comparison() || lhs.len() != rhs.len() {
+                    return Err(format!("plan compares rows with a bad operator or of different sizes"));
+                }
+
+                SExpression::RowCompare {
+                    op: op,
+                    lhs: lhs,
+                    rhs: rhs
+                }
+            },
+            TAG_SEMI_JOIN => SExpression::SemiJoin {
+                anti: try!(self.read_bool()),
+                values: try!(self.read_expressions()),
+                query: try!(self.read_boxed_expression())
+            },
+            TAG_VALUE => SExpression::Value(try!(self.reader.read_variant())),
+            tag => return Err(format!("plan contains an unknown expression: {}", tag))
+        })
+    }
+
+    fn read_window_function(&mut self) -> Result<WindowFunction<'a, TempDb>, String> {
+        let op = match try!(self.reader.read_u8()) {
+            0 => WindowOp::RowNumber,
+            1 => WindowOp::Rank,
+            2 => WindowOp::DenseRank,
+            3 => WindowOp::Lag,
+            4 => WindowOp::Lead,
+            WINDOW_AGGREGATE => WindowOp::Aggregate(try!(aggregate_op_from_code(try!(self.reader.read_u8())))),
+            code => return Err(format!("plan contains an unknown window function: {}", code))
+        };
+
+        let arguments = try!(self.read_expressions());
+
+        if !op.accepts_argument_count(arguments.len()) {
+            return Err(format!("plan calls a window function with the wrong number of arguments"));
+        }
+
+        let partition_by = try!(self.read_expressions());
+        let count = try!(self.reader.read_uvar());
+        let mut order_by = Vec::new();
+
+        for _ in 0..count {
+            let value = try!(self.read_expression());
+            order_by.push((value, try!(self.read_bool())));
+        }
+
+        let frame = match try!(self.reader.read_u8()) {
+            0 => None,
+            _ => Some(WindowFrame {
+                start: try!(self.read_frame_bound()),
+                end: try!(self.read_frame_bound())
+            })
+        };
+
+        Ok(WindowFunction {
+            op: op,
+            arguments: arguments,
+            partition_by: partition_by,
+            order_by: order_by,
+            frame: frame
+        })
+    }
+
+    fn read_frame_bound(&mut self) -> Result<FrameBound, String> {
+        Ok(match try!(self.reader.read_u8()) {
+            0 => FrameBound::UnboundedPreceding,
+            1 => FrameBound::Preceding(try!(self.reader.read_uvar())),
+            2 => FrameBound::CurrentRow,
+            3 => FrameBound::Following(try!(self.reader.read_uvar())),
+            4 => FrameBound::UnboundedFollowing,
+            code => return Err(format!("plan contains an unknown window frame bound: {}", code))
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::{ExecuteStatementResponse, TempDb};
+    use sqlsyntax::ast::Statement;
+    use sqlsyntax::{parse_statement, parse_statements};
+    use types::Variant;
+
+    /// The rows are sorted, because groups aren't in any particular order
+    fn rows(response: ExecuteStatementResponse) -> (Vec<String>, Vec<Vec<Variant>>) {
+        match response {
+            ExecuteStatementResponse::Select { column_names, rows, .. } => {
+                let mut rows: Vec<_> = rows.map(|row| row.into_vec()).collect();
+                rows.sort_by(|a, b| format!("{:?}", a).cmp(&format!("{:?}", b)));
+                (column_names.into_vec(), rows)
+            },
+            _ => panic!()
+        }
+    }
+
+    fn encode(db: &TempDb, sql: &str) -> Vec<u8> {
+        match parse_statement(sql).unwrap() {
+            Statement::Select(select) => db.encode_select_plan(select).unwrap(),
+            _ => panic!()
+        }
+    }
+
+    #[test]
+    fn test_plan_round_trip() {
+        let mut db = TempDb::new();
+
+        for stmt in parse_statements("CREATE TABLE t (a U32, b STRING NULL);
+            INSERT INTO t VALUES (1, 'x'), (2, NULL), (3, 'x'); SET b = 'x';").unwrap() {
+            db.execute_statement(stmt).unwrap();
+        }
+
+        let queries = [
+            "SELECT a, b FROM t WHERE a > 1 AND b = 'x' OR a = 1",
+            "SELECT b, count(*), sum(a) FROM t GROUP BY b HAVING max(a) >= 2",
+            "SELECT a, -a, hex(b) || 'z' FROM t WHERE EXISTS (SELECT 1 FROM t AS u WHERE u.a = t.a + 1)",
+            "SELECT a, rank() OVER (ORDER BY a DESC), sum(a) OVER (ROWS BETWEEN 1 PRECEDING AND CURRENT ROW) FROM t",
+            "SELECT count(*) FROM t",
+            "SELECT value FROM generate_series(1, 3) WHERE value NOT IN (SELECT a FROM t WHERE a <> 2)",
+            "SELECT a AS c, b FROM t ORDER BY b DESC, c, a + 1",
+            "SELECT a FROM t ORDER BY a DESC LIMIT 1 OFFSET 1",
+            "SELECT a FROM t WHERE b = current_setting('b')",
+            "SELECT t.a, u.b FROM t LEFT JOIN t AS u ON u.a = t.a + 1 CROSS JOIN t AS v WHERE v.a = 1",
+            "SELECT a, b FROM t WHERE (a, b) > (1, 'x') AND (b, a) NOT IN (SELECT u.b, u.a + 1 FROM t AS u)",
+            "SELECT DISTINCT a, b FROM t ORDER BY a DESC LIMIT 2",
+            "SELECT b, count(DISTINCT b), sum(DISTINCT a / 2) FROM t GROUP BY b",
+            "SELECT t.a, u.b, v.a FROM t LEFT JOIN (t AS u JOIN t AS v ON u.a = v.a + 1) ON u.a = t.a + 1"
+        ];
+
+        for query in queries.iter() {
+            let expected = rows(db.execute_statement(parse_statement(query).unwrap()).unwrap());
+            let plan = encode(&db, query);
+            assert_eq!(rows(db.execute_encoded_plan(&plan).unwrap()), expected);
+        }
+
+        let plan = encode(&db, "SELECT a FROM t");
+        assert!(db.execute_encoded_plan(&plan[0..plan.len() - 1]).is_err());
+        assert!(db.execute_encoded_plan(b"llamadb plan\0\x03").is_err());
+
+        // Offsets into a row past its end fail instead of panicking. The
+        // plans end with the column's offset, and with the sort key's offset
+        // and direction.
+        let mut plan = encode(&db, "SELECT a FROM t");
+        *plan.last_mut().unwrap() = 9;
+        assert!(db.execute_encoded_plan(&plan).is_err());
+        let mut plan = encode(&db, "SELECT a FROM t ORDER BY b");
+        let offset = plan.len() - 2;
+        plan[offset] = 9;
+        assert!(db.execute_encoded_plan(&plan).is_err());
+
+        // The table's columns must not change
+        let mut other = TempDb::new();
+        other.execute_statement(parse_statement("CREATE TABLE t (a U32)").unwrap()).unwrap();
+        assert!(other.execute_encoded_plan(&plan).is_err());
+        assert!(TempDb::new().execute_encoded_plan(&plan).is_err());
+    }
+}
@@ -0,0 +1,392 @@
+//! Rewrites the queries of materialized views when a table or column they
+//! depend on is renamed.
+//!
+//! Names are resolved the way the query planner resolves them: a column
+//! reference belongs to the one table of the innermost query that has a
+//! column of that name, and only refers to an outer query's tables if no
+//! table of an inner query has it. References that don't resolve are left
+//! alone.
+
+use identifier::Identifier;
+use sqlsyntax::ast;
+
+use super::tablefunction;
+
+pub enum Rename<'a> {
+    Table {
+        old_name: &'a Identifier,
+        new_name: &'a str
+    },
+    Column {
+        table: &'a Identifier,
+        old_name: &'a Identifier,
+        new_name: &'a str
+    }
+}
+
+/// A table or subquery in a FROM clause
+struct Source {
+    /// The name the source is referred to by: its alias, or its table name
+    reference_name: Identifier,
+    /// Whether the table was given an alias
+    aliased: bool,
+    /// The table's name, or None for a subquery or table function
+    table: Option<Identifier>,
+    columns: Vec<Column>
+}
+
+/// A column of a source, by its name before the rename
+struct Column {
+    name: Identifier,
+    /// The column's name after the rename, if it changes
+    new_name: Option<String>
+}
+
+pub struct Renamer<'a> {
+    rename: Rename<'a>,
+    /// Looks up the column names of a table, as they were before the rename
+    table_columns: &'a Fn(&Identifier) -> Option<Vec<Identifier>>
+}
+
+impl<'a> Renamer<'a> {
+    pub fn new(rename: Rename<'a>, table_columns: &'a Fn(&Identifier) -> Option<Vec<Identifier>>) -> Renamer<'a> {
+        Renamer {
+            rename: rename,
+            table_columns: table_columns
+        }
+    }
+
+    /// Returns the result columns whose names changed, along with their new
+    /// names. Only columns selected with `*` can change their names.
+    pub fn rewrite_query(&self, query: &mut ast::SelectStatement) -> Vec<(Identifier, String)> {
+        self.rewrite_select(query, &mut Vec::new()).into_iter().filter_map(|column| {
+            match column.new_name {
+                Some(new_name) => Some((column.name, new_name)),
+                None => None
+            }
+        }).collect()
+    }
+
+    fn rewrite_select(&self, stmt: &mut ast::SelectStatement, scopes: &mut Vec<Vec<Source>>) -> Vec<Column> {
+        let sources = match stmt.from {
+            Some(ast::From::Cross(ref mut tables)) => {
+                tables.iter_mut().map(|t| self.rewrite_table_or_subquery(t, scopes)).collect()
+            },
+            Some(ast::From::Join(ref mut join)) => {
+                join.parts_mut().0.into_iter().map(|t| self.rewrite_table_or_subquery(t, scopes)).collect()
+            },
+            None => Vec::new()
+        };
+
+        scopes.push(sources);
+
+        if let Some(ast::From::Join(ref mut join)) = stmt.from {
+            for on in join.parts_mut().1 {
+                self.rewrite_expression(on, scopes);
+            }
+        }
+
+        if let Some(ref mut expr) = stmt.where_expr {
+            self.rewrite_expression(expr, scopes);
+        }
+
+        for expr in stmt.group_by.iter_mut() {
+            self.rewrite_expression(expr, scopes);
+        }
+
+        if let Some(ref mut expr) = stmt.having {
+            self.rewrite_expression(expr, scopes);
+        }
+
+        for term in stmt.order_by.iter_mut() {
+            self.rewrite_expression(&mut term.expr, scopes);
+        }
+
+        let mut columns = Vec::new();
+        let mut arbitrary_column_count = 0;
+
+        for column in stmt.result_columns.iter_mut() {
+            match column {
+                &mut ast::SelectColumn::AllColumns => {
+                    let sources = scopes.last().unwrap();
+
+                    columns.extend(sources.iter().flat_map(|s| s.columns.iter()).map(|c| Column {
+                        name: c.name.clone(),
+                        new_name: c.new_name.clone()
+                    }));
+                },
+                &mut ast::SelectColumn::Expr { ref mut expr, ref mut alias } => {
+                    let old_name = match *expr {
+                        ast::Expression::Ident(ref name) if alias.is_none() => Some(name.clone()),
+                        _ => None
+                    };
+
+                    self.rewrite_expression(expr, scopes);
+
+                    // A renamed column keeps its old name in the result, so
+                    // that outer queries and the view's columns are unchanged
+                    if let Some(old_name) = old_name {
+                        if *expr != ast::Expression::Ident(old_name.clone()) {
+                            *alias = Some(old_name);
+                        }
+                    }
+
+                    // The planner names the other result columns _0, _1, ...
+                    let name = match (&*alias, &*expr) {
+                        (&Some(ref name), _) | (&None, &ast::Expression::Ident(ref name)) => new_identifier(name),
+                        _ => {
+                            let name = format!("_{}", arbitrary_column_count);
+                            arbitrary_column_count += 1;
+                            new_identifier(&name)
+                        }
+                    };
+
+                    columns.extend(name.map(|name| Column {
+                        name: name,
+                        new_name: None
+                    }).into_iter());
+                }
+            }
+        }
+
+        scopes.pop();
+
+        columns
+    }
+
+    fn rewrite_table_or_subquery(&self, table_or_subquery: &mut ast::TableOrSubquery, scopes: &mut Vec<Vec<Source>>)
+    -> Source
+    {
+        match table_or_subquery {
+            &mut ast::TableOrSubquery::Subquery { ref mut subquery, ref alias } => {
+                let columns = self.rewrite_select(subquery, scopes);
+
+                Source {
+                    reference_name: new_identifier(alias).unwrap_or(Identifier::new("_").unwrap()),
+                    aliased: true,
+                    table: None,
+                    columns: columns
+                }
+            },
+            &mut ast::TableOrSubquery::Table { ref mut table, ref alias } => {
+                let name = new_identifier(&table.table_name);
+                let column_names = name.as_ref().and_then(|name| (self.table_columns)(name)).unwrap_or(Vec::new());
+
+                let columns = column_names.into_iter().map(|column_name| {
+                    let new_name = match self.rename {
+                        Rename::Column { table, old_name, new_name } => {
+                            if name.as_ref() == Some(table) && &column_name == old_name {
+                                Some(new_name.to_string())
+                            } else {
+                                None
+                            }
+                        },
+                        Rename::Table { .. } => None
+                    };
+
+                    Column {
+                        name: column_name,
+                        new_name: new_name
+                    }
+                }).collect();
+
+                if let Rename::Table { old_name, new_name } = self.rename {
+                    if name.as_ref() == Some(old_name) {
+                        table.table_name = new_name.to_string();
+                    }
+                }
+
+                let reference_name = match *alias {
+                    Some(ref alias) => new_identifier(alias),
+                    None => name.clone()
+                };
+
+                Source {
+                    reference_name: reference_name.unwrap_or(Identifier::new("_").unwrap()),
+                    aliased: alias.is_some(),
+                    table: name,
+                    columns: columns
+                }
+            },
+            &mut ast::TableOrSubquery::Function { ref function, ref mut arguments, ref alias } => {
+                for argument in arguments.iter_mut() {
+                    self.rewrite_expression(argument, scopes);
+                }
+
+                let name = new_identifier(function);
+                let column_names = name.as_ref().and_then(|name| tablefunction::column_names(name)).unwrap_or(Vec::new());
+
+                let reference_name = match *alias {
+                    Some(ref alias) => new_identifier(alias),
+                    None => name
+                };
+
+                Source {
+                    reference_name: reference_name.unwrap_or(Identifier::new("_").unwrap()),
+                    aliased: alias.is_some(),
+                    table: None,
+                    columns: column_names.into_iter().map(|column_name| Column {
+                        name: column_name,
+                        new_name: None
+                    }).collect()
+                }
+            }
+        }
+    }
+
+    fn rewrite_expression(&self, expr: &mut ast::Expression, scopes: &mut Vec<Vec<Source>>) {
+        match expr {
+            &mut ast::Expression::Ident(ref mut column) => {
+                let resolved = new_identifier(column).and_then(|c| resolve(scopes, None, &c));
+
+                if let Some((_, &Column { new_name: Some(ref new_name), .. })) = resolved {
+                    *column = new_name.clone();
+                }
+            },
+            &mut ast::Expression::IdentMember(ref mut qualifier, ref mut column) => {
+                let resolved = match (new_identifier(qualifier), new_identifier(column)) {
+                    (Some(q), Some(c)) => resolve(scopes, Some(&q), &c),
+                    _ => None
+                };
+
+                if let Some((source, resolved_column)) = resolved {
+                    if let Some(ref new_name) = resolved_column.new_name {
+                        *column = new_name.clone();
+                    }
+
+                    if let Rename::Table { old_name, new_name } = self.rename {
+                        if source.table.as_ref() == Some(old_name) && !source.aliased {
+                            *qualifier = new_name.to_string();
+                        }
+                    }
+                }
+            },
+            &mut ast::Expression::FunctionCall { ref mut arguments, .. } => {
+                for argument in arguments.iter_mut() {
+                    self.rewrite_expression(argument, scopes);
+                }
+            },
+            &mut ast::Expression::WindowFunctionCall { ref mut arguments, ref mut window, .. } => {
+                for expr in arguments.iter_mut().chain(window.partition_by.iter_mut()) {
+                    self.rewrite_expression(expr, scopes);
+                }
+                for term in window.order_by.iter_mut() {
+                    self.rewrite_expression(&mut term.expr, scopes);
+                }
+            },
+            &mut ast::Expression::UnaryOp { ref mut expr, .. } => {
+                self.rewrite_expression(expr, scopes);
+            },
+            &mut ast::Expression::BinaryOp { ref mut lhs, ref mut rhs, .. } => {
+                self.rewrite_expression(lhs, scopes);
+                self.rewrite_expression(rhs, scopes);
+            },
+            &mut ast::Expression::Subquery(ref mut subquery) |
+            &mut ast::Expression::Exists { ref mut subquery, .. } => {
+                self.rewrite_select(subquery, scopes);
+            },
+            &mut ast::Expression::InSubquery { ref mut expr, ref mut subquery, .. } => {
+                self.rewrite_expression(expr, scopes);
+                self.rewrite_select(subquery, scopes);
+            },
+            &mut ast::Expression::Row(ref mut exprs) => {
+                for expr in exprs.iter_mut() {
+                    self.rewrite_expression(expr, scopes);
+                }
+            },
+            &mut ast::Expression::StringLiteral(_) |
+            &mut ast::Expression::Number(_) |
+            &mut ast::Expression::Null |
+            &mut ast::Expression::Placeholder(_) |
+            &mut ast::Expression::FunctionCallAggregateAll { .. } => ()
+        }
+    }
+}
+
+fn new_identifier(name: &str) -> Option<Identifier> {
+    Identifier::new(name)
+}
+
+/// Finds the column a reference is to, optionally qualified by a table name
+/// or alias. Fails if the reference is ambiguous.
+fn resolve<'b>(scopes: &'b [Vec<Source>], qualifier: Option<&Identifier>, column: &Identifier)
+-> Option<(&'b Source, &'b Column)>
+{
+    for sources in scopes.iter().rev() {
+        let mut matching_sources = sources.iter()
+            .filter(|source| qualifier.map_or(true, |q| &source.reference_name == q))
+            .peekable();
+
+        // An inner table hides outer tables with the same name
+        let hides_outer_sources = qualifier.is_some() && matching_sources.peek().is_some();
+
+        let mut candidates = matching_sources
+            .flat_map(|source| source.columns.iter().filter(|c| &c.name == column).map(move |c| (source, c)));
+
+        match (candidates.next(), candidates.next()) {
+            (Some(candidate), None) => return Some(candidate),
+            (Some(_), Some(_)) => return None,
+            (None, _) if hides_outer_sources => return None,
+            (None, _) => ()
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Rename, Renamer};
+    use identifier::Identifier;
+    use sqlsyntax::{ast, parse_statement};
+
+    fn rewrite(sql: &str, rename: Rename) -> String {
+        let table_columns = |name: &Identifier| -> Option<Vec<Identifier>> {
+            let columns: &[&str] = match name as &str {
+                "t" => &["a", "b"],
+                "u" => &["a", "c"],
+                _ => return None
+            };
+
+            Some(columns.iter().map(|c| Identifier::new(c).unwrap()).collect())
+        };
+
+        let mut query = match parse_statement(sql).unwrap() {
+            ast::Statement::Select(query) => query,
+            _ => panic!()
+        };
+
+        Renamer::new(rename, &table_columns).rewrite_query(&mut query);
+        query.to_string()
+    }
+
+    #[test]
+    fn test_rename_table() {
+        let t = Identifier::new("t").unwrap();
+        let rename = || Rename::Table { old_name: &t, new_name: "t2" };
+
+        assert_eq!(rewrite("SELECT t.a, b FROM t", rename()), "SELECT t2.a, b FROM t2");
+        assert_eq!(rewrite("SELECT t.a FROM t AS x, u AS t", rename()), "SELECT t.a FROM t2 AS x, u AS t");
+        assert_eq!(rewrite("SELECT (SELECT t.b FROM u) FROM t", rename()), "SELECT (SELECT t2.b FROM u) FROM t2");
+    }
+
+    #[test]
+    fn test_rename_column() {
+        let t = Identifier::new("t").unwrap();
+        let a = Identifier::new("a").unwrap();
+        let rename = || Rename::Column { table: &t, old_name: &a, new_name: "z" };
+
+        assert_eq!(rewrite("SELECT a, b FROM t WHERE a > 1", rename()), "SELECT z AS a, b FROM t WHERE z > 1");
+        assert_eq!(rewrite("SELECT x.a, u.a FROM t AS x, u", rename()), "SELECT x.z, u.a FROM t AS x, u");
+        // Ambiguous references are left alone
+        assert_eq!(rewrite("SELECT a FROM t, u", rename()), "SELECT a FROM t, u");
+        // Columns selected with * take their new names
+        assert_eq!(rewrite("SELECT s.a FROM (SELECT * FROM t) AS s", rename()),
+            "SELECT s.z FROM (SELECT * FROM t) AS s");
+        assert_eq!(rewrite("SELECT c FROM u WHERE c > (SELECT t.a FROM t)", rename()),
+            "SELECT c FROM u WHERE c > (SELECT t.z FROM t)");
+        // Inner queries' columns hide outer queries' columns of the same name
+        assert_eq!(rewrite("SELECT c FROM u WHERE a IN (SELECT a FROM t)", rename()),
+            "SELECT c FROM u WHERE a IN (SELECT z AS a FROM t)");
+    }
+}
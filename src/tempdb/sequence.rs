@@ -0,0 +1,75 @@
+use identifier::Identifier;
+use std::cell::Cell;
+
+/// A named counter. Values are handed out by `next_value`, even while the
+/// database is being read by a query.
+#[derive(Debug)]
+pub struct Sequence {
+    pub name: Identifier,
+    pub increment: i64,
+
+    /// The value the next call to `next_value` returns, or None once the
+    /// sequence is exhausted
+    next: Cell<Option<i64>>,
+    /// The value most recently returned by `next_value`
+    current: Cell<Option<i64>>
+}
+
+impl Sequence {
+    pub fn new(name: Identifier, next: Option<i64>, increment: i64) -> Sequence {
+        Sequence {
+            name: name,
+            increment: increment,
+            next: Cell::new(next),
+            current: Cell::new(None)
+        }
+    }
+
+    /// Hands out the next value. The last value that fits an i64 is handed
+    /// out too, and only the call after it fails.
+    pub fn next_value(&self) -> Result<i64, String> {
+        match self.next.get() {
+            Some(value) => {
+                self.next.set(value.checked_add(self.increment));
+                self.current.set(Some(value));
+                Ok(value)
+            },
+            None => Err(format!("sequence {} has reached its limit", self.name))
+        }
+    }
+
+    /// The value the next call to `next_value` returns, without using it up.
+    /// Returns None if the sequence is exhausted.
+    pub fn peek_next_value(&self) -> Option<i64> {
+        self.next.get()
+    }
+
+    pub fn current_value(&self) -> Result<i64, String> {
+        self.current.get().ok_or(format!("nextval has not been called for sequence {}", self.name))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use identifier::Identifier;
+    use std::i64;
+    use super::Sequence;
+
+    #[test]
+    fn test_sequence_limit() {
+        let sequence = Sequence::new(Identifier::new("s").unwrap(), Some(i64::MAX - 1), 1);
+
+        assert_eq!(sequence.next_value(), Ok(i64::MAX - 1));
+        assert_eq!(sequence.next_value(), Ok(i64::MAX));
+        assert_eq!(sequence.peek_next_value(), None);
+        assert!(sequence.next_value().is_err());
+        assert!(sequence.next_value().is_err());
+        assert_eq!(sequence.current_value(), Ok(i64::MAX));
+
+        let sequence = Sequence::new(Identifier::new("s").unwrap(), Some(i64::MIN + 2), -2);
+
+        assert_eq!(sequence.next_value(), Ok(i64::MIN + 2));
+        assert_eq!(sequence.next_value(), Ok(i64::MIN));
+        assert!(sequence.next_value().is_err());
+    }
+}
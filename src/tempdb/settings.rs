@@ -0,0 +1,286 @@
+//! Settings that tune the database's behavior. They can be read and changed
+//! from SQL with `PRAGMA name` and `PRAGMA name = value`.
+
+use std::{i64, u32};
+
+use identifier::{CaseFolding, Identifier};
+use sqlsyntax::SqlMode;
+use types::Variant;
+
+pub struct Settings {
+    /// How the names of new tables, columns and result columns are displayed
+    pub case_folding: CaseFolding,
+    /// Statements that take at least this many milliseconds are reported to
+    /// the slow query sink. None if they aren't reported.
+    pub slow_query_threshold: Option<u64>,
+    /// How deeply a query's expressions and subqueries can be nested
+    pub max_query_depth: u32,
+    /// How deeply a compiled query plan can be nested when it's executed
+    pub max_plan_depth: u32,
+    /// How many bytes a query can hold on to while it's executed, such as
+    /// its results and the rows it groups. None if there's no limit.
+    pub query_memory_limit: Option<u64>,
+    pub synchronous: Synchronous,
+    /// What double quotes mean, and whether inserted values are converted
+    /// to their column's type when they don't fit it exactly
+    pub sql_mode: SqlMode
+}
+
+/// How much the database waits for saved files to reach the disk
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Synchronous {
+    /// Saved files are left to the operating system to write. A crash or
+    /// power loss shortly after saving can lose or corrupt the file.
+    Off,
+    /// The file's contents are synced before it replaces the previous file,
+    /// but the rename may still be lost.
+    Normal,
+    /// The directory is synced after the rename as well, so a save that
+    /// succeeded survives a power loss.
+    Full
+}
+
+impl Settings {
+    pub fn new() -> Settings {
+        Settings {
+            case_folding: CaseFolding::Lower,
+            slow_query_threshold: None,
+            max_query_depth: 100,
+            max_plan_depth: 200,
+            query_memory_limit: None,
+            synchronous: Synchronous::Full,
+            sql_mode: SqlMode::Permissive
+        }
+    }
+
+    pub fn get(&self, name: &Identifier) -> Result<Variant, String> {
+        match name as &str {
+            "case_folding" => {
+                let value = match self.case_folding {
+                    CaseFolding::Lower => "lower",
+                    CaseFolding::Upper => "upper",
+                    CaseFolding::Preserve => "preserve"
+                };
+                Ok(Variant::StringLiteral(value.to_string()))
+            },
+            "slow_query_threshold" => Ok(match self.slow_query_threshold {
+                Some(ms) => Variant::UnsignedInteger(ms),
+                None => Variant::StringLiteral("off".to_string())
+            }),
+            "max_query_depth" => Ok(Variant::UnsignedInteger(self.max_query_depth as u64)),
+            "max_plan_depth" => Ok(Variant::UnsignedInteger(self.max_plan_depth as u64)),
+            "query_memory_limit" => Ok(match self.query_memory_limit {
+                Some(bytes) => Variant::UnsignedInteger(bytes),
+                None => Variant::StringLiteral("off".to_string())
+            }),
+            "synchronous" => {
+                let value = match self.synchronous {
+                    Synchronous::Off => "off",
+                    Synchronous::Normal => "normal",
+                    Synchronous::Full => "full"
+                };
+                Ok(Variant::StringLiteral(value.to_string()))
+            },
+            "sql_mode" => {
+                let value = match self.sql_mode {
+                    SqlMode::Permissive => "permissive",
+                    SqlMode::Strict => "strict"
+                };
+                Ok(Variant::StringLiteral(value.to_string()))
+            },
+            _ => Err(unknown_setting(name))
+        }
+    }
+
+    pub fn set(&mut self, name: &Identifier, value: Variant) -> Result<(), String> {
+        match name as &str {
+            "case_folding" => {
+                self.case_folding = match &try!(keyword(name, &value)) as &str {
+                    "lower" => CaseFolding::Lower,
+                    "upper" => CaseFolding::Upper,
+                    "preserve" => CaseFolding::Preserve,
+                    _ => return Err(bad_value(name, &value, "lower, upper, or preserve"))
+                };
+                Ok(())
+            },
+            "slow_query_threshold" => {
+                self.slow_query_threshold = match value {
+                    Variant::StringLiteral(ref s) if s.to_lowercase() == "off" => None,
+                    // Statements are timed as a signed number of milliseconds
+                    Variant::UnsignedInteger(ms) if ms <= i64::MAX as u64 => Some(ms),
+                    Variant::SignedInteger(ms) if ms >= 0 => Some(ms as u64),
+                    value => return Err(bad_value(name, &value, &format!("milliseconds up to {}, or off", i64::MAX)))
+                };
+                Ok(())
+            },
+            "max_query_depth" => {
+                self.max_query_depth = try!(depth(name, &value));
+                Ok(())
+            },
+            "max_plan_depth" => {
+                self.max_plan_depth = try!(depth(name, &value));
+                Ok(())
+            },
+            "query_memory_limit" => {
+                self.query_memory_limit = match value {
+                    Variant::StringLiteral(ref s) if s.to_lowercase() == "off" => None,
+                    Variant::UnsignedInteger(bytes) => Some(bytes),
+                    Variant::SignedInteger(bytes) if bytes >= 0 => Some(bytes as u64),
+                    value => return Err(bad_value(name, &value, "bytes or off"))
+                };
+                Ok(())
+            },
+            "synchronous" => {
+                self.synchronous = match &try!(keyword(name, &value)) as &str {
+                    "off" => Synchronous::Off,
+                    "normal" => Synchronous::Normal,
+                    "full" => Synchronous::Full,
+                    _ => return Err(bad_value(name, &value, "off, normal, or full"))
+                };
+                Ok(())
+            },
+            "sql_mode" => {
+                self.sql_mode = match &try!(keyword(name, &value)) as &str {
+                    "permissive" => SqlMode::Permissive,
+                    "strict" => SqlMode::Strict,
+                    _ => return Err(bad_value(name, &value, "permissive or strict"))
+                };
+                Ok(())
+            },
+            _ => Err(unknown_setting(name))
+        }
+    }
+}
+
+/// Settings are usually set to a word, such as `PRAGMA case_folding = upper`.
+fn keyword(name: &Identifier, value: &Variant) -> Result<String, String> {
+    match value {
+        &Variant::StringLiteral(ref s) => Ok(s.to_lowercase()),
+        _ => Err(bad_value(name, value, "a word"))
+    }
+}
+
+fn depth(name: &Identifier, value: &Variant) -> Result<u32, String> {
+    match value {
+        &Variant::UnsignedInteger(n) if n >= 1 && n <= u32::MAX as u64 => Ok(n as u32),
+        &Variant::SignedInteger(n) if n >= 1 && n <= u32::MAX as i64 => Ok(n as u32),
+        _ => Err(bad_value(name, value, "a positive integer"))
+    }
+}
+
+fn unknown_setting(name: &Identifier) -> String {
+    format!("unknown setting {}", name)
+}
+
+fn bad_value(name: &Identifier, value: &Variant, expected: &str) -> String {
+    format!("{} is not a valid value for {}; expected {}", value, name, expected)
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::TempDb;
+    use sqlsyntax::{SqlMode, parse_statements_with_mode};
+
+    fn execute(db: &mut TempDb, sql: &str) -> Result<(), String> {
+        for stmt in parse_statements_with_mode(sql, db.sql_mode()).unwrap() {
+            try!(db.execute_statement(stmt));
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_sql_mode() {
+        let mut db = TempDb::new();
+        execute(&mut db, "CREATE TABLE t (a U8, b I16 NULL, c STRING NULL, d F64 NULL);").unwrap();
+
+        // Values are truncated or converted to the column's type
+        execute(&mut db, "INSERT INTO t (a, c) VALUES (300, \"text\");").unwrap();
+        execute(&mut db, "INSERT INTO t (a, b, c) VALUES (1.5, -1, 2);").unwrap();
+
+        execute(&mut db, "PRAGMA sql_mode = strict;").unwrap();
+        assert_eq!(db.sql_mode(), SqlMode::Strict);
+
+        execute(&mut db, "INSERT INTO t (a, b, c, d) VALUES (255, -32768, 'text', 1);").unwrap();
+        assert!(execute(&mut db, "INSERT INTO t (a) VALUES (256);").is_err());
+        assert!(execute(&mut db, "INSERT INTO t (a) VALUES (-1);").is_err());
+        assert!(execute(&mut db, "INSERT INTO t (a) VALUES (1.5);").is_err());
+        assert!(execute(&mut db, "INSERT INTO t (a, b) VALUES (1, -32769);").is_err());
+        assert!(execute(&mut db, "INSERT INTO t (a, c) VALUES (1, 2);").is_err());
+
+        // Double quotes are identifiers
+        execute(&mut db, "INSERT INTO \"t\" (\"a\") VALUES (2);").unwrap();
+        assert!(execute(&mut db, "INSERT INTO t (a, c) VALUES (3, \"text\");").is_err());
+
+        assert!(execute(&mut db, "PRAGMA sql_mode = ansi;").is_err());
+    }
+
+    #[test]
+    fn test_synchronous() {
+        use std::env;
+        use std::fs;
+        use identifier::Identifier;
+        use types::Variant;
+        use super::Synchronous;
+
+        let mut db = TempDb::new();
+        let name = Identifier::new("synchronous").unwrap();
+        assert_eq!(db.settings.get(&name), Ok(Variant::StringLiteral("full".to_string())));
+
+        execute(&mut db, "PRAGMA synchronous = off;").unwrap();
+        assert_eq!(db.settings.synchronous, Synchronous::Off);
+        execute(&mut db, "PRAGMA synchronous = NORMAL;").unwrap();
+        assert_eq!(db.settings.get(&name), Ok(Variant::StringLiteral("normal".to_string())));
+
+        assert!(execute(&mut db, "PRAGMA synchronous = 1;").is_err());
+        assert!(execute(&mut db, "PRAGMA synchronous = always;").is_err());
+        assert_eq!(db.settings.synchronous, Synchronous::Normal);
+
+        // Saving works whether or not the file is synced
+        let path = env::temp_dir().join("llamadb-test-synchronous");
+        execute(&mut db, "CREATE TABLE t (a U32);").unwrap();
+
+        for &synchronous in &[Synchronous::Off, Synchronous::Normal, Synchronous::Full] {
+            db.set_synchronous(synchronous);
+            db.save_to(&path).unwrap();
+            assert!(TempDb::open(&path).is_ok());
+        }
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_user_version() {
+        let migrations = [
+            "CREATE TABLE t (a U32);",
+            "CREATE TABLE u (b U32); INSERT INTO u (b) VALUES (1);"
+        ];
+
+        let mut db = TempDb::new();
+        assert_eq!(db.user_version(), 0);
+        assert_eq!(db.migrate(&migrations[..1]), Ok(1));
+        assert_eq!(db.migrate(&migrations), Ok(2));
+        assert_eq!(db.migrate(&migrations), Ok(2));
+        assert!(db.migrate(&migrations[..1]).is_err());
+
+        execute(&mut db, "PRAGMA user_version = 7;").unwrap();
+        assert_eq!(db.user_version(), 7);
+        assert!(execute(&mut db, "PRAGMA user_version = -1;").is_err());
+        assert!(execute(&mut db, "PRAGMA user_version = off;").is_err());
+
+        let mut opened = TempDb::new();
+        opened.load_snapshot(&db.snapshot()).unwrap();
+        assert_eq!(opened.user_version(), 7);
+
+        // A failed migration is undone, but the ones before it stay applied
+        let mut db = TempDb::new();
+        let result = db.migrate(&[
+            "CREATE TABLE t (a U32);",
+            "CREATE TABLE u (b U32); INSERT INTO u (b) VALUES (1); INSERT INTO v (c) VALUES (1);"
+        ]);
+        assert!(result.is_err());
+        assert_eq!(db.user_version(), 1);
+        assert!(execute(&mut db, "SELECT * FROM t;").is_ok());
+        assert!(execute(&mut db, "SELECT * FROM u;").is_err());
+    }
+}
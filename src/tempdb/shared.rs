@@ -0,0 +1,216 @@
+//! Named in-memory databases that can be opened from several handles, even
+//! on different threads.
+//!
+//! A database exists as long as a handle to it does. Opening a name whose
+//! handles have all been dropped creates a new, empty database.
+//!
+//! Handles share the tables, sequences, user version and change log. Each
+//! handle has its own session, like a connection: its temporary tables,
+//! settings and session variables. The session is swapped into the database
+//! while the handle holds the lock.
+
+use std::collections::HashMap;
+use std::mem;
+use std::sync::{Arc, Mutex, Once, ONCE_INIT, Weak};
+
+use identifier::Identifier;
+use sqlsyntax::ast;
+use types::Variant;
+
+use super::{ExecuteStatementResult, TempDb};
+use super::settings::Settings;
+use super::table::Table;
+
+type Registry = Mutex<HashMap<String, Weak<Mutex<TempDb>>>>;
+
+static REGISTRY_INIT: Once = ONCE_INIT;
+static mut REGISTRY: *const Registry = 0 as *const Registry;
+
+fn registry() -> &'static Registry {
+    unsafe {
+        REGISTRY_INIT.call_once(|| {
+            let registry: Box<Registry> = Box::new(Mutex::new(HashMap::new()));
+            REGISTRY = mem::transmute(registry);
+        });
+
+        &*REGISTRY
+    }
+}
+
+/// The state that belongs to a handle rather than to the database
+struct Session {
+    temp_tables: Vec<Table>,
+    settings: Settings,
+    variables: HashMap<Identifier, Variant>
+}
+
+impl Session {
+    fn new() -> Session {
+        Session {
+            temp_tables: Vec::new(),
+            settings: Settings::new(),
+            variables: HashMap::new()
+        }
+    }
+
+    /// Exchanges the session with the one the database is using.
+    fn swap(&mut self, db: &mut TempDb) {
+        mem::swap(&mut self.temp_tables, &mut db.temp_tables);
+        mem::swap(&mut self.settings, &mut db.settings);
+        mem::swap(&mut self.variables, &mut db.variables);
+    }
+}
+
+/// A handle to a named in-memory database. Cloning the handle shares the
+/// database, just like opening the same name again, and starts a new
+/// session.
+pub struct SharedTempDb {
+    name: String,
+    db: Arc<Mutex<TempDb>>,
+    session: Mutex<Session>
+}
+
+impl Clone for SharedTempDb {
+    fn clone(&self) -> SharedTempDb {
+        SharedTempDb {
+            name: self.name.clone(),
+            db: self.db.clone(),
+            session: Mutex::new(Session::new())
+        }
+    }
+}
+
+impl SharedTempDb {
+    /// Opens the database with this name, creating it if no handle to it
+    /// exists.
+    pub fn open(name: &str) -> SharedTempDb {
+        let mut databases = registry().lock().unwrap();
+
+        let existing = databases.get(name).and_then(|db| db.upgrade());
+
+        let db = match existing {
+            Some(db) => db,
+            None => {
+                // Forget the databases that no longer have handles
+                let dropped: Vec<String> = databases.iter().filter(|&(_, db)| db.upgrade().is_none())
+                    .map(|(name, _)| name.clone()).collect();
+
+                for name in dropped {
+                    databases.remove(&name);
+                }
+
+                let db = Arc::new(Mutex::new(TempDb::new()));
+                databases.insert(name.to_string(), Arc::downgrade(&db));
+                db
+            }
+        };
+
+        SharedTempDb {
+            name: name.to_string(),
+            db: db,
+            session: Mutex::new(Session::new())
+        }
+    }
+
+    pub fn name(&self) -> &str { &self.name }
+
+    /// Locks the database and calls `f` with it, so that a series of
+    /// statements can be executed without other handles seeing or changing
+    /// the database in between. The database uses this handle's session.
+    ///
+    /// Panics if a thread panicked while holding the lock.
+    pub fn with_lock<F, T>(&self, f: F) -> T where F: FnOnce(&mut TempDb) -> T {
+        let mut session = self.session.lock().unwrap();
+        let mut db = self.db.lock().unwrap();
+
+        session.swap(&mut *db);
+        let result = f(&mut *db);
+        session.swap(&mut *db);
+        result
+    }
+
+    /// Executes a statement while holding the lock. The rows of a SELECT
+    /// have been computed by the time the lock is released.
+    pub fn execute_statement(&self, stmt: ast::Statement) -> ExecuteStatementResult<'static> {
+        self.with_lock(|db| db.execute_statement(stmt))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::SharedTempDb;
+    use super::super::ExecuteStatementResponse;
+    use sqlsyntax::parse_statement;
+    use std::thread;
+
+    fn execute(db: &SharedTempDb, sql: &str) -> Option<u64> {
+        match db.execute_statement(parse_statement(sql).unwrap()).unwrap() {
+            ExecuteStatementResponse::Select { rows, .. } => Some(rows.count() as u64),
+            _ => None
+        }
+    }
+
+    fn fails(db: &SharedTempDb, sql: &str) -> bool {
+        db.execute_statement(parse_statement(sql).unwrap()).is_err()
+    }
+
+    #[test]
+    fn test_shared_tempdb() {
+        let db = SharedTempDb::open("test_shared_tempdb");
+        execute(&db, "CREATE TABLE t (a U32);");
+
+        let threads: Vec<_> = (0..4).map(|i| {
+            thread::spawn(move || {
+                let db = SharedTempDb::open("test_shared_tempdb");
+                execute(&db, &format!("INSERT INTO t VALUES ({});", i));
+            })
+        }).collect();
+
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        assert_eq!(execute(&db, "SELECT a FROM t;"), Some(4));
+
+        // Other names are other databases
+        let other = SharedTempDb::open("test_shared_tempdb_other");
+        assert!(fails(&other, "SELECT a FROM t;"));
+
+        // The database is dropped along with its last handle
+        drop(db);
+        let db = SharedTempDb::open("test_shared_tempdb");
+        assert!(fails(&db, "SELECT a FROM t;"));
+    }
+
+    #[test]
+    fn test_shared_tempdb_sessions() {
+        use sqlsyntax::SqlMode;
+
+        let db = SharedTempDb::open("test_shared_tempdb_sessions");
+        let other = SharedTempDb::open("test_shared_tempdb_sessions");
+
+        execute(&db, "CREATE TABLE t (a U32);");
+        execute(&db, "CREATE TEMPORARY TABLE scratch (a U32);");
+        execute(&db, "SET tenant = 'acme';");
+        execute(&db, "PRAGMA sql_mode = strict;");
+
+        // Tables are shared, but temporary tables, variables and settings
+        // belong to the handle
+        assert_eq!(execute(&other, "SELECT a FROM t;"), Some(0));
+        assert!(fails(&other, "SELECT a FROM scratch;"));
+        assert!(fails(&other, "SELECT current_setting('tenant');"));
+        assert_eq!(other.with_lock(|db| db.sql_mode()), SqlMode::Permissive);
+
+        assert_eq!(execute(&db, "SELECT a FROM scratch;"), Some(0));
+        assert_eq!(execute(&db, "SELECT current_setting('tenant');"), Some(1));
+        assert_eq!(db.with_lock(|db| db.sql_mode()), SqlMode::Strict);
+
+        // A temporary table hides the shared table only for its handle
+        execute(&other, "CREATE TEMPORARY TABLE t (b U32);");
+        assert!(fails(&db, "SELECT b FROM t;"));
+        assert_eq!(execute(&db, "SELECT a FROM t;"), Some(0));
+
+        // A clone starts its own session
+        assert!(fails(&db.clone(), "SELECT a FROM scratch;"));
+    }
+}
@@ -0,0 +1,316 @@
+//! The file format used by `TempDb::save_to` and `TempDb::open`.
+//!
+//! A snapshot starts with a magic string, a format version and the user
+//! version set by `PRAGMA user_version`, followed by the tables, the
+//! sequences and the change log's LSN. Numbers and lengths are written as
+//! uvars. Rows are written as their keys, in the format described in `table`,
+//! so they're loaded without being decoded. Their checksums are checked as
+//! they're loaded.
+//!
+//! Temporary tables aren't saved, and neither are settings, which belong to
+//! the connection rather than to the database.
+
+use std::borrow::Cow;
+
+use byteutils;
+use columnvalueops::ColumnValueOps;
+use identifier::Identifier;
+use types::{DbType, Variant, MAX_FIXED_BYTES};
+
+pub const MAGIC: &'static [u8] = b"llamadb tempdb\0";
+pub const VERSION: u64 = 1;
+
+/// A table's kind, written before its columns
+pub const KIND_TABLE: u8 = 0;
+pub const KIND_MATERIALIZED_VIEW: u8 = 1;
+pub const KIND_VIRTUAL_TABLE: u8 = 2;
+
+pub struct SnapshotWriter {
+    pub buf: Vec<u8>
+}
+
+impl SnapshotWriter {
+    pub fn new() -> SnapshotWriter {
+        SnapshotWriter::with_header(MAGIC, VERSION)
+    }
+
+    /// A writer for another format built from the same values, such as the
+    /// change log
+    pub fn with_header(magic: &[u8], version: u64) -> SnapshotWriter {
+        let mut writer = SnapshotWriter::without_header();
+
+        writer.buf.push_all(magic);
+        writer.write_uvar(version);
+        writer
+    }
+
+    /// A writer for values kept inside something else, such as a row's key
+    pub fn without_header() -> SnapshotWriter {
+        SnapshotWriter {
+            buf: Vec::new()
+        }
+    }
+
+    pub fn write_u8(&mut self, value: u8) {
+        self.buf.push(value);
+    }
+
+    pub fn write_uvar(&mut self, value: u64) {
+        let mut buf = [0; 10];
+        let len = byteutils::write_uvar(value, &mut buf).unwrap();
+        self.buf.push_all(&buf[0..len]);
+    }
+
+    pub fn write_i64(&mut self, value: i64) {
+        let mut buf = [0; 8];
+        byteutils::write_sdbinteger(value, &mut buf);
+        self.buf.push_all(&buf);
+    }
+
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        self.write_uvar(bytes.len() as u64);
+        self.buf.push_all(bytes);
+    }
+
+    pub fn write_str(&mut self, s: &str) {
+        self.write_bytes(s.as_bytes());
+    }
+
+    pub fn write_optional_str(&mut self, s: Option<&str>) {
+        match s {
+            Some(s) => {
+                self.write_u8(1);
+                self.write_str(s);
+            },
+            None => self.write_u8(0)
+        }
+    }
+
+    /// Identifiers are written as displayed and then normalized, since one
+    /// can't always be derived from the other.
+    pub fn write_identifier(&mut self, ident: &Identifier) {
+        self.write_str(&ident.to_string());
+        self.write_str(ident);
+    }
+
+    pub fn write_dbtype(&mut self, dbtype: DbType) {
+        match dbtype {
+            DbType::Null => self.write_u8(0),
+            DbType::ByteDynamic => self.write_u8(1),
+            DbType::ByteFixed(n) => {
+                self.write_u8(2);
+                self.write_uvar(n);
+            },
+            DbType::Integer { signed, bytes } => {
+                self.write_u8(if signed { 3 } else { 4 });
+                self.write_u8(bytes);
+            },
+            DbType::F64 => self.write_u8(5),
+            DbType::String => self.write_u8(6)
+        }
+    }
+
+    pub fn write_variant(&mut self, value: &Variant) {
+        let dbtype = value.get_dbtype();
+        self.write_dbtype(dbtype);
+
+        // NULL has no data
+        if dbtype != DbType::Null {
+            let bytes = value.clone().to_bytes(dbtype).unwrap();
+            self.write_bytes(&bytes);
+        }
+    }
+}
+
+pub struct SnapshotReader<'a> {
+    buf: &'a [u8],
+    offset: usize,
+    version: u64
+}
+
+impl<'a> SnapshotReader<'a> {
+    /// Fails if the buffer isn't a snapshot, or was written by a newer
+    /// version of llamadb.
+    pub fn new(buf: &'a [u8]) -> Result<SnapshotReader<'a>, String> {
+        SnapshotReader::with_header(buf, MAGIC, VERSION, "database")
+    }
+
+    /// A reader for a format written with `SnapshotWriter::with_header`.
+    /// `format` names the format in errors.
+    pub fn with_header(buf: &'a [u8], magic: &[u8], max_version: u64, format: &str)
+    -> Result<SnapshotReader<'a>, String>
+    {
+        if !buf.starts_with(magic) {
+            return Err(format!("not a llamadb {}", format));
+        }
+
+        let mut reader = SnapshotReader {
+            buf: buf,
+            offset: magic.len(),
+            version: 0
+        };
+
+        let version = try!(reader.read_uvar());
+        if version == 0 || version > max_version {
+            return Err(format!("unsupported {} format version {}", format, version));
+        }
+
+        reader.version = version;
+        Ok(reader)
+    }
+
+    /// A reader for values written by `SnapshotWriter::without_header`,
+    /// which have no format version
+    pub fn without_header(buf: &'a [u8]) -> SnapshotReader<'a> {
+        SnapshotReader {
+            buf: buf,
+            offset: 0,
+            version: 0
+        }
+    }
+
+    /// The format version the snapshot was written with
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    pub fn is_at_end(&self) -> bool {
+        self.offset == self.buf.len()
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], String> {
+        if self.buf.len() - self.offset < len {
+            return Err(truncated());
+        }
+
+        let bytes = &self.buf[self.offset..self.offset + len];
+        self.offset += len;
+        Ok(bytes)
+    }
+
+    pub fn read_u8(&mut self) -> Result<u8, String> {
+        self.take(1).map(|bytes| bytes[0])
+    }
+
+    pub fn read_uvar(&mut self) -> Result<u64, String> {
+        let (len, value) = try!(byteutils::read_uvar(&self.buf[self.offset..]).ok_or(truncated()));
+        self.offset += len;
+        Ok(value)
+    }
+
+    pub fn read_i64(&mut self) -> Result<i64, String> {
+        self.take(8).map(byteutils::read_sdbinteger)
+    }
+
+    pub fn read_bytes(&mut self) -> Result<&'a [u8], String> {
+        let len = try!(self.read_uvar());
+        self.take(len as usize)
+    }
+
+    pub fn read_string(&mut self) -> Result<String, String> {
+        let bytes = try!(self.read_bytes());
+        String::from_utf8(bytes.to_vec()).map_err(|_| format!("database contains a malformed string"))
+    }
+
+    pub fn read_optional_string(&mut self) -> Result<Option<String>, String> {
+        match try!(self.read_u8()) {
+            0 => Ok(None),
+            _ => self.read_string().map(Some)
+        }
+    }
+
+    pub fn read_identifier(&mut self) -> Result<Identifier, String> {
+        let value = try!(self.read_string());
+        let normalized = try!(self.read_string());
+        Identifier::from_parts(&value, &normalized)
+            .ok_or(format!("database contains a bad name: {}", value))
+    }
+
+    /// Fails on sizes that no column could be declared with, since the
+    /// values of such a type couldn't be read or written.
+    pub fn read_dbtype(&mut self) -> Result<DbType, String> {
+        Ok(match try!(self.read_u8()) {
+            0 => DbType::Null,
+            1 => DbType::ByteDynamic,
+            2 => match try!(self.read_uvar()) {
+                n if n <= MAX_FIXED_BYTES => DbType::ByteFixed(n),
+                n => return Err(format!("database contains a byte array type with a bad size: {}", n))
+            },
+            tag @ 3 | tag @ 4 => match try!(self.read_u8()) {
+                bytes if bytes >= 1 && bytes <= 8 => DbType::Integer { signed: tag == 3, bytes: bytes },
+                bytes => return Err(format!("database contains an integer type with a bad width: {}", bytes))
+            },
+            5 => DbType::F64,
+            6 => DbType::String,
+            tag => return Err(format!("database contains an unknown type: {}", tag))
+        })
+    }
+
+    pub fn read_variant(&mut self) -> Result<Variant, String> {
+        let dbtype = try!(self.read_dbtype());
+
+        if dbtype == DbType::Null {
+            Ok(Variant::Null)
+        } else {
+            let bytes = try!(self.read_bytes());
+            ColumnValueOps::from_bytes(dbtype, Cow::Borrowed(bytes))
+                .map_err(|()| format!("database contains a malformed {:?} value", dbtype))
+        }
+    }
+}
+
+fn truncated() -> String {
+    format!("database file is truncated")
+}
+
+#[cfg(test)]
+mod test {
+    use super::{SnapshotReader, SnapshotWriter};
+    use std::u64;
+    use types::{DbType, Variant};
+
+    #[test]
+    fn test_snapshot_round_trip() {
+        let mut writer = SnapshotWriter::new();
+        writer.write_uvar(300);
+        writer.write_i64(-5);
+        writer.write_str("héllo");
+        writer.write_optional_str(Some("comment"));
+        writer.write_optional_str(None);
+        writer.write_dbtype(DbType::Integer { signed: false, bytes: 2 });
+        writer.write_variant(&Variant::StringLiteral("a.csv".to_string()));
+        writer.write_variant(&Variant::Null);
+
+        let mut reader = SnapshotReader::new(&writer.buf).unwrap();
+        assert_eq!(reader.read_uvar().unwrap(), 300);
+        assert_eq!(reader.read_i64().unwrap(), -5);
+        assert_eq!(reader.read_string().unwrap(), "héllo");
+        assert_eq!(reader.read_optional_string().unwrap(), Some("comment".to_string()));
+        assert_eq!(reader.read_optional_string().unwrap(), None);
+        assert_eq!(reader.read_dbtype().unwrap(), DbType::Integer { signed: false, bytes: 2 });
+        assert_eq!(reader.read_variant().unwrap(), Variant::StringLiteral("a.csv".to_string()));
+        assert_eq!(reader.read_variant().unwrap(), Variant::Null);
+        assert!(reader.is_at_end());
+        assert!(reader.read_u8().is_err());
+
+        let mut writer = SnapshotWriter::new();
+        writer.write_str("truncated");
+        let truncated = &writer.buf[0..writer.buf.len() - 1];
+        assert!(SnapshotReader::new(truncated).unwrap().read_string().is_err());
+
+        assert!(SnapshotReader::new(b"SQLite format 3\0").is_err());
+
+        // Integers are 1 to 8 bytes wide
+        for &(tag, width) in [(3, 0), (4, 9), (3, 255)].iter() {
+            let mut writer = SnapshotWriter::new();
+            writer.write_u8(tag);
+            writer.write_u8(width);
+            assert!(SnapshotReader::new(&writer.buf).unwrap().read_dbtype().is_err());
+        }
+
+        let mut writer = SnapshotWriter::new();
+        writer.write_u8(2);
+        writer.write_uvar(u64::MAX);
+        assert!(SnapshotReader::new(&writer.buf).unwrap().read_dbtype().is_err());
+    }
+}
@@ -1,10 +1,26 @@
 use byteutils;
-use types::DbType;
+use columnvalueops::ColumnValueOps;
+use types::{DbType, Variant};
 use databaseinfo::{ColumnInfo, TableInfo};
 use identifier::Identifier;
-use std::collections::BTreeSet;
+use sqlsyntax::ast;
+use std::borrow::Cow;
+use std::collections::{BTreeSet, HashMap};
 use std::fmt;
 
+use super::snapshot::{SnapshotReader, SnapshotWriter};
+use super::virtualtable::VirtualTable;
+
+/// The format of a row's key, which is self-describing so that columns can
+/// be added and have their types changed without rewriting every row.
+///
+/// A key is the row's rowid as 8 big-endian bytes, so that keys sort by
+/// rowid, followed by the format version and the number of columns the row
+/// was stored with. Each column is then written as its type, as in
+/// snapshots, and unless it's NULL, its bytes. The key ends with the CRC-32
+/// of everything before it, as 4 big-endian bytes.
+const ROW_FORMAT_VERSION: u8 = 1;
+
 pub enum UpdateError {
     ValidationError {
         column_name: Identifier,
@@ -27,7 +43,18 @@ pub struct Table {
     pub columns: Vec<Column>,
 
     pub next_rowid: u64,
-    pub rowid_index: BTreeSet<Vec<u8>>
+    /// The keys of the rows. Keys are only added once they're encoded or
+    /// checked, so that `decode_row` can't fail.
+    pub rowid_index: BTreeSet<Vec<u8>>,
+
+    /// If the table is a materialized view, the query that computes its rows
+    pub view_query: Option<ast::SelectStatement>,
+
+    /// If the table is virtual, where its rows are read from.
+    /// Virtual tables have no rows of their own.
+    pub virtual_table: Option<Box<VirtualTable>>,
+
+    pub comment: Option<String>
 }
 
 #[derive(Debug)]
@@ -35,7 +62,11 @@ pub struct Column {
     pub offset: u32,
     pub name: Identifier,
     pub dbtype: DbType,
-    pub nullable: bool
+    pub nullable: bool,
+    /// The most characters a string column's values can have, as declared
+    /// by `VARCHAR(n)`
+    pub max_length: Option<u64>,
+    pub comment: Option<String>
 }
 
 impl TableInfo for Table {
@@ -58,23 +89,110 @@ impl TableInfo for Table {
     fn find_column_by_name(&self, name: &Identifier) -> Option<&Column> {
         self.columns.iter().find(|c| &c.name == name)
     }
+
+    fn get_comment(&self) -> Option<&str> { self.comment.as_ref().map(|s| s as &str) }
 }
 
 impl Table {
     /// rowid is automatically added, and is not included as a specified column
     pub fn insert_row<I>(&mut self, column_data: I) -> Result<(), UpdateError>
     where I: ExactSizeIterator, I: Iterator<Item = (Box<[u8]>, Option<bool>)>
+    {
+        let key = try!(self.encode_row(self.next_rowid, column_data));
+
+        trace!("inserting row {} into {}", self.next_rowid, self.name);
+        trace!("inserting key into {}: {:?}", self.name, key);
+
+        self.rowid_index.insert(key);
+        self.next_rowid += 1;
+        Ok(())
+    }
+
+    /// Inserts a batch of rows, returning the amount of rows inserted.
+    ///
+    /// Every row is encoded before any of them are inserted, so a batch
+    /// containing an invalid row leaves the table untouched.
+    pub fn insert_many<R, I>(&mut self, rows: R) -> Result<u64, UpdateError>
+    where R: Iterator<Item = I>,
+    I: ExactSizeIterator, I: Iterator<Item = (Box<[u8]>, Option<bool>)>
+    {
+        let mut rowid = self.next_rowid;
+        let mut keys = Vec::new();
+
+        for column_data in rows {
+            keys.push(try!(self.encode_row(rowid, column_data)));
+            rowid += 1;
+        }
+
+        // Keys are prefixed with their big-endian rowid, and rowids are
+        // handed out in ascending order. The batch is therefore already
+        // sorted, and every key lands after the existing ones.
+        let count = keys.len() as u64;
+
+        trace!("inserting rows {}..{} into {}", self.next_rowid, rowid, self.name);
+
+        self.rowid_index.extend(keys);
+        self.next_rowid = rowid;
+        Ok(count)
+    }
+
+    /// Deletes the rows with these keys, and returns the rowid and values of
+    /// each.
+    pub fn delete_rows(&mut self, keys: Vec<Vec<u8>>) -> Vec<(u64, Vec<Variant>)> {
+        keys.into_iter().map(|key| {
+            self.rowid_index.remove(&key);
+            (key_rowid(&key), self.decode_row(&key))
+        }).collect()
+    }
+
+    /// Replaces the values of the rows with these keys, keeping their
+    /// rowids, and returns the rowid, old values and new values of each. The
+    /// new values must already have the columns' types. No row is changed if
+    /// one of them can't be encoded.
+    pub fn update_rows(&mut self, rows: Vec<(Vec<u8>, Vec<Variant>)>)
+    -> Result<Vec<(u64, Vec<Variant>, Vec<Variant>)>, String>
+    {
+        let encoded: Vec<_> = try!(rows.into_iter().map(|(key, values)| -> Result<_, String> {
+            let new_key = try!(self.encode_values(key_rowid(&key), values.clone()));
+            Ok((key, new_key, values))
+        }).collect());
+
+        // A row's key starts with its rowid, so the new key takes the old
+        // key's place in the index
+        Ok(encoded.into_iter().map(|(key, new_key, values)| {
+            let old = self.decode_row(&key);
+            self.rowid_index.remove(&key);
+            self.rowid_index.insert(new_key);
+            (key_rowid(&key), old, values)
+        }).collect())
+    }
+
+    /// The keys of the rows with these rowids, in the same order. Fails if
+    /// one of the rows doesn't exist.
+    pub fn find_row_keys(&self, rowids: &[u64]) -> Result<Vec<Vec<u8>>, String> {
+        // One pass over the rows, rather than one for each rowid
+        let keys: HashMap<u64, &Vec<u8>> = self.rowid_index.iter().map(|key| (key_rowid(key), key)).collect();
+
+        rowids.iter().map(|rowid| {
+            keys.get(rowid).map(|&key| key.clone()).ok_or(format!("table {} has no row {}", self.name, rowid))
+        }).collect()
+    }
+
+    /// Encodes a row's key, in the format described at `ROW_FORMAT_VERSION`
+    fn encode_row<I>(&self, rowid: u64, column_data: I) -> Result<Vec<u8>, UpdateError>
+    where I: ExactSizeIterator, I: Iterator<Item = (Box<[u8]>, Option<bool>)>
     {
         assert_eq!(self.columns.len(), column_data.len());
 
-        let mut key: Vec<u8> = Vec::new();
+        let mut writer = SnapshotWriter::without_header();
         {
             let mut buf = [0; 8];
-            byteutils::write_udbinteger(self.next_rowid, &mut buf);
-            key.push_all(&buf);
+            byteutils::write_udbinteger(rowid, &mut buf);
+            writer.buf.push_all(&buf);
         }
 
-        let mut lengths = Vec::new();
+        writer.write_u8(ROW_FORMAT_VERSION);
+        writer.write_uvar(self.columns.len() as u64);
 
         trace!("columns: {:?}", self.columns);
 
@@ -83,55 +201,128 @@ impl Table {
 
             trace!("column data for {}: {:?}", column.name, data);
 
-            let len = data.len() as u64;
+            if is_null == Some(true) {
+                assert_eq!(data.len(), 0);
+                writer.write_dbtype(DbType::Null);
+            } else if column.dbtype.is_valid_length(data.len() as u64) {
+                assert_eq!(column.nullable, is_null.is_some());
+                writer.write_dbtype(column.dbtype);
+                writer.write_bytes(data);
+            } else {
+                return Err(UpdateError::ValidationError {
+                    column_name: column.name.clone()
+                });
+            }
+        }
 
-            let append_data = match is_null {
-                Some(true) => {
-                    assert_eq!(len, 0);
-                    key.push(1);
+        let mut checksum = [0; 4];
+        byteutils::write_udbinteger(byteutils::crc32(&writer.buf) as u64, &mut checksum);
+        writer.buf.push_all(&checksum);
 
-                    false
-                },
-                Some(false) => {
-                    key.push(0);
+        Ok(writer.buf)
+    }
 
-                    true
-                },
-                None => true
-            };
+    /// Encodes a row's key from its values, which must already have the
+    /// columns' types.
+    fn encode_values(&self, rowid: u64, values: Vec<Variant>) -> Result<Vec<u8>, String> {
+        let column_data: Vec<_> = try!(self.columns.iter().zip(values).map(|(column, value)| -> Result<_, String> {
+            if value.is_null() {
+                Ok((Vec::new().into_boxed_slice(), Some(true)))
+            } else {
+                let data = try!(value.to_bytes(column.dbtype).map_err(|()| {
+                    format!("a value of column {} cannot be stored as {:?}", column.name, column.dbtype)
+                }));
+                Ok((data, if column.nullable { Some(false) } else { None }))
+            }
+        }).collect());
 
-            if append_data {
-                if column.dbtype.is_valid_length(len) {
-                    if column.dbtype.is_variable_length() {
-                        let mut buf = [0; 8];
-                        byteutils::write_udbinteger(len, &mut buf);
-                        lengths.push_all(&buf);
-                    }
+        self.encode_row(rowid, column_data.into_iter()).map_err(|e| format!("{}", e))
+    }
 
-                    assert_eq!(column.nullable, is_null.is_some());
+    /// Decodes the column values of a row's key. Every key in `rowid_index`
+    /// is either encoded by `encode_row` or checked by `check_row` when a
+    /// snapshot is loaded, so keys are assumed to be well-formed.
+    pub fn decode_row(&self, key: &[u8]) -> Vec<Variant> {
+        match self.read_row(key) {
+            Ok(values) => values,
+            Err(e) => panic!("{}", e)
+        }
+    }
 
-                    if let Some(is_null) = is_null {
-                        if is_null {
-                        }
+    /// Fails if a row's key is malformed, its checksum doesn't match, or one
+    /// of its values can't be converted to its column's type.
+    pub fn check_row(&self, key: &[u8]) -> Result<(), String> {
+        self.read_row(key).map(|_| ())
+    }
 
-                    }
+    /// Decodes the column values of a row's key, as the table's columns are
+    /// now. Columns that were added after the row was stored take their
+    /// default, and values stored before their column's type changed are
+    /// converted to the new type.
+    fn read_row(&self, key: &[u8]) -> Result<Vec<Variant>, String> {
+        let (rowid, stored) = try!(read_stored_values(key));
+        let mut stored = stored.into_iter();
 
-                    key.push_all(data);
-                } else {
-                    return Err(UpdateError::ValidationError {
-                        column_name: column.name.clone()
-                    });
-                }
+        self.columns.iter().map(|column| {
+            match stored.next() {
+                Some((DbType::Null, _)) => Ok(Variant::Null),
+                Some((dbtype, value)) if dbtype == column.dbtype => Ok(value),
+                Some((_, value)) => convert(value, column.dbtype).ok_or(format!(
+                    "row {} of table {} has a value that cannot be converted to {:?}", rowid, self.name, column.dbtype)),
+                None => Ok(column.default_value())
+            }
+        }).collect()
+    }
+
+    /// Adds a column after the others. Rows aren't rewritten: those stored
+    /// without the column read it as its default.
+    pub fn add_column(&mut self, name: Identifier, dbtype: DbType, nullable: bool, max_length: Option<u64>) {
+        let offset = self.columns.len() as u32;
+
+        self.columns.push(Column {
+            offset: offset,
+            name: name,
+            dbtype: dbtype,
+            nullable: nullable,
+            max_length: max_length,
+            comment: None
+        });
+    }
+
+    /// Changes a column's type. The values of the column must be
+    /// convertible to it.
+    ///
+    /// Rows aren't rewritten, and their values are converted as they're
+    /// read, so that each row only needs to convert from the type it was
+    /// stored with. Rows that were stored before the column's previous type
+    /// change, or before it was added, are therefore rewritten first.
+    pub fn set_column_type(&mut self, offset: u32, dbtype: DbType) -> Result<(), String> {
+        let i = offset as usize;
+        let mut rewritten = Vec::new();
+
+        for key in self.rowid_index.iter() {
+            let (rowid, stored) = try!(read_stored_values(key));
+
+            let is_current = match stored.get(i) {
+                Some(&(DbType::Null, _)) => true,
+                Some(&(stored_type, _)) => stored_type == self.columns[i].dbtype,
+                None => false
+            };
+
+            if !is_current {
+                let values = try!(self.read_row(key));
+                rewritten.push((key.clone(), try!(self.encode_values(rowid, values))));
             }
         }
 
-        key.extend(lengths);
+        trace!("rewriting {} rows of {} before changing the type of column {}", rewritten.len(), self.name, i);
 
-        trace!("inserting row {} into {}", self.next_rowid, self.name);
-        trace!("inserting key into {}: {:?}", self.name, key);
+        for (old_key, new_key) in rewritten {
+            self.rowid_index.remove(&old_key);
+            self.rowid_index.insert(new_key);
+        }
 
-        self.rowid_index.insert(key);
-        self.next_rowid += 1;
+        self.columns[i].dbtype = dbtype;
         Ok(())
     }
 
@@ -140,8 +331,168 @@ impl Table {
     }
 }
 
+impl Column {
+    /// The value of a column that a row doesn't have, as when it's left out
+    /// of an INSERT
+    pub fn default_value(&self) -> Variant {
+        if self.nullable {
+            Variant::Null
+        } else {
+            ColumnValueOps::from_bytes(self.dbtype, self.dbtype.get_default()).unwrap()
+        }
+    }
+}
+
+/// Converts a value to a type as it would be when stored in a column of
+/// that type, or returns None if it can't be.
+pub fn convert(value: Variant, dbtype: DbType) -> Option<Variant> {
+    match value.to_bytes(dbtype) {
+        Ok(bytes) => ColumnValueOps::from_bytes(dbtype, Cow::Owned(bytes.into_vec())).ok(),
+        Err(()) => None
+    }
+}
+
+/// The rowid of a row's key, which is checked when it's inserted or loaded
+pub fn key_rowid(key: &[u8]) -> u64 {
+    byteutils::read_udbinteger(&key[0..8])
+}
+
+/// Reads a row's rowid, and the type and value of each column it was
+/// stored with.
+fn read_stored_values(key: &[u8]) -> Result<(u64, Vec<(DbType, Variant)>), String> {
+    if key.len() < 12 {
+        return Err(format!("row is truncated"));
+    }
+
+    let (body, checksum) = key.split_at(key.len() - 4);
+    let rowid = byteutils::read_udbinteger(&body[0..8]);
+
+    if byteutils::read_udbinteger(checksum) != byteutils::crc32(body) as u64 {
+        return Err(format!("row {} is corrupt: its checksum doesn't match", rowid));
+    }
+
+    let mut reader = SnapshotReader::without_header(&body[8..]);
+
+    let version = try!(reader.read_u8());
+    if version != ROW_FORMAT_VERSION {
+        return Err(format!("row {} has an unsupported format version: {}", rowid, version));
+    }
+
+    let column_count = try!(reader.read_uvar());
+    let mut values = Vec::new();
+
+    for _ in 0..column_count {
+        let dbtype = try!(reader.read_dbtype());
+
+        let value = if dbtype == DbType::Null {
+            Variant::Null
+        } else {
+            let bytes = try!(reader.read_bytes());
+            let malformed = || format!("row {} has a malformed {:?} value", rowid, dbtype);
+
+            if !dbtype.is_valid_length(bytes.len() as u64) {
+                return Err(malformed());
+            }
+
+            try!(ColumnValueOps::from_bytes(dbtype, Cow::Borrowed(bytes)).map_err(|()| malformed()))
+        };
+
+        values.push((dbtype, value));
+    }
+
+    if !reader.is_at_end() {
+        return Err(format!("row {} has trailing data", rowid));
+    }
+
+    Ok((rowid, values))
+}
+
 impl ColumnInfo for Column {
     fn get_offset(&self) -> u32 { self.offset }
     fn get_name(&self) -> &Identifier { &self.name }
     fn get_dbtype(&self) -> &DbType { &self.dbtype }
+    fn get_comment(&self) -> Option<&str> { self.comment.as_ref().map(|s| s as &str) }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Table;
+    use columnvalueops::ColumnValueOps;
+    use identifier::Identifier;
+    use std::collections::BTreeSet;
+    use types::{DbType, Variant};
+
+    #[test]
+    fn test_row_format() {
+        let mut table = Table {
+            name: Identifier::new("t").unwrap(),
+            columns: Vec::new(),
+            next_rowid: 1,
+            rowid_index: BTreeSet::new(),
+            view_query: None,
+            virtual_table: None,
+            comment: None
+        };
+
+        table.add_column(Identifier::new("a").unwrap(), DbType::Integer { signed: false, bytes: 4 }, false, None);
+        table.add_column(Identifier::new("b").unwrap(), DbType::String, true, None);
+
+        let row = |values: Vec<Variant>| vec![Variant::from_u64(1)].into_iter().chain(values).collect::<Vec<_>>();
+
+        let key = table.encode_values(1, row(vec![Variant::StringLiteral("x".to_string())])).unwrap();
+        assert_eq!(table.decode_row(&key), row(vec![Variant::StringLiteral("x".to_string())]));
+
+        let key_null = table.encode_values(1, row(vec![Variant::Null])).unwrap();
+        assert_eq!(table.decode_row(&key_null), row(vec![Variant::Null]));
+
+        // Truncated rows fail instead of panicking
+        for i in 0..key.len() {
+            assert!(table.check_row(&key[..i]).is_err());
+        }
+
+        // Every byte is covered by the checksum
+        for i in 0..key.len() {
+            let mut corrupt = key.clone();
+            corrupt[i] ^= 0x10;
+            assert!(table.check_row(&corrupt).is_err());
+        }
+
+        assert!(table.check_row(&key[1..]).is_err());
+    }
+
+    #[test]
+    fn test_insert_many() {
+        let mut table = Table {
+            name: Identifier::new("t").unwrap(),
+            columns: Vec::new(),
+            next_rowid: 1,
+            rowid_index: BTreeSet::new(),
+            view_query: None,
+            virtual_table: None,
+            comment: None
+        };
+
+        table.add_column(Identifier::new("a").unwrap(), DbType::Integer { signed: false, bytes: 4 }, false, None);
+        table.add_column(Identifier::new("b").unwrap(), DbType::String, true, None);
+
+        let row = |a: &[u8], b: &[u8]| vec![
+            (a.to_vec().into_boxed_slice(), None),
+            (b.to_vec().into_boxed_slice(), Some(false))
+        ].into_iter();
+
+        // The second row's integer is too short for a, so none of the rows
+        // are inserted
+        let rows = vec![row(&[0, 0, 0, 1], b"x"), row(&[0, 0, 2], b"y"), row(&[0, 0, 0, 3], b"z")];
+        assert!(table.insert_many(rows.into_iter()).is_err());
+        assert!(table.rowid_index.is_empty());
+        assert_eq!(table.next_rowid, 1);
+
+        let rows = vec![row(&[0, 0, 0, 1], b"x"), row(&[0, 0, 0, 2], b"y")];
+        assert_eq!(table.insert_many(rows.into_iter()).ok(), Some(2));
+        assert_eq!(table.next_rowid, 3);
+        assert_eq!(table.rowid_index.iter().map(|key| table.decode_row(key)).collect::<Vec<_>>(), vec![
+            vec![Variant::from_u64(1), Variant::StringLiteral("x".to_string())],
+            vec![Variant::from_u64(2), Variant::StringLiteral("y".to_string())]
+        ]);
+    }
 }
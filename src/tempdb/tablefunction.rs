@@ -0,0 +1,190 @@
+//! Built-in table functions, which are used in FROM like tables. Their rows
+//! are computed from their arguments each time the query runs.
+//!
+//! * `generate_series(start, stop[, step])` counts from `start` to `stop`,
+//!   by `step` or 1, in a column named `value`.
+//! * `json_each(json)` has a row for each element of a JSON array or each
+//!   field of a JSON object, with the columns `name`, `value` and `type`.
+//!   `name` is a field's name, or an element's index from 0. It isn't called
+//!   `key` as in SQLite, because `KEY` is a keyword.
+
+use columnvalueops::{ColumnValueOps, ColumnValueOpsExt};
+use identifier::Identifier;
+use types::{DbType, Variant};
+
+use super::json_to_variant;
+use super::jsonlines::{self, JsonValue};
+
+/// The columns of a table function, or None if there's no such function.
+pub fn column_names(name: &Identifier) -> Option<Vec<Identifier>> {
+    let names: &[&str] = match name as &str {
+        "generate_series" => &["value"],
+        "json_each" => &["name", "value", "type"],
+        _ => return None
+    };
+
+    Some(names.iter().map(|name| Identifier::new(name).unwrap()).collect())
+}
+
+pub fn call(name: &Identifier, arguments: Vec<Variant>) -> Result<Box<Iterator<Item=Vec<Variant>>>, String> {
+    match name as &str {
+        "generate_series" => generate_series(arguments),
+        "json_each" => json_each(arguments),
+        _ => Err(format!("no table function named {}", name))
+    }
+}
+
+/// A NULL argument gives no rows.
+fn generate_series(arguments: Vec<Variant>) -> Result<Box<Iterator<Item=Vec<Variant>>>, String> {
+    if arguments.len() != 2 && arguments.len() != 3 {
+        return Err(format!("generate_series takes a start, a stop and an optional step"));
+    }
+
+    if arguments.iter().any(|value| value.is_null()) {
+        return Ok(Box::new(None.into_iter()));
+    }
+
+    let integers: Vec<i64> = try!(arguments.into_iter().map(|value| {
+        match value.cast(DbType::Integer { signed: true, bytes: 8 }) {
+            Some(Variant::SignedInteger(n)) => Ok(n),
+            _ => Err(format!("generate_series takes integer arguments"))
+        }
+    }).collect());
+
+    let step = integers.get(2).cloned().unwrap_or(1);
+
+    if step == 0 {
+        return Err(format!("generate_series step cannot be zero"));
+    }
+
+    Ok(Box::new(Series {
+        next: Some(integers[0]),
+        stop: integers[1],
+        step: step
+    }))
+}
+
+struct Series {
+    /// None once the series has passed `stop` or would overflow
+    next: Option<i64>,
+    stop: i64,
+    step: i64
+}
+
+impl Iterator for Series {
+    type Item = Vec<Variant>;
+
+    fn next(&mut self) -> Option<Vec<Variant>> {
+        match self.next {
+            Some(n) if (self.step > 0 && n <= self.stop) || (self.step < 0 && n >= self.stop) => {
+                self.next = n.checked_add(self.step);
+                Some(vec![Variant::from_i64(n)])
+            },
+            _ => None
+        }
+    }
+}
+
+/// Nested arrays and objects are values of the type `array` or `object`,
+/// kept as JSON text. A scalar has a single row with a NULL name, and NULL
+/// gives no rows.
+fn json_each(arguments: Vec<Variant>) -> Result<Box<Iterator<Item=Vec<Variant>>>, String> {
+    let text = match (arguments.len(), arguments.into_iter().nth(0)) {
+        (1, Some(Variant::Null)) => return Ok(Box::new(None.into_iter())),
+        (1, Some(Variant::StringLiteral(text))) => text,
+        _ => return Err(format!("json_each takes JSON text as its only argument"))
+    };
+
+    let json_error = |e| format!("json_each: {}", e);
+
+    let fields: Vec<(Variant, JsonValue)> = match try!(jsonlines::parse_value(&text).map_err(&json_error)) {
+        JsonValue::Nested(ref text) if text.starts_with("[") => {
+            try!(jsonlines::parse_array(text).map_err(&json_error)).into_iter().enumerate().map(|(i, value)| {
+                (Variant::from_u64(i as u64), value)
+            }).collect()
+        },
+        JsonValue::Nested(ref text) => {
+            try!(jsonlines::parse_object(text).map_err(&json_error)).into_iter().map(|(name, value)| {
+                (Variant::StringLiteral(name), value)
+            }).collect()
+        },
+        value => vec![(Variant::Null, value)]
+    };
+
+    let rows: Vec<_> = fields.into_iter().map(|(name, value)| {
+        let json_type = match value {
+            JsonValue::Null => "null",
+            JsonValue::Bool(true) => "true",
+            JsonValue::Bool(false) => "false",
+            JsonValue::Integer(_) => "integer",
+            JsonValue::Float(_) => "real",
+            JsonValue::String(_) => "text",
+            JsonValue::Nested(ref text) if text.starts_with("[") => "array",
+            JsonValue::Nested(_) => "object"
+        };
+
+        vec![name, json_to_variant(value), Variant::StringLiteral(json_type.to_string())]
+    }).collect();
+
+    Ok(Box::new(rows.into_iter()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::call;
+    use columnvalueops::ColumnValueOps;
+    use identifier::Identifier;
+    use std::i64;
+    use types::Variant;
+
+    fn rows(name: &str, arguments: Vec<Variant>) -> Result<Vec<Vec<Variant>>, String> {
+        call(&Identifier::new(name).unwrap(), arguments).map(|rows| rows.collect())
+    }
+
+    fn string(s: &str) -> Variant {
+        Variant::StringLiteral(s.to_string())
+    }
+
+    #[test]
+    fn test_generate_series() {
+        let series = |arguments| rows("generate_series", arguments).map(|rows| {
+            rows.into_iter().map(|row| row[0].clone()).collect::<Vec<_>>()
+        });
+
+        assert_eq!(series(vec![Variant::from_i64(1), Variant::from_i64(3)]).unwrap(),
+            vec![Variant::from_i64(1), Variant::from_i64(2), Variant::from_i64(3)]);
+        assert_eq!(series(vec![Variant::from_i64(10), Variant::from_i64(1), Variant::from_i64(-4)]).unwrap(),
+            vec![Variant::from_i64(10), Variant::from_i64(6), Variant::from_i64(2)]);
+        assert_eq!(series(vec![Variant::from_i64(3), Variant::from_i64(1)]).unwrap(), vec![]);
+        assert_eq!(series(vec![Variant::from_i64(1), Variant::Null]).unwrap(), vec![]);
+        assert_eq!(series(vec![Variant::from_i64(i64::MAX - 1), Variant::from_i64(i64::MAX), Variant::from_i64(5)]).unwrap(),
+            vec![Variant::from_i64(i64::MAX - 1)]);
+
+        assert!(series(vec![Variant::from_i64(1), Variant::from_i64(3), Variant::from_i64(0)]).is_err());
+        assert!(series(vec![Variant::from_i64(1)]).is_err());
+        assert!(series(vec![Variant::from_i64(1), string("x")]).is_err());
+    }
+
+    #[test]
+    fn test_json_each() {
+        assert_eq!(rows("json_each", vec![string(r#"{"a": 1, "b": [true], "c": null}"#)]).unwrap(), vec![
+            vec![string("a"), Variant::from_i64(1), string("integer")],
+            vec![string("b"), string("[true]"), string("array")],
+            vec![string("c"), Variant::Null, string("null")]
+        ]);
+
+        assert_eq!(rows("json_each", vec![string(r#"["x", 2.5, {}]"#)]).unwrap(), vec![
+            vec![Variant::UnsignedInteger(0), string("x"), string("text")],
+            vec![Variant::UnsignedInteger(1), Variant::from_f64(2.5), string("real")],
+            vec![Variant::UnsignedInteger(2), string("{}"), string("object")]
+        ]);
+
+        assert_eq!(rows("json_each", vec![string("false")]).unwrap(), vec![
+            vec![Variant::Null, Variant::UnsignedInteger(0), string("false")]
+        ]);
+        assert!(rows("json_each", vec![Variant::Null]).unwrap().is_empty());
+
+        assert!(rows("json_each", vec![string("[1,")]).is_err());
+        assert!(rows("json_each", vec![Variant::from_i64(1)]).is_err());
+    }
+}
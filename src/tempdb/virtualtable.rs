@@ -0,0 +1,51 @@
+//! Virtual tables are read-only tables whose rows are kept outside of the
+//! database. Each module provides a kind of virtual table, such as `csv`.
+
+use std::fmt;
+
+use identifier::{CaseFolding, Identifier};
+use types::Variant;
+
+use super::csv::CsvTable;
+use super::table::Column;
+
+pub trait VirtualTable: fmt::Debug + Send {
+    /// Reads the rows one at a time, with values of the columns' types.
+    ///
+    /// Rows are read each time the table is scanned, so they're never stale.
+    fn read_rows<'a>(&'a self, columns: &'a [Column])
+    -> Result<Box<Iterator<Item=Result<Vec<Variant>, String>> + 'a>, String>;
+
+    /// The module and arguments that create the table again, such as when
+    /// the database is reopened.
+    fn module(&self) -> &'static str;
+    fn arguments(&self) -> Vec<Variant>;
+}
+
+/// Creates a virtual table from a module's name and arguments.
+///
+/// If `columns` is None, the module infers the columns.
+/// Returns the table along with its columns.
+pub fn create(module: &Identifier, arguments: Vec<Variant>, columns: Option<Vec<Column>>,
+    case_folding: CaseFolding)
+-> Result<(Box<VirtualTable>, Vec<Column>), String>
+{
+    match module as &str {
+        "csv" => {
+            let path = match (arguments.len(), arguments.into_iter().nth(0)) {
+                (1, Some(Variant::StringLiteral(path))) => path,
+                _ => return Err(format!("csv requires a file path as its only argument"))
+            };
+
+            let table = CsvTable::new(path);
+
+            let columns = match columns {
+                Some(columns) => columns,
+                None => try!(table.infer_columns(case_folding))
+            };
+
+            Ok((Box::new(table), columns))
+        },
+        _ => Err(format!("no virtual table module named {}", module))
+    }
+}
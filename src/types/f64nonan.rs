@@ -4,6 +4,10 @@ use std::mem;
 use std::ops::Deref;
 
 /// A double-precision floating point number, `f64`, guaranteed never to be NaN.
+///
+/// Without NaN, floats are totally ordered: negative infinity comes before
+/// every other number, and positive infinity after. -0.0 and 0.0 are equal,
+/// and hash the same so that they're grouped together.
 #[derive(Copy, Clone, PartialOrd, PartialEq)]
 pub struct F64NoNaN {
     value: f64
@@ -34,7 +38,9 @@ impl Ord for F64NoNaN {
 
 impl Hash for F64NoNaN {
     fn hash<H>(&self, state: &mut H) where H: Hasher {
-        let raw: u64 = unsafe { mem::transmute(self.value) };
+        // -0.0 == 0.0, so they must hash the same
+        let value = if self.value == 0.0 { 0.0 } else { self.value };
+        let raw: u64 = unsafe { mem::transmute(value) };
         raw.hash(state)
     }
 }
@@ -7,7 +7,11 @@ pub use self::variant::Variant;
 
 use std::borrow::Cow;
 
-#[derive(Debug, Copy, Clone)]
+/// The most bytes a fixed length byte array can have, so that its default
+/// value is never too large to allocate
+pub const MAX_FIXED_BYTES: u64 = 1 << 24;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
 pub enum DbType {
     /// A type that only contains the NULL value.
     Null,
@@ -32,9 +36,9 @@ impl DbType {
         match (&ident as &str, array_size) {
             ("byte", None) => Some(DbType::Integer { signed: false, bytes: 1 }),
             ("byte", Some(None)) => Some(DbType::ByteDynamic),
-            ("byte", Some(Some(v))) => Some(DbType::ByteFixed(v)),
+            ("byte", Some(Some(v))) if v <= MAX_FIXED_BYTES => Some(DbType::ByteFixed(v)),
             ("f64", None) | ("double", None) => Some(DbType::F64),
-            ("string", None) | ("varchar", None) => Some(DbType::String),
+            ("string", None) | ("varchar", None) | ("char", None) => Some(DbType::String),
             ("int", None) | ("integer", None) => Some(DbType::Integer { signed: true, bytes: 4 }),
             (ident, None) => {
                 if ident.len() >= 2 {
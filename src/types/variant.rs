@@ -3,7 +3,11 @@ use columnvalueops::ColumnValueOps;
 use types::DbType;
 use types::F64NoNaN;
 use std::borrow::{Cow, IntoCow};
+use std::cmp::Ordering;
 use std::fmt;
+use std::mem;
+use std::{i64, u64};
+use std::str;
 
 #[derive(Clone, Hash, Eq, PartialEq, Ord, PartialOrd)]
 pub enum Variant {
@@ -43,20 +47,12 @@ impl ColumnValueOps for Variant {
         Ok(Variant::StringLiteral(s.into_owned()))
     }
 
-    fn from_number_literal(s: Cow<str>) -> Result<Variant, Cow<str>> {
-        if let Ok(number) = s.parse() {
-            Ok(Variant::SignedInteger(number))
-        } else if let Ok(number) = s.parse() {
-            Ok(Variant::UnsignedInteger(number))
-        } else if let Ok(number) = s.parse() {
-            Ok(Variant::Float(F64NoNaN::new(number).unwrap()))
-        } else {
-            Err(s)
-        }
-    }
-
+    /// NaN isn't a value, so it's NULL
     fn from_f64(value: f64) -> Variant {
-        Variant::Float(F64NoNaN::new(value).unwrap())
+        match F64NoNaN::new(value) {
+            Some(value) => Variant::Float(value),
+            None => Variant::Null
+        }
     }
 
     fn to_f64(self) -> Result<f64, ()> {
@@ -68,6 +64,10 @@ impl ColumnValueOps for Variant {
         }
     }
 
+    fn from_i64(value: i64) -> Variant {
+        Variant::SignedInteger(value)
+    }
+
     fn from_u64(value: u64) -> Variant {
         Variant::UnsignedInteger(value)
     }
@@ -105,13 +105,18 @@ impl ColumnValueOps for Variant {
             },
             DbType::F64 => {
                 let f = byteutils::read_dbfloat(&bytes);
-                Ok(Variant::Float(F64NoNaN::new(f).unwrap()))
+                match F64NoNaN::new(f) {
+                    Some(f) => Ok(Variant::Float(f)),
+                    None => Err(())
+                }
             },
             DbType::String => {
                 let len = bytes.len();
                 if len > 0 && bytes[len - 1] == 0 {
-                    let s = String::from_utf8_lossy(&bytes[0..len - 1]);
-                    Ok(Variant::StringLiteral(s.into_owned()))
+                    match str::from_utf8(&bytes[0..len - 1]) {
+                        Ok(s) => Ok(Variant::StringLiteral(s.to_string())),
+                        Err(_) => Err(())
+                    }
                 } else {
                     Err(())
                 }
@@ -130,7 +135,8 @@ impl ColumnValueOps for Variant {
                 // NULL has no data.
                 Err(())
             },
-            (Variant::Bytes(v), DbType::ByteDynamic) => {
+            (Variant::Bytes(v), DbType::ByteDynamic) | (Variant::Bytes(v), DbType::ByteFixed(_)) => {
+                // cast() has checked the length of a fixed byte array
                 Ok(v.into_boxed_slice())
             },
             (Variant::StringLiteral(s), DbType::String) => {
@@ -168,6 +174,16 @@ impl ColumnValueOps for Variant {
         }
     }
 
+    fn memory_size(&self) -> usize {
+        let data_size = match self {
+            &Variant::Bytes(ref bytes) => bytes.capacity(),
+            &Variant::StringLiteral(ref s) => s.capacity(),
+            _ => 0
+        };
+
+        mem::size_of::<Variant>() + data_size
+    }
+
     fn to_3vl(&self) -> i8 {
         fn b(value: bool) -> i8 { if value { 1 } else { -1 } }
 
@@ -220,6 +236,10 @@ impl ColumnValueOps for Variant {
                     Err(()) => None
                 }
             },
+            (Variant::Float(float), DbType::Integer { .. }) if float.is_infinite() => {
+                // an infinity has no integer value
+                None
+            },
             (Variant::Float(float), DbType::Integer { signed, .. }) => {
                 // truncates
                 if signed {
@@ -250,149 +270,532 @@ impl ColumnValueOps for Variant {
     // 0: self == rhs
     // 1: self > rhs
     fn compare(&self, rhs: &Self) -> Option<i8> {
-        let dbtype = self.get_dbtype();
-        if let Some(r) = rhs.clone().cast(dbtype) {
-            match (self, &r) {
-                (&Variant::Null, _) | (_, &Variant::Null) => None,
-                (&Variant::UnsignedInteger(l), &Variant::UnsignedInteger(r)) => {
-                    Some(if l < r { -1 } else if l > r { 1 } else { 0 })
-                },
-                (&Variant::SignedInteger(l), &Variant::SignedInteger(r)) => {
-                    Some(if l < r { -1 } else if l > r { 1 } else { 0 })
-                },
-                (&Variant::Float(l), &Variant::Float(r)) => {
-                    Some(if l < r { -1 } else if l > r { 1 } else { 0 })
-                },
-                (&Variant::Bytes(ref l), &Variant::Bytes(ref r)) => {
-                    Some(if l < r { -1 } else if l > r { 1 } else { 0 })
-                },
-                (&Variant::StringLiteral(ref l), &Variant::StringLiteral(ref r)) => {
-                    Some(if l < r { -1 } else if l > r { 1 } else { 0 })
-                },
-                _ => unreachable!()
+        let ordering = match (self, rhs) {
+            (&Variant::Null, _) | (_, &Variant::Null) => return None,
+            (&Variant::Bytes(ref l), &Variant::Bytes(ref r)) => l.cmp(r),
+            (&Variant::StringLiteral(ref l), &Variant::StringLiteral(ref r)) => l.cmp(r),
+            (l, r) => match compare_numbers(l, r) {
+                Some(ordering) => ordering,
+                None => return None
+            }
+        };
+
+        Some(match ordering {
+            Ordering::Less => -1,
+            Ordering::Equal => 0,
+            Ordering::Greater => 1
+        })
+    }
+
+    fn total_compare(&self, rhs: &Self) -> Ordering {
+        fn rank(value: &Variant) -> u8 {
+            match value {
+                &Variant::Null => 0,
+                &Variant::SignedInteger(..) | &Variant::UnsignedInteger(..) | &Variant::Float(..) => 1,
+                &Variant::StringLiteral(..) => 2,
+                &Variant::Bytes(..) => 3
             }
-        } else {
-            None
+        }
+
+        match self.compare(rhs) {
+            Some(-1) => Ordering::Less,
+            Some(0) => Ordering::Equal,
+            Some(_) => Ordering::Greater,
+            None => rank(self).cmp(&rank(rhs))
         }
     }
 
-    fn concat(&self, rhs: &Self) -> Self {
+    fn write_key(&self, key: &mut Vec<u8>) {
+        match self {
+            &Variant::Null => key.push(byteutils::KEY_NULL),
+            &Variant::StringLiteral(ref s) => {
+                key.push_all(&[byteutils::KEY_NOT_NULL, byteutils::KEY_STRING]);
+                byteutils::write_dbbytes_key(s.as_bytes(), key);
+            },
+            &Variant::Bytes(ref bytes) => {
+                key.push_all(&[byteutils::KEY_NOT_NULL, byteutils::KEY_BYTES]);
+                byteutils::write_dbbytes_key(bytes, key);
+            },
+            number => {
+                key.push_all(&[byteutils::KEY_NOT_NULL, byteutils::KEY_NUMBER]);
+                write_number_key(number, key);
+            }
+        }
+    }
+
+    fn concat(&self, rhs: &Self) -> Result<Self, String> {
         match (self, rhs) {
-            (&Variant::StringLiteral(ref l), &Variant::StringLiteral(ref r)) => {
-                Variant::StringLiteral(format!("{}{}", l, r))
+            (&Variant::Null, _) | (_, &Variant::Null) => Ok(Variant::Null),
+            (&Variant::Bytes(ref l), &Variant::Bytes(ref r)) => {
+                let mut bytes = l.clone();
+                bytes.push_all(r);
+                Ok(Variant::Bytes(bytes))
             },
-            (e @ &Variant::StringLiteral(_), rhs) => {
-                match rhs.clone().cast(DbType::String) {
-                    Some(r) => e.concat(&r),
-                    None => e.clone()
-                }
+            (&Variant::Bytes(..), _) | (_, &Variant::Bytes(..)) => {
+                Err(format!("cannot concatenate {} and {}", type_description(self), type_description(rhs)))
             },
-            (e, _) => e.clone()
+            // Numbers are converted to text
+            (l, r) => Ok(Variant::StringLiteral(format!("{}{}", l, r)))
         }
     }
 
-    fn add(&self, rhs: &Self) -> Self {
-        // TODO: treat overflow!
-        let dbtype = self.get_dbtype();
-        if let Some(r) = rhs.clone().cast(dbtype) {
-            match (self, r) {
-                (&Variant::UnsignedInteger(l), Variant::UnsignedInteger(r)) => {
-                    Variant::UnsignedInteger(l + r)
-                },
-                (&Variant::SignedInteger(l), Variant::SignedInteger(r)) => {
-                    Variant::SignedInteger(l + r)
-                },
-                (&Variant::Float(l), Variant::Float(r)) => {
-                    Variant::Float(F64NoNaN::new(*l + *r).unwrap())
-                },
-                _ => self.clone()
+    fn add(&self, rhs: &Self) -> Result<Self, String> {
+        arithmetic(self, rhs,
+            |l, r| l.checked_add(r).map(Variant::UnsignedInteger),
+            |l, r| l.checked_add(r).map(Variant::SignedInteger),
+            |l, r| F64NoNaN::new(l + r).map(Variant::Float))
+    }
+
+    fn sub(&self, rhs: &Self) -> Result<Self, String> {
+        arithmetic(self, rhs,
+            // Subtracting a larger unsigned integer gives a negative result
+            |l, r| if l >= r { Some(Variant::UnsignedInteger(l - r)) } else { negate_u64(r - l) },
+            |l, r| l.checked_sub(r).map(Variant::SignedInteger),
+            |l, r| F64NoNaN::new(l - r).map(Variant::Float))
+    }
+
+    fn mul(&self, rhs: &Self) -> Result<Self, String> {
+        arithmetic(self, rhs,
+            |l, r| l.checked_mul(r).map(Variant::UnsignedInteger),
+            |l, r| l.checked_mul(r).map(Variant::SignedInteger),
+            |l, r| F64NoNaN::new(l * r).map(Variant::Float))
+    }
+
+    fn div(&self, rhs: &Self) -> Result<Self, String> {
+        // Division by zero is NULL
+        arithmetic(self, rhs,
+            |l, r| if r == 0 { Some(Variant::Null) } else { Some(Variant::UnsignedInteger(l / r)) },
+            |l, r| if r == 0 { Some(Variant::Null) } else { l.checked_div(r).map(Variant::SignedInteger) },
+            |l, r| if r == 0.0 { Some(Variant::Null) } else { F64NoNaN::new(l / r).map(Variant::Float) })
+    }
+
+    fn negate(&self) -> Result<Self, String> {
+        match self {
+            &Variant::SignedInteger(n) => 0i64.checked_sub(n).map(Variant::SignedInteger).ok_or(overflow()),
+            &Variant::UnsignedInteger(n) => negate_u64(n).ok_or(overflow()),
+            &Variant::Float(n) => Ok(Variant::Float(F64NoNaN::new(-*n).unwrap())),
+            &Variant::Null => Ok(Variant::Null),
+            &Variant::Bytes(..) |
+            &Variant::StringLiteral(..) => {
+                Err(format!("cannot negate {}", type_description(self)))
             }
-        } else {
-            self.clone()
         }
     }
 
-    fn sub(&self, rhs: &Self) -> Self {
-        // TODO: treat overflow!
-        let dbtype = self.get_dbtype();
-        if let Some(r) = rhs.clone().cast(dbtype) {
-            match (self, r) {
-                (&Variant::UnsignedInteger(l), Variant::UnsignedInteger(r)) => {
-                    Variant::UnsignedInteger(l - r)
-                },
-                (&Variant::SignedInteger(l), Variant::SignedInteger(r)) => {
-                    Variant::SignedInteger(l - r)
-                },
-                (&Variant::Float(l), Variant::Float(r)) => {
-                    Variant::Float(F64NoNaN::new(*l - *r).unwrap())
-                },
-                _ => self.clone()
+    fn length(&self) -> Result<Self, String> {
+        let length = match self {
+            &Variant::Null => return Ok(Variant::Null),
+            &Variant::Bytes(ref bytes) => bytes.len(),
+            &Variant::StringLiteral(ref s) => s.chars().count(),
+            // Numbers are converted to text
+            n => n.to_string().chars().count()
+        };
+
+        Ok(Variant::UnsignedInteger(length as u64))
+    }
+
+    fn hex(&self) -> Result<Self, String> {
+        let text;
+        let bytes = match self {
+            &Variant::Null => return Ok(Variant::Null),
+            &Variant::Bytes(ref bytes) => &bytes[..],
+            &Variant::StringLiteral(ref s) => s.as_bytes(),
+            n => {
+                text = n.to_string();
+                text.as_bytes()
             }
-        } else {
-            self.clone()
+        };
+
+        let mut s = String::with_capacity(bytes.len() * 2);
+        for byte in bytes {
+            s.push_str(&format!("{:02X}", byte));
         }
+
+        Ok(Variant::StringLiteral(s))
     }
 
-    fn mul(&self, rhs: &Self) -> Self {
-        // TODO: treat overflow!
-        let dbtype = self.get_dbtype();
-        if let Some(r) = rhs.clone().cast(dbtype) {
-            match (self, r) {
-                (&Variant::UnsignedInteger(l), Variant::UnsignedInteger(r)) => {
-                    Variant::UnsignedInteger(l * r)
-                },
-                (&Variant::SignedInteger(l), Variant::SignedInteger(r)) => {
-                    Variant::SignedInteger(l * r)
-                },
-                (&Variant::Float(l), Variant::Float(r)) => {
-                    Variant::Float(F64NoNaN::new(*l * *r).unwrap())
-                },
-                _ => self.clone()
+    fn unhex(&self) -> Result<Self, String> {
+        fn digit(c: u8) -> Option<u8> {
+            match c {
+                b'0'...b'9' => Some(c - b'0'),
+                b'a'...b'f' => Some(c - b'a' + 10),
+                b'A'...b'F' => Some(c - b'A' + 10),
+                _ => None
             }
-        } else {
-            self.clone()
         }
-    }
 
-    fn div(&self, rhs: &Self) -> Self {
-        // TODO: treat overflow!
-        let dbtype = self.get_dbtype();
-        if let Some(r) = rhs.clone().cast(dbtype) {
-            match (self, r) {
-                (&Variant::UnsignedInteger(_), Variant::UnsignedInteger(0)) => Variant::Null,
-                (&Variant::SignedInteger(_), Variant::SignedInteger(0)) => Variant::Null,
-
-                (&Variant::UnsignedInteger(l), Variant::UnsignedInteger(r)) => {
-                    Variant::UnsignedInteger(l / r)
-                },
-                (&Variant::SignedInteger(l), Variant::SignedInteger(r)) => {
-                    Variant::SignedInteger(l / r)
-                },
-                (&Variant::Float(l), Variant::Float(r)) => {
-                    if r == F64NoNaN::new(0.0).unwrap() {
-                        Variant::Null
-                    } else {
-                        Variant::Float(F64NoNaN::new(*l / *r).unwrap())
-                    }
-                },
-                _ => self.clone()
+        let text = match self {
+            &Variant::Null => return Ok(Variant::Null),
+            &Variant::Bytes(..) => {
+                return Err(format!("cannot unhex {}", type_description(self)));
+            },
+            &Variant::StringLiteral(ref s) => s.clone(),
+            n => n.to_string()
+        };
+
+        let digits = text.as_bytes();
+        if digits.len() % 2 != 0 {
+            return Ok(Variant::Null);
+        }
+
+        let mut bytes = Vec::with_capacity(digits.len() / 2);
+        for pair in digits.chunks(2) {
+            match (digit(pair[0]), digit(pair[1])) {
+                (Some(high), Some(low)) => bytes.push(high * 16 + low),
+                _ => return Ok(Variant::Null)
             }
-        } else {
-            self.clone()
         }
+
+        Ok(Variant::Bytes(bytes))
     }
+}
 
-    fn negate(&self) -> Self {
-        // TODO: treat overflow!
-        match self {
-            &Variant::SignedInteger(n) => Variant::SignedInteger(-n),
-            &Variant::UnsignedInteger(n) => Variant::SignedInteger(-(n as i64)),
-            &Variant::Float(n) => Variant::Float(F64NoNaN::new(-*n).unwrap()),
-            &Variant::Null |
-            &Variant::Bytes(..) |
-            &Variant::StringLiteral(..) => {
-                self.clone()
-            }
+/// A number's key is the float nearest to it, followed by a suffix that
+/// orders the numbers that round to the same float: the integers by value,
+/// then the float itself. A float with an integer value that fits in an i64
+/// or a u64 is written as that integer, so that equal numbers have the same
+/// key.
+fn write_number_key(value: &Variant, key: &mut Vec<u8>) {
+    const NEGATIVE: u8 = 0x00;
+    const NON_NEGATIVE: u8 = 0x01;
+    const FLOAT: u8 = 0x02;
+
+    let integer = match value {
+        &Variant::SignedInteger(n) if n < 0 => Some((NEGATIVE, n as u64)),
+        &Variant::SignedInteger(n) => Some((NON_NEGATIVE, n as u64)),
+        &Variant::UnsignedInteger(n) => Some((NON_NEGATIVE, n)),
+        &Variant::Float(n) if n.fract() == 0.0 && *n >= i64::MIN as f64 && *n < 0.0 => {
+            Some((NEGATIVE, *n as i64 as u64))
+        },
+        // 2^64 rounds down to u64::MAX as an integer, so it's excluded
+        &Variant::Float(n) if n.fract() == 0.0 && *n >= 0.0 && *n < u64::MAX as f64 => {
+            Some((NON_NEGATIVE, *n as u64))
+        },
+        _ => None
+    };
+
+    let mut buf = [0; 8];
+
+    match integer {
+        Some((sign, n)) => {
+            let float = if sign == NEGATIVE { n as i64 as f64 } else { n as f64 };
+            byteutils::write_dbfloat_key(float, &mut buf);
+            key.push_all(&buf);
+            key.push(sign);
+
+            // Negative integers are in two's complement, which sorts by value
+            // among themselves
+            byteutils::write_udbinteger(n, &mut buf);
+            key.push_all(&buf);
+        },
+        None => {
+            let float = match value {
+                &Variant::Float(n) => *n,
+                _ => unreachable!()
+            };
+
+            byteutils::write_dbfloat_key(float, &mut buf);
+            key.push_all(&buf);
+            key.push(FLOAT);
+        }
+    }
+}
+
+/// Numbers of different types are compared by value. None if either value
+/// isn't a number.
+fn compare_numbers(lhs: &Variant, rhs: &Variant) -> Option<Ordering> {
+    match (lhs, rhs) {
+        (&Variant::UnsignedInteger(l), &Variant::UnsignedInteger(r)) => Some(l.cmp(&r)),
+        (&Variant::SignedInteger(l), &Variant::SignedInteger(r)) => Some(l.cmp(&r)),
+        (&Variant::SignedInteger(l), &Variant::UnsignedInteger(r)) => Some(compare_i64_u64(l, r)),
+        (&Variant::UnsignedInteger(l), &Variant::SignedInteger(r)) => Some(compare_i64_u64(r, l).reverse()),
+        (&Variant::Float(l), &Variant::Float(r)) => Some(l.cmp(&r)),
+        (&Variant::SignedInteger(l), &Variant::Float(r)) => Some(compare_i64_f64(l, *r)),
+        (&Variant::Float(l), &Variant::SignedInteger(r)) => Some(compare_i64_f64(r, *l).reverse()),
+        (&Variant::UnsignedInteger(l), &Variant::Float(r)) => Some(compare_u64_f64(l, *r)),
+        (&Variant::Float(l), &Variant::UnsignedInteger(r)) => Some(compare_u64_f64(r, *l).reverse()),
+        _ => None
+    }
+}
+
+fn compare_i64_u64(l: i64, r: u64) -> Ordering {
+    if l < 0 { Ordering::Less } else { (l as u64).cmp(&r) }
+}
+
+/// Large integers lose precision as floats, so if they're equal as floats,
+/// the float is compared as an integer instead.
+fn compare_i64_f64(l: i64, r: f64) -> Ordering {
+    match (l as f64).partial_cmp(&r).unwrap() {
+        Ordering::Equal if r < i64::MAX as f64 => l.cmp(&(r as i64)),
+        Ordering::Equal => Ordering::Less,
+        ordering => ordering
+    }
+}
+
+fn compare_u64_f64(l: u64, r: f64) -> Ordering {
+    match (l as f64).partial_cmp(&r).unwrap() {
+        Ordering::Equal if r < u64::MAX as f64 => l.cmp(&(r as u64)),
+        Ordering::Equal => Ordering::Less,
+        ordering => ordering
+    }
+}
+
+/// The operands of an arithmetic operator, converted to a common type
+enum Operands {
+    Null,
+    Unsigned(u64, u64),
+    Signed(i64, i64),
+    Float(f64, f64)
+}
+
+/// Integers stay integers. A signed and an unsigned integer are both
+/// treated as signed, and an integer with a float is treated as a float.
+fn numeric_operands(lhs: &Variant, rhs: &Variant) -> Result<Operands, String> {
+    fn to_i64(n: u64) -> Result<i64, String> {
+        if n <= i64::MAX as u64 { Ok(n as i64) } else { Err(overflow()) }
+    }
+
+    fn to_f64(value: &Variant) -> Option<f64> {
+        match value {
+            &Variant::UnsignedInteger(n) => Some(n as f64),
+            &Variant::SignedInteger(n) => Some(n as f64),
+            &Variant::Float(n) => Some(*n),
+            _ => None
         }
     }
+
+    Ok(match (lhs, rhs) {
+        (&Variant::Null, _) | (_, &Variant::Null) => Operands::Null,
+        (&Variant::UnsignedInteger(l), &Variant::UnsignedInteger(r)) => Operands::Unsigned(l, r),
+        (&Variant::SignedInteger(l), &Variant::SignedInteger(r)) => Operands::Signed(l, r),
+        (&Variant::SignedInteger(l), &Variant::UnsignedInteger(r)) => Operands::Signed(l, try!(to_i64(r))),
+        (&Variant::UnsignedInteger(l), &Variant::SignedInteger(r)) => Operands::Signed(try!(to_i64(l)), r),
+        (l, r) => match (to_f64(l), to_f64(r)) {
+            (Some(l), Some(r)) => Operands::Float(l, r),
+            _ => return Err(format!("cannot do arithmetic with {} and {}",
+                type_description(lhs), type_description(rhs)))
+        }
+    })
+}
+
+/// Integer operations return None if they overflow, and float operations
+/// return None if the result is NaN.
+fn arithmetic<U, S, F>(lhs: &Variant, rhs: &Variant, unsigned: U, signed: S, float: F)
+-> Result<Variant, String>
+where U: Fn(u64, u64) -> Option<Variant>, S: Fn(i64, i64) -> Option<Variant>, F: Fn(f64, f64) -> Option<Variant>
+{
+    match try!(numeric_operands(lhs, rhs)) {
+        Operands::Null => Ok(Variant::Null),
+        Operands::Unsigned(l, r) => unsigned(l, r).ok_or(overflow()),
+        Operands::Signed(l, r) => signed(l, r).ok_or(overflow()),
+        Operands::Float(l, r) => float(l, r).ok_or(format!("result is not a number"))
+    }
+}
+
+fn negate_u64(n: u64) -> Option<Variant> {
+    if n <= i64::MAX as u64 {
+        Some(Variant::SignedInteger(-(n as i64)))
+    } else if n == i64::MAX as u64 + 1 {
+        Some(Variant::SignedInteger(i64::MIN))
+    } else {
+        None
+    }
+}
+
+fn overflow() -> String {
+    format!("integer overflow")
+}
+
+fn type_description(value: &Variant) -> &'static str {
+    match value {
+        &Variant::Null => "NULL",
+        &Variant::Bytes(..) => "a byte array",
+        &Variant::StringLiteral(..) => "a string",
+        &Variant::SignedInteger(..) | &Variant::UnsignedInteger(..) => "an integer",
+        &Variant::Float(..) => "a float"
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Variant;
+    use columnvalueops::ColumnValueOps;
+    use types::DbType;
+    use std::borrow::IntoCow;
+    use std::cmp::Ordering;
+    use std::i64;
+
+    fn u(n: u64) -> Variant { Variant::UnsignedInteger(n) }
+    fn i(n: i64) -> Variant { Variant::SignedInteger(n) }
+    fn f(n: f64) -> Variant { ColumnValueOps::from_f64(n) }
+
+    #[test]
+    fn test_arithmetic() {
+        assert_eq!(u(2).add(&u(3)), Ok(u(5)));
+        assert_eq!(u(2).sub(&u(3)), Ok(i(-1)));
+        assert_eq!(u(2).mul(&i(-3)), Ok(i(-6)));
+        assert_eq!(i(7).div(&u(2)), Ok(i(3)));
+        assert_eq!(i(1).add(&f(0.5)), Ok(f(1.5)));
+        assert_eq!(f(0.5).mul(&u(4)), Ok(f(2.0)));
+
+        assert_eq!(i(1).add(&Variant::Null), Ok(Variant::Null));
+        assert_eq!(i(1).div(&i(0)), Ok(Variant::Null));
+        assert_eq!(f(1.0).div(&f(0.0)), Ok(Variant::Null));
+
+        assert!(u(!0).add(&u(1)).is_err());
+        assert!(i(i64::MAX).mul(&i(2)).is_err());
+        assert!(i(i64::MIN).div(&i(-1)).is_err());
+        assert!(i(1).add(&u(!0)).is_err());
+        assert!(i(1).add(&Variant::StringLiteral("1".to_string())).is_err());
+    }
+
+    #[test]
+    fn test_compare() {
+        let s = |v: &str| Variant::StringLiteral(v.to_string());
+
+        assert_eq!(u(1).compare(&i(-1)), Some(1));
+        assert_eq!(i(-1).compare(&u(!0)), Some(-1));
+        assert_eq!(i(1).compare(&f(1.5)), Some(-1));
+        assert_eq!(f(2.0).compare(&u(2)), Some(0));
+        assert_eq!(u(!0).compare(&f(18446744073709551615.0)), Some(-1));
+        assert_eq!(i(i64::MAX).compare(&f(9223372036854775807.0)), Some(-1));
+        assert_eq!(s("10").compare(&s("9")), Some(-1));
+
+        // Strings and numbers aren't comparable, and neither is NULL
+        assert_eq!(s("1").compare(&i(1)), None);
+        assert_eq!(Variant::Null.compare(&Variant::Null), None);
+    }
+
+    #[test]
+    fn test_total_compare() {
+        let mut values = vec![Variant::Bytes(vec![0]), Variant::StringLiteral("a".to_string()),
+            f(1.5), Variant::Null, u(1), i(-2)];
+        values.sort_by(|l, r| l.total_compare(r));
+
+        assert_eq!(values, vec![Variant::Null, i(-2), u(1), f(1.5),
+            Variant::StringLiteral("a".to_string()), Variant::Bytes(vec![0])]);
+        assert_eq!(Variant::Null.total_compare(&Variant::Null), Ordering::Equal);
+    }
+
+    #[test]
+    fn test_write_key() {
+        use std::f64::{INFINITY, NEG_INFINITY};
+        use std::u64;
+
+        let key = |value: &Variant| {
+            let mut key = Vec::new();
+            value.write_key(&mut key);
+            key
+        };
+
+        let s = |v: &str| Variant::StringLiteral(v.to_string());
+
+        // Keys sort like total_compare
+        let ordered = vec![Variant::Null, f(NEG_INFINITY), f(-1e300), i(i64::MIN), i(-3), f(-2.5), f(-0.5), i(0),
+            f(0.5), u(1), f(1.5), u(1 << 53), u((1 << 53) + 1), u(u64::MAX), f(u64::MAX as f64), f(1e300),
+            f(INFINITY), s(""), s("a"), s("ab"), s("b"), Variant::Bytes(vec![]), Variant::Bytes(vec![0])];
+
+        for pair in ordered.windows(2) {
+            assert_eq!(pair[0].total_compare(&pair[1]), Ordering::Less, "{:?} < {:?}", pair[0], pair[1]);
+            assert!(key(&pair[0]) < key(&pair[1]), "{:?} < {:?}", pair[0], pair[1]);
+        }
+
+        // Equal values have the same key
+        assert_eq!(key(&u(1)), key(&i(1)));
+        assert_eq!(key(&u(1)), key(&f(1.0)));
+        assert_eq!(key(&i(-3)), key(&f(-3.0)));
+        assert_eq!(key(&f(0.0)), key(&f(-0.0)));
+        assert_eq!(key(&i(i64::MIN)), key(&f(i64::MIN as f64)));
+        assert_eq!(key(&Variant::Null), key(&Variant::Null));
+        assert!(key(&s("1")) != key(&u(1)));
+    }
+
+    #[test]
+    fn test_concat() {
+        let s = |v: &str| Variant::StringLiteral(v.to_string());
+
+        assert_eq!(s("a").concat(&s("b")), Ok(s("ab")));
+        assert_eq!(s("a").concat(&i(-1)), Ok(s("a-1")));
+        assert_eq!(f(1.5).concat(&s("x")), Ok(s("1.5x")));
+        assert_eq!(u(1).concat(&u(2)), Ok(s("12")));
+        assert_eq!(s("a").concat(&Variant::Null), Ok(Variant::Null));
+        assert_eq!(Variant::Bytes(vec![1]).concat(&Variant::Bytes(vec![2])), Ok(Variant::Bytes(vec![1, 2])));
+        assert!(Variant::Bytes(vec![1]).concat(&s("a")).is_err());
+    }
+
+    #[test]
+    fn test_negate() {
+        assert_eq!(u(5).negate(), Ok(i(-5)));
+        assert_eq!(u(i64::MAX as u64 + 1).negate(), Ok(i(i64::MIN)));
+        assert!(u(i64::MAX as u64 + 2).negate().is_err());
+        assert!(i(i64::MIN).negate().is_err());
+        assert_eq!(f(1.5).negate(), Ok(f(-1.5)));
+        assert_eq!(Variant::Null.negate(), Ok(Variant::Null));
+    }
+
+    #[test]
+    fn test_infinity_and_nan() {
+        use std::collections::HashSet;
+        use std::f64::{INFINITY, NEG_INFINITY, NAN};
+
+        // Infinities are numbers beyond every other number
+        assert_eq!(f(INFINITY).compare(&u(!0)), Some(1));
+        assert_eq!(f(NEG_INFINITY).compare(&i(i64::MIN)), Some(-1));
+        assert_eq!(f(INFINITY).compare(&f(INFINITY)), Some(0));
+        assert_eq!(f(-0.0).compare(&f(0.0)), Some(0));
+
+        let mut values = vec![f(INFINITY), u(1), f(NEG_INFINITY), Variant::Null];
+        values.sort_by(|l, r| l.total_compare(r));
+        assert_eq!(values, vec![Variant::Null, f(NEG_INFINITY), u(1), f(INFINITY)]);
+
+        // -0.0 and 0.0 are grouped together
+        let set: HashSet<Variant> = vec![f(-0.0), f(0.0)].into_iter().collect();
+        assert_eq!(set.len(), 1);
+
+        // NaN isn't a value
+        assert_eq!(f(NAN), Variant::Null);
+        assert!(f(INFINITY).sub(&f(INFINITY)).is_err());
+        assert!(f(INFINITY).mul(&u(0)).is_err());
+        assert_eq!(f(INFINITY).add(&u(1)), Ok(f(INFINITY)));
+        assert_eq!(f(1.0).div(&f(INFINITY)), Ok(f(0.0)));
+
+        assert_eq!(f(INFINITY).cast(DbType::Integer { signed: true, bytes: 8 }), None);
+        assert_eq!(f(INFINITY).to_bytes(DbType::F64).map(|bytes| Variant::from_bytes(DbType::F64, bytes.into_vec().into_cow())),
+            Ok(Ok(f(INFINITY))));
+        assert!(Variant::from_bytes(DbType::F64, vec![0xFF; 8].into_cow()).is_err());
+    }
+
+    #[test]
+    fn test_bytes() {
+        let s = |v: &str| Variant::StringLiteral(v.to_string());
+        let b = |v: &[u8]| Variant::Bytes(v.to_vec());
+
+        // Byte arrays are compared like memcmp, and a prefix comes first
+        assert_eq!(b(&[1, 2]).compare(&b(&[1, 2])), Some(0));
+        assert_eq!(b(&[1, 255]).compare(&b(&[2])), Some(-1));
+        assert_eq!(b(&[1]).compare(&b(&[1, 0])), Some(-1));
+        assert_eq!(b(&[]).total_compare(&b(&[0])), Ordering::Less);
+        assert_eq!(b(&[1]).compare(&s("1")), None);
+
+        assert_eq!(b(&[1, 2]).to_bytes(DbType::ByteFixed(2)), Ok(vec![1, 2].into_boxed_slice()));
+        assert!(b(&[1, 2]).to_bytes(DbType::ByteFixed(3)).is_err());
+
+        assert_eq!(b(&[0, 171, 255]).length(), Ok(u(3)));
+        assert_eq!(s("h\u{e9}").length(), Ok(u(2)));
+        assert_eq!(i(-10).length(), Ok(u(3)));
+        assert_eq!(Variant::Null.length(), Ok(Variant::Null));
+
+        assert_eq!(b(&[0, 171, 255]).hex(), Ok(s("00ABFF")));
+        assert_eq!(s("hi").hex(), Ok(s("6869")));
+        assert_eq!(u(12).hex(), Ok(s("3132")));
+        assert_eq!(Variant::Null.hex(), Ok(Variant::Null));
+
+        assert_eq!(s("00abFF").unhex(), Ok(b(&[0, 171, 255])));
+        assert_eq!(s("").unhex(), Ok(b(&[])));
+        assert_eq!(u(12).unhex(), Ok(b(&[18])));
+        assert_eq!(s("abc").unhex(), Ok(Variant::Null));
+        assert_eq!(s("zz").unhex(), Ok(Variant::Null));
+        assert_eq!(Variant::Null.unhex(), Ok(Variant::Null));
+        assert!(b(&[1]).unhex().is_err());
+    }
 }